@@ -1,30 +1,22 @@
 use librarius::{
-    FileSource, Librarius, LibrariusBuilder, MemorySource, ObjectSize, Persistent,
-    PersistentPointer, Result, TypedLibrariusBuilder, TypedTransaction,
+    FileSource, Librarius, LibrariusBuilder, MemorySource, Persistent, PersistentPointer, Result,
+    TypedLibrariusBuilder, TypedTransaction,
 };
 use std::env;
 
+#[derive(Persistent)]
+#[repr(C)]
 struct Data {
     value: usize,
 }
 
-impl Persistent for Data {
-    fn size() -> ObjectSize {
-        ObjectSize::new_with_usize(0, std::mem::size_of::<Data>())
-    }
-}
-
+#[derive(Persistent)]
+#[repr(C)]
 struct Root {
     data: PersistentPointer<Data>,
     value: usize,
 }
 
-impl Persistent for Root {
-    fn size() -> ObjectSize {
-        ObjectSize::new_with_usize(8, 8)
-    }
-}
-
 impl Root {
     fn new() -> Root {
         println!("running constructor...");
@@ -0,0 +1,359 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parenthesized, parse_macro_input, parse_quote, token, Data, DataEnum, DeriveInput, Error,
+    Fields, Type,
+};
+
+/*
+ * Implements Persistent for a #[repr(C)] struct. The layout is added up
+ * from the Layout of each field, so a field can be another such struct,
+ * or an array, as long as all the pointers still come first. A struct
+ * with only Pod fields is Pod itself.
+ *
+ * A #[repr(u8)] enum is Tagged as well, its variants can only hold Pod.
+ */
+#[proc_macro_derive(Persistent)]
+pub fn derive_persistent(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        Data::Enum(data) => return expand_enum(input, data),
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "Persistent can only be derived for structs and enums",
+            ))
+        }
+    };
+    if !has_repr(input, "C") {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Persistent structs need #[repr(C)], so that their fields stay in order",
+        ));
+    }
+
+    let members = fields.members();
+    let offset_members = fields.members();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+
+    let hash = fnv(describe(fields).as_bytes());
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    /* only applies when all the fields are Pod, the bounds aren't checked up front */
+    let mut generics = input.generics.clone();
+    let pod_where = generics.make_where_clause();
+    for ty in &types {
+        pod_where
+            .predicates
+            .push(parse_quote!(for<'pod> #ty: ::librarius::Pod));
+    }
+    let pod_where = &generics.where_clause;
+
+    /* without generics, the layout can be checked right away */
+    let check = match input.generics.params.is_empty() {
+        true => quote! {
+            const _: usize = <#name as ::librarius::Layout>::POINTERS;
+        },
+        false => quote! {},
+    };
+
+    Ok(quote! {
+        unsafe impl #impl_generics ::librarius::Layout for #name #ty_generics #where_clause {
+            const POINTERS: usize = ::librarius::layout::compose(&[
+                #((
+                    ::std::mem::offset_of!(Self, #members),
+                    <#types as ::librarius::Layout>::POINTERS,
+                    ::std::mem::size_of::<#types>(),
+                ),)*
+            ]);
+        }
+        #check
+
+        impl #impl_generics ::librarius::Persistent for #name #ty_generics #where_clause {
+            fn size() -> ::librarius::ObjectSize {
+                let pointers = <Self as ::librarius::Layout>::POINTERS;
+                ::librarius::ObjectSize::new_with_usize(
+                    pointers,
+                    ::std::mem::size_of::<Self>() - pointers,
+                )
+            }
+
+            fn fingerprint() -> u64 {
+                let size = <Self as ::librarius::Persistent>::size();
+                /* the offsets catch padding changes that keep the size */
+                let offsets = [#(::std::mem::offset_of!(Self, #offset_members) as u64),*];
+                offsets.iter().fold(#hash, |hash, offset| hash.rotate_left(7) ^ offset)
+                    ^ ((size.pointers as u64) << 32 | size.data as u64)
+            }
+        }
+
+        unsafe impl #impl_generics ::librarius::Pod for #name #ty_generics #pod_where {}
+    })
+}
+
+fn expand_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    if !has_repr(input, "u8") {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Persistent enums need #[repr(u8)], so that their tag is the first byte",
+        ));
+    }
+    if data.variants.is_empty() || data.variants.len() > u8::MAX as usize {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Persistent enums need between 1 and 255 variants",
+        ));
+    }
+
+    let mut types = Vec::new();
+    let mut variants = String::new();
+    for variant in &data.variants {
+        if let Some((_, discriminant)) = &variant.discriminant {
+            return Err(Error::new_spanned(
+                discriminant,
+                "the tags of Persistent enums are their positions",
+            ));
+        }
+        for field in &variant.fields {
+            if is_pointer(&field.ty) {
+                return Err(Error::new_spanned(
+                    field,
+                    "enum variants can only hold plain data, pointers have to come first in an object",
+                ));
+            }
+            types.push(&field.ty);
+        }
+        variants.push_str(&format!("{}({})", variant.ident, describe(&variant.fields)));
+    }
+    let hash = fnv(variants.as_bytes());
+    let count = data.variants.len() as u8;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::librarius::Persistent for #name #ty_generics #where_clause {
+            fn size() -> ::librarius::ObjectSize {
+                fn pod<T: ::librarius::Pod>() {}
+                #(pod::<#types>();)*
+
+                ::librarius::ObjectSize::new_with_usize(0, ::std::mem::size_of::<Self>())
+            }
+
+            fn fingerprint() -> u64 {
+                let size = <Self as ::librarius::Persistent>::size();
+                #hash ^ ((size.pointers as u64) << 32 | size.data as u64)
+            }
+        }
+
+        unsafe impl #impl_generics ::librarius::Tagged for #name #ty_generics #where_clause {
+            const VARIANTS: u8 = #count;
+        }
+    })
+}
+
+/* the names and types of the fields, what a fingerprint is made of */
+fn describe(fields: &Fields) -> String {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let ty = &field.ty;
+            match &field.ident {
+                Some(ident) => format!("{}: {};", ident, quote!(#ty)),
+                None => format!("{}: {};", i, quote!(#ty)),
+            }
+        })
+        .collect()
+}
+
+/* the same in every build, unlike the hashers of std */
+fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn has_repr(input: &DeriveInput, repr: &str) -> bool {
+    input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+        .any(|attr| {
+            let mut found = false;
+            let _ = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident(repr) {
+                    found = true;
+                } else if meta.input.peek(token::Paren) {
+                    /* like align(8), the argument doesn't matter */
+                    let _argument;
+                    parenthesized!(_argument in meta.input);
+                }
+                Ok(())
+            });
+            found
+        })
+}
+
+fn is_pointer(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+            segment.ident == "PersistentPointer"
+                || segment.ident == "PersistentSlice"
+                || segment.ident == "PArc"
+                || segment.ident == "PArray"
+                || segment.ident == "UntypedPointer"
+        }),
+        Type::Array(array) => is_pointer(&array.elem),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use librarius::{
+        layout, ObjectSize, PArc, PArray, Persistent, PersistentPointer, PersistentSlice, Pod,
+        Tagged, UntypedPointer,
+    };
+    use std::mem::size_of;
+
+    #[derive(Persistent)]
+    #[repr(C)]
+    struct Leaf {
+        value: u32,
+        bytes: [u8; 3],
+    }
+
+    #[derive(Persistent)]
+    #[repr(C, align(16))]
+    struct Node {
+        left: PersistentPointer<Node>,
+        right: PersistentPointer<Node>,
+        raw: UntypedPointer,
+        leaves: PersistentSlice<Leaf>,
+        shared: PArc<Leaf>,
+        children: PArray<Node, 3>,
+        more: [PersistentPointer<Node>; 2],
+        leaf: Leaf,
+        value: u64,
+    }
+
+    #[derive(Persistent)]
+    #[repr(C)]
+    struct Edges {
+        from: PersistentPointer<Node>,
+        to: PersistentPointer<Node>,
+    }
+
+    /* its data ends the pointers of whatever it's in */
+    #[derive(Persistent)]
+    #[repr(C)]
+    struct Branch {
+        next: PersistentPointer<Node>,
+        leaf: Leaf,
+    }
+
+    #[derive(Persistent)]
+    #[repr(C)]
+    struct Tree {
+        root: PersistentPointer<Node>,
+        edges: [Edges; 2],
+        branch: Branch,
+        leaves: [Leaf; 2],
+        value: u64,
+    }
+
+    /* same size as Leaf, different fields */
+    #[derive(Persistent)]
+    #[repr(C)]
+    struct Other {
+        value: u32,
+        flags: [u8; 3],
+    }
+
+    #[derive(Persistent)]
+    #[repr(u8)]
+    #[allow(dead_code)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { width: u32, height: u32 },
+    }
+
+    /* same variants, in a different order */
+    #[derive(Persistent)]
+    #[repr(u8)]
+    #[allow(dead_code)]
+    enum Swapped {
+        Circle(u32),
+        Empty,
+        Rect { width: u32, height: u32 },
+    }
+
+    fn parts(size: ObjectSize) -> (u32, u32) {
+        (size.pointers, size.data)
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parts(Leaf::size()), (0, 8));
+
+        let pointers = 10 * size_of::<UntypedPointer>();
+        assert_eq!(
+            parts(Node::size()),
+            (pointers as u32, (size_of::<Node>() - pointers) as u32)
+        );
+    }
+
+    #[test]
+    fn nested() {
+        fn pod<T: Pod>() {}
+        pod::<Leaf>();
+        pod::<[Leaf; 2]>();
+
+        let pointers = 6 * size_of::<UntypedPointer>();
+        assert_eq!(
+            parts(Tree::size()),
+            (pointers as u32, (size_of::<Tree>() - pointers) as u32)
+        );
+
+        /* data, or padding, before a pointer */
+        let pointer = size_of::<UntypedPointer>();
+        assert_eq!(layout::compose(&[(0, pointer, pointer), (8, 0, 8)]), 8);
+        assert_eq!(layout::compose(&[(8, 0, 8), (0, pointer, pointer)]), 8);
+        assert!(std::panic::catch_unwind(|| layout::compose(&[(0, 0, 8), (8, 8, 8)])).is_err());
+        assert!(std::panic::catch_unwind(|| layout::compose(&[(0, 8, 8), (16, 8, 8)])).is_err());
+    }
+
+    #[test]
+    fn fingerprint() {
+        assert_eq!(parts(Leaf::size()), parts(Other::size()));
+        assert_ne!(Leaf::fingerprint(), Other::fingerprint());
+        assert_ne!(Leaf::fingerprint(), Node::fingerprint());
+    }
+
+    #[test]
+    fn tagged() {
+        assert_eq!(parts(Shape::size()), (0, size_of::<Shape>() as u32));
+        assert_eq!(Shape::VARIANTS, 3);
+
+        let rect = Shape::Rect {
+            width: 2,
+            height: 3,
+        };
+        let bytes = unsafe { std::mem::transmute::<Shape, [u8; 12]>(rect) };
+        assert_eq!(bytes[0], 2);
+
+        assert_eq!(parts(Shape::size()), parts(Swapped::size()));
+        assert_ne!(Shape::fingerprint(), Swapped::fingerprint());
+    }
+}
@@ -0,0 +1,333 @@
+use librarius::{
+    Error, Librarius, LibrariusBuilder, MemorySource, ReadTransaction, UntypedPointer,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+use std::rc::Rc;
+use std::time::Duration;
+
+create_exception!(librarius, LibrariusError, PyException);
+
+fn py_err(err: Error) -> PyErr {
+    LibrariusError::new_err(err.to_string())
+}
+
+/*
+ * An object as it is in a snapshot. Pointers are the addresses of the
+ * objects they point to, None where they point nowhere.
+ */
+#[pyclass]
+struct Object {
+    #[pyo3(get)]
+    address: usize,
+    #[pyo3(get)]
+    kind: u64,
+    #[pyo3(get)]
+    pointers: Vec<Option<usize>>,
+    data: Vec<u8>,
+}
+
+#[pymethods]
+impl Object {
+    #[getter]
+    fn data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.data)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Object(address={}, kind={:#x}, pointers={}, data={} bytes)",
+            self.address,
+            self.kind,
+            self.pointers.len(),
+            self.data.len()
+        )
+    }
+}
+
+fn object<'tx>(
+    tx: &ReadTransaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+) -> Result<Object, Error> {
+    let size = tx.object_size(pointer)?;
+    let pointers = tx
+        .pointers(pointer)?
+        .iter()
+        .map(|pointer| Some(pointer.address()).filter(|_| pointer.is_some()))
+        .collect();
+    Ok(Object {
+        address: pointer.address(),
+        kind: tx.object_kind(pointer)?,
+        pointers,
+        data: tx.read(pointer, &size)?[size.pointers as usize..].to_vec(),
+    })
+}
+
+fn address(pointer: Option<&UntypedPointer>) -> Option<usize> {
+    pointer
+        .filter(|pointer| pointer.is_some())
+        .map(UntypedPointer::address)
+}
+
+/*
+ * The store as it was when the snapshot was taken, for as long as Python
+ * holds on to it. Objects are found by walking from the roots, there's no
+ * going from an address to an object otherwise.
+ */
+#[pyclass(unsendable)]
+struct Snapshot {
+    /* borrows from the store below, so it's dropped first */
+    snapshot: librarius::Snapshot<'static, 'static>,
+    _librarius: Rc<Librarius<'static>>,
+}
+
+#[pymethods]
+impl Snapshot {
+    #[getter]
+    fn version(&self) -> usize {
+        self.snapshot.version()
+    }
+
+    fn root(&self) -> Option<usize> {
+        self.snapshot
+            .run(|tx| Ok(address(Some(tx.root()))))
+            .unwrap()
+    }
+
+    fn named_root(&self, name: &str) -> PyResult<Option<usize>> {
+        self.snapshot
+            .run(|tx| Ok(address(tx.named_root(name)?)))
+            .map_err(py_err)
+    }
+
+    /* every object reachable from the roots */
+    fn scan(&self) -> PyResult<Vec<Object>> {
+        self.snapshot
+            .run(|tx| {
+                let mut objects = Vec::new();
+                tx.scan(|pointer| {
+                    objects.push(object(tx, pointer)?);
+                    Ok(())
+                })?;
+                Ok(objects)
+            })
+            .map_err(py_err)
+    }
+
+    fn read(&self, address: usize) -> PyResult<Object> {
+        let found = self
+            .snapshot
+            .run(|tx| {
+                let mut found = None;
+                tx.scan(|pointer| {
+                    if found.is_none() && pointer.address() == address {
+                        found = Some(object(tx, pointer)?);
+                    }
+                    Ok(())
+                })?;
+                Ok(found)
+            })
+            .map_err(py_err)?;
+        found.ok_or_else(|| PyKeyError::new_err(address))
+    }
+}
+
+#[pyclass(unsendable)]
+struct Store {
+    librarius: Rc<Librarius<'static>>,
+}
+
+#[pymethods]
+impl Store {
+    fn snapshot(&self) -> PyResult<Snapshot> {
+        let snapshot = self.librarius.snapshot().map_err(py_err)?;
+        /*
+         * The store is behind an Rc that the snapshot holds on to, so it
+         * stays where it is for as long as the snapshot borrows from it.
+         */
+        let snapshot = unsafe {
+            std::mem::transmute::<
+                librarius::Snapshot<'_, 'static>,
+                librarius::Snapshot<'static, 'static>,
+            >(snapshot)
+        };
+        Ok(Snapshot {
+            snapshot,
+            _librarius: self.librarius.clone(),
+        })
+    }
+
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let tx = self.librarius.stats();
+        let transactions = PyDict::new(py);
+        transactions.set_item("attempts", tx.attempts)?;
+        transactions.set_item("conflicts", tx.conflicts)?;
+        transactions.set_item("objects_read", tx.objects_read)?;
+        transactions.set_item("versions_skipped", tx.versions_skipped)?;
+        transactions.set_item("objects_written", tx.objects_written)?;
+        transactions.set_item("reads_validated", tx.reads_validated)?;
+        transactions.set_item("bytes_allocated", tx.bytes_allocated)?;
+        transactions.set_item("pages_touched", tx.pages_touched)?;
+
+        let buffer = self.librarius.buffer_stats();
+        let resident = PyDict::new(py);
+        resident.set_item("resident", buffer.resident)?;
+        resident.set_item("evicted", buffer.evicted)?;
+        resident.set_item("written_back", buffer.written_back)?;
+
+        let stats = PyDict::new(py);
+        stats.set_item("transactions", transactions)?;
+        stats.set_item("buffer", resident)?;
+        Ok(stats)
+    }
+
+    /* by source */
+    fn usage<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.librarius
+            .usage()
+            .iter()
+            .map(|usage| {
+                let source = PyDict::new(py);
+                source.set_item("perf_level", usage.perf_level)?;
+                source.set_item("persistent", usage.persistent)?;
+                source.set_item("byte_addressable", usage.byte_addressable)?;
+                source.set_item("total", usage.total)?;
+                source.set_item("free", usage.free)?;
+                source.set_item("unrotated", usage.unrotated)?;
+                Ok(source)
+            })
+            .collect()
+    }
+
+    /* by the kind objects were allocated as */
+    fn usage_by_type<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let kinds = PyDict::new(py);
+        for (kind, usage) in self.librarius.usage_by_type().map_err(py_err)? {
+            let entry = PyDict::new(py);
+            entry.set_item("objects", usage.objects)?;
+            entry.set_item("live_bytes", usage.live_bytes)?;
+            entry.set_item("dead_bytes", usage.dead_bytes)?;
+            kinds.set_item(kind, entry)?;
+        }
+        Ok(kinds)
+    }
+
+    /* fails while snapshots of the store are still around */
+    #[pyo3(signature = (timeout = 1.0))]
+    fn close(&self, timeout: f64) -> PyResult<()> {
+        self.librarius
+            .close(Duration::from_secs_f64(timeout))
+            .map_err(py_err)
+    }
+}
+
+/*
+ * Opens the store in the given files, with memory bytes of DRAM in front
+ * of them, and without ever writing to them unless read_only is False.
+ */
+#[pyfunction]
+#[pyo3(signature = (*paths, read_only = true, memory = 64 << 20, pagesize = 4096))]
+fn open(
+    paths: &Bound<'_, PyTuple>,
+    read_only: bool,
+    memory: usize,
+    pagesize: usize,
+) -> PyResult<Store> {
+    let mut builder = LibrariusBuilder::new()
+        .pagesize(pagesize)
+        .source(MemorySource::new(memory).map_err(py_err)?);
+    for path in paths.iter() {
+        builder = builder.open_file(&path.extract::<String>()?);
+    }
+    if read_only {
+        builder = builder.read_only();
+    }
+    Ok(Store {
+        librarius: Rc::new(builder.open().map_err(py_err)?),
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "librarius")]
+fn librarius_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("LibrariusError", m.py().get_type::<LibrariusError>())?;
+    m.add_class::<Store>()?;
+    m.add_class::<Snapshot>()?;
+    m.add_class::<Object>()?;
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use librarius::{FileSource, ObjectSize};
+    use pyo3::ffi::c_str;
+    use pyo3::wrap_pymodule;
+
+    #[test]
+    fn python() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("librarius-py-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&7u64.to_le_bytes());
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let (_, users) = tx.alloc_named_root("users", ObjectSize::new(0, 4))?;
+            users.copy_from_slice(b"abcd");
+            Ok(())
+        })?;
+        librarius.close(Duration::from_secs(1))?;
+        drop(librarius);
+
+        Python::with_gil(|py| -> PyResult<()> {
+            let locals = PyDict::new(py);
+            locals.set_item("librarius", wrap_pymodule!(librarius_module)(py))?;
+            locals.set_item("path", path)?;
+            py.run(
+                c_str!(
+                    r#"
+store = librarius.open(path)
+snapshot = store.snapshot()
+objects = {object.address: object for object in snapshot.scan()}
+root = objects[snapshot.root()]
+assert root.data == (7).to_bytes(8, "little")
+assert root.pointers == []
+users = snapshot.read(snapshot.named_root("users"))
+assert users.data == b"abcd"
+assert snapshot.named_root("groups") is None
+try:
+    snapshot.read(1 << 40)
+    assert False
+except KeyError:
+    pass
+assert any(source["persistent"] for source in store.usage())
+assert sum(kind["objects"] for kind in store.usage_by_type().values()) == len(objects)
+assert store.stats()["transactions"]["attempts"] >= 1
+del snapshot, objects, root, users
+store.close()
+try:
+    store.snapshot()
+    assert False
+except librarius.LibrariusError:
+    pass
+"#
+                ),
+                None,
+                Some(&locals),
+            )
+        })
+        .map_err(|err| Python::with_gil(|py| err.display(py)))
+        .unwrap();
+
+        std::fs::remove_file(path).ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use crate::error::{Error, Result};
+use crate::librarius::{Librarius, Snapshot};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/* nothing an operator sends is anywhere near this long */
+const MAX_LINE_LEN: u64 = 8 << 10;
+
+fn io_err(err: io::Error) -> Error {
+    Error::FileIO { err }
+}
+
+/* an error message as a JSON string */
+fn quoted(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/*
+ * Serves the state of a running store over HTTP, for operators, with JSON
+ * replies: GET /health, /stats and /usage, and snapshots listed by GET,
+ * taken by POST and dropped by DELETE /snapshots/<id>. Snapshots taken
+ * here are kept by the Admin until they're dropped, or it is. It's one
+ * request per connection, whoever accepts them hands them to serve().
+ */
+pub struct Admin<'a, 'data> {
+    librarius: &'a Librarius<'data>,
+    snapshots: Mutex<(u64, BTreeMap<u64, Snapshot<'a, 'data>>)>,
+}
+
+impl<'a, 'data> Admin<'a, 'data> {
+    pub fn new(librarius: &'a Librarius<'data>) -> Self {
+        Admin {
+            librarius,
+            snapshots: Mutex::new((0, BTreeMap::new())),
+        }
+    }
+
+    pub fn serve(&self, stream: TcpStream) -> Result<()> {
+        let mut rx = BufReader::new(stream.try_clone().map_err(io_err)?).take(MAX_LINE_LEN);
+        let mut line = String::new();
+        rx.read_line(&mut line).map_err(io_err)?;
+        let mut words = line.split_whitespace();
+        let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+
+        /* the headers don't matter, and no request has a body */
+        loop {
+            let mut header = String::new();
+            rx.set_limit(MAX_LINE_LEN);
+            if rx.read_line(&mut header).map_err(io_err)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let (status, body) = self.route(method, path);
+        let reason = match status {
+            200 => "OK",
+            201 => "Created",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        let mut tx = stream;
+        write!(
+            tx,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason,
+            body.len(),
+            body
+        )
+        .and_then(|_| tx.flush())
+        .map_err(io_err)
+    }
+
+    fn route(&self, method: &str, path: &str) -> (u16, String) {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let result = match (method, segments.as_slice()) {
+            ("GET", ["health"]) => return self.health(),
+            ("GET", ["stats"]) => Ok((200, self.stats())),
+            ("GET", ["usage"]) => Ok((200, self.usage())),
+            ("GET", ["snapshots"]) => Ok((200, self.snapshots())),
+            ("POST", ["snapshots"]) => self.take_snapshot().map(|body| (201, body)),
+            ("DELETE", ["snapshots", id]) => Ok(self.drop_snapshot(id)),
+            (_, ["health"]) | (_, ["stats"]) | (_, ["usage"]) | (_, ["snapshots"]) => {
+                return (405, String::from("{\"error\":\"method not allowed\"}"));
+            }
+            _ => return (404, String::from("{\"error\":\"not found\"}")),
+        };
+
+        match result {
+            Ok(reply) => reply,
+            Err(Error::Closed {}) => (503, format!("{{\"error\":{}}}", quoted("closed"))),
+            Err(err) => (500, format!("{{\"error\":{}}}", quoted(&err.to_string()))),
+        }
+    }
+
+    fn health(&self) -> (u16, String) {
+        match self.librarius.is_closed() {
+            false => (200, String::from("{\"status\":\"open\"}")),
+            true => (503, String::from("{\"status\":\"closed\"}")),
+        }
+    }
+
+    fn stats(&self) -> String {
+        let tx = self.librarius.stats();
+        let buffer = self.librarius.buffer_stats();
+        format!(
+            "{{\"transactions\":{{\"attempts\":{},\"conflicts\":{},\"objects_read\":{},\
+             \"versions_skipped\":{},\"objects_written\":{},\"reads_validated\":{},\
+             \"bytes_allocated\":{},\"pages_touched\":{}}},\
+             \"buffer\":{{\"resident\":{},\"evicted\":{},\"written_back\":{}}}}}",
+            tx.attempts,
+            tx.conflicts,
+            tx.objects_read,
+            tx.versions_skipped,
+            tx.objects_written,
+            tx.reads_validated,
+            tx.bytes_allocated,
+            tx.pages_touched,
+            buffer.resident,
+            buffer.evicted,
+            buffer.written_back
+        )
+    }
+
+    fn usage(&self) -> String {
+        let sources: Vec<String> = self
+            .librarius
+            .usage()
+            .iter()
+            .map(|usage| {
+                format!(
+                    "{{\"perf_level\":{},\"persistent\":{},\"byte_addressable\":{},\
+                     \"total\":{},\"free\":{},\"unrotated\":{}}}",
+                    usage.perf_level,
+                    usage.persistent,
+                    usage.byte_addressable,
+                    usage.total,
+                    usage.free,
+                    usage.unrotated
+                )
+            })
+            .collect();
+        format!("[{}]", sources.join(","))
+    }
+
+    fn snapshot_json(id: u64, snapshot: &Snapshot) -> String {
+        format!("{{\"id\":{},\"version\":{}}}", id, snapshot.version())
+    }
+
+    fn snapshots(&self) -> String {
+        let snapshots = self.snapshots.lock();
+        let listed: Vec<String> = snapshots
+            .1
+            .iter()
+            .map(|(id, snapshot)| Self::snapshot_json(*id, snapshot))
+            .collect();
+        format!("[{}]", listed.join(","))
+    }
+
+    fn take_snapshot(&self) -> Result<String> {
+        let snapshot = self.librarius.snapshot()?;
+        let mut snapshots = self.snapshots.lock();
+        snapshots.0 += 1;
+        let id = snapshots.0;
+        let body = Self::snapshot_json(id, &snapshot);
+        snapshots.1.insert(id, snapshot);
+        Ok(body)
+    }
+
+    fn drop_snapshot(&self, id: &str) -> (u16, String) {
+        let id = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return (404, String::from("{\"error\":\"no such snapshot\"}")),
+        };
+        match self.snapshots.lock().1.remove(&id) {
+            Some(snapshot) => (200, Self::snapshot_json(id, &snapshot)),
+            None => (404, String::from("{\"error\":\"no such snapshot\"}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::{LibrariusBuilder, ObjectSize};
+    use std::net::TcpListener;
+
+    fn request(addr: std::net::SocketAddr, request: &str) -> Result<(u16, String)> {
+        let mut stream = TcpStream::connect(addr).map_err(io_err)?;
+        write!(stream, "{} HTTP/1.1\r\nHost: localhost\r\n\r\n", request).map_err(io_err)?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).map_err(io_err)?;
+
+        let status = reply[9..12].parse().unwrap();
+        let body = reply.split("\r\n\r\n").nth(1).unwrap_or("");
+        Ok((status, body.to_string()))
+    }
+
+    #[test]
+    fn admin_endpoint() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&[5; 8]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.write(root, &ObjectSize::new(0, 8))?[0] = 6;
+            Ok(())
+        })?;
+
+        let admin = Admin::new(&librarius);
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(io_err)?;
+        let addr = listener.local_addr().map_err(io_err)?;
+        let requests = [
+            ("GET /health", 200, "{\"status\":\"open\"}"),
+            ("POST /snapshots", 201, "{\"id\":1,\"version\":2}"),
+            ("GET /snapshots", 200, "[{\"id\":1,\"version\":2}]"),
+            ("DELETE /snapshots/1", 200, "{\"id\":1,\"version\":2}"),
+            (
+                "DELETE /snapshots/1",
+                404,
+                "{\"error\":\"no such snapshot\"}",
+            ),
+            ("GET /snapshots", 200, "[]"),
+            ("PUT /stats", 405, "{\"error\":\"method not allowed\"}"),
+            ("GET /fsck", 404, "{\"error\":\"not found\"}"),
+            ("GET /stats", 200, ""),
+            ("GET /usage", 200, ""),
+        ];
+
+        /* the listener goes with the server, a client can't wait on it for good */
+        let replies = std::thread::scope(|scope| {
+            let admin = &admin;
+            let server = scope.spawn(move || -> Result<()> {
+                for _ in 0..requests.len() {
+                    let (stream, _) = listener.accept().map_err(io_err)?;
+                    admin.serve(stream)?;
+                }
+                Ok(())
+            });
+            let replies: Vec<_> = requests
+                .iter()
+                .map(|(line, ..)| request(addr, line))
+                .collect();
+            server.join().unwrap().map(|_| replies)
+        })?;
+
+        let replies = replies.into_iter().collect::<Result<Vec<_>>>()?;
+        for ((line, status, body), (replied, replied_body)) in requests.iter().zip(&replies) {
+            assert_eq!(replied, status, "{}", line);
+            if !body.is_empty() {
+                assert_eq!(replied_body, body, "{}", line);
+            }
+        }
+        assert!(replies[8].1.contains("\"objects_written\":1,"));
+        assert!(replies[9].1.contains("\"total\":1048576"));
+
+        Ok(())
+    }
+}
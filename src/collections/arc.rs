@@ -0,0 +1,220 @@
+use crate::error::Error;
+use crate::tx::Transaction;
+use crate::typed::{Layout, Persistent, PersistentPointer, ReadGuard, TypedTransaction};
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/*
+ * Shared ownership of a T. All the clones point to one small object, that
+ * points to the T, and the number of clones is kept in the spare bits of
+ * that pointer. The small object is never written, so every clone sees the
+ * T as it is, and changing the count writes the T anew, which makes it as
+ * transactional as the T itself. The T is freed with the last clone.
+ */
+#[repr(C)]
+pub struct PArc<T: Persistent> {
+    shared: UntypedPointer,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Persistent> Persistent for PArc<T> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 0)
+    }
+}
+
+unsafe impl<T: Persistent> Layout for PArc<T> {
+    const POINTERS: usize = size_of::<UntypedPointer>();
+}
+
+impl<T: Persistent> PArc<T> {
+    pub fn new(tx: &mut Transaction, value: T) -> Result<Self> {
+        let value = super::alloc_value(tx, value)?;
+        let (shared, data) = tx.alloc(Self::shared_size())?;
+        *unsafe_utils::any_from_slice_mut::<UntypedPointer>(data) = value.with_refcount(1);
+        Ok(PArc {
+            shared,
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn new_none() -> Self {
+        PArc {
+            shared: UntypedPointer::new_none(),
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.shared.is_none()
+    }
+
+    fn shared_size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 0)
+    }
+
+    /* the pointer to the T, the same one for all the clones */
+    fn value<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx PersistentPointer<T>> {
+        if self.is_none() {
+            return Err(Error::NullPointer {});
+        }
+        let data = tx.read(&self.shared, &Self::shared_size())?;
+        Ok(PersistentPointer::from_raw_ref(
+            unsafe_utils::any_from_slice::<UntypedPointer>(data),
+        ))
+    }
+
+    pub fn read<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<ReadGuard<'tx, T>> {
+        let value = self.value(tx)?;
+        tx.read_typed(value)
+    }
+
+    /* the T is shared, every clone sees the write */
+    pub fn write<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx mut T> {
+        let value = self.value(tx)?;
+        tx.write_typed(value)
+    }
+
+    /* the number of clones, including this one */
+    pub fn count<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<u8> {
+        Ok(self.value(tx)?.as_raw().refcount())
+    }
+
+    pub fn clone_in<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<Self> {
+        let value = self.value(tx)?;
+        tx.set_refcount(value.as_raw(), &T::size(), |count| {
+            count.checked_add(1).ok_or(Error::TooManyReferences {})
+        })?;
+        Ok(PArc {
+            shared: self.shared.clone(),
+            phantom: PhantomData,
+        })
+    }
+
+    /*
+     * Gives up this clone. The last one frees the T at commit, it can still
+     * be read until then. The clone itself still points to where the T
+     * was, and shouldn't be used again.
+     */
+    pub fn drop_in<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<()> {
+        let value = self.value(tx)?;
+        let count = tx.set_refcount(value.as_raw(), &T::size(), |count| {
+            Ok(count.saturating_sub(1))
+        })?;
+        if count == 0 {
+            tx.free_typed(value)?;
+            tx.free(&self.shared)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    struct Item {
+        value: usize,
+    }
+
+    impl Persistent for Item {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Item>())
+        }
+    }
+
+    #[repr(C)]
+    struct Owners {
+        first: PArc<Item>,
+        second: PArc<Item>,
+    }
+
+    impl Persistent for Owners {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(size_of::<Owners>(), 0)
+        }
+    }
+
+    #[test]
+    fn shared() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Owners {
+                first: PArc::new_none(),
+                second: PArc::new_none(),
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let first = PArc::new(tx, Item { value: 1 })?;
+            tx.write_typed(root)?.first = first;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            assert_eq!(owners.first.count(tx)?, 1);
+            let second = owners.first.clone_in(tx)?;
+            tx.write_typed(root)?.second = second;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            owners.second.write(tx)?.value = 2;
+            assert_eq!(owners.first.read(tx)?.value, 2);
+            assert_eq!(owners.second.count(tx)?, 2);
+            Ok(())
+        })?;
+
+        /* an aborted clone leaves the count as it was */
+        let result = librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            owners.first.clone_in(tx)?;
+            assert_eq!(owners.first.count(tx)?, 3);
+            tx.abort_manually::<()>()
+        });
+        assert!(matches!(result, Err(Error::UserAborted {})));
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            assert_eq!(owners.first.count(tx)?, 2);
+            owners.first.drop_in(tx)?;
+            tx.write_typed(root)?.first = PArc::new_none();
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            assert_eq!(owners.second.count(tx)?, 1);
+            assert_eq!(owners.second.read(tx)?.value, 2);
+            owners.second.drop_in(tx)?;
+            Ok(())
+        })?;
+
+        let result = librarius.run(|tx| {
+            let root = tx.root_typed::<Owners>();
+            let owners = tx.read_typed(root)?.get();
+            owners.second.read(tx).map(|_| ())
+        });
+        assert!(matches!(result, Err(Error::ObjectFreed {})));
+
+        Ok(())
+    }
+}
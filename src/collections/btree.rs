@@ -0,0 +1,664 @@
+use super::{alloc_value, take, unbound};
+use crate::tx::{ReadTransaction, Transaction};
+use crate::typed::{Persistent, PersistentPointer, Pod, TypedReadTransaction, TypedTransaction};
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Bound, RangeBounds};
+
+/* every node but the root has between MIN_DEGREE - 1 and CAPACITY keys */
+const MIN_DEGREE: usize = 6;
+const CAPACITY: usize = 2 * MIN_DEGREE - 1;
+
+/*
+ * An ordered map, as a B-tree. Keys are stored in the nodes, values are
+ * objects of their own, just like the elements of a PVec. Nodes are split
+ * on the way down when inserting and topped up on the way down when
+ * removing, so that a single pass from the root is always enough. The
+ * number of entries is kept next to the root, by the same transactions,
+ * so every insert and remove also writes the map itself, and writers of
+ * one map always conflict with each other on it. Counts on disk are
+ * little-endian u64s, whatever the width of usize.
+ */
+#[repr(C)]
+pub struct PBTreeMap<K: Pod + Ord + Copy, V: Persistent> {
+    root: UntypedPointer,
+    len: u64,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K: Pod + Ord + Copy, V: Persistent> Persistent for PBTreeMap<K, V> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), size_of::<u64>())
+    }
+}
+
+impl<K: Pod + Ord + Copy, V: Persistent> Default for PBTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Pod + Ord + Copy, V: Persistent> PBTreeMap<K, V> {
+    pub fn new() -> Self {
+        PBTreeMap {
+            root: UntypedPointer::new_none(),
+            len: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        u64::from_le(self.len) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/* a leaf has no children at all */
+#[repr(C)]
+struct Node<K: Pod + Copy> {
+    children: [UntypedPointer; CAPACITY + 1],
+    values: [UntypedPointer; CAPACITY],
+    keys: [K; CAPACITY],
+    len: u64,
+}
+
+impl<K: Pod + Copy> Persistent for Node<K> {
+    fn size() -> ObjectSize {
+        let pointers = (2 * CAPACITY + 1) * size_of::<UntypedPointer>();
+        ObjectSize::new_with_usize(pointers, size_of::<Self>() - pointers)
+    }
+}
+
+impl<K: Pod + Ord + Copy> Node<K> {
+    fn new() -> Self {
+        Node {
+            children: [(); CAPACITY + 1].map(|_| UntypedPointer::new_none()),
+            values: [(); CAPACITY].map(|_| UntypedPointer::new_none()),
+            /* any bytes are a valid key */
+            keys: unsafe { std::mem::zeroed() },
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        u64::from_le(self.len) as usize
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.len = (len as u64).to_le();
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children[0].is_none()
+    }
+
+    fn search(&self, key: &K) -> std::result::Result<usize, usize> {
+        self.keys[..self.len()].binary_search(key)
+    }
+
+    /* the position of the first key that's within the bound */
+    fn lower_bound(&self, start: Bound<&K>) -> usize {
+        let keys = &self.keys[..self.len()];
+        match start {
+            Bound::Included(start) => keys.iter().take_while(|key| *key < start).count(),
+            Bound::Excluded(start) => keys.iter().take_while(|key| *key <= start).count(),
+            Bound::Unbounded => 0,
+        }
+    }
+
+    fn insert_entry(&mut self, i: usize, key: K, value: UntypedPointer) {
+        let len = self.len();
+        self.keys[i..=len].rotate_right(1);
+        self.keys[i] = key;
+        self.values[i..=len].rotate_right(1);
+        self.values[i] = value;
+        self.set_len(self.len() + 1);
+    }
+
+    fn remove_entry(&mut self, i: usize) -> (K, UntypedPointer) {
+        let len = self.len();
+        let key = self.keys[i];
+        let value = take(&mut self.values[i]);
+        self.keys[i..len].rotate_left(1);
+        self.values[i..len].rotate_left(1);
+        self.set_len(self.len() - 1);
+        (key, value)
+    }
+
+    /* children go before their entries on insert, and after them on remove */
+    fn insert_child(&mut self, i: usize, child: UntypedPointer) {
+        let count = self.len() + 1;
+        self.children[i..=count].rotate_right(1);
+        self.children[i] = child;
+    }
+
+    fn remove_child(&mut self, i: usize) -> UntypedPointer {
+        let count = self.len() + 1;
+        let child = take(&mut self.children[i]);
+        self.children[i..count].rotate_left(1);
+        child
+    }
+}
+
+fn read_node<'tx, K: Pod + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+) -> Result<&'tx Node<K>> {
+    Ok(unsafe_utils::any_from_slice(
+        tx.read(pointer, &Node::<K>::size())?,
+    ))
+}
+
+fn write_node<'tx, K: Pod + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+) -> Result<&'tx mut Node<K>> {
+    Ok(unsafe_utils::any_from_slice_mut(
+        tx.write(pointer, &Node::<K>::size())?,
+    ))
+}
+
+fn alloc_node<'tx, K: Pod + Ord + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+) -> Result<(UntypedPointer, &'tx mut Node<K>)> {
+    let (pointer, data) = tx.alloc(Node::<K>::size())?;
+    let node = unsafe_utils::any_from_slice_mut(data);
+    unsafe { std::ptr::write(node, Node::new()) };
+    Ok((pointer, node))
+}
+
+/* moves the upper half of a full child into a new sibling, and its median into the parent */
+fn split_child<'tx, K: Pod + Ord + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    parent: &mut Node<K>,
+    i: usize,
+) -> Result<()> {
+    let child = write_node::<K>(tx, unbound(&parent.children[i]))?;
+    let (pointer, sibling) = alloc_node::<K>(tx)?;
+
+    for j in 0..MIN_DEGREE - 1 {
+        sibling.keys[j] = child.keys[MIN_DEGREE + j];
+        sibling.values[j] = take(&mut child.values[MIN_DEGREE + j]);
+    }
+    for j in 0..MIN_DEGREE {
+        sibling.children[j] = take(&mut child.children[MIN_DEGREE + j]);
+    }
+    sibling.set_len(MIN_DEGREE - 1);
+
+    let key = child.keys[MIN_DEGREE - 1];
+    let value = take(&mut child.values[MIN_DEGREE - 1]);
+    child.set_len(MIN_DEGREE - 1);
+
+    parent.insert_child(i + 1, pointer);
+    parent.insert_entry(i, key, value);
+
+    Ok(())
+}
+
+/* folds the entry i and the child to the right of it into the child to the left */
+fn merge_children<'tx, K: Pod + Ord + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    parent: &mut Node<K>,
+    i: usize,
+) -> Result<()> {
+    let left = write_node::<K>(tx, unbound(&parent.children[i]))?;
+    let right_pointer = unbound(&parent.children[i + 1]);
+    let right = read_node::<K>(tx, right_pointer)?;
+    tx.free(right_pointer)?;
+
+    parent.remove_child(i + 1);
+    let (key, value) = parent.remove_entry(i);
+
+    let len = left.len();
+    left.keys[len] = key;
+    left.values[len] = value;
+    for j in 0..right.len() {
+        left.keys[len + 1 + j] = right.keys[j];
+        left.values[len + 1 + j] = right.values[j].clone();
+    }
+    for j in 0..=right.len() {
+        left.children[len + 1 + j] = right.children[j].clone();
+    }
+    left.set_len(len + 1 + right.len());
+
+    Ok(())
+}
+
+/* makes sure the child i has a key to spare, returns where it ended up */
+fn top_up_child<'tx, K: Pod + Ord + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    parent: &mut Node<K>,
+    i: usize,
+) -> Result<usize> {
+    if read_node::<K>(tx, unbound(&parent.children[i]))?.len() >= MIN_DEGREE {
+        return Ok(i);
+    }
+
+    if i > 0 && read_node::<K>(tx, unbound(&parent.children[i - 1]))?.len() >= MIN_DEGREE {
+        let child = write_node::<K>(tx, unbound(&parent.children[i]))?;
+        let left = write_node::<K>(tx, unbound(&parent.children[i - 1]))?;
+
+        let (key, value) = left.remove_entry(left.len() - 1);
+        let grandchild = take(&mut left.children[left.len() + 1]);
+        child.insert_child(0, grandchild);
+        child.insert_entry(0, parent.keys[i - 1], take(&mut parent.values[i - 1]));
+        parent.keys[i - 1] = key;
+        parent.values[i - 1] = value;
+
+        return Ok(i);
+    }
+
+    if i < parent.len() && read_node::<K>(tx, unbound(&parent.children[i + 1]))?.len() >= MIN_DEGREE
+    {
+        let child = write_node::<K>(tx, unbound(&parent.children[i]))?;
+        let right = write_node::<K>(tx, unbound(&parent.children[i + 1]))?;
+
+        let grandchild = right.remove_child(0);
+        let (key, value) = right.remove_entry(0);
+        let len = child.len();
+        child.children[len + 1] = grandchild;
+        child.insert_entry(len, parent.keys[i], take(&mut parent.values[i]));
+        parent.keys[i] = key;
+        parent.values[i] = value;
+
+        return Ok(i);
+    }
+
+    if i < parent.len() {
+        merge_children(tx, parent, i)?;
+        Ok(i)
+    } else {
+        merge_children(tx, parent, i - 1)?;
+        Ok(i - 1)
+    }
+}
+
+/* the largest or the smallest entry under the pointer */
+fn edge_entry<'tx, K: Pod + Ord + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    mut pointer: &'tx UntypedPointer,
+    largest: bool,
+) -> Result<(K, UntypedPointer)> {
+    loop {
+        let node = read_node::<K>(tx, pointer)?;
+        let i = if largest { node.len() } else { 0 };
+        if node.is_leaf() {
+            let entry = if largest { i - 1 } else { i };
+            return Ok((node.keys[entry], node.values[entry].clone()));
+        }
+        pointer = &node.children[i];
+    }
+}
+
+/* the entries under the pointer in order, as of the snapshot of a read-only transaction */
+fn read_entries<'tx, K: Pod + Ord + Copy + 'tx, V: Persistent + 'tx>(
+    tx: &ReadTransaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+    entries: &mut Vec<(K, &'tx V)>,
+) -> Result<()> {
+    if pointer.is_none() {
+        return Ok(());
+    }
+    let node: &Node<K> = unsafe_utils::any_from_slice(tx.read(pointer, &Node::<K>::size())?);
+    for i in 0..node.len() {
+        read_entries(tx, &node.children[i], entries)?;
+        let value = PersistentPointer::<V>::from_raw_ref(&node.values[i]);
+        entries.push((node.keys[i], tx.read_typed(value)?));
+    }
+    read_entries(tx, &node.children[node.len()], entries)
+}
+
+impl<K: Pod + Ord + Copy, V: Persistent> PersistentPointer<PBTreeMap<K, V>> {
+    pub fn get<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: &K) -> Result<Option<&'tx V>>
+    where
+        K: 'tx,
+    {
+        match self.find(tx, key)? {
+            Some(value) => Ok(Some(tx.read_typed(value)?.get())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_mut<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        key: &K,
+    ) -> Result<Option<&'tx mut V>>
+    where
+        K: 'tx,
+    {
+        match self.find(tx, key)? {
+            Some(value) => Ok(Some(tx.write_typed(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /* returns whether the key was already there, its old value is freed */
+    pub fn insert<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: K, value: V) -> Result<bool>
+    where
+        K: 'tx,
+    {
+        if let Some(old) = self.find(tx, &key)? {
+            let old = tx.write_typed(old)?;
+            unsafe { std::ptr::write(old, value) };
+            return Ok(true);
+        }
+
+        let map = tx.read_typed(self)?.get();
+        let full = map.root.is_some() && read_node::<K>(tx, &map.root)?.len() == CAPACITY;
+        if map.root.is_none() || full {
+            let map = tx.write_typed(self)?;
+            let (pointer, root) = alloc_node::<K>(tx)?;
+            root.children[0] = take(&mut map.root);
+            if full {
+                split_child(tx, root, 0)?;
+            }
+            map.root = pointer;
+        }
+
+        let mut pointer = &tx.read_typed(self)?.get().root;
+        loop {
+            let node = write_node::<K>(tx, pointer)?;
+            let mut i = node.search(&key).unwrap_err();
+            if node.is_leaf() {
+                node.insert_entry(i, key, alloc_value(tx, value)?);
+                let map = tx.write_typed(self)?;
+                map.len = (u64::from_le(map.len) + 1).to_le();
+                return Ok(false);
+            }
+            if read_node::<K>(tx, unbound(&node.children[i]))?.len() == CAPACITY {
+                split_child(tx, node, i)?;
+                if key > node.keys[i] {
+                    i += 1;
+                }
+            }
+            pointer = unbound(&node.children[i]);
+        }
+    }
+
+    /*
+     * Frees the value of the key. It can still be read until the transaction
+     * is over, the space isn't reused before then.
+     */
+    pub fn remove<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: &K) -> Result<Option<&'tx V>>
+    where
+        K: 'tx,
+    {
+        let value = match self.find(tx, key)? {
+            Some(pointer) => {
+                let value = tx.read_typed(pointer)?.get();
+                tx.free_typed(pointer)?;
+                value
+            }
+            None => return Ok(None),
+        };
+
+        let mut key = *key;
+        let mut pointer = &tx.read_typed(self)?.get().root;
+        loop {
+            let node = write_node::<K>(tx, pointer)?;
+            match node.search(&key) {
+                Ok(i) if node.is_leaf() => {
+                    node.remove_entry(i);
+                    break;
+                }
+                Ok(i) => {
+                    let left = unbound(&node.children[i]);
+                    let right = unbound(&node.children[i + 1]);
+                    /* an entry next to it takes its place, and is removed from below instead */
+                    let (next, child) = if read_node::<K>(tx, left)?.len() >= MIN_DEGREE {
+                        (Some(edge_entry(tx, left, true)?), i)
+                    } else if read_node::<K>(tx, right)?.len() >= MIN_DEGREE {
+                        (Some(edge_entry(tx, right, false)?), i + 1)
+                    } else {
+                        merge_children(tx, node, i)?;
+                        (None, i)
+                    };
+                    if let Some((next, value)) = next {
+                        node.keys[i] = next;
+                        node.values[i] = value;
+                        key = next;
+                    }
+                    pointer = unbound(&node.children[child]);
+                }
+                Err(i) => {
+                    let i = top_up_child(tx, node, i)?;
+                    pointer = unbound(&node.children[i]);
+                }
+            }
+        }
+
+        let map = tx.write_typed(self)?;
+        map.len = (u64::from_le(map.len) - 1).to_le();
+
+        /* a root without keys is replaced by its only child, if any */
+        let map = tx.read_typed(self)?.get();
+        let root = read_node::<K>(tx, &map.root)?;
+        if root.len() == 0 {
+            let child = root.children[0].clone();
+            tx.free(&map.root)?;
+            tx.write_typed(self)?.root = child;
+        }
+
+        Ok(Some(value))
+    }
+
+    /* the entries within the range, in order */
+    pub fn range<'a, 'tx, 'data, R>(
+        &'tx self,
+        tx: &'a mut Transaction<'tx, 'data>,
+        range: R,
+    ) -> Result<PBTreeRange<'a, 'tx, 'data, K, V>>
+    where
+        K: 'tx,
+        R: RangeBounds<K>,
+    {
+        let mut stack = Vec::new();
+        let mut pointer = &tx.read_typed(self)?.get().root;
+        while pointer.is_some() {
+            let node = read_node::<K>(tx, pointer)?;
+            let i = node.lower_bound(range.start_bound());
+            stack.push((node, i));
+            pointer = &node.children[i];
+        }
+
+        Ok(PBTreeRange {
+            tx,
+            stack,
+            end: range.end_bound().cloned(),
+            phantom: PhantomData,
+        })
+    }
+
+    pub(crate) fn read_all<'tx>(
+        &'tx self,
+        tx: &ReadTransaction<'tx, '_>,
+    ) -> Result<Vec<(K, &'tx V)>>
+    where
+        K: 'tx,
+    {
+        let mut entries = Vec::new();
+        read_entries(tx, &tx.read_typed(self)?.root, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn find<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        key: &K,
+    ) -> Result<Option<&'tx PersistentPointer<V>>>
+    where
+        K: 'tx,
+    {
+        let mut pointer = &tx.read_typed(self)?.get().root;
+        while pointer.is_some() {
+            let node = read_node::<K>(tx, pointer)?;
+            match node.search(key) {
+                Ok(i) => return Ok(Some(PersistentPointer::from_raw_ref(&node.values[i]))),
+                Err(i) => pointer = &node.children[i],
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub struct PBTreeRange<'a, 'tx, 'data, K: Pod + Ord + Copy + 'tx, V: Persistent + 'tx> {
+    tx: &'a mut Transaction<'tx, 'data>,
+    /* the path to the next entry, with the position in each node */
+    stack: Vec<(&'tx Node<K>, usize)>,
+    end: Bound<K>,
+    phantom: PhantomData<V>,
+}
+
+impl<'a, 'tx, 'data, K: Pod + Ord + Copy + 'tx, V: Persistent + 'tx> Iterator
+    for PBTreeRange<'a, 'tx, 'data, K, V>
+{
+    type Item = Result<(K, &'tx V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, i) = loop {
+            let (node, i) = self.stack.last_mut()?;
+            if *i < node.len() {
+                *i += 1;
+                break (*node, *i - 1);
+            }
+            self.stack.pop();
+        };
+
+        let key = node.keys[i];
+        let past = match &self.end {
+            Bound::Included(end) => key > *end,
+            Bound::Excluded(end) => key >= *end,
+            Bound::Unbounded => false,
+        };
+        if past {
+            self.stack.clear();
+            return None;
+        }
+
+        let result = (|| {
+            let mut pointer = &node.children[i + 1];
+            while pointer.is_some() {
+                let child = read_node::<K>(self.tx, pointer)?;
+                self.stack.push((child, 0));
+                pointer = &child.children[0];
+            }
+            let value = PersistentPointer::<V>::from_raw_ref(&node.values[i]);
+            Ok((key, self.tx.read_typed(value)?.get()))
+        })();
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    struct Item {
+        value: u64,
+    }
+
+    impl Persistent for Item {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Item>())
+        }
+    }
+
+    /* every key below 200, in an order that splits nodes all over the tree */
+    fn shuffled() -> Vec<u64> {
+        (0..200).map(|i| (i * 73) % 200).collect()
+    }
+
+    fn entries<'tx, R: RangeBounds<u64>>(
+        map: &'tx PersistentPointer<PBTreeMap<u64, Item>>,
+        tx: &mut Transaction<'tx, '_>,
+        range: R,
+    ) -> Result<Vec<(u64, u64)>> {
+        map.range(tx, range)?
+            .map(|entry| entry.map(|(key, item)| (key, item.value)))
+            .collect()
+    }
+
+    #[test]
+    fn insert_remove() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PBTreeMap::<u64, Item>::new)
+            .source(MemorySource::new(1 << 22)?)
+            .open()?;
+
+        for chunk in shuffled().chunks(50) {
+            librarius.run(|tx| {
+                let map = tx.root_typed::<PBTreeMap<u64, Item>>();
+                for &key in chunk {
+                    assert!(!map.insert(tx, key, Item { value: key * 10 })?);
+                }
+                Ok(())
+            })?;
+        }
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PBTreeMap<u64, Item>>();
+            assert_eq!(map.get(tx, &42)?.map(|item| item.value), Some(420));
+            assert!(map.get(tx, &200)?.is_none());
+            assert_eq!(tx.read_typed(map)?.len(), 200);
+            map.get_mut(tx, &7)?.unwrap().value = 71;
+            assert!(map.insert(tx, 9, Item { value: 91 })?);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PBTreeMap<u64, Item>>();
+            let all = entries(map, tx, ..)?;
+            let mut expected: Vec<_> = (0..200).map(|key| (key, key * 10)).collect();
+            expected[7].1 = 71;
+            expected[9].1 = 91;
+            assert_eq!(all, expected);
+            assert_eq!(entries(map, tx, 100..103)?, expected[100..103]);
+            assert_eq!(entries(map, tx, 197..=250)?, expected[197..]);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PBTreeMap<u64, Item>>();
+            for key in shuffled().into_iter().filter(|key| key % 2 == 0) {
+                let removed = map.remove(tx, &key)?.map(|item| item.value / 10);
+                assert_eq!(removed, Some(key));
+            }
+            assert!(map.remove(tx, &0)?.is_none());
+            assert_eq!(tx.read_typed(map)?.len(), 100);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PBTreeMap<u64, Item>>();
+            let keys: Vec<_> = entries(map, tx, ..)?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            assert_eq!(
+                keys,
+                (0..200).filter(|key| key % 2 == 1).collect::<Vec<_>>()
+            );
+            assert!(map.get(tx, &42)?.is_none());
+            assert_eq!(map.get(tx, &43)?.map(|item| item.value), Some(430));
+
+            for key in (0..200).filter(|key| key % 2 == 1) {
+                assert!(map.remove(tx, &key)?.is_some());
+            }
+            assert!(tx.read_typed(map)?.is_empty());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
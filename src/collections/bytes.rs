@@ -0,0 +1,280 @@
+use crate::error::Error;
+use crate::tx::Transaction;
+use crate::typed::{Persistent, PersistentPointer, TypedTransaction};
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::cmp::max;
+use std::mem::size_of;
+
+/*
+ * A growable buffer of bytes. The bytes are an object of their own, which
+ * is reallocated at twice the size whenever it runs out of room, and the
+ * buffer keeps track of how many of them are in use.
+ */
+#[repr(C)]
+pub struct PBytes {
+    data: UntypedPointer,
+    len: usize,
+    capacity: usize,
+}
+
+impl Persistent for PBytes {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 2 * size_of::<usize>())
+    }
+}
+
+impl Default for PBytes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PBytes {
+    const MIN_CAPACITY: usize = 16;
+
+    pub fn new() -> Self {
+        PBytes {
+            data: UntypedPointer::new_none(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn data_size(capacity: usize) -> ObjectSize {
+        ObjectSize::new_with_usize(0, capacity)
+    }
+}
+
+impl PersistentPointer<PBytes> {
+    pub fn as_bytes<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx [u8]> {
+        let bytes = tx.read_typed(self)?.get();
+        if bytes.data.is_none() {
+            return Ok(&[]);
+        }
+        let data = tx.read(&bytes.data, &PBytes::data_size(bytes.capacity))?;
+        Ok(&data[..bytes.len])
+    }
+
+    /* replaces the contents, the buffer only ever grows */
+    pub fn set<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, src: &[u8]) -> Result<()> {
+        self.reserve(tx, 0, src.len())?.copy_from_slice(src);
+        Ok(())
+    }
+
+    pub fn extend_from_slice<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        src: &[u8],
+    ) -> Result<()> {
+        let len = tx.read_typed(self)?.get().len;
+        self.reserve(tx, len, src.len())?.copy_from_slice(src);
+        Ok(())
+    }
+
+    pub fn clear<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<()> {
+        tx.write_typed(self)?.len = 0;
+        Ok(())
+    }
+
+    /* makes the length at + additional, and returns the bytes past at to fill in */
+    fn reserve<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        at: usize,
+        additional: usize,
+    ) -> Result<&'tx mut [u8]> {
+        let bytes = tx.write_typed(self)?;
+        let len = at + additional;
+        bytes.len = len;
+        if additional == 0 {
+            return Ok(&mut []);
+        }
+
+        let grown = len > bytes.capacity;
+        if grown {
+            bytes.capacity = max(PBytes::MIN_CAPACITY, max(len, 2 * bytes.capacity));
+        }
+        let mut fresh = None;
+        if bytes.data.is_none() {
+            let (data, slice) = tx.alloc(PBytes::data_size(bytes.capacity))?;
+            bytes.data = data;
+            fresh = Some(slice);
+        }
+
+        let bytes: &'tx PBytes = bytes;
+        let size = PBytes::data_size(bytes.capacity);
+        let data = match fresh {
+            Some(data) => data,
+            None if grown => tx.realloc(&bytes.data, size)?,
+            None => tx.write(&bytes.data, &size)?,
+        };
+
+        Ok(&mut data[at..len])
+    }
+}
+
+/* a PBytes that only ever holds UTF-8 */
+#[repr(C)]
+pub struct PString {
+    bytes: PBytes,
+}
+
+impl Persistent for PString {
+    fn size() -> ObjectSize {
+        PBytes::size()
+    }
+}
+
+impl Default for PString {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PString {
+    pub fn new() -> Self {
+        PString {
+            bytes: PBytes::new(),
+        }
+    }
+
+    /* in bytes */
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl PersistentPointer<PString> {
+    pub fn as_str<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx str> {
+        let bytes = self.bytes().as_bytes(tx)?;
+        std::str::from_utf8(bytes).map_err(|_| Error::InvalidUtf8 {})
+    }
+
+    pub fn set<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, src: &str) -> Result<()> {
+        self.bytes().set(tx, src.as_bytes())
+    }
+
+    pub fn push_str<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, src: &str) -> Result<()> {
+        self.bytes().extend_from_slice(tx, src.as_bytes())
+    }
+
+    pub fn clear<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<()> {
+        self.bytes().clear(tx)
+    }
+
+    fn bytes(&self) -> &PersistentPointer<PBytes> {
+        PersistentPointer::from_raw_ref(self.as_raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    #[repr(C)]
+    struct Names {
+        first: PersistentPointer<PString>,
+        raw: PersistentPointer<PBytes>,
+    }
+
+    impl Persistent for Names {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(2 * size_of::<UntypedPointer>(), 0)
+        }
+    }
+
+    #[test]
+    fn strings() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PString::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let string = tx.root_typed::<PString>();
+            assert_eq!(string.as_str(tx)?, "");
+            string.set(tx, "hello")?;
+            string.push_str(tx, ", world")?;
+            assert_eq!(string.as_str(tx)?, "hello, world");
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let string = tx.root_typed::<PString>();
+            assert_eq!(string.as_str(tx)?, "hello, world");
+            let long = "ż".repeat(100);
+            string.push_str(tx, &long)?;
+            assert_eq!(tx.read_typed(string)?.len(), 12 + long.len());
+
+            string.set(tx, "short")?;
+            assert_eq!(string.as_str(tx)?, "short");
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let string = tx.root_typed::<PString>();
+            assert_eq!(string.as_str(tx)?, "short");
+            string.clear(tx)?;
+            assert!(tx.read_typed(string)?.is_empty());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn fields() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Names {
+                first: PersistentPointer::new_none(),
+                raw: PersistentPointer::new_none(),
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Names>();
+            let names = tx.write_typed(root)?;
+            names.first = tx.alloc_typed(PString::new)?;
+            names.raw = tx.alloc_typed(PBytes::new)?;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Names>();
+            let names = tx.read_typed(root)?.get();
+            names.first.set(tx, "librarius")?;
+            names.raw.extend_from_slice(tx, &[1, 2, 3])?;
+            names.raw.extend_from_slice(tx, &[0; 20])?;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Names>();
+            let names = tx.read_typed(root)?.get();
+            assert_eq!(names.first.as_str(tx)?, "librarius");
+            let raw = names.raw.as_bytes(tx)?;
+            assert_eq!(raw.len(), 23);
+            assert_eq!(&raw[..4], &[1, 2, 3, 0]);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use super::{alloc_value, take, unbound};
+use crate::tx::Transaction;
+use crate::typed::{Persistent, PersistentPointer, Pod, TypedTransaction};
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/*
+ * An unordered map, with a chain of links in each bucket. Like in a
+ * PBTreeMap, keys are stored in the links and values are objects of their
+ * own. The buckets are rehashed into an array twice the size once the map
+ * is three quarters full, in the transaction that filled it.
+ */
+#[repr(C)]
+pub struct PHashMap<K: Pod + Eq + Hash + Copy, V: Persistent> {
+    buckets: UntypedPointer,
+    len: usize,
+    capacity: usize,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K: Pod + Eq + Hash + Copy, V: Persistent> Persistent for PHashMap<K, V> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 2 * size_of::<usize>())
+    }
+}
+
+impl<K: Pod + Eq + Hash + Copy, V: Persistent> Default for PHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Pod + Eq + Hash + Copy, V: Persistent> PHashMap<K, V> {
+    const MIN_CAPACITY: usize = 8;
+
+    pub fn new() -> Self {
+        PHashMap {
+            buckets: UntypedPointer::new_none(),
+            len: 0,
+            capacity: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn buckets_size(capacity: usize) -> ObjectSize {
+        ObjectSize::new_with_usize(capacity * size_of::<UntypedPointer>(), 0)
+    }
+
+    /* the hash has to be the same in every process that opens the store */
+    fn bucket(key: &K, capacity: usize) -> usize {
+        let mut hasher = crc32fast::Hasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % capacity
+    }
+}
+
+#[repr(C)]
+struct Link<K: Pod + Copy> {
+    next: UntypedPointer,
+    value: UntypedPointer,
+    key: K,
+}
+
+impl<K: Pod + Copy> Persistent for Link<K> {
+    fn size() -> ObjectSize {
+        let pointers = 2 * size_of::<UntypedPointer>();
+        ObjectSize::new_with_usize(pointers, size_of::<Self>() - pointers)
+    }
+}
+
+fn read_link<'tx, K: Pod + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+) -> Result<&'tx Link<K>> {
+    Ok(unsafe_utils::any_from_slice(
+        tx.read(pointer, &Link::<K>::size())?,
+    ))
+}
+
+fn write_link<'tx, K: Pod + Copy + 'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    pointer: &'tx UntypedPointer,
+) -> Result<&'tx mut Link<K>> {
+    Ok(unsafe_utils::any_from_slice_mut(
+        tx.write(pointer, &Link::<K>::size())?,
+    ))
+}
+
+/*
+ * Moves every link into a new array of buckets. The links are all written
+ * first, the next pointer of each one is what leads to the one after it.
+ */
+fn grow<'tx, K: Pod + Eq + Hash + Copy + 'tx, V: Persistent>(
+    tx: &mut Transaction<'tx, '_>,
+    map: &mut PHashMap<K, V>,
+) -> Result<()> {
+    let capacity = std::cmp::max(PHashMap::<K, V>::MIN_CAPACITY, 2 * map.capacity);
+    let (pointer, data) = tx.alloc(PHashMap::<K, V>::buckets_size(capacity))?;
+    data.fill(0);
+    let buckets = unsafe_utils::many_from_slice_mut::<UntypedPointer>(data);
+
+    if map.buckets.is_some() {
+        let old = unbound(&map.buckets);
+        let size = PHashMap::<K, V>::buckets_size(map.capacity);
+        let slots = unsafe_utils::many_from_slice(tx.read(old, &size)?);
+
+        let mut links = Vec::with_capacity(map.len);
+        for slot in slots {
+            let mut pointer: &'tx UntypedPointer = slot;
+            while pointer.is_some() {
+                let link = write_link::<K>(tx, pointer)?;
+                let next = unbound(&link.next);
+                links.push((pointer.clone(), link));
+                pointer = next;
+            }
+        }
+        for (pointer, link) in links {
+            let i = PHashMap::<K, V>::bucket(&link.key, capacity);
+            link.next = take(&mut buckets[i]);
+            buckets[i] = pointer;
+        }
+
+        tx.free(old)?;
+    }
+
+    map.buckets = pointer;
+    map.capacity = capacity;
+
+    Ok(())
+}
+
+impl<K: Pod + Eq + Hash + Copy, V: Persistent> PersistentPointer<PHashMap<K, V>> {
+    pub fn get<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: &K) -> Result<Option<&'tx V>>
+    where
+        K: 'tx,
+    {
+        match self.find(tx, key)? {
+            Some(value) => Ok(Some(tx.read_typed(value)?.get())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_mut<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        key: &K,
+    ) -> Result<Option<&'tx mut V>>
+    where
+        K: 'tx,
+    {
+        match self.find(tx, key)? {
+            Some(value) => Ok(Some(tx.write_typed(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /* returns whether the key was already there, its old value is overwritten */
+    pub fn insert<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: K, value: V) -> Result<bool>
+    where
+        K: 'tx,
+    {
+        match self.entry(tx, key)? {
+            PHashMapEntry::Occupied(entry) => {
+                let old = entry.get_mut(tx)?;
+                unsafe { std::ptr::write(old, value) };
+                Ok(true)
+            }
+            PHashMapEntry::Vacant(entry) => {
+                entry.insert(tx, value)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /*
+     * Frees the value of the key. It can still be read until the transaction
+     * is over, the space isn't reused before then.
+     */
+    pub fn remove<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, key: &K) -> Result<Option<&'tx V>>
+    where
+        K: 'tx,
+    {
+        if self.find(tx, key)?.is_none() {
+            return Ok(None);
+        }
+
+        let map = tx.write_typed(self)?;
+        map.len -= 1;
+        let map: &'tx PHashMap<K, V> = map;
+
+        let size = PHashMap::<K, V>::buckets_size(map.capacity);
+        let index = PHashMap::<K, V>::bucket(key, map.capacity);
+        let slots = unsafe_utils::many_from_slice::<UntypedPointer>(tx.read(&map.buckets, &size)?);
+
+        /* the link that points to the removed one, if it isn't first */
+        let mut previous = None;
+        let mut pointer = &slots[index];
+        let link = loop {
+            let link = read_link::<K>(tx, pointer)?;
+            if link.key == *key {
+                break link;
+            }
+            previous = Some(pointer);
+            pointer = &link.next;
+        };
+
+        let value_pointer = PersistentPointer::<V>::from_raw_ref(&link.value);
+        let value = tx.read_typed(value_pointer)?.get();
+        tx.free_typed(value_pointer)?;
+        tx.free(pointer)?;
+
+        let next = link.next.clone();
+        match previous {
+            Some(previous) => write_link::<K>(tx, previous)?.next = next,
+            None => {
+                let data = tx.write(&map.buckets, &size)?;
+                unsafe_utils::many_from_slice_mut::<UntypedPointer>(data)[index] = next;
+            }
+        }
+
+        Ok(Some(value))
+    }
+
+    /* the place of the key in the map, for looking it up and inserting it at once */
+    pub fn entry<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        key: K,
+    ) -> Result<PHashMapEntry<'tx, K, V>>
+    where
+        K: 'tx,
+    {
+        Ok(match self.find(tx, &key)? {
+            Some(value) => PHashMapEntry::Occupied(POccupiedEntry {
+                map: self,
+                key,
+                value,
+            }),
+            None => PHashMapEntry::Vacant(PVacantEntry { map: self, key }),
+        })
+    }
+
+    fn find<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        key: &K,
+    ) -> Result<Option<&'tx PersistentPointer<V>>>
+    where
+        K: 'tx,
+    {
+        let map = tx.read_typed(self)?.get();
+        if map.buckets.is_none() {
+            return Ok(None);
+        }
+        let size = PHashMap::<K, V>::buckets_size(map.capacity);
+        let slots = unsafe_utils::many_from_slice::<UntypedPointer>(tx.read(&map.buckets, &size)?);
+
+        let mut pointer = &slots[PHashMap::<K, V>::bucket(key, map.capacity)];
+        while pointer.is_some() {
+            let link = read_link::<K>(tx, pointer)?;
+            if link.key == *key {
+                return Ok(Some(PersistentPointer::from_raw_ref(&link.value)));
+            }
+            pointer = &link.next;
+        }
+        Ok(None)
+    }
+}
+
+pub enum PHashMapEntry<'tx, K: Pod + Eq + Hash + Copy, V: Persistent> {
+    Occupied(POccupiedEntry<'tx, K, V>),
+    Vacant(PVacantEntry<'tx, K, V>),
+}
+
+impl<'tx, K: Pod + Eq + Hash + Copy + 'tx, V: Persistent> PHashMapEntry<'tx, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            PHashMapEntry::Occupied(entry) => entry.key(),
+            PHashMapEntry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, tx: &mut Transaction<'tx, '_>, value: V) -> Result<&'tx mut V> {
+        self.or_insert_with(tx, || value)
+    }
+
+    pub fn or_insert_with<F>(self, tx: &mut Transaction<'tx, '_>, f: F) -> Result<&'tx mut V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            PHashMapEntry::Occupied(entry) => entry.get_mut(tx),
+            PHashMapEntry::Vacant(entry) => entry.insert(tx, f()),
+        }
+    }
+}
+
+pub struct POccupiedEntry<'tx, K: Pod + Eq + Hash + Copy, V: Persistent> {
+    map: &'tx PersistentPointer<PHashMap<K, V>>,
+    key: K,
+    value: &'tx PersistentPointer<V>,
+}
+
+impl<'tx, K: Pod + Eq + Hash + Copy + 'tx, V: Persistent> POccupiedEntry<'tx, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /* the value, for use with TypedTransaction */
+    pub fn pointer(&self) -> &'tx PersistentPointer<V> {
+        self.value
+    }
+
+    pub fn get(&self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx V> {
+        Ok(tx.read_typed(self.value)?.get())
+    }
+
+    pub fn get_mut(&self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx mut V> {
+        tx.write_typed(self.value)
+    }
+
+    pub fn remove(self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx V> {
+        let value = self.map.remove(tx, &self.key)?;
+        Ok(value.expect("occupied entry has a value"))
+    }
+}
+
+pub struct PVacantEntry<'tx, K: Pod + Eq + Hash + Copy, V: Persistent> {
+    map: &'tx PersistentPointer<PHashMap<K, V>>,
+    key: K,
+}
+
+impl<'tx, K: Pod + Eq + Hash + Copy + 'tx, V: Persistent> PVacantEntry<'tx, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn insert(self, tx: &mut Transaction<'tx, '_>, value: V) -> Result<&'tx mut V> {
+        let map = tx.write_typed(self.map)?;
+        map.len += 1;
+        if map.len * 4 > map.capacity * 3 {
+            grow(tx, map)?;
+        }
+        let map: &'tx PHashMap<K, V> = map;
+
+        let size = PHashMap::<K, V>::buckets_size(map.capacity);
+        let index = PHashMap::<K, V>::bucket(&self.key, map.capacity);
+        let data = tx.write(&map.buckets, &size)?;
+        let buckets = unsafe_utils::many_from_slice_mut::<UntypedPointer>(data);
+
+        let value = alloc_value(tx, value)?;
+        let (pointer, data) = tx.alloc(Link::<K>::size())?;
+        let link: &'tx mut Link<K> = unsafe_utils::any_from_slice_mut(data);
+        let next = take(&mut buckets[index]);
+        unsafe {
+            std::ptr::write(
+                link,
+                Link {
+                    next,
+                    value,
+                    key: self.key,
+                },
+            )
+        };
+        buckets[index] = pointer;
+
+        tx.write_typed(PersistentPointer::from_raw_ref(&link.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    struct Item {
+        value: u64,
+    }
+
+    impl Persistent for Item {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Item>())
+        }
+    }
+
+    #[test]
+    fn insert_remove() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PHashMap::<u64, Item>::new)
+            .source(MemorySource::new(1 << 22)?)
+            .open()?;
+
+        /* the first transaction grows the buckets a few times over */
+        for keys in &[0..100, 100..300] {
+            librarius.run(|tx| {
+                let map = tx.root_typed::<PHashMap<u64, Item>>();
+                for key in keys.clone() {
+                    assert!(!map.insert(tx, key, Item { value: key * 10 })?);
+                }
+                Ok(())
+            })?;
+        }
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PHashMap<u64, Item>>();
+            assert_eq!(tx.read_typed(map)?.len(), 300);
+            for key in 0..300 {
+                assert_eq!(map.get(tx, &key)?.map(|item| item.value), Some(key * 10));
+            }
+            assert!(map.get(tx, &300)?.is_none());
+
+            map.get_mut(tx, &7)?.unwrap().value = 71;
+            assert!(map.insert(tx, 9, Item { value: 91 })?);
+            map.entry(tx, 11)?.or_insert(tx, Item { value: 0 })?.value += 1;
+            map.entry(tx, 300)?
+                .or_insert_with(tx, || Item { value: 3000 })?;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PHashMap<u64, Item>>();
+            assert_eq!(tx.read_typed(map)?.len(), 301);
+            assert_eq!(map.get(tx, &7)?.map(|item| item.value), Some(71));
+            assert_eq!(map.get(tx, &9)?.map(|item| item.value), Some(91));
+            assert_eq!(map.get(tx, &11)?.map(|item| item.value), Some(111));
+            assert_eq!(map.get(tx, &300)?.map(|item| item.value), Some(3000));
+
+            for key in (0..300).filter(|key| key % 2 == 0) {
+                let removed = map.remove(tx, &key)?.map(|item| item.value / 10);
+                assert_eq!(removed, Some(key));
+            }
+            assert!(map.remove(tx, &0)?.is_none());
+            match map.entry(tx, 1)? {
+                PHashMapEntry::Occupied(entry) => assert_eq!(entry.remove(tx)?.value, 10),
+                PHashMapEntry::Vacant(_) => panic!("key 1 is in the map"),
+            }
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PHashMap<u64, Item>>();
+            assert_eq!(tx.read_typed(map)?.len(), 150);
+            for key in 0..300 {
+                let present = key % 2 == 1 && key != 1;
+                assert_eq!(map.get(tx, &key)?.is_some(), present);
+            }
+            assert!(map.get(tx, &300)?.is_some());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
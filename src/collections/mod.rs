@@ -0,0 +1,37 @@
+use crate::tx::Transaction;
+use crate::typed::Persistent;
+use crate::vos::UntypedPointer;
+use crate::Result;
+
+pub mod arc;
+pub mod btree;
+pub mod bytes;
+pub mod hashmap;
+pub mod pvec;
+pub mod queue;
+
+pub use arc::PArc;
+pub use btree::{PBTreeMap, PBTreeRange};
+pub use bytes::{PBytes, PString};
+pub use hashmap::{PHashMap, PHashMapEntry, POccupiedEntry, PVacantEntry};
+pub use pvec::{PVec, PVecIter};
+pub use queue::PQueue;
+
+/* moves the value into a new object of its own */
+fn alloc_value<T: Persistent>(tx: &mut Transaction, value: T) -> Result<UntypedPointer> {
+    let (pointer, bytes) = tx.alloc(T::size())?;
+    unsafe { std::ptr::write(bytes.as_mut_ptr() as *mut T, value) };
+    Ok(pointer)
+}
+
+fn take(pointer: &mut UntypedPointer) -> UntypedPointer {
+    std::mem::replace(pointer, UntypedPointer::new_none())
+}
+
+/*
+ * A pointer in an object that is being changed, so that the object it
+ * points to can be written while the other one is still borrowed.
+ */
+fn unbound<'tx>(pointer: &UntypedPointer) -> &'tx UntypedPointer {
+    unsafe { &*(pointer as *const UntypedPointer) }
+}
@@ -0,0 +1,274 @@
+use super::alloc_value;
+use crate::tx::{ReadTransaction, Transaction};
+use crate::typed::{Persistent, PersistentPointer, TypedReadTransaction, TypedTransaction};
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+/*
+ * A growable vector. The elements are objects of their own, so that they
+ * can hold pointers too, and the vector points to an array with a pointer
+ * to each of them. Writing an element doesn't copy the array, and the
+ * array is reallocated at twice the size whenever it runs out of room.
+ */
+#[repr(C)]
+pub struct PVec<T: Persistent> {
+    slots: UntypedPointer,
+    len: usize,
+    capacity: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Persistent> Persistent for PVec<T> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 2 * size_of::<usize>())
+    }
+}
+
+impl<T: Persistent> Default for PVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Persistent> PVec<T> {
+    const MIN_CAPACITY: usize = 4;
+
+    pub fn new() -> Self {
+        PVec {
+            slots: UntypedPointer::new_none(),
+            len: 0,
+            capacity: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn slots_size(capacity: usize) -> ObjectSize {
+        ObjectSize::new_with_usize(capacity * size_of::<UntypedPointer>(), 0)
+    }
+}
+
+impl<T: Persistent> PersistentPointer<PVec<T>> {
+    /* the value becomes a new object, owned by the vector */
+    pub fn push<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, value: T) -> Result<()> {
+        let vec = tx.write_typed(self)?;
+        let index = vec.len;
+        vec.len += 1;
+
+        let mut fresh = None;
+        let grown = index == vec.capacity;
+        if vec.slots.is_none() {
+            vec.capacity = PVec::<T>::MIN_CAPACITY;
+            let (slots, data) = tx.alloc(PVec::<T>::slots_size(vec.capacity))?;
+            data.fill(0);
+            vec.slots = slots;
+            fresh = Some(data);
+        } else if grown {
+            vec.capacity *= 2;
+        }
+
+        let vec: &'tx PVec<T> = vec;
+        let size = PVec::<T>::slots_size(vec.capacity);
+        let data = match fresh {
+            Some(data) => data,
+            None if grown => tx.realloc(&vec.slots, size)?,
+            None => tx.write(&vec.slots, &size)?,
+        };
+
+        let element = alloc_value(tx, value)?;
+        unsafe_utils::many_from_slice_mut::<UntypedPointer>(data)[index] = element;
+
+        Ok(())
+    }
+
+    /*
+     * Frees the last element. It can still be read until the transaction
+     * is over, the space isn't reused before then.
+     */
+    pub fn pop<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<Option<&'tx T>> {
+        if tx.read_typed(self)?.get().is_empty() {
+            return Ok(None);
+        }
+        let vec = tx.write_typed(self)?;
+        vec.len -= 1;
+        let vec: &'tx PVec<T> = vec;
+
+        let data = tx.write(&vec.slots, &PVec::<T>::slots_size(vec.capacity))?;
+        let slot = &unsafe_utils::many_from_slice::<UntypedPointer>(data)[vec.len];
+        let slot = PersistentPointer::<T>::from_raw_ref(slot);
+
+        let value = tx.read_typed(slot)?.get();
+        tx.free_typed(slot)?;
+        slot.as_raw()
+            .compare_and_swap(slot.as_raw().clone(), UntypedPointer::new_none());
+
+        Ok(Some(value))
+    }
+
+    pub fn get<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        index: usize,
+    ) -> Result<Option<&'tx T>> {
+        match self.slot(tx, index)? {
+            Some(slot) => Ok(Some(tx.read_typed(slot)?.get())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_mut<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        index: usize,
+    ) -> Result<Option<&'tx mut T>> {
+        match self.slot(tx, index)? {
+            Some(slot) => Ok(Some(tx.write_typed(slot)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn iter<'a, 'tx, 'data>(
+        &'tx self,
+        tx: &'a mut Transaction<'tx, 'data>,
+    ) -> Result<PVecIter<'a, 'tx, 'data, T>> {
+        let slots = self.slots(tx)?;
+        Ok(PVecIter {
+            tx,
+            slots: slots.iter(),
+            phantom: PhantomData,
+        })
+    }
+
+    fn slots<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx [UntypedPointer]> {
+        let vec = tx.read_typed(self)?.get();
+        if vec.slots.is_none() {
+            return Ok(&[]);
+        }
+        let data = tx.read(&vec.slots, &PVec::<T>::slots_size(vec.capacity))?;
+        Ok(&unsafe_utils::many_from_slice(data)[..vec.len])
+    }
+
+    fn slot<'tx>(
+        &'tx self,
+        tx: &mut Transaction<'tx, '_>,
+        index: usize,
+    ) -> Result<Option<&'tx PersistentPointer<T>>> {
+        Ok(self
+            .slots(tx)?
+            .get(index)
+            .map(PersistentPointer::from_raw_ref))
+    }
+}
+
+impl<T: Persistent> PersistentPointer<PVec<T>> {
+    /* every element, as of the snapshot of a read-only transaction */
+    pub(crate) fn read_all<'tx>(&'tx self, tx: &ReadTransaction<'tx, '_>) -> Result<Vec<&'tx T>> {
+        let vec = tx.read_typed(self)?;
+        if vec.slots.is_none() {
+            return Ok(Vec::new());
+        }
+        let data = tx.read(&vec.slots, &PVec::<T>::slots_size(vec.capacity))?;
+        unsafe_utils::many_from_slice::<UntypedPointer>(data)[..vec.len]
+            .iter()
+            .map(|slot| tx.read_typed(PersistentPointer::from_raw_ref(slot)))
+            .collect()
+    }
+}
+
+pub struct PVecIter<'a, 'tx, 'data, T: Persistent + 'tx> {
+    tx: &'a mut Transaction<'tx, 'data>,
+    slots: std::slice::Iter<'tx, UntypedPointer>,
+    phantom: PhantomData<T>,
+}
+
+impl<'a, 'tx, 'data, T: Persistent + 'tx> Iterator for PVecIter<'a, 'tx, 'data, T> {
+    type Item = Result<&'tx T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = PersistentPointer::<T>::from_raw_ref(self.slots.next()?);
+        Some(self.tx.read_typed(slot).map(|value| value.get()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    struct Item {
+        value: usize,
+    }
+
+    impl Persistent for Item {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Item>())
+        }
+    }
+
+    #[test]
+    fn push_pop() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PVec::<Item>::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let vec = tx.root_typed::<PVec<Item>>();
+            for value in 0..5 {
+                vec.push(tx, Item { value })?;
+            }
+            assert_eq!(vec.get(tx, 4)?.map(|item| item.value), Some(4));
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let vec = tx.root_typed::<PVec<Item>>();
+            for value in 5..20 {
+                vec.push(tx, Item { value })?;
+            }
+            vec.get_mut(tx, 3)?.unwrap().value = 100;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let vec = tx.root_typed::<PVec<Item>>();
+            assert_eq!(tx.read_typed(vec)?.len(), 20);
+            assert!(vec.get(tx, 20)?.is_none());
+            let values = vec
+                .iter(tx)?
+                .map(|item| Ok(item?.value))
+                .collect::<Result<Vec<_>>>()?;
+            let mut expected: Vec<_> = (0..20).collect();
+            expected[3] = 100;
+            assert_eq!(values, expected);
+
+            assert_eq!(vec.pop(tx)?.map(|item| item.value), Some(19));
+            assert_eq!(vec.pop(tx)?.map(|item| item.value), Some(18));
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let vec = tx.root_typed::<PVec<Item>>();
+            assert_eq!(tx.read_typed(vec)?.len(), 18);
+            while vec.pop(tx)?.is_some() {}
+            assert!(tx.read_typed(vec)?.is_empty());
+            vec.push(tx, Item { value: 7 })?;
+            assert_eq!(vec.pop(tx)?.map(|item| item.value), Some(7));
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
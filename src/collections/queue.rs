@@ -0,0 +1,303 @@
+use super::{alloc_value, take, unbound};
+use crate::tx::Transaction;
+use crate::typed::{Persistent, PersistentPointer, TypedTransaction};
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use crate::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+const LANES: usize = 8;
+
+/*
+ * A queue split into lanes, each of them a ring of pointers to elements.
+ * Every thread pushes to a lane of its own and starts popping from it, so
+ * producers on different threads rarely touch the same objects. Elements
+ * come out in the order they were pushed to their lane, there's no order
+ * between the lanes.
+ */
+#[repr(C)]
+pub struct PQueue<T: Persistent> {
+    lanes: [UntypedPointer; LANES],
+    phantom: PhantomData<T>,
+}
+
+impl<T: Persistent> Persistent for PQueue<T> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(LANES * size_of::<UntypedPointer>(), 0)
+    }
+}
+
+impl<T: Persistent> Default for PQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Persistent> PQueue<T> {
+    pub fn new() -> Self {
+        PQueue {
+            lanes: [(); LANES].map(|_| UntypedPointer::new_none()),
+            phantom: PhantomData,
+        }
+    }
+}
+
+#[repr(C)]
+struct Lane {
+    slots: UntypedPointer,
+    head: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl Persistent for Lane {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<UntypedPointer>(), 3 * size_of::<usize>())
+    }
+}
+
+impl Lane {
+    const MIN_CAPACITY: usize = 4;
+
+    fn slots_size(capacity: usize) -> ObjectSize {
+        ObjectSize::new_with_usize(capacity * size_of::<UntypedPointer>(), 0)
+    }
+}
+
+/* the same for as long as the thread runs */
+fn thread_lane() -> usize {
+    let mut hasher = DefaultHasher::new();
+    std::thread::current().id().hash(&mut hasher);
+    hasher.finish() as usize % LANES
+}
+
+impl<T: Persistent> PersistentPointer<PQueue<T>> {
+    /* the value becomes a new object, owned by the queue */
+    pub fn push_back<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>, value: T) -> Result<()> {
+        let queue = self.lanes(tx)?;
+        let pointer = &queue.lanes[thread_lane()];
+        let lane = tx.write_typed(PersistentPointer::<Lane>::from_raw_ref(pointer))?;
+
+        let mut fresh = None;
+        let grown = lane.len == lane.capacity;
+        let capacity = lane.capacity;
+        if lane.slots.is_none() {
+            lane.capacity = Lane::MIN_CAPACITY;
+            let (slots, data) = tx.alloc(Lane::slots_size(lane.capacity))?;
+            data.fill(0);
+            lane.slots = slots;
+            fresh = Some(data);
+        } else if grown {
+            lane.capacity *= 2;
+        }
+
+        let wrapped = grown && fresh.is_none();
+        let size = Lane::slots_size(lane.capacity);
+        let data = match fresh {
+            Some(data) => data,
+            None if grown => tx.realloc(unbound(&lane.slots), size)?,
+            None => tx.write(unbound(&lane.slots), &size)?,
+        };
+        let slots = unsafe_utils::many_from_slice_mut::<UntypedPointer>(data);
+
+        /* the elements that wrapped around go after the others */
+        if wrapped {
+            for i in 0..lane.head {
+                slots[capacity + i] = take(&mut slots[i]);
+            }
+        }
+
+        let index = (lane.head + lane.len) % lane.capacity;
+        slots[index] = alloc_value(tx, value)?;
+        lane.len += 1;
+
+        Ok(())
+    }
+
+    /*
+     * Frees the element at the front. It can still be read until the
+     * transaction is over, the space isn't reused before then.
+     */
+    pub fn pop_front<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<Option<&'tx T>> {
+        let queue = tx.read_typed(self)?.get();
+        let first = thread_lane();
+        for i in 0..LANES {
+            let pointer = &queue.lanes[(first + i) % LANES];
+            if pointer.is_none() {
+                return Ok(None);
+            }
+            let lane = PersistentPointer::<Lane>::from_raw_ref(pointer);
+            if tx.read_typed(lane)?.get().len == 0 {
+                continue;
+            }
+
+            let lane = tx.write_typed(lane)?;
+            let size = Lane::slots_size(lane.capacity);
+            let data = tx.write(unbound(&lane.slots), &size)?;
+            let slots = unsafe_utils::many_from_slice_mut::<UntypedPointer>(data);
+
+            let slot = PersistentPointer::<T>::from_raw_ref(unbound(&slots[lane.head]));
+            let value = tx.read_typed(slot)?.get();
+            tx.free_typed(slot)?;
+            take(&mut slots[lane.head]);
+
+            lane.head = (lane.head + 1) % lane.capacity;
+            lane.len -= 1;
+
+            return Ok(Some(value));
+        }
+        Ok(None)
+    }
+
+    pub fn len<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<usize> {
+        let queue = tx.read_typed(self)?.get();
+        let mut len = 0;
+        for pointer in queue.lanes.iter().take_while(|lane| lane.is_some()) {
+            len += tx
+                .read_typed(PersistentPointer::<Lane>::from_raw_ref(pointer))?
+                .get()
+                .len;
+        }
+        Ok(len)
+    }
+
+    pub fn is_empty<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<bool> {
+        Ok(self.len(tx)? == 0)
+    }
+
+    /*
+     * The lanes are all made by the first push, so that the queue itself is
+     * never written again after that.
+     */
+    fn lanes<'tx>(&'tx self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx PQueue<T>> {
+        let queue = tx.read_typed(self)?.get();
+        if queue.lanes[0].is_some() {
+            return Ok(queue);
+        }
+
+        let queue = tx.write_typed(self)?;
+        for lane in queue.lanes.iter_mut() {
+            let pointer = tx.alloc_typed(|| Lane {
+                slots: UntypedPointer::new_none(),
+                head: 0,
+                len: 0,
+                capacity: 0,
+            })?;
+            *lane = pointer.as_raw().clone();
+        }
+        Ok(queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::TypedLibrariusBuilder;
+    use crate::LibrariusBuilder;
+
+    struct Item {
+        value: usize,
+    }
+
+    impl Persistent for Item {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Item>())
+        }
+    }
+
+    #[test]
+    fn fifo() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PQueue::<Item>::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let queue = tx.root_typed::<PQueue<Item>>();
+            assert!(queue.pop_front(tx)?.is_none());
+            for value in 0..3 {
+                queue.push_back(tx, Item { value })?;
+            }
+            assert_eq!(queue.pop_front(tx)?.map(|item| item.value), Some(0));
+            Ok(())
+        })?;
+
+        /* wraps around the ring before it grows */
+        librarius.run(|tx| {
+            let queue = tx.root_typed::<PQueue<Item>>();
+            for value in 3..10 {
+                queue.push_back(tx, Item { value })?;
+            }
+            assert_eq!(queue.len(tx)?, 9);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let queue = tx.root_typed::<PQueue<Item>>();
+            for value in 1..10 {
+                assert_eq!(queue.pop_front(tx)?.map(|item| item.value), Some(value));
+            }
+            assert!(queue.pop_front(tx)?.is_none());
+            assert!(queue.is_empty(tx)?);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn producers() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PQueue::<Item>::new)
+            .source(MemorySource::new(1 << 22)?)
+            .open()?;
+        let (nthreads, pushes) = (4, 50);
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..nthreads)
+                .map(|thread| {
+                    let librarius = &librarius;
+                    scope.spawn(move || {
+                        (0..pushes).try_for_each(|i| {
+                            librarius.run(|tx| {
+                                let queue = tx.root_typed::<PQueue<Item>>();
+                                queue.push_back(
+                                    tx,
+                                    Item {
+                                        value: thread * pushes + i,
+                                    },
+                                )
+                            })
+                        })
+                    })
+                })
+                .collect();
+            threads.into_iter().try_for_each(|th| th.join().unwrap())
+        })?;
+
+        let values = librarius.run(|tx| {
+            let queue = tx.root_typed::<PQueue<Item>>();
+            let mut values = Vec::new();
+            while let Some(item) = queue.pop_front(tx)? {
+                values.push(item.value);
+            }
+            Ok(values)
+        })?;
+
+        /* each producer's elements come out in order */
+        assert_eq!(values.len(), nthreads * pushes);
+        for thread in 0..nthreads {
+            let own: Vec<_> = values
+                .iter()
+                .filter(|value| **value / pushes == thread)
+                .collect();
+            assert!(own.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use crate::collections::{PBTreeMap, PVec};
+use crate::error::{Error, Result};
+use crate::tx::ReadTransaction;
+use crate::typed::{Persistent, PersistentPointer, Pod};
+use arrow_array::{
+    ArrayRef, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array, Int8Array,
+    RecordBatch, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use std::io::Write;
+use std::sync::Arc;
+
+/*
+ * Plain data that makes up the columns of a record batch. A number is a
+ * column of its own, named after whatever it's in, a struct puts together
+ * the columns of its fields, e.g., as "name.field".
+ */
+pub trait ArrowRow: Persistent + Pod {
+    fn fields(name: &str) -> Vec<Field>;
+    fn columns(rows: &[&Self]) -> Vec<ArrayRef>;
+}
+
+macro_rules! impl_arrow_row {
+    ($($t:ty => $array:ty, $data_type:expr;)*) => {
+        $(
+            impl ArrowRow for $t {
+                fn fields(name: &str) -> Vec<Field> {
+                    vec![Field::new(name, $data_type, false)]
+                }
+
+                fn columns(rows: &[&Self]) -> Vec<ArrayRef> {
+                    vec![Arc::new(<$array>::from_iter_values(rows.iter().map(|row| **row)))]
+                }
+            }
+        )*
+    };
+}
+
+impl_arrow_row! {
+    u8 => UInt8Array, DataType::UInt8;
+    u16 => UInt16Array, DataType::UInt16;
+    u32 => UInt32Array, DataType::UInt32;
+    u64 => UInt64Array, DataType::UInt64;
+    i8 => Int8Array, DataType::Int8;
+    i16 => Int16Array, DataType::Int16;
+    i32 => Int32Array, DataType::Int32;
+    i64 => Int64Array, DataType::Int64;
+    f32 => Float32Array, DataType::Float32;
+    f64 => Float64Array, DataType::Float64;
+}
+
+fn record_batch(fields: Vec<Field>, columns: Vec<ArrayRef>) -> Result<RecordBatch> {
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(|err| Error::Arrow { err })
+}
+
+/*
+ * Exports read the whole collection at the version of the transaction,
+ * e.g., of a Snapshot, without going through the collection a row at a
+ * time as a read-write transaction would.
+ */
+impl<T: ArrowRow> PersistentPointer<PVec<T>> {
+    /* the elements in order, in the columns of "value" */
+    pub fn to_record_batch<'tx>(&'tx self, tx: &ReadTransaction<'tx, '_>) -> Result<RecordBatch> {
+        let rows = self.read_all(tx)?;
+        record_batch(T::fields("value"), T::columns(&rows))
+    }
+}
+
+impl<K: ArrowRow + Ord + Copy, V: ArrowRow> PersistentPointer<PBTreeMap<K, V>> {
+    /* the entries by key, in the columns of "key" followed by those of "value" */
+    pub fn to_record_batch<'tx>(&'tx self, tx: &ReadTransaction<'tx, '_>) -> Result<RecordBatch>
+    where
+        K: 'tx,
+    {
+        let entries = self.read_all(tx)?;
+        let keys: Vec<&K> = entries.iter().map(|(key, _)| key).collect();
+        let values: Vec<&V> = entries.iter().map(|(_, value)| *value).collect();
+
+        let mut fields = K::fields("key");
+        fields.extend(V::fields("value"));
+        let mut columns = K::columns(&keys);
+        columns.extend(V::columns(&values));
+        record_batch(fields, columns)
+    }
+}
+
+/* a whole parquet file, with the batch in it */
+pub fn write_parquet<W: Write + Send>(writer: W, batch: &RecordBatch) -> Result<()> {
+    let mut parquet =
+        ArrowWriter::try_new(writer, batch.schema(), None).map_err(|err| Error::Parquet { err })?;
+    parquet.write(batch).map_err(|err| Error::Parquet { err })?;
+    parquet.close().map_err(|err| Error::Parquet { err })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::{TypedLibrariusBuilder, TypedReadTransaction, TypedTransaction};
+    use crate::vos::ObjectSize;
+    use crate::LibrariusBuilder;
+    use arrow_array::Array;
+    use std::mem::size_of;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Row {
+        id: u64,
+        score: f64,
+    }
+
+    impl Persistent for Row {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Row>())
+        }
+    }
+
+    unsafe impl Pod for Row {}
+
+    impl ArrowRow for Row {
+        fn fields(name: &str) -> Vec<Field> {
+            let mut fields = u64::fields(&format!("{}.id", name));
+            fields.extend(f64::fields(&format!("{}.score", name)));
+            fields
+        }
+
+        fn columns(rows: &[&Self]) -> Vec<ArrayRef> {
+            let ids: Vec<&u64> = rows.iter().map(|row| &row.id).collect();
+            let scores: Vec<&f64> = rows.iter().map(|row| &row.score).collect();
+            let mut columns = u64::columns(&ids);
+            columns.extend(f64::columns(&scores));
+            columns
+        }
+    }
+
+    #[test]
+    fn export_at_snapshot() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PVec::<Row>::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let push = |ids: std::ops::Range<u64>| {
+            librarius.run(|tx| {
+                let vec = tx.root_typed::<PVec<Row>>();
+                for id in ids.clone() {
+                    vec.push(
+                        tx,
+                        Row {
+                            id,
+                            score: id as f64 / 2.0,
+                        },
+                    )?;
+                }
+                Ok(())
+            })
+        };
+        push(0..10)?;
+
+        let snapshot = librarius.snapshot()?;
+        push(10..20)?;
+        let batch = snapshot.run(|tx| tx.root_typed::<PVec<Row>>().to_record_batch(tx))?;
+        assert_eq!(batch.num_rows(), 10);
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, ["value.id", "value.score"]);
+        let scores = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(scores.value(9), 4.5);
+        drop(snapshot);
+
+        let mut file = Vec::new();
+        write_parquet(&mut file, &batch)?;
+        assert!(file.starts_with(b"PAR1") && file.ends_with(b"PAR1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn export_map() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(PBTreeMap::<u32, u64>::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let map = tx.root_typed::<PBTreeMap<u32, u64>>();
+            for key in (0..100).rev() {
+                map.insert(tx, key, key as u64 * 3)?;
+            }
+            Ok(())
+        })?;
+
+        let batch = librarius
+            .snapshot()?
+            .run(|tx| tx.root_typed::<PBTreeMap<u32, u64>>().to_record_batch(tx))?;
+        assert_eq!(batch.num_rows(), 100);
+        let keys = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        let values = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert!(keys.values().iter().copied().eq(0..100));
+        assert_eq!(values.value(42), 126);
+        assert_eq!(values.null_count(), 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use crate::error::{Error, Result};
+use crate::librarius::Librarius;
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::mem::size_of;
+
+const FORMAT: u32 = 1;
+
+/*
+ * Everything reachable from the root, the root first. Pointers become
+ * indices into the objects, so a dump can be loaded into any store, no
+ * matter where its objects end up. The layouts come from the sizes the
+ * objects were allocated with, pointers first, as the typed layer lays
+ * them out. Data is in the byte order of the machine that made the dump.
+ * Each object keeps the fingerprint of the type it was allocated as, so
+ * typed reads of the imported graph are checked like the original ones.
+ */
+#[derive(Serialize, Deserialize)]
+struct Dump {
+    format: u32,
+    fingerprint: u64,
+    objects: Vec<DumpObject>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DumpObject {
+    /* dumps from before kinds were kept have untyped objects */
+    #[serde(default)]
+    kind: u64,
+    pointers: Vec<Option<usize>>,
+    data: String,
+}
+
+impl<'data> Librarius<'data> {
+    /* writes out a snapshot of the object graph, it's as consistent as any read */
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<()> {
+        let objects = self.run_read(|tx| {
+            let root = tx.root();
+            let mut objects = Vec::new();
+            if root.is_none() {
+                return Ok(objects);
+            }
+
+            let mut indices = HashMap::new();
+            indices.insert(root.address(), 0);
+            let mut pending = vec![root];
+            while let Some(pointer) = pending.get(objects.len()).copied() {
+                let size = tx.object_size(pointer)?;
+                let (pointers, data) = tx.read(pointer, &size)?.split_at(size.pointers as usize);
+
+                let mut children = Vec::new();
+                for child in unsafe_utils::many_from_slice::<UntypedPointer>(pointers) {
+                    if child.is_none() {
+                        children.push(None);
+                        continue;
+                    }
+                    let index = *indices.entry(child.address()).or_insert_with(|| {
+                        pending.push(child);
+                        pending.len() - 1
+                    });
+                    children.push(Some(index));
+                }
+
+                objects.push(DumpObject {
+                    kind: tx.object_kind(pointer)?,
+                    pointers: children,
+                    data: hex(data),
+                });
+            }
+            Ok(objects)
+        })?;
+
+        let dump = Dump {
+            format: FORMAT,
+            fingerprint: self.root_fingerprint()?,
+            objects,
+        };
+        serde_json::to_writer(writer, &dump).map_err(|err| Error::DumpFormat { err })
+    }
+
+    /*
+     * Loads a dump made by export_json, all in one transaction. The root
+     * becomes the root of the dump, so it can't point to anything yet,
+     * like in a store that was just created.
+     */
+    pub fn import_json<R: Read>(&self, reader: R) -> Result<()> {
+        let dump: Dump =
+            serde_json::from_reader(reader).map_err(|err| Error::DumpFormat { err })?;
+        if dump.format != FORMAT {
+            return Err(Error::InvalidDump {
+                reason: "unknown format",
+            });
+        }
+        if dump.objects.is_empty() {
+            return Err(Error::InvalidDump { reason: "no root" });
+        }
+
+        let objects = dump
+            .objects
+            .iter()
+            .map(|object| {
+                if object
+                    .pointers
+                    .iter()
+                    .any(|index| index.is_some_and(|index| index >= dump.objects.len()))
+                {
+                    return Err(Error::InvalidDump {
+                        reason: "pointer to an object that isn't there",
+                    });
+                }
+                let data = unhex(&object.data)?;
+                let size = ObjectSize::new_with_usize(
+                    object.pointers.len() * size_of::<UntypedPointer>(),
+                    data.len(),
+                );
+                Ok((size, object.kind, &object.pointers, data))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.run(|tx| {
+            let root = tx.root();
+            let size = tx.object_size(root)?;
+            let (pointers, _) = tx.read(root, &size)?.split_at(size.pointers as usize);
+            if unsafe_utils::many_from_slice::<UntypedPointer>(pointers)
+                .iter()
+                .any(|pointer| pointer.is_some())
+            {
+                return Err(Error::RootNotEmpty {});
+            }
+
+            /* all the objects have to exist before anything can point to them */
+            let mut allocated = Vec::with_capacity(objects.len());
+            for (i, (size, _, _, data)) in objects.iter().enumerate() {
+                let (pointer, slice) = match i {
+                    0 => {
+                        let slice = tx.realloc(root, *size)?;
+                        (root.clone(), slice)
+                    }
+                    _ => tx.alloc(*size)?,
+                };
+                slice[size.pointers as usize..].copy_from_slice(data);
+                allocated.push((pointer, slice));
+            }
+
+            let targets: Vec<_> = allocated
+                .iter()
+                .map(|(pointer, _)| pointer.clone())
+                .collect();
+            for ((size, _, pointers, _), (_, slice)) in objects.iter().zip(allocated) {
+                let slots = unsafe_utils::many_from_slice_mut::<UntypedPointer>(
+                    &mut slice[..size.pointers as usize],
+                );
+                for (slot, index) in slots.iter_mut().zip(pointers.iter()) {
+                    *slot = match index {
+                        Some(index) => targets[*index].clone(),
+                        None => UntypedPointer::new_none(),
+                    };
+                }
+            }
+            for ((_, kind, _, _), target) in objects.iter().zip(&targets) {
+                tx.retype(target, *kind)?;
+            }
+            Ok(())
+        })?;
+
+        self.set_root_fingerprint(dump.fingerprint)
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(Error::InvalidDump {
+            reason: "data isn't hex",
+        });
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::InvalidDump {
+                reason: "data isn't hex",
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::typed::{
+        Persistent, PersistentPointer, TypedLibrariusBuilder, TypedReadTransaction,
+        TypedTransaction,
+    };
+    use crate::LibrariusBuilder;
+
+    #[repr(C)]
+    struct Node {
+        next: PersistentPointer<Node>,
+        value: u64,
+    }
+
+    impl Persistent for Node {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(size_of::<UntypedPointer>(), size_of::<u64>())
+        }
+    }
+
+    fn open<'data>() -> Result<Librarius<'data>> {
+        LibrariusBuilder::new()
+            .create_with_typed(|| Node {
+                next: PersistentPointer::new_none(),
+                value: 0,
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let librarius = open()?;
+
+        /* a ring of three nodes, the last one points back to the root */
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Node>();
+            tx.write_typed(rootp)?.value = 1;
+            let ring = tx.root().clone();
+            let last = tx.alloc_typed(|| Node {
+                next: PersistentPointer::from_raw(ring.clone()),
+                value: 3,
+            })?;
+            let middle = tx.alloc_typed(|| Node {
+                next: PersistentPointer::from_raw(last.as_raw().clone()),
+                value: 2,
+            })?;
+            tx.write_typed(rootp)?.next = middle;
+            Ok(())
+        })?;
+
+        let mut exported = Vec::new();
+        librarius.export_json(&mut exported)?;
+
+        let imported = open()?;
+        imported.import_json(exported.as_slice())?;
+        let values = imported.run_read(|tx| {
+            let mut node = tx.read_typed(tx.root_typed::<Node>())?;
+            let mut values = Vec::new();
+            for _ in 0..4 {
+                values.push(node.value);
+                node = tx.read_typed(&node.next)?;
+            }
+            Ok(values)
+        })?;
+        assert_eq!(values, [1, 2, 3, 1]);
+        assert_eq!(imported.root_fingerprint()?, Node::fingerprint());
+        let kinds = imported.run_read(|tx| {
+            let root = tx.root_typed::<Node>();
+            let middle = &tx.read_typed(root)?.next;
+            Ok((
+                tx.object_kind(root.as_raw())?,
+                tx.object_kind(middle.as_raw())?,
+            ))
+        })?;
+        assert_eq!(kinds, (Node::fingerprint(), Node::fingerprint()));
+
+        /* the same graph, wherever its objects are */
+        let mut again = Vec::new();
+        imported.export_json(&mut again)?;
+        assert_eq!(exported, again);
+
+        assert!(matches!(
+            imported.import_json(exported.as_slice()),
+            Err(Error::RootNotEmpty {})
+        ));
+        assert!(matches!(
+            open()?.import_json(&b"{\"format\": 1"[..]),
+            Err(Error::DumpFormat { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -1,14 +1,22 @@
+use crate::las::LogicalAddress;
+use crate::tx::RootAccess;
 use snafu::Snafu;
+use std::fmt;
 use std::io;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("unable to open storage file "))]
-    SourceError {  },
+    SourceError {},
 
+    #[cfg(feature = "mmap")]
     #[snafu(display("memory mapping failed: {}", errno))]
     MemoryAlloc { errno: errno::Errno },
 
+    #[cfg(windows)]
+    #[snafu(display("virtual memory allocation failed: {}", err))]
+    VirtualAlloc { err: io::Error },
+
     #[snafu(display("invalid page source was provided"))]
     InvalidSource {},
 
@@ -48,11 +56,222 @@ pub enum Error {
     #[snafu(display("tried to create an allocation larger than a pagesize"))]
     AllocationTooLarge {},
 
+    #[snafu(display(
+        "fetch granularity {} is not a power of two within a page",
+        granularity
+    ))]
+    InvalidFetchGranularity { granularity: usize },
+
     #[snafu(display("incorrect page context length"))]
     ContextTooLarge {},
 
-    #[snafu(display("conflict during commit"))]
-    TxAborted {},
+    #[snafu(display("conflict during commit: {}", reason))]
+    TxAborted { reason: ConflictReason },
+
+    #[snafu(display("transaction ran past its timeout"))]
+    TxTimedOut {},
+
+    #[snafu(display("transaction was cancelled"))]
+    TxCancelled {},
+
+    #[snafu(display("transaction was aborted by the user"))]
+    UserAborted {},
+
+    #[snafu(display("malformed I/O trace"))]
+    InvalidTrace {},
+
+    #[snafu(display("librarius is closed"))]
+    Closed {},
+
+    #[snafu(display("timed out waiting for in-flight transactions"))]
+    ShutdownTimedOut {},
+
+    #[snafu(display("malformed pointer token"))]
+    InvalidToken {},
+
+    #[snafu(display("pointer token was issued by a different store epoch"))]
+    StaleToken {},
+
+    #[snafu(display("page failed to decrypt or authenticate"))]
+    DecryptionFailed {},
+
+    #[snafu(display("page failed to decompress"))]
+    CorruptedPage {},
+
+    #[snafu(display("librarius was opened read-only"))]
+    ReadOnly {},
+
+    #[snafu(display("source can't be resized to the requested length"))]
+    CannotResize {},
+
+    #[snafu(display("no source can rotate the key it encrypts with"))]
+    CannotRotateKey {},
+
+    #[snafu(display("not enough space left on the device"))]
+    NoSpaceOnDevice {},
+
+    #[snafu(display("source lost power, writes since its last flush are gone"))]
+    PowerLoss {},
+
+    #[snafu(display("the pool is already open in another process"))]
+    PoolBusy {},
+
+    #[snafu(display("the object has been freed"))]
+    ObjectFreed {},
+
+    #[snafu(display("write past the end of the object"))]
+    OutOfBounds {},
+
+    #[snafu(display("string object isn't valid UTF-8"))]
+    InvalidUtf8 {},
+
+    #[snafu(display("dereferenced a none pointer"))]
+    NullPointer {},
+
+    #[snafu(display(
+        "root has layout {:x}, expected {:x} and no migration leads there",
+        stored,
+        expected
+    ))]
+    LayoutMismatch { stored: u64, expected: u64 },
+
+    #[snafu(display(
+        "type {} has layout {:x}, but was stored as {:x}",
+        name,
+        expected,
+        stored
+    ))]
+    TypeLayoutMismatch {
+        name: String,
+        stored: u64,
+        expected: u64,
+    },
+
+    #[snafu(display("read an enum with an invalid tag {}", tag))]
+    InvalidTag { tag: u8 },
+
+    #[snafu(display("expected an object of type {:#x}, found {:#x}", expected, found))]
+    TypeMismatch { expected: u64, found: u64 },
+
+    #[cfg(feature = "serde")]
+    #[snafu(display("unable to encode or decode a dump: {}", err))]
+    DumpFormat { err: serde_json::Error },
+
+    #[snafu(display("invalid dump: {}", reason))]
+    InvalidDump { reason: &'static str },
+
+    #[snafu(display("replaying transaction {} diverged: {}", transaction, reason))]
+    ReplayDiverged {
+        transaction: usize,
+        reason: &'static str,
+    },
+
+    #[cfg(feature = "arrow")]
+    #[snafu(display("unable to build a record batch: {}", err))]
+    Arrow { err: arrow_schema::ArrowError },
+
+    #[cfg(feature = "arrow")]
+    #[snafu(display("unable to write a parquet file: {}", err))]
+    Parquet { err: parquet::errors::ParquetError },
+
+    #[snafu(display("the root already points to other objects"))]
+    RootNotEmpty {},
+
+    #[snafu(display(
+        "version {} can't be read, only {} to {} are kept",
+        version,
+        oldest,
+        newest
+    ))]
+    VersionUnavailable {
+        version: usize,
+        oldest: usize,
+        newest: usize,
+    },
+
+    #[snafu(display("object at {:#x} doesn't match its checksum", address))]
+    ChecksumMismatch { address: usize },
+
+    #[snafu(display("the object is compressed, but the store was opened without a codec"))]
+    NoCodec {},
+
+    #[snafu(display("the store was made before there were named roots"))]
+    NoRootDirectory {},
+
+    #[snafu(display("there's already a root named {}", name))]
+    NamedRootExists { name: String },
+
+    #[snafu(display("the root policy denied {:?} access to {}", access, name))]
+    RootAccessDenied { name: String, access: RootAccess },
+
+    #[snafu(display("invalid root name {:?}, it has to be 1 to 32 bytes long", name))]
+    InvalidRootName { name: String },
+
+    #[snafu(display("the catalog of type layouts is corrupted"))]
+    CorruptedCatalog {},
+
+    #[snafu(display("root name {:?} is reserved for the store itself", name))]
+    ReservedRootName { name: String },
+
+    #[snafu(display("slice elements can't mix pointers and data, or have padding"))]
+    InvalidSliceElement {},
+
+    #[snafu(display("objects are aligned to {} bytes, not {}", max, align))]
+    UnsupportedAlignment { align: usize, max: usize },
+
+    #[snafu(display("an object can't be shared by more than 255 references"))]
+    TooManyReferences {},
+
+    /* the transaction did commit, running it again would apply it twice */
+    #[snafu(display(
+        "committed as version {}, but writing back its commit group failed, it may not be durable",
+        version
+    ))]
+    CommittedNotDurable { version: usize },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/* why a transaction conflicted, and on which object */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConflictReason {
+    /* an object read for writing was changed by a commit since */
+    ReadValidation { address: LogicalAddress },
+    /* another transaction swung the pointer to a new version first */
+    WriteRace { address: LogicalAddress },
+    /* reading for write found a version newer than the snapshot */
+    VersionConflict { address: LogicalAddress },
+    /* another transaction freed the object first */
+    FreeRace { address: LogicalAddress },
+    /* an older transaction holds the lock of the object */
+    Locked { address: LogicalAddress },
+    /* the transaction returned the conflict itself */
+    User,
+}
+
+impl ConflictReason {
+    pub fn address(&self) -> Option<LogicalAddress> {
+        match *self {
+            ConflictReason::ReadValidation { address }
+            | ConflictReason::WriteRace { address }
+            | ConflictReason::VersionConflict { address }
+            | ConflictReason::FreeRace { address }
+            | ConflictReason::Locked { address } => Some(address),
+            ConflictReason::User => None,
+        }
+    }
+}
+
+impl fmt::Display for ConflictReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self {
+            ConflictReason::ReadValidation { .. } => "read validation failed",
+            ConflictReason::WriteRace { .. } => "lost a write race",
+            ConflictReason::VersionConflict { .. } => "newer version than the snapshot",
+            ConflictReason::FreeRace { .. } => "lost a free race",
+            ConflictReason::Locked { .. } => "object locked by an older transaction",
+            ConflictReason::User => return write!(f, "requested by the transaction"),
+        };
+        write!(f, "{} at {:#x}", what, self.address().unwrap())
+    }
+}
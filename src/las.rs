@@ -1,9 +1,11 @@
 use crate::error::{Error, Result};
-use crate::source::{Page, Source, SourceAllocator};
+use crate::source::memory_source::current_node;
+use crate::source::{Page, Source, SourceAllocator, SourceUsage, SyncMode};
 use crate::utils::{crc, crc_slice, math, unsafe_utils, OptionExt};
 use memoffset::offset_of;
-use parking_lot::RwLock;
-use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
 use std::mem::size_of;
 use std::ops::{Bound::Included, Deref, DerefMut};
 use std::{fmt::Debug, sync::Arc};
@@ -55,17 +57,16 @@ impl LogicalSlice {
         self.offset - base_offset - page.offset()
     }
 
+    /* a slice can cover a run of pages, but never more than one source */
     fn to_page(&self, pagesize: usize, base_offset: LogicalAddress) -> Page {
-        let offset = math::align_down(self.offset - base_offset, pagesize);
-        let len = math::align_up(self.len, pagesize);
-        assert_eq!(len, pagesize); //a single page can't span two locations
+        let aligned = self.page_aligned(pagesize);
 
-        Page::new(offset, len)
+        Page::new(aligned.offset - base_offset, aligned.len)
     }
 
     fn page_aligned(&self, pagesize: usize) -> Self {
         let offset = math::align_down(self.offset, pagesize);
-        let len = math::align_up(self.len, pagesize);
+        let len = math::align_up(self.offset + self.len, pagesize) - offset;
         LogicalSlice::new(offset, len)
     }
 
@@ -96,13 +97,16 @@ struct MetaData {
     slice: LogicalSlice,
 }
 
-pub const ROOT_SIZE: usize = 64;
+/* the header of the internal root object, two pointers and a fingerprint */
+pub const ROOT_SIZE: usize = 88;
 
 struct Meta {
     hdr: PageHeader,
     data: MetaData,
     crc: u32,
     root: [u8; ROOT_SIZE],
+    /* the newest version of any object written back, for reopening to go on from */
+    version: u64,
 }
 
 impl Debug for Meta {
@@ -125,6 +129,7 @@ impl Meta {
             data,
             crc,
             root: [0; ROOT_SIZE],
+            version: 0,
         }
     }
 
@@ -167,6 +172,10 @@ impl<'data> LogicalMutRef<'data> {
 
         Some((slice, new))
     }
+
+    pub fn address(&self) -> LogicalAddress {
+        self.slice.address()
+    }
 }
 
 impl<'data> Deref for LogicalMutRef<'data> {
@@ -185,7 +194,8 @@ impl<'data> DerefMut for LogicalMutRef<'data> {
 
 const CONTEXT_SIZE: usize = 16;
 
-pub type PageAlloc<'tx> = Box<dyn Fn() -> Result<LogicalMutRef<'tx>> + 'tx>;
+/* hands out at least the given number of bytes, a single page for anything that fits */
+pub type PageAlloc<'tx, 'data> = Box<dyn Fn(usize) -> Result<LogicalMutRef<'data>> + 'tx>;
 
 #[derive(Copy, Clone, Debug)]
 pub struct ByteLogicalSlice(pub LogicalSlice);
@@ -237,30 +247,70 @@ impl StoredLogicalSlice {
     }
 }
 
+pub const DEFAULT_FETCH_GRANULARITY: usize = 512;
+
+/*
+ * Decides which source new pages come from when more than one of them
+ * qualifies. Sources that compare equal are ranked by their logical address,
+ * so placement never depends on anything but the configuration.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    /* highest Source::perf_level first */
+    #[default]
+    Fastest,
+    /* lowest Source::perf_level first, keeps the fast tiers free for caching */
+    Slowest,
+    /* whatever comes first in the address space */
+    AddressOrder,
+}
+
 pub struct LogicalAddressSpace<'data> {
-    sources: BTreeMap<LogicalAddress, Arc<SourceAllocator<'data>>>,
+    sources: RwLock<BTreeMap<LogicalAddress, Arc<SourceAllocator<'data>>>>,
     pagesize: usize,
+    fetch_granularity: usize,
+    fetch_cache: Mutex<Option<LogicalMutRef<'data>>>,
+    /* fetched copies no one can be reading anymore, for later fetches to reuse */
+    fetch_free: Mutex<Vec<LogicalMutRef<'data>>>,
     root: StoredLogicalSlice,
     root_bytes: ByteLogicalSlice,
     backing: RwLock<HashMap<LogicalAddress, StoredLogicalSlice>>,
+    read_only: bool,
+    placement: PlacementPolicy,
+    punch_holes: bool,
+    sync_mode: SyncMode,
+    stored_version: Mutex<usize>,
 }
 
 impl<'data> LogicalAddressSpace<'data> {
+    /* everything past valid comes straight from the builder */
+    #[allow(clippy::too_many_arguments)]
     pub fn new<F>(
         pagesize: usize,
         raw_sources: impl Iterator<Item = Box<dyn Source + 'data>>,
         valid: F,
         create: bool,
+        read_only: bool,
+        placement: PlacementPolicy,
+        punch_holes: bool,
+        sync_mode: SyncMode,
     ) -> Result<Self>
     where
-        F: Fn(&[u8]) -> bool,
+        F: Fn(&[u8]) -> usize,
     {
         let mut sources = BTreeMap::new();
         let mut unallocated = Vec::new();
         let mut root = None;
 
         for source in raw_sources {
-            let allocator = SourceAllocator::new(source, pagesize, |data| valid(data))?;
+            let allocator = SourceAllocator::new(
+                source,
+                pagesize,
+                |data| valid(data),
+                read_only,
+                punch_holes,
+                sync_mode,
+            )?;
             let metapage = allocator.get_meta()?;
 
             let mut data = vec![0; pagesize];
@@ -271,8 +321,10 @@ impl<'data> LogicalAddressSpace<'data> {
                     if root.is_some() {
                         return Err(Error::RootExists {});
                     }
-                    let slice =
-                        LogicalSlice::new(metap.slice().offset + offset_of!(Meta, root), ROOT_SIZE);
+                    let slice = LogicalSlice::new(
+                        metap.slice().offset + metapage.offset() + offset_of!(Meta, root),
+                        ROOT_SIZE,
+                    );
                     root = Some(StoredLogicalSlice::new(
                         slice,
                         allocator.is_byte_addressable(),
@@ -287,7 +339,8 @@ impl<'data> LogicalAddressSpace<'data> {
                     sources.insert(start, Arc::new(allocator));
                 }
             } else {
-                if !create {
+                /* volatile sources never have anything worth opening */
+                if !create && allocator.is_persistent() {
                     return Err(Error::OpenOnUninitialized {});
                 }
                 unallocated.push(allocator);
@@ -295,31 +348,29 @@ impl<'data> LogicalAddressSpace<'data> {
         }
 
         for source in unallocated {
-            let last = sources.iter().next_back();
-            let offset = last.map_or(0, |(offset, allocator)| offset + allocator.length());
-
-            let slice = LogicalSlice::new(offset, source.length());
-
-            let meta = Meta::new(slice);
-            let data = unsafe_utils::any_as_slice(&meta);
-
-            let metapage = source.get_meta()?;
-
-            source.write_from(&metapage, 0, data)?;
-
-            sources.insert(meta.slice().offset, Arc::new(source));
+            Self::append(&mut sources, source)?;
         }
 
         let mut las = LogicalAddressSpace {
-            sources,
+            sources: RwLock::new(sources),
             pagesize,
+            fetch_granularity: std::cmp::min(DEFAULT_FETCH_GRANULARITY, pagesize),
+            fetch_cache: Mutex::new(None),
+            fetch_free: Mutex::new(Vec::new()),
             root: StoredLogicalSlice::new_byte(LogicalSlice::none()),
             root_bytes: ByteLogicalSlice(LogicalSlice::none()),
             backing: RwLock::new(HashMap::new()),
+            read_only,
+            placement,
+            punch_holes,
+            sync_mode,
+            stored_version: Mutex::new(0),
         };
 
         if root.is_none() {
-            assert!(create);
+            if !create {
+                return Err(Error::OpenOnUninitialized {});
+            }
             println!("root none");
 
             let (base_offset, source) = las
@@ -360,25 +411,190 @@ impl<'data> LogicalAddressSpace<'data> {
             las.root_bytes = las.root.unwrap_byte().clone();
         }
 
+        let version = las.read(&las.meta_field(offset_of!(Meta, version), size_of::<u64>()))?;
+        *las.stored_version.get_mut() = u64::from_le_bytes(version.try_into().unwrap()) as usize;
+
         Ok(las)
     }
 
-    pub fn boxed_page_alloc<'tx>(&'tx self) -> PageAlloc<'tx> {
-        Box::new(move || self.alloc())
+    /* maps a fresh source right after everything that's already mapped */
+    fn append(
+        sources: &mut BTreeMap<LogicalAddress, Arc<SourceAllocator<'data>>>,
+        source: SourceAllocator<'data>,
+    ) -> Result<()> {
+        let last = sources.iter().next_back();
+        let offset = last.map_or(0, |(offset, allocator)| offset + allocator.length());
+
+        let slice = LogicalSlice::new(offset, source.length());
+
+        let meta = Meta::new(slice);
+        let data = unsafe_utils::any_as_slice(&meta);
+
+        let metapage = source.get_meta()?;
+
+        source.write_from(&metapage, 0, data)?;
+
+        sources.insert(meta.slice().offset, Arc::new(source));
+
+        Ok(())
+    }
+
+    /*
+     * Extends the address space of a live store with a new, empty source.
+     * Sources that already belong to a store are refused, their contents
+     * would be lost otherwise.
+     */
+    pub fn add_source(&self, source: Box<dyn Source + 'data>) -> Result<()> {
+        if self.read_only && source.is_persistent() {
+            return Err(Error::ReadOnly {});
+        }
+
+        let allocator = SourceAllocator::new(
+            source,
+            self.pagesize,
+            |_| 0,
+            false,
+            self.punch_holes,
+            self.sync_mode,
+        )?;
+
+        let mut data = vec![0; self.pagesize];
+        allocator.read_into(&allocator.get_meta()?, 0, &mut data)?;
+        if unsafe_utils::any_from_slice::<Meta>(&data).is_valid() {
+            return Err(Error::InvalidSource {});
+        }
+
+        Self::append(&mut self.sources.write(), allocator)
+    }
+
+    /*
+     * Grows the source with the given index, in address order, to len bytes.
+     * Its logical range has to stay clear of the next source, so it's
+     * usually the last one that can grow.
+     */
+    pub fn grow(&self, id: usize, len: usize) -> Result<()> {
+        /* the root's meta page is cached in DRAM and written back whole */
+        let cached = match &self.root {
+            StoredLogicalSlice::Block(root) => {
+                let address = self.root_bytes.0.address() - offset_of!(Meta, root);
+                let slice = LogicalSlice::new(address, size_of::<Meta>());
+                Some((root.0.address(), self.write(&ByteLogicalSlice(slice))?))
+            }
+            StoredLogicalSlice::Byte(_) => None,
+        };
+
+        let sources = self.sources.write();
+        let mut mapped = sources.iter().skip(id);
+        let (base_offset, source) = mapped.next().ok_or(Error::InvalidSource {})?;
+        if let Some((next, _)) = mapped.next() {
+            if base_offset + math::align_down(len, self.pagesize) > *next {
+                return Err(Error::CannotResize {});
+            }
+        }
+
+        source.grow(len)?;
+
+        let data = MetaData {
+            slice: LogicalSlice::new(*base_offset, source.length()),
+        };
+        let crc = crc(&data);
+        let metapage = source.get_meta()?;
+        source.write_from(
+            &metapage,
+            offset_of!(Meta, data),
+            unsafe_utils::any_as_slice(&data),
+        )?;
+        source.write_from(
+            &metapage,
+            offset_of!(Meta, crc),
+            unsafe_utils::any_as_slice(&crc),
+        )?;
+
+        if let Some((root, bytes)) = cached {
+            if (*base_offset..base_offset + source.length()).contains(&root) {
+                let meta = unsafe_utils::any_from_slice_mut::<Meta>(bytes);
+                meta.data = data;
+                meta.crc = crc;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_fetch_granularity(&mut self, granularity: usize) -> Result<()> {
+        if !granularity.is_power_of_two() || granularity > self.pagesize {
+            return Err(Error::InvalidFetchGranularity { granularity });
+        }
+        self.fetch_granularity = granularity;
+        Ok(())
+    }
+
+    pub fn boxed_page_alloc<'tx>(&'tx self) -> PageAlloc<'tx, 'data> {
+        Box::new(move |len| self.alloc_run(len))
     }
 
     fn page_valid(bytes: &[u8]) -> bool {
         false
     }
 
-    fn get_best_source<F>(&self, f: F) -> Option<(usize, Arc<SourceAllocator<'data>>)>
+    /* all sources matching f, in the order the placement policy prefers them */
+    fn ranked_sources<F>(&self, f: F) -> Vec<(usize, Arc<SourceAllocator<'data>>)>
     where
         F: Fn(&Arc<SourceAllocator>) -> bool,
     {
-        self.sources
+        let mut ranked: Vec<_> = self
+            .sources
+            .read()
             .iter()
-            .find(|(_, s)| f(s))
+            .filter(|(_, s)| f(s))
             .map(|(base_offset, source)| (*base_offset, source.clone()))
+            .collect();
+
+        /* the sort is stable and sources are already in address order */
+        match self.placement {
+            PlacementPolicy::Fastest => {
+                ranked.sort_by_key(|(_, s)| std::cmp::Reverse(s.perf_level()))
+            }
+            PlacementPolicy::Slowest => ranked.sort_by_key(|(_, s)| s.perf_level()),
+            PlacementPolicy::AddressOrder => {}
+        }
+
+        /* memory on the caller's own NUMA node comes before everything else */
+        if let Some(local) = current_node() {
+            ranked.sort_by_key(|(_, s)| s.numa_node().is_some_and(|node| node != local));
+        }
+
+        ranked
+    }
+
+    fn get_best_source<F>(&self, f: F) -> Option<(usize, Arc<SourceAllocator<'data>>)>
+    where
+        F: Fn(&Arc<SourceAllocator>) -> bool,
+    {
+        self.ranked_sources(f).into_iter().next()
+    }
+
+    /*
+     * Takes len bytes worth of pages from the most preferred source that
+     * still has them, spilling over to the next tier once a source runs full.
+     */
+    fn allocate_from<F>(
+        &self,
+        len: usize,
+        f: F,
+    ) -> Result<(usize, Arc<SourceAllocator<'data>>, Page)>
+    where
+        F: Fn(&Arc<SourceAllocator>) -> bool,
+    {
+        for (base_offset, source) in self.ranked_sources(f) {
+            match source.allocate_run(len) {
+                Ok(page) => return Ok((base_offset, source, page)),
+                Err(Error::NoAvailableMemory {}) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(Error::NoAvailableMemory {})
     }
 
     fn get_best_persistent(&self) -> Option<(usize, Arc<SourceAllocator<'data>>)> {
@@ -389,10 +605,118 @@ impl<'data> LogicalAddressSpace<'data> {
         self.get_best_source(|s| s.is_byte_addressable())
     }
 
+    pub fn pagesize(&self) -> usize {
+        self.pagesize
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn has_persistent(&self) -> bool {
+        self.get_best_persistent().is_some()
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        for source in self.sources.read().values() {
+            source.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        for source in self.sources.read().values() {
+            source.close();
+        }
+    }
+
+    /* every source that encrypts gets the new key */
+    pub fn rotate_key(&self, key: &[u8]) -> Result<()> {
+        let mut rotated = false;
+        for source in self.sources.read().values() {
+            match source.rotate_key(key) {
+                Err(Error::CannotRotateKey {}) => {}
+                result => {
+                    result?;
+                    rotated = true;
+                }
+            }
+        }
+        match rotated {
+            true => Ok(()),
+            false => Err(Error::CannotRotateKey {}),
+        }
+    }
+
+    /* bytes rewritten, a source that fails is left for the next time */
+    pub fn reencrypt(&self, budget: usize) -> usize {
+        let mut written = 0;
+        for source in self.sources.read().values() {
+            written += source.reencrypt(budget - written).unwrap_or(0);
+        }
+        written
+    }
+
+    pub fn usage(&self) -> Vec<SourceUsage> {
+        self.sources
+            .read()
+            .values()
+            .map(|source| source.usage())
+            .collect()
+    }
+
     pub fn root_location(&self) -> &ByteLogicalSlice {
         &self.root_bytes
     }
 
+    /* where something in the memory of a byte addressable source is, if it's in one */
+    pub fn locate<T>(&self, object: &T) -> Option<LogicalAddress> {
+        let address = object as *const T as usize;
+        self.sources.read().iter().find_map(|(base, source)| {
+            let memory = source.get_bytes(&Page::new(0, source.length())).ok()??;
+            let start = memory.as_ptr() as usize;
+            (start..start + memory.len())
+                .contains(&address)
+                .then(|| base + (address - start))
+        })
+    }
+
+    /* a field of the meta page that holds the root, in its DRAM copy if it's cached */
+    fn meta_field(&self, offset: usize, len: usize) -> ByteLogicalSlice {
+        let meta = self.root_bytes.0.address() - offset_of!(Meta, root);
+        ByteLogicalSlice(LogicalSlice::new(meta + offset, len))
+    }
+
+    /* the newest version written back before the store was last closed */
+    pub fn stored_version(&self) -> usize {
+        *self.stored_version.lock()
+    }
+
+    /*
+     * Raises the version kept in the meta page to cover an object that's
+     * about to be written back. A cached meta page only goes out along with
+     * the root, after everything else, so the field is also written to the
+     * source right away.
+     */
+    pub fn store_version(&self, version: usize) -> Result<()> {
+        let mut stored = self.stored_version.lock();
+        if self.read_only || version <= *stored {
+            return Ok(());
+        }
+
+        let bytes = (version as u64).to_le_bytes();
+        self.write(&self.meta_field(offset_of!(Meta, version), bytes.len()))?
+            .copy_from_slice(&bytes);
+        if let StoredLogicalSlice::Block(root) = &self.root {
+            self.with_source(&root.0, |_, source| {
+                source.write_from(&source.get_meta()?, offset_of!(Meta, version), &bytes)
+            })?;
+        }
+        *stored = version;
+
+        Ok(())
+    }
+
     pub fn get_backing(&self, slice: &ByteLogicalSlice) -> Result<Option<StoredLogicalSlice>> {
         let slice_aligned = slice.0.page_aligned(self.pagesize);
         self.with_source(&slice_aligned, |base_offset, source| {
@@ -403,7 +727,7 @@ impl<'data> LogicalAddressSpace<'data> {
                 return Ok(Some(StoredLogicalSlice::Byte(slice.clone())));
             }
             let backing = self.backing.read().get(&slice_aligned.address()).copied();
-            if let Some(backing) = backing {
+            if let Some(backing) = backing.filter(|b| b.raw().len() >= slice_aligned.len()) {
                 let slice = LogicalSlice::new(backing.raw().address() + offset, slice.0.len);
                 Ok(Some(match backing {
                     StoredLogicalSlice::Block(_) => StoredLogicalSlice::new_block(slice),
@@ -415,77 +739,130 @@ impl<'data> LogicalAddressSpace<'data> {
         })
     }
 
-    fn allocate_backing(&self, key: LogicalAddress) -> Result<()> {
-        match self.backing.write().entry(key) {
-            Entry::Occupied(_) => {}
-            Entry::Vacant(v) => {
-                let (base_offset, allocator) = self
-                    .get_best_persistent()
-                    .ok_or(Error::NoAvailableMemory {})?;
-                let page = allocator.allocate_page()?;
-                let slice_new = LogicalSlice::from_page(page, base_offset);
-                v.insert(StoredLogicalSlice::new(
-                    slice_new,
-                    allocator.is_byte_addressable(),
-                ));
-            }
+    /*
+     * Backing is as long as whatever was flushed from the page, a run of
+     * pages gets a run of its own. Backing that turns out too short is
+     * replaced, everything stored in it before still points to the old one.
+     */
+    fn allocate_backing(&self, key: LogicalAddress, len: usize) -> Result<StoredLogicalSlice> {
+        if self.read_only {
+            return Err(Error::ReadOnly {});
         }
-        Ok(())
+        let mut backing = self.backing.write();
+        if let Some(existing) = backing.get(&key).filter(|b| b.raw().len() >= len) {
+            return Ok(*existing);
+        }
+
+        let (base_offset, allocator, page) = self.allocate_from(len, |s| s.is_persistent())?;
+        let slice_new = LogicalSlice::from_page(page, base_offset);
+        let stored = StoredLogicalSlice::new(slice_new, allocator.is_byte_addressable());
+        backing.insert(key, stored);
+
+        Ok(stored)
+    }
+
+    /*
+     * Writes the bytes to persistent space of their own, for data that
+     * can't go to the backing of the page it's in, since that's reused.
+     */
+    pub fn store(&self, data: &[u8]) -> Result<StoredLogicalSlice> {
+        if self.read_only {
+            return Err(Error::ReadOnly {});
+        }
+        let len = math::align_up(data.len(), self.pagesize);
+        let (base_offset, source, page) = self.allocate_from(len, |s| s.is_persistent())?;
+        source.write_batch(&[(page, data)])?;
+
+        let slice = LogicalSlice::from_page(page, base_offset);
+        let slice = LogicalSlice::new(slice.address(), data.len());
+        Ok(StoredLogicalSlice::new(slice, source.is_byte_addressable()))
     }
 
     pub fn flush(&self, slice: &ByteLogicalSlice) -> Result<StoredLogicalSlice> {
-        let slice_aligned = slice.0.page_aligned(self.pagesize);
+        Ok(self.flush_batch(std::slice::from_ref(slice))?.remove(0))
+    }
 
-        /* XXX: this is really inefficient and always flushes the entire page... */
-        self.with_source(&slice_aligned, |base_offset, source| {
-            assert!(source.is_byte_addressable());
-            let page = slice_aligned.to_page(self.pagesize, base_offset);
-            let data = source.get_bytes(&page)?.unwrap();
-            let offset = slice.0.page_offset(page, base_offset);
+    /*
+     * Page writes that end up on the same persistent source are handed to it
+     * as a single batch, followed by one flush of that source.
+     */
+    pub fn flush_batch(&self, slices: &[ByteLogicalSlice]) -> Result<Vec<StoredLogicalSlice>> {
+        self.write_back(slices, true)
+    }
 
-            if source.is_persistent() {
-                source.flush_partial(data)?;
-                Ok(StoredLogicalSlice::Byte(slice.clone()))
-            } else {
-                let backing = self.backing.read().get(&slice_aligned.address()).copied();
-                if let Some(backing) = backing {
-                    println!("flushing {:?} {:?}", slice_aligned.address(), backing);
-                    self.with_source(&backing.raw(), |dst_base_offset, dst_source| {
-                        assert!(dst_source.is_persistent());
-
-                        let dst_page = backing.raw().to_page(self.pagesize, dst_base_offset);
-                        println!("writing data... {:?}", dst_page);
-                        if dst_page.offset() == 4096 {
-                            let metap = unsafe_utils::any_from_slice::<Meta>(data);
-                            println!("flushing {:?} {:?}", slice_aligned, metap);
-                        }
-                        dst_source.write_from(&dst_page, 0, data)?;
-
-                        Ok(())
-                    })?;
-
-                    let slice = LogicalSlice::new(backing.raw().address() + offset, slice.0.len);
-                    Ok(match backing {
-                        StoredLogicalSlice::Block(_) => StoredLogicalSlice::new_block(slice),
-                        StoredLogicalSlice::Byte(_) => StoredLogicalSlice::new_byte(slice),
-                    })
-                } else {
-                    self.allocate_backing(slice_aligned.address())?;
-                    self.flush(slice)
+    /* like flush_batch, but nothing is durable until the next sync() */
+    pub fn write_back_batch(&self, slices: &[ByteLogicalSlice]) -> Result<Vec<StoredLogicalSlice>> {
+        self.write_back(slices, false)
+    }
+
+    fn write_back(
+        &self,
+        slices: &[ByteLogicalSlice],
+        sync: bool,
+    ) -> Result<Vec<StoredLogicalSlice>> {
+        let mut flushed = Vec::with_capacity(slices.len());
+        let mut writes: BTreeMap<LogicalAddress, (Arc<SourceAllocator<'data>>, Vec<_>)> =
+            BTreeMap::new();
+
+        for slice in slices {
+            let slice_aligned = slice.0.page_aligned(self.pagesize);
+
+            /* XXX: this is really inefficient and always flushes the entire page... */
+            let stored = self.with_source(&slice_aligned, |base_offset, source| {
+                assert!(source.is_byte_addressable());
+                let page = slice_aligned.to_page(self.pagesize, base_offset);
+                let data = source.get_bytes(&page)?.unwrap();
+                let offset = slice.0.page_offset(page, base_offset);
+
+                if source.is_persistent() {
+                    if sync {
+                        source.flush_partial(data)?;
+                    }
+                    return Ok(StoredLogicalSlice::Byte(slice.clone()));
                 }
+
+                let key = slice_aligned.address();
+                let backing = self.allocate_backing(key, slice_aligned.len())?;
+                self.with_source(&backing.raw(), |dst_base_offset, dst_source| {
+                    assert!(dst_source.is_persistent());
+
+                    let dst_page = backing.raw().to_page(self.pagesize, dst_base_offset);
+                    writes
+                        .entry(dst_base_offset)
+                        .or_insert_with(|| (dst_source, Vec::new()))
+                        .1
+                        .push((dst_page, data));
+
+                    Ok(())
+                })?;
+
+                let slice = LogicalSlice::new(backing.raw().address() + offset, slice.0.len);
+                Ok(match backing {
+                    StoredLogicalSlice::Block(_) => StoredLogicalSlice::new_block(slice),
+                    StoredLogicalSlice::Byte(_) => StoredLogicalSlice::new_byte(slice),
+                })
+            })?;
+            flushed.push(stored);
+        }
+
+        for (source, batch) in writes.values() {
+            match sync {
+                true => source.write_batch(batch)?,
+                false => source.write_batch_unsynced(batch)?,
             }
-        })
+        }
+
+        Ok(flushed)
     }
 
-    pub fn alloc<'tx>(&'tx self) -> Result<LogicalMutRef<'tx>>
-    where
-        'data: 'tx,
-    {
-        let (base_offset, source) = self
-            .get_best_byte_addressable()
-            .ok_or(Error::NoAvailableMemory {})?;
+    pub fn alloc(&self) -> Result<LogicalMutRef<'data>> {
+        self.alloc_run(self.pagesize - size_of::<PageHeader>())
+    }
 
-        let page = source.allocate_page()?;
+    /* a single page, or a run of contiguous ones for anything bigger */
+    pub fn alloc_run(&self, len: usize) -> Result<LogicalMutRef<'data>> {
+        let len = math::align_up(len + size_of::<PageHeader>(), self.pagesize);
+        let (base_offset, source, page) = self.allocate_from(len, |s| s.is_byte_addressable())?;
 
         let data = source.get_bytes_mut(&page)?.unwrap();
 
@@ -510,11 +887,13 @@ impl<'data> LogicalAddressSpace<'data> {
     {
         let (base_offset, source) = self
             .sources
+            .read()
             .range((Included(&0), Included(&slice.offset)))
             .next_back()
+            .map(|(base_offset, source)| (*base_offset, source.clone()))
             .ok_or(Error::InvalidLogicalAddress {})?;
 
-        f(*base_offset, source.clone())
+        f(base_offset, source)
     }
 
     pub fn read(&self, slice: &ByteLogicalSlice) -> Result<&'data [u8]> {
@@ -533,29 +912,133 @@ impl<'data> LogicalAddressSpace<'data> {
         })
     }
 
+    /*
+     * Fetched data is packed into shared cache pages in fetch granularity
+     * sized chunks, so that a small object doesn't cost an entire DRAM page.
+     * A whole page request always ends up at the start of a fresh page,
+     * anything bigger than that gets a run of pages of its own.
+     */
+    fn alloc_fetch(&self, len: usize) -> Result<(LogicalSlice, &'data mut [u8])> {
+        if let Some(reused) = self.reuse_fetched(len) {
+            return Ok(reused);
+        }
+        if len > self.pagesize {
+            let mut run = self.alloc_run(len)?;
+            return run
+                .try_consume_bytes(len, len)
+                .ok_or(Error::AllocationTooLarge {});
+        }
+
+        let mut active = self.fetch_cache.lock();
+        let mut fresh = false;
+
+        loop {
+            if active.is_none() {
+                *active = Some(self.alloc()?);
+                fresh = true;
+            }
+            let page = active.as_mut().unwrap();
+            match page.try_consume_bytes(len, len) {
+                Some(it) => return Ok(it),
+                None if fresh => return Err(Error::AllocationTooLarge {}),
+                None => *active = None,
+            }
+        }
+    }
+
+    fn reuse_fetched(&self, len: usize) -> Option<(LogicalSlice, &'data mut [u8])> {
+        let mut free = self.fetch_free.lock();
+        let index = free.iter().position(|chunk| chunk.len() >= len)?;
+        let reused = free[index].try_consume_bytes(len, len);
+        if free[index].is_empty() {
+            free.swap_remove(index);
+        }
+        reused
+    }
+
+    /*
+     * Gives back the memory of a fetched copy, it's up to the caller to make
+     * sure that nothing read from it is still around.
+     */
+    pub fn recycle_fetched(&self, copy: &ByteLogicalSlice) -> Result<()> {
+        self.fetch_free.lock().push(self.write_ref(copy)?);
+        Ok(())
+    }
+
+    /* memory for a copy put together from fetched data, recycled just like it */
+    pub fn alloc_copy(&self, len: usize) -> Result<(ByteLogicalSlice, &'data mut [u8])> {
+        let (slice, data) = self.alloc_fetch(len)?;
+        Ok((ByteLogicalSlice(slice), data))
+    }
+
+    /* memory for short-lived copies, packed and never given back, like fetched data */
+    pub fn alloc_scratch(&self, len: usize) -> Result<&'data mut [u8]> {
+        Ok(self.alloc_fetch(len)?.1)
+    }
+
     pub fn fetch(&self, slice: &StoredLogicalSlice) -> Result<ByteLogicalSlice> {
-        let raw = slice.raw();
-        let mut src_data = vec![0 as u8; self.pagesize];
+        Ok(self.fetch_batch(std::slice::from_ref(slice))?.remove(0))
+    }
 
-        let offset = self.with_source(raw, |base_offset, source| {
-            let page = raw.to_page(self.pagesize, base_offset);
+    pub fn fetch_batch(&self, slices: &[StoredLogicalSlice]) -> Result<Vec<ByteLogicalSlice>> {
+        let mut fetched = Vec::with_capacity(slices.len());
+        let mut reads: BTreeMap<LogicalAddress, (Arc<SourceAllocator<'data>>, Vec<_>)> =
+            BTreeMap::new();
 
-            source.read_into(&page, 0, src_data.as_mut_slice())?;
+        for slice in slices {
+            let raw = slice.raw();
 
-            Ok(raw.page_offset(page, base_offset))
-        })?;
+            let start = math::align_down(raw.address(), self.fetch_granularity);
+            let end = math::align_up(raw.address() + raw.len(), self.fetch_granularity);
 
-        let mut page = self.alloc()?;
+            let (chunk, data) = self.alloc_fetch(end - start)?;
 
-        page.copy_from_slice(src_data.as_slice());
+            self.with_source(raw, |base_offset, source| {
+                let page = raw.to_page(self.pagesize, base_offset);
+                reads
+                    .entry(base_offset)
+                    .or_insert_with(|| (source, Vec::new()))
+                    .1
+                    .push((page, start - base_offset - page.offset(), data));
 
-        println!("fetch with {}", offset);
+                Ok(())
+            })?;
 
-        let slice = LogicalSlice::new(page.slice.address() + offset, raw.len);
+            let slice = LogicalSlice::new(chunk.address() + (raw.address() - start), raw.len);
+            fetched.push(ByteLogicalSlice(slice));
+        }
+
+        for (source, batch) in reads.values_mut() {
+            source.read_batch(batch)?;
+        }
 
+        Ok(fetched)
+    }
+
+    /* like fetch, but the read is awaited rather than blocked on */
+    pub async fn fetch_async(&self, slice: &StoredLogicalSlice) -> Result<ByteLogicalSlice> {
+        let raw = *slice.raw();
+
+        let start = math::align_down(raw.address(), self.fetch_granularity);
+        let end = math::align_up(raw.address() + raw.len(), self.fetch_granularity);
+
+        let (chunk, data) = self.alloc_fetch(end - start)?;
+
+        let read = self.with_source(&raw, |base_offset, source| {
+            let page = raw.to_page(self.pagesize, base_offset);
+            Ok(source.read_into_async(&page, start - base_offset - page.offset(), end - start))
+        })?;
+        data.copy_from_slice(&read.await?);
+
+        let slice = LogicalSlice::new(chunk.address() + (raw.address() - start), raw.len);
         Ok(ByteLogicalSlice(slice))
     }
 
+    /* like write, but the bytes can then be carved up like a freshly allocated page */
+    pub fn write_ref(&self, slice: &ByteLogicalSlice) -> Result<LogicalMutRef<'data>> {
+        Ok(LogicalMutRef::new(self.write(slice)?, slice.0))
+    }
+
     pub fn write(&self, slice: &ByteLogicalSlice) -> Result<&'data mut [u8]> {
         let raw = &slice.0;
         self.with_source(raw, |base_offset, source| {
@@ -577,14 +1060,23 @@ impl<'data> LogicalAddressSpace<'data> {
 mod tests {
     use super::*;
     use crate::is_enum_variant;
-    use crate::source::MemorySource;
+    use crate::source::{FileSource, MemorySource};
 
     use std::iter;
 
     #[test]
     fn basic_test() -> Result<()> {
         let source: Box<dyn Source> = Box::new(MemorySource::new(1 << 20)?);
-        let las = LogicalAddressSpace::new(4096, iter::once(source), |data| false, true)?;
+        let las = LogicalAddressSpace::new(
+            4096,
+            iter::once(source),
+            |data| 0,
+            true,
+            false,
+            PlacementPolicy::default(),
+            false,
+            SyncMode::default(),
+        )?;
 
         let root = las.root_location();
 
@@ -599,4 +1091,154 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn recycle_fetched() -> Result<()> {
+        let source: Box<dyn Source> = Box::new(MemorySource::new(1 << 20)?);
+        let las = LogicalAddressSpace::new(
+            4096,
+            iter::once(source),
+            |_| 0,
+            true,
+            false,
+            PlacementPolicy::default(),
+            false,
+            SyncMode::default(),
+        )?;
+
+        let (copy, _) = las.alloc_fetch(64)?;
+        let (next, _) = las.alloc_fetch(64)?;
+        las.recycle_fetched(&ByteLogicalSlice(copy))?;
+
+        /* the copy is carved up before the page moves on */
+        assert_eq!(las.alloc_fetch(32)?.0.address(), copy.address());
+        assert_eq!(las.alloc_fetch(32)?.0.address(), copy.address() + 32);
+        assert_eq!(las.alloc_fetch(32)?.0.address(), next.address() + 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fetch_granularity() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-sectors-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let memory: Box<dyn Source> = Box::new(MemorySource::new(1 << 20)?);
+        let file: Box<dyn Source> = Box::new(FileSource::new(path, 1 << 20)?);
+        let mut las = LogicalAddressSpace::new(
+            4096,
+            vec![memory, file].into_iter(),
+            |_| 0,
+            true,
+            false,
+            PlacementPolicy::default(),
+            false,
+            SyncMode::default(),
+        )?;
+        assert!(matches!(
+            las.set_fetch_granularity(768),
+            Err(Error::InvalidFetchGranularity { granularity: 768 })
+        ));
+        assert!(matches!(
+            las.set_fetch_granularity(8192),
+            Err(Error::InvalidFetchGranularity { .. })
+        ));
+        las.set_fetch_granularity(1024)?;
+
+        let small = las.store(&[1; 16])?;
+        let large = las.store(&[2; 1500])?;
+        let fetched = las.fetch_batch(&[small, large])?;
+        assert_eq!(las.read(&fetched[0])?, &[1; 16][..]);
+        assert_eq!(las.read(&fetched[1])?, &[2; 1500][..]);
+
+        /* each takes the sectors around it, one after the other in the cache page */
+        let sector = |fetched: &ByteLogicalSlice, stored: &StoredLogicalSlice| {
+            fetched.0.address() - stored.raw().address() % 1024
+        };
+        let first = sector(&fetched[0], &small);
+        assert_eq!(first % 4096, 0);
+        assert_eq!(sector(&fetched[1], &large), first + 1024);
+
+        drop(las);
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn stored_version() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-version-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let memory: Box<dyn Source> = Box::new(MemorySource::new(1 << 20)?);
+        let file: Box<dyn Source> = Box::new(FileSource::new(path, 1 << 20)?);
+        let las = LogicalAddressSpace::new(
+            4096,
+            vec![memory, file].into_iter(),
+            |_| 0,
+            true,
+            false,
+            PlacementPolicy::default(),
+            false,
+            SyncMode::default(),
+        )?;
+
+        /* the meta page holds it little-endian, in the cached copy and on the file */
+        las.store_version(0x0102)?;
+        assert_eq!(las.stored_version(), 0x0102);
+        let field = las.meta_field(offset_of!(Meta, version), size_of::<u64>());
+        assert_eq!(las.read(&field)?, &0x0102u64.to_le_bytes()[..]);
+        let StoredLogicalSlice::Block(root) = &las.root else {
+            panic!()
+        };
+        let mut stored = [0; 8];
+        las.with_source(&root.0, |_, source| {
+            source.read_into(&source.get_meta()?, offset_of!(Meta, version), &mut stored)
+        })?;
+        assert_eq!(u64::from_le_bytes(stored), 0x0102);
+
+        drop(las);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn placement() -> Result<()> {
+        use crate::source::MappedFileSource;
+
+        let path = std::env::temp_dir().join(format!("librarius-tiers-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let tiers = |placement| {
+            let slow: Box<dyn Source> = Box::new(MappedFileSource::new(path, 1 << 20)?);
+            let fast: Box<dyn Source> = Box::new(MemorySource::new(4 * 4096)?);
+            LogicalAddressSpace::new(
+                4096,
+                vec![slow, fast].into_iter(),
+                |_| 0,
+                true,
+                false,
+                placement,
+                false,
+                SyncMode::default(),
+            )
+        };
+        let free = |las: &LogicalAddressSpace| -> Vec<usize> {
+            las.usage().iter().map(|usage| usage.free).collect()
+        };
+
+        let las = tiers(PlacementPolicy::Fastest)?;
+        let [slow, fast] = free(&las)[..] else {
+            panic!()
+        };
+        las.alloc()?;
+        las.alloc()?;
+        assert_eq!(free(&las), vec![slow, fast - 2 * 4096]);
+        las.alloc()?;
+        assert_eq!(free(&las), vec![slow - 4096, 0]);
+        drop(las);
+
+        let las = tiers(PlacementPolicy::AddressOrder)?;
+        las.alloc()?;
+        assert_eq!(free(&las), vec![slow - 4096, fast]);
+        drop(las);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
 }
@@ -1,18 +1,68 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
+#[cfg(feature = "admin-server")]
+mod admin;
+mod collections;
+#[cfg(feature = "arrow")]
+mod columnar;
+#[cfg(feature = "serde")]
+mod dump;
 mod error;
 mod las;
 mod librarius;
+mod record;
 mod source;
 mod tx;
 mod typed;
 mod utils;
 mod vos;
 
-pub use crate::librarius::{Librarius, LibrariusBuilder};
-pub use error::{Error, Result};
-pub use source::{FileSource, MemorySource, Source};
-pub use tx::Transaction;
-pub use typed::{Persistent, PersistentPointer, TypedLibrariusBuilder, TypedTransaction};
-pub use vos::{ObjectSize, UntypedPointer};
+pub use crate::librarius::{
+    Librarius, LibrariusBuilder, Maintenance, MaintenanceWork, Snapshot, TypeUsage,
+};
+#[cfg(feature = "admin-server")]
+pub use admin::Admin;
+pub use collections::{
+    PArc, PBTreeMap, PBTreeRange, PBytes, PHashMap, PHashMapEntry, POccupiedEntry, PQueue, PString,
+    PVacantEntry, PVec, PVecIter,
+};
+#[cfg(feature = "arrow")]
+pub use columnar::{write_parquet, ArrowRow};
+pub use error::{ConflictReason, Error, Result};
+pub use las::PlacementPolicy;
+#[cfg(feature = "derive")]
+pub use librarius_derive::Persistent;
+pub use record::{KeyPath, Link, RecordedObject, RecordedTransaction, Workload};
+#[cfg(all(
+    feature = "blockdev",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use source::BlockDeviceSource;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub use source::IoUringFileSource;
+#[cfg(feature = "lz4")]
+pub use source::Lz4Codec;
+#[cfg(all(feature = "mmap", unix))]
+pub use source::MappedFileSource;
+#[cfg(all(feature = "mmap", target_os = "linux", target_arch = "x86_64"))]
+pub use source::PmemSource;
+pub use source::{
+    block_on, AsyncSource, CompressedSource, DirectoryStore, EncryptedSource, FileSource, IoEvent,
+    IoFuture, IoOp, IoTrace, MemorySource, MirrorHealth, MirroredSource, ObjectStore,
+    ObjectStoreSource, PageCipher, PageCodec, RemoteSource, ReplaySource, Source,
+    SourceCapabilities, SourceUsage, StripedSource, SyncMode, TracingSource, HUGE_PAGE_SIZE,
+};
+#[cfg(feature = "faults")]
+pub use source::{FaultInjector, FaultySource};
+pub use tx::{
+    CancelToken, CommitInfo, ReadTransaction, RootAccess, Transaction, TxFuture, TxOptions, TxStats,
+};
+#[doc(hidden)]
+pub use typed::layout;
+pub use typed::{
+    Layout, PArray, Persistent, PersistentPointer, PersistentSlice, Pod, ReadGuard, Tagged,
+    TypedLibrariusBuilder, TypedReadTransaction, TypedTransaction,
+};
+pub use vos::{AllocLocality, BufferStats, ChecksumMode, ObjectSize, PointerToken, UntypedPointer};
@@ -1,22 +1,91 @@
 use crate::error::{Error, Result};
-use crate::las::LogicalAddressSpace;
-use crate::source::Source;
-use crate::tx::Transaction;
+use crate::las::{LogicalAddressSpace, PlacementPolicy};
+use crate::record::Recording;
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+use crate::source::{memory_source::online_nodes, MemorySource};
+use crate::source::{FileSource, PageCodec, Source, SourceUsage, SyncMode};
+use crate::tx::{
+    CommitInfo, Deadline, ObjectLocks, ReadTransaction, RootAccess, RootPolicy, Transaction,
+    TxFuture, TxOptions, TxStats,
+};
 use crate::utils::unsafe_utils;
-use crate::vos::{ObjectHeader, ObjectSize, UntypedPointer, Version, VersionedObjectStore};
+use crate::vos::{
+    AllocLocality, BufferStats, ChecksumMode, Compression, ObjectHeader, ObjectSize,
+    UntypedPointer, Version, VersionedObjectStore, UNTYPED,
+};
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::mem::size_of;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+/* the named root with the layouts of the registered types */
+const TYPE_CATALOG: &str = "librarius.types";
+/* an entry's fingerprint and the length of the name that follows it */
+const CATALOG_HEADER_LEN: usize = 2 * size_of::<u64>();
+
+type MigrationFn<'root> = Box<dyn Fn(&mut Transaction) -> Result<()> + 'root>;
+
+/* moves a root with one layout to another, in a transaction */
+pub(crate) struct Migration<'root> {
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+    pub(crate) run: MigrationFn<'root>,
+}
 
 pub struct LibrariusBuilder<'data, 'root> {
     sources: Vec<Box<dyn Source + 'data>>,
+    files: Vec<String>,
     pagesize: usize,
+    fetch_granularity: Option<usize>,
     root: Option<(ObjectSize, Box<dyn Fn(&mut [u8]) -> Result<()> + 'root>)>,
+    /* the layout the root is expected to have, if it's typed */
+    fingerprint: Option<u64>,
+    migrations: Vec<Migration<'root>>,
+    /* the names and layouts of the types kept in the store */
+    types: Vec<(String, u64)>,
+    read_only: bool,
+    placement: PlacementPolicy,
+    punch_holes: bool,
+    sync_mode: SyncMode,
+    pessimistic: bool,
+    group_commit: Option<Duration>,
+    history: usize,
+    checksums: ChecksumMode,
+    compression: Option<(Box<dyn PageCodec>, usize)>,
+    buffer_limit: Option<usize>,
+    deferred_maintenance: bool,
+    policy: Option<Box<RootPolicy<'data>>>,
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    numa_memory: Option<usize>,
 }
 
 impl<'data, 'root> LibrariusBuilder<'data, 'root> {
     pub fn new() -> Self {
         LibrariusBuilder {
             sources: Vec::new(),
+            files: Vec::new(),
             pagesize: 4096,
+            fetch_granularity: None,
             root: None,
+            fingerprint: None,
+            migrations: Vec::new(),
+            types: Vec::new(),
+            read_only: false,
+            placement: PlacementPolicy::default(),
+            punch_holes: false,
+            sync_mode: SyncMode::default(),
+            pessimistic: false,
+            group_commit: None,
+            history: 0,
+            checksums: ChecksumMode::default(),
+            compression: None,
+            buffer_limit: None,
+            deferred_maintenance: false,
+            policy: None,
+            #[cfg(all(feature = "mmap", target_os = "linux"))]
+            numa_memory: None,
         }
     }
 
@@ -29,51 +98,616 @@ impl<'data, 'root> LibrariusBuilder<'data, 'root> {
         self
     }
 
+    pub(crate) fn fingerprint(mut self, fingerprint: u64) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    pub(crate) fn migration(mut self, migration: Migration<'root>) -> Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    pub(crate) fn register(mut self, name: &str, fingerprint: u64) -> Self {
+        self.types.push((name.to_string(), fingerprint));
+        self
+    }
+
     pub fn source(mut self, source: impl Source + 'data) -> Self {
         self.sources.push(Box::new(source));
         self
     }
 
+    /*
+     * An existing file of the pool, used at whatever size it has. It's only
+     * opened in open(), which is also where any error is reported.
+     */
+    pub fn open_file(mut self, path: &str) -> Self {
+        self.files.push(path.to_string());
+        self
+    }
+
     pub fn pagesize(mut self, pagesize: usize) -> Self {
         self.pagesize = pagesize;
         self
     }
 
-    pub fn open(self) -> Result<Librarius<'data>> {
-        Librarius::new(self.pagesize, self.sources.into_iter(), self.root)
+    pub fn fetch_granularity(mut self, granularity: usize) -> Self {
+        self.fetch_granularity = Some(granularity);
+        self
+    }
+
+    pub fn placement(mut self, placement: PlacementPolicy) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /*
+     * Releases the storage behind free pages, so that files only take up as
+     * much disk space as the data that's actually live and SSDs get freed
+     * pages TRIMmed. Sources that can't discard anything are left alone, a
+     * filesystem without hole punching makes opening fail.
+     */
+    pub fn punch_holes(mut self) -> Self {
+        self.punch_holes = true;
+        self
+    }
+
+    /*
+     * How hard committing a transaction tries to get it onto stable storage,
+     * SyncMode::Fdatasync unless set otherwise.
+     */
+    pub fn sync_mode(mut self, mode: SyncMode) -> Self {
+        self.sync_mode = mode;
+        self
+    }
+
+    /*
+     * Makes writes lock the objects they modify instead of only finding out
+     * about conflicts at commit. Worth it when many transactions keep
+     * writing the same few objects and would otherwise keep aborting each
+     * other.
+     */
+    pub fn pessimistic_locking(mut self) -> Self {
+        self.pessimistic = true;
+        self
+    }
+
+    /*
+     * Makes every transaction durable before run() returns. Commits that
+     * arrive within the window of each other are written back together, so
+     * that they share the flushes of the persistent sources. If that fails,
+     * run() returns Error::CommittedNotDurable, the transaction is committed
+     * and visible regardless, and must not be run again.
+     */
+    pub fn group_commit(mut self, window: Duration) -> Self {
+        self.group_commit = Some(window);
+        self
+    }
+
+    /*
+     * Keeps the given number of the latest versions around for run_at(),
+     * on top of whatever running transactions still need. The space of
+     * objects they replaced or freed is only reused once they're too old.
+     */
+    pub fn history(mut self, versions: usize) -> Self {
+        self.history = versions;
+        self
+    }
+
+    /*
+     * Checksums the data of every object a transaction commits, and checks
+     * it when reading as often as the mode says. ChecksumMode::Never, the
+     * default, leaves objects without one.
+     */
+    pub fn checksums(mut self, mode: ChecksumMode) -> Self {
+        self.checksums = mode;
+        self
+    }
+
+    /*
+     * Objects with at least `above` bytes of payload are compressed with the
+     * codec when they're written back to a block source, and decompressed
+     * when fetched. A store with any of them has to be opened with it again.
+     */
+    pub fn compression(mut self, codec: impl PageCodec + 'static, above: usize) -> Self {
+        self.compression = Some((Box::new(codec), above));
+        self
+    }
+
+    /*
+     * Caps how much DRAM copies fetched from block sources take up. Past
+     * that, the copies in the least recently read pages are evicted, and
+     * fetched again when needed. Without a limit they stay for good.
+     */
+    pub fn buffer_limit(mut self, bytes: usize) -> Self {
+        self.buffer_limit = Some(bytes);
+        self
+    }
+
+    /*
+     * Adds a DRAM source of len bytes on every NUMA node of the machine.
+     * Transactions then allocate from the memory local to their thread.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    pub fn numa_memory(mut self, len: usize) -> Self {
+        self.numa_memory = Some(len);
+        self
+    }
+
+    /*
+     * Asked before a transaction gets to a named root, with its name and
+     * what the transaction can do with it. A root it says no to can't be
+     * found or created, for stores shared by tenants that mustn't see each
+     * other's data. The anonymous root isn't covered.
+     */
+    pub fn root_policy(
+        mut self,
+        policy: impl Fn(&str, RootAccess) -> bool + Send + Sync + 'data,
+    ) -> Self {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /*
+     * Transactions stop collecting old versions and evicting fetched copies
+     * on their own, it's up to run_maintenance() from then on. Until it
+     * runs, nothing freed is reused and the buffer limit isn't kept.
+     */
+    pub fn deferred_maintenance(mut self) -> Self {
+        self.deferred_maintenance = true;
+        self
+    }
+
+    /*
+     * Opens an existing store without ever writing to its persistent
+     * sources. Transactions can read, but any attempt to modify or allocate
+     * fails with Error::ReadOnly.
+     */
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn open(mut self) -> Result<Librarius<'data>> {
+        for path in &self.files {
+            self.sources.push(Box::new(FileSource::open(path)?));
+        }
+
+        #[cfg(all(feature = "mmap", target_os = "linux"))]
+        if let Some(len) = self.numa_memory {
+            for node in online_nodes()? {
+                self.sources
+                    .push(Box::new(MemorySource::new_on_node(len, node)?));
+            }
+        }
+
+        let fingerprint = self
+            .fingerprint
+            .or_else(|| self.migrations.last().map(|migration| migration.to));
+        let root = self
+            .root
+            .map(|(size, f)| (size, fingerprint.unwrap_or(0), f));
+        let mut librarius = Librarius::new(
+            self.pagesize,
+            self.sources.into_iter(),
+            root,
+            self.read_only,
+            self.placement,
+            self.punch_holes,
+            self.sync_mode,
+        )?;
+        if let Some(granularity) = self.fetch_granularity {
+            librarius.las.set_fetch_granularity(granularity)?;
+        }
+        librarius.vos.set_history(self.history);
+        librarius.vos.set_checksums(self.checksums);
+        if let Some((codec, above)) = self.compression {
+            librarius
+                .vos
+                .set_compression(Compression::new(codec, above));
+        }
+        if let Some(bytes) = self.buffer_limit {
+            librarius.vos.set_buffer_limit(bytes);
+        }
+        if self.deferred_maintenance {
+            librarius.vos.set_deferred();
+        }
+        if self.pessimistic {
+            librarius.locks = Some(ObjectLocks::new());
+        }
+        if let Some(window) = self.group_commit {
+            librarius.group = Some(GroupCommit::new(window));
+        }
+        librarius.migrate(fingerprint, &self.migrations)?;
+        librarius.policy = self.policy;
+        librarius.check_types(&self.types)?;
+        Ok(librarius)
+    }
+}
+
+struct QuiesceState {
+    active: usize,
+    paused: bool,
+    closed: bool,
+}
+
+struct Quiesce {
+    state: Mutex<QuiesceState>,
+    idle: Condvar,
+    resumed: Condvar,
+}
+
+struct ActiveTransaction<'q> {
+    quiesce: &'q Quiesce,
+}
+
+impl<'q> Drop for ActiveTransaction<'q> {
+    fn drop(&mut self) {
+        let mut state = self.quiesce.state.lock();
+        state.active -= 1;
+        if state.active == 0 {
+            self.quiesce.idle.notify_all();
+        }
+    }
+}
+
+impl Quiesce {
+    fn new() -> Self {
+        Quiesce {
+            state: Mutex::new(QuiesceState {
+                active: 0,
+                paused: false,
+                closed: false,
+            }),
+            idle: Condvar::new(),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn enter(&self) -> Result<ActiveTransaction<'_>> {
+        let mut state = self.state.lock();
+        while state.paused && !state.closed {
+            self.resumed.wait(&mut state);
+        }
+        if state.closed {
+            return Err(Error::Closed {});
+        }
+        state.active += 1;
+
+        Ok(ActiveTransaction { quiesce: self })
+    }
+
+    fn pause(&self) {
+        self.state.lock().paused = true;
+    }
+
+    fn resume(&self) {
+        self.state.lock().paused = false;
+        self.resumed.notify_all();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    fn close(&self) -> bool {
+        let mut state = self.state.lock();
+        let was_closed = state.closed;
+        state.closed = true;
+        self.resumed.notify_all();
+
+        !was_closed
+    }
+
+    fn is_idle(&self) -> bool {
+        self.state.lock().active == 0
+    }
+
+    fn wait_idle(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        while state.active != 0 {
+            if self.idle.wait_until(&mut state, deadline).timed_out() {
+                return Err(Error::ShutdownTimedOut {});
+            }
+        }
+        Ok(())
+    }
+}
+
+struct MaintenanceState {
+    paused: bool,
+    stopped: bool,
+}
+
+/* what a round of run_maintenance() does, the higher priorities first */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaintenanceWork {
+    /* old versions and freed space, made reusable */
+    Collect,
+    /* fetched copies over the buffer limit, written back if changed */
+    Evict,
+    /* pages of encrypted sources still sealed with an older key */
+    Reencrypt,
+}
+
+/* earned at a rate per second, with at most a second's worth saved up */
+struct TokenBucket {
+    rate: u64,
+    tokens: u64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        TokenBucket {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned =
+            now.duration_since(self.refilled).as_nanos() * self.rate as u128 / 1_000_000_000;
+        if earned > 0 {
+            self.tokens = (self.tokens as u128 + earned).min(self.rate as u128) as u64;
+            self.refilled = now;
+        }
+    }
+
+    fn take(&mut self, tokens: u64) {
+        self.tokens = self.tokens.saturating_sub(tokens);
+    }
+}
+
+/*
+ * Paces run_maintenance(): at most one round per interval. Transactions
+ * come first, a round waits for a moment none is running, and is skipped
+ * if there's none within another interval. The bytes written back and the
+ * time spent in rounds are budgeted per second, once either runs out, the
+ * work that's left waits for a later round.
+ */
+pub struct Maintenance {
+    interval: Duration,
+    /* bytes per second */
+    io: u64,
+    /* nanoseconds per second */
+    cpu: u64,
+    priorities: [(MaintenanceWork, u8); 3],
+    state: Mutex<MaintenanceState>,
+    changed: Condvar,
+}
+
+impl Maintenance {
+    pub fn new(rounds_per_second: u32) -> Self {
+        Maintenance {
+            interval: Duration::from_secs(1) / rounds_per_second.max(1),
+            io: u64::MAX,
+            cpu: u64::MAX,
+            priorities: [
+                (MaintenanceWork::Collect, 0),
+                (MaintenanceWork::Evict, 0),
+                (MaintenanceWork::Reencrypt, 0),
+            ],
+            state: Mutex::new(MaintenanceState {
+                paused: false,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn io_budget(mut self, bytes_per_second: u64) -> Self {
+        self.io = bytes_per_second;
+        self
+    }
+
+    pub fn cpu_budget(mut self, per_second: Duration) -> Self {
+        self.cpu = per_second.as_nanos().try_into().unwrap_or(u64::MAX);
+        self
+    }
+
+    /* work with the same priority is done in the order of MaintenanceWork */
+    pub fn priority(mut self, work: MaintenanceWork, priority: u8) -> Self {
+        for entry in self.priorities.iter_mut().filter(|(w, _)| *w == work) {
+            entry.1 = priority;
+        }
+        self
+    }
+
+    fn order(&self) -> [MaintenanceWork; 3] {
+        let mut priorities = self.priorities;
+        priorities.sort_by_key(|(_, priority)| std::cmp::Reverse(*priority));
+        priorities.map(|(work, _)| work)
+    }
+
+    pub fn pause(&self) {
+        self.state.lock().paused = true;
+    }
+
+    pub fn resume(&self) {
+        self.state.lock().paused = false;
+        self.changed.notify_all();
+    }
+
+    /* run_maintenance() returns once the round it's in, if any, is done */
+    pub fn stop(&self) {
+        self.state.lock().stopped = true;
+        self.changed.notify_all();
+    }
+
+    /* false once stopped */
+    fn wait_turn(&self) -> bool {
+        let deadline = Instant::now() + self.interval;
+        let mut state = self.state.lock();
+        while !state.stopped && (state.paused || Instant::now() < deadline) {
+            match state.paused {
+                true => self.changed.wait(&mut state),
+                false => {
+                    self.changed.wait_until(&mut state, deadline);
+                }
+            }
+        }
+        !state.stopped
+    }
+}
+
+struct GroupState {
+    /* the group that new commits join */
+    open: u64,
+    /* whether the open group already has a leader */
+    led: bool,
+    /* every group up to this one has been written back */
+    done: u64,
+    /* groups whose write-back failed, reported to all of their members */
+    failed: Vec<u64>,
+}
+
+/*
+ * The first commit of a group leads it: it waits for the window to pass, so
+ * that others can join, closes the group and writes all of it back at once.
+ * The others just wait for that to be done. Write-backs of consecutive
+ * groups never overlap.
+ */
+struct GroupCommit {
+    window: Duration,
+    state: Mutex<GroupState>,
+    written: Condvar,
+    write_back: Mutex<()>,
+}
+
+impl GroupCommit {
+    fn new(window: Duration) -> Self {
+        GroupCommit {
+            window,
+            state: Mutex::new(GroupState {
+                open: 1,
+                led: false,
+                done: 0,
+                failed: Vec::new(),
+            }),
+            written: Condvar::new(),
+            write_back: Mutex::new(()),
+        }
+    }
+
+    /* whether the group was written back */
+    fn join<F>(&self, write_back: F) -> bool
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let mut state = self.state.lock();
+        let group = state.open;
+        if state.led {
+            while state.done < group {
+                self.written.wait(&mut state);
+            }
+            return !state.failed.contains(&group);
+        }
+        state.led = true;
+        drop(state);
+
+        std::thread::sleep(self.window);
+        {
+            let mut state = self.state.lock();
+            state.open += 1;
+            state.led = false;
+        }
+
+        let result = {
+            let _guard = self.write_back.lock();
+            write_back()
+        };
+
+        let mut state = self.state.lock();
+        if result.is_err() {
+            state.failed.push(group);
+        }
+        state.done = std::cmp::max(state.done, group);
+        self.written.notify_all();
+
+        result.is_ok()
     }
 }
 
+/* the space objects allocated as one type take up, headers included */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TypeUsage {
+    pub objects: usize,
+    pub live_bytes: usize,
+    /* versions that newer ones replaced, kept until no snapshot reads them */
+    pub dead_bytes: usize,
+}
+
 pub struct Librarius<'data> {
     las: LogicalAddressSpace<'data>,
     vos: VersionedObjectStore<'data>,
     root: &'data UntypedPointer,
+    directory: Option<&'data UntypedPointer>,
+    quiesce: Quiesce,
+    /* everything all transactions did since the store was opened */
+    stats: Mutex<TxStats>,
+    /* only in the pessimistic mode */
+    locks: Option<ObjectLocks>,
+    /* only when commits have to be durable */
+    group: Option<GroupCommit>,
+    policy: Option<Box<RootPolicy<'data>>>,
+    pub(crate) recording: Recording,
 }
 
 impl<'data> Librarius<'data> {
     pub fn new<F>(
         pagesize: usize,
         sources: impl Iterator<Item = Box<dyn Source + 'data>>,
-        root: Option<(ObjectSize, F)>,
+        root: Option<(ObjectSize, u64, F)>,
+        read_only: bool,
+        placement: PlacementPolicy,
+        punch_holes: bool,
+        sync_mode: SyncMode,
     ) -> Result<Librarius<'data>>
     where
         F: Fn(&mut [u8]) -> Result<()>,
     {
+        if read_only && root.is_some() {
+            return Err(Error::ReadOnly {});
+        }
+
         let las = LogicalAddressSpace::new(
             pagesize,
             sources,
             VersionedObjectStore::valid_page,
             root.is_some(),
+            read_only,
+            placement,
+            punch_holes,
+            sync_mode,
         )?;
-        let vos = VersionedObjectStore::new();
+        let vos = VersionedObjectStore::new(pagesize);
+        vos.restore_version(las.stored_version());
 
-        let root = if let Some((root_size, root_constr)) = root {
-            Self::root_alloc(&las, &vos, root_size, root_constr)?
+        let root = if let Some((root_size, fingerprint, root_constr)) = root {
+            Self::root_alloc(&las, &vos, root_size, fingerprint, root_constr)?
         } else {
             Self::root_read(&las, &vos)?
         };
 
-        Ok(Librarius { las, vos, root })
+        let directory = Self::directory_read(&las)?;
+
+        Ok(Librarius {
+            las,
+            vos,
+            root,
+            directory,
+            quiesce: Quiesce::new(),
+            stats: Mutex::new(TxStats::default()),
+            locks: None,
+            group: None,
+            policy: None,
+            recording: Recording::new(),
+        })
     }
 
     fn root_read(
@@ -91,10 +725,31 @@ impl<'data> Librarius<'data> {
         Ok(ptr_root)
     }
 
+    /* the directory of named roots comes right after the root pointer */
+    fn directory_read(las: &LogicalAddressSpace<'data>) -> Result<Option<&'data UntypedPointer>> {
+        let (header, data) = las
+            .read(las.root_location())?
+            .split_at(size_of::<ObjectHeader>());
+        let header: &ObjectHeader = unsafe_utils::any_from_slice(header);
+        if (header.size.pointers as usize) < 2 * size_of::<UntypedPointer>() {
+            return Ok(None);
+        }
+        Ok(Some(unsafe_utils::any_from_slice(
+            &data[size_of::<UntypedPointer>()..],
+        )))
+    }
+
+    fn root_owning(las: &LogicalAddressSpace<'data>) -> UntypedPointer {
+        let root_location = las.root_location();
+
+        UntypedPointer::new_byte(root_location.0.address() + std::mem::size_of::<ObjectHeader>())
+    }
+
     fn root_alloc<F>(
         las: &LogicalAddressSpace<'data>,
         vos: &VersionedObjectStore<'data>,
         size: ObjectSize,
+        fingerprint: u64,
         f: F,
     ) -> Result<&'data UntypedPointer>
     where
@@ -110,26 +765,37 @@ impl<'data> Librarius<'data> {
             }
         }
 
-        let owning = root_location.0.address() + std::mem::size_of::<ObjectHeader>();
+        let ptr_owning = Self::root_owning(las);
 
-        let ptr_owning = UntypedPointer::new_byte(owning);
-
-        let internal_size = ObjectSize::new(8, 0);
+        /*
+         * The root pointer and the one to the directory of named roots,
+         * followed by the fingerprint of the root's layout.
+         */
+        let internal_size = ObjectSize::new(16, 8);
         let data = las.write(&root_location)?;
         let userdata = allocator.init_object(
             data,
             internal_size,
+            UNTYPED,
             Version::new_base(),
             UntypedPointer::new_none(),
         );
 
-        let ptr_root: &UntypedPointer = unsafe_utils::any_from_slice(userdata);
+        let (pointers, stored) = userdata.split_at_mut(internal_size.pointers as usize);
+        stored.copy_from_slice(&fingerprint.to_le_bytes());
+        let pointers = unsafe_utils::many_from_slice::<UntypedPointer>(pointers);
 
-        let (root, data) = allocator.alloc_new(size, Version::new_base())?;
+        /* an untyped root has no fingerprint, the same as no kind */
+        let (root, data) = allocator.alloc_new(size, fingerprint, Version::new_base())?;
 
         f(data)?;
 
-        let result = ptr_root.compare_and_swap(UntypedPointer::new_none(), root);
+        /* there's always a directory, named roots only ever reallocate it */
+        let (directory, _) =
+            allocator.alloc_new(ObjectSize::new(0, 0), UNTYPED, Version::new_base())?;
+
+        let result = pointers[0].compare_and_swap(UntypedPointer::new_none(), root)
+            && pointers[1].compare_and_swap(UntypedPointer::new_none(), directory);
         assert!(result);
         let reader = vos.new_versioned_reader(las);
         if let Err(err) = reader.flush(&ptr_owning) {
@@ -139,224 +805,3254 @@ impl<'data> Librarius<'data> {
         Self::root_read(las, vos)
     }
 
-    pub fn run_once<R, TX>(&self, func: TX) -> Result<R>
-    where
-        TX: FnOnce(&mut Transaction) -> Result<R>,
-    {
-        let mut tx = Transaction::new(&self.las, &self.vos, self.root);
-        let result = func(&mut tx);
-
-        match result {
-            Ok(_) => tx.commit()?,
-            Err(_) => tx.abort(),
+    /*
+     * The layout the root was made or last migrated with, 0 if it isn't known.
+     * It's little-endian like pointers, so an image means the same anywhere.
+     */
+    pub(crate) fn root_fingerprint(&self) -> Result<u64> {
+        let (header, data) = self
+            .las
+            .read(self.las.root_location())?
+            .split_at(size_of::<ObjectHeader>());
+        let header: &ObjectHeader = unsafe_utils::any_from_slice(header);
+        if header.size.data < 8 {
+            return Ok(0);
         }
+        let offset = header.size.pointers as usize;
+        let stored = &data[offset..offset + 8];
+        Ok(u64::from_le_bytes(
+            stored.try_into().expect("fingerprint is 8 bytes"),
+        ))
+    }
 
-        result
+    pub(crate) fn set_root_fingerprint(&self, fingerprint: u64) -> Result<()> {
+        let data = self.las.write(self.las.root_location())?;
+        let header: &ObjectHeader = unsafe_utils::any_from_slice(data);
+        let offset = size_of::<ObjectHeader>() + header.size.pointers as usize;
+        data[offset..offset + 8].copy_from_slice(&fingerprint.to_le_bytes());
+
+        match self
+            .vos
+            .new_versioned_reader(&self.las)
+            .flush(&Self::root_owning(&self.las))
+        {
+            /* only volatile memory, there's nothing to make it durable on */
+            Err(Error::NoAvailableMemory {}) => Ok(()),
+            result => result,
+        }
     }
 
-    pub fn run<R, TX>(&self, transaction: TX) -> Result<R>
-    where
-        TX: Fn(&mut Transaction) -> Result<R>,
-    {
-        loop {
-            match self.run_once(&transaction) {
-                Ok(result) => return Ok(result),
-                Err(Error::TxAborted {}) => {}
-                Err(error) => return Err(error),
-            }
+    /*
+     * Brings the root to the expected layout, one migration at a time. The
+     * fingerprint is updated right after each migration commits, a crash in
+     * between makes the migration run again on the next open.
+     */
+    fn migrate(&self, expected: Option<u64>, migrations: &[Migration]) -> Result<()> {
+        let mut stored = self.root_fingerprint()?;
+        if stored == 0 {
+            /* made without a type, there's nothing to check against */
+            return Ok(());
+        }
+        while let Some(migration) = migrations
+            .iter()
+            .find(|migration| migration.from == stored && migration.to != stored)
+        {
+            self.run(|tx| (migration.run)(tx))?;
+            self.set_root_fingerprint(migration.to)?;
+            stored = migration.to;
+        }
+
+        match expected {
+            Some(expected) if expected != stored => Err(Error::LayoutMismatch { stored, expected }),
+            _ => Ok(()),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::source::MemorySource;
-    use std::mem::size_of;
-    use std::sync::Arc;
+    /*
+     * Compares the registered types with the layouts they were first stored
+     * with. Types that aren't in the catalog yet are added to it, unless the
+     * store is read-only.
+     */
+    fn check_types(&self, types: &[(String, u64)]) -> Result<()> {
+        if types.is_empty() || self.directory.is_none() {
+            return Ok(());
+        }
+        let missing = self.run_read(|tx| {
+            let stored = match tx.find_named_root(TYPE_CATALOG)? {
+                Some(catalog) => {
+                    let size = tx.object_size(catalog)?;
+                    tx.read(catalog, &size)?
+                }
+                None => &[],
+            };
+            let stored = catalog_entries(stored)?;
+
+            let mut missing = Vec::new();
+            for (name, expected) in types {
+                match stored.get(name.as_bytes()) {
+                    Some(&stored) if stored != *expected => {
+                        return Err(Error::TypeLayoutMismatch {
+                            name: name.clone(),
+                            stored,
+                            expected: *expected,
+                        })
+                    }
+                    Some(_) => {}
+                    None => missing.extend(catalog_entry(name, *expected)),
+                }
+            }
+            Ok(missing)
+        })?;
+        if missing.is_empty() || self.las.is_read_only() {
+            return Ok(());
+        }
 
-    struct BasicRoot {
-        value: u64,
+        self.run(|tx| {
+            let len = missing.len();
+            let data = match tx.find_named_root(TYPE_CATALOG)? {
+                Some(catalog) => {
+                    let old = tx.object_size(catalog)?.data as usize;
+                    &mut tx.realloc(catalog, ObjectSize::new_with_usize(0, old + len))?[old..]
+                }
+                None => {
+                    tx.create_named_root(TYPE_CATALOG, ObjectSize::new_with_usize(0, len))?
+                        .1
+                }
+            };
+            data.copy_from_slice(&missing);
+            Ok(())
+        })
     }
-    impl Persistent for BasicRoot {
-        fn size() -> ObjectSize {
-            ObjectSize::new_with_usize(0, size_of::<BasicRoot>())
+
+    /*
+     * Does the work deferred_maintenance() took away from transactions, a
+     * round at a time, as paced by the given Maintenance, until it's stopped.
+     * It's meant to have a thread of its own, which can be a scoped one.
+     */
+    pub fn run_maintenance(&self, maintenance: &Maintenance) {
+        let mut io = TokenBucket::new(maintenance.io);
+        let mut cpu = TokenBucket::new(maintenance.cpu);
+        while maintenance.wait_turn() {
+            if self.quiesce.wait_idle(maintenance.interval).is_err() {
+                continue;
+            }
+            io.refill();
+            cpu.refill();
+            for work in &maintenance.order() {
+                if cpu.tokens == 0 {
+                    break;
+                }
+                let start = Instant::now();
+                match *work {
+                    MaintenanceWork::Collect => self.vos.collect(&self.las),
+                    MaintenanceWork::Evict => {
+                        let budget = io.tokens.try_into().unwrap_or(usize::MAX);
+                        io.take(self.vos.evict(&self.las, budget) as u64);
+                    }
+                    MaintenanceWork::Reencrypt => {
+                        let budget = io.tokens.try_into().unwrap_or(usize::MAX);
+                        io.take(self.las.reencrypt(budget) as u64);
+                    }
+                }
+                cpu.take(start.elapsed().as_nanos().try_into().unwrap_or(u64::MAX));
+            }
         }
     }
 
-    #[test]
-    fn basic() -> Result<()> {
-        let librarius = LibrariusBuilder::new()
-            .create_with_typed(|| BasicRoot { value: 0 })
-            .source(MemorySource::new(1 << 20)?)
-            .open()?;
+    pub fn pause(&self) {
+        self.quiesce.pause()
+    }
 
-        Ok(())
+    pub fn resume(&self) {
+        self.quiesce.resume()
     }
 
-    #[test]
-    fn counter() -> Result<()> {
-        let root_size = ObjectSize::new_with_usize(0, std::mem::size_of::<usize>());
+    pub(crate) fn is_closed(&self) -> bool {
+        self.quiesce.is_closed()
+    }
 
-        let librarius = LibrariusBuilder::new()
-            .create_with(root_size, |data| {
-                let counter: &mut usize = unsafe_utils::any_from_slice_mut(data);
-                *counter = 0;
+    pub fn close(&self, timeout: Duration) -> Result<()> {
+        if !self.quiesce.close() {
+            return Err(Error::Closed {});
+        }
+        self.quiesce.wait_idle(timeout)?;
 
-                Ok(())
-            })
-            .source(MemorySource::new(1 << 20)?)
-            .open()?;
+        self.shutdown()
+    }
 
-        let nthreads = 10;
+    /* everything that's been committed so far ends up on persistent storage */
+    fn persist(&self) -> Result<()> {
+        if !self.las.has_persistent() {
+            return Ok(());
+        }
+        let reader = self.vos.new_versioned_reader(&self.las);
+        reader.flush_grouped(&Self::root_owning(&self.las))
+    }
 
-        let librarius = Arc::new(librarius);
+    fn shutdown(&self) -> Result<()> {
+        /* nothing is running anymore, so every redo record can be applied */
+        self.vos.collect(&self.las);
+        if self.las.has_persistent() && !self.las.is_read_only() {
+            let reader = self.vos.new_versioned_reader(&self.las);
+            reader.flush(&Self::root_owning(&self.las))?;
+        }
+        self.las.sync()?;
+        self.las.close();
 
-        let mut threads = Vec::new();
+        Ok(())
+    }
 
-        for i in 0..nthreads {
+    /*
+     * Grows a running store. The pages of the new source can be allocated
+     * from as soon as this returns. When the store is reopened, the source
+     * has to be passed to the builder along with all the others.
+     */
+    pub fn add_source(&self, source: impl Source + 'data) -> Result<()> {
+        self.las.add_source(Box::new(source))
+    }
+
+    /*
+     * Extends a source, identified by its index in usage(), with new free
+     * pages. Only sources that support Source::resize can grow.
+     */
+    pub fn grow(&self, source_id: usize, len: usize) -> Result<()> {
+        self.las.grow(source_id, len)
+    }
+
+    pub fn usage(&self) -> Vec<SourceUsage> {
+        self.las.usage()
+    }
+
+    /*
+     * Seals whatever the encrypted sources write from now on with a new
+     * key. The pages sealed with older ones are rewritten by
+     * run_maintenance(), at the pace of its io budget, until the unrotated
+     * bytes of usage() are down to nothing. Up to then, the ciphers have to
+     * be given the older keys when the store is reopened.
+     */
+    pub fn rotate_key(&self, key: &[u8]) -> Result<()> {
+        self.las.rotate_key(key)
+    }
+
+    /*
+     * Usage by the fingerprint of the type objects were allocated as,
+     * UNTYPED for the rest. Live objects are the ones reachable from the
+     * roots in the current snapshot, found by walking all of them.
+     */
+    pub fn usage_by_type(&self) -> Result<HashMap<u64, TypeUsage>> {
+        let mut usage: HashMap<u64, TypeUsage> = HashMap::new();
+        self.run_read(|tx| {
+            let mut pending: Vec<&UntypedPointer> = vec![tx.root()];
+            pending.extend(self.directory);
+            let mut seen = HashSet::new();
+            while let Some(pointer) = pending.pop() {
+                if pointer.is_none() || !seen.insert(pointer.address()) {
+                    continue;
+                }
+                let size = tx.object_size(pointer)?;
+                let entry = usage.entry(tx.object_kind(pointer)?).or_default();
+                entry.objects += 1;
+                entry.live_bytes += size_of::<ObjectHeader>() + size.total();
+
+                let pointers = &tx.read(pointer, &size)?[..size.pointers as usize];
+                pending.extend(unsafe_utils::many_from_slice::<UntypedPointer>(pointers));
+            }
+            Ok(())
+        })?;
+
+        for (kind, bytes) in self.vos.superseded(&self.las)? {
+            usage.entry(kind).or_default().dead_bytes += bytes;
+        }
+        Ok(usage)
+    }
+
+    pub fn stats(&self) -> TxStats {
+        *self.stats.lock()
+    }
+
+    pub fn locality(&self) -> AllocLocality {
+        self.vos.locality()
+    }
+
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.vos.buffer_stats()
+    }
+
+    pub fn run_once<R, TX>(&self, func: TX) -> Result<R>
+    where
+        TX: FnOnce(&mut Transaction) -> Result<R>,
+    {
+        Ok(self.run_once_with_info(func)?.0)
+    }
+
+    pub fn run_once_with_info<R, TX>(&self, func: TX) -> Result<(R, CommitInfo)>
+    where
+        TX: FnOnce(&mut Transaction) -> Result<R>,
+    {
+        let deadline = Deadline::new(&TxOptions::default());
+        self.attempt(func, self.ticket(), &deadline).0
+    }
+
+    fn ticket(&self) -> u64 {
+        self.locks.as_ref().map_or(0, |locks| locks.ticket())
+    }
+
+    /* a single try at running the transaction, along with what it took */
+    fn attempt<R, TX>(
+        &self,
+        func: TX,
+        ticket: u64,
+        deadline: &Deadline,
+    ) -> (Result<(R, CommitInfo)>, TxStats)
+    where
+        TX: FnOnce(&mut Transaction) -> Result<R>,
+    {
+        let _active = match self.quiesce.enter() {
+            Ok(active) => active,
+            Err(err) => return (Err(err), TxStats::default()),
+        };
+
+        let mut tx = self.begin(ticket, deadline);
+        /* a panic mustn't leave uncommitted versions behind for everyone to trip over */
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| func(&mut tx))) {
+            Ok(result) => result,
+            Err(payload) => {
+                tx.abort();
+                drop(tx);
+                panic::resume_unwind(payload)
+            }
+        };
+        self.finish(tx, result)
+    }
+
+    fn begin<'a>(&'a self, ticket: u64, deadline: &'a Deadline) -> Transaction<'a, 'data> {
+        let mut tx = Transaction::new(&self.las, &self.vos, self.root);
+        tx.directory_with(self.directory, self.policy.as_deref());
+        if let Some(locks) = &self.locks {
+            tx.lock_with(locks, ticket);
+        }
+        tx.limit_with(deadline);
+        if self.recording.is_on() {
+            tx.record_with(&self.recording);
+        }
+        tx
+    }
+
+    fn begin_read(&self) -> ReadTransaction<'_, 'data> {
+        let mut tx = ReadTransaction::new(&self.las, &self.vos, self.root);
+        tx.directory_with(self.directory, self.policy.as_deref());
+        tx
+    }
+
+    /* commits or aborts, depending on what the transaction returned */
+    fn finish<R>(
+        &self,
+        mut tx: Transaction<'_, 'data>,
+        result: Result<R>,
+    ) -> (Result<(R, CommitInfo)>, TxStats) {
+        let result = match result {
+            Ok(result) => tx.commit().map(|info| (result, info)),
+            Err(err) => {
+                tx.abort();
+                Err(err)
+            }
+        };
+
+        let mut stats = tx.stats();
+        if let Err(Error::TxAborted { .. }) = result {
+            stats.conflicts = 1;
+        }
+        self.stats.lock().merge(&stats);
+        drop(tx);
+
+        /* visible to everyone by now, only whether it's durable is left open */
+        if let (Some(group), Ok((_, info))) = (&self.group, &result) {
+            if let Some(version) = info.version {
+                if !group.join(|| self.persist()) {
+                    return (Err(Error::CommittedNotDurable { version }), stats);
+                }
+            }
+        }
+
+        (result, stats)
+    }
+
+    pub fn run<R, TX>(&self, transaction: TX) -> Result<R>
+    where
+        TX: Fn(&mut Transaction) -> Result<R>,
+    {
+        Ok(self.run_with_info(transaction)?.0)
+    }
+
+    pub fn run_with_info<R, TX>(&self, transaction: TX) -> Result<(R, CommitInfo)>
+    where
+        TX: Fn(&mut Transaction) -> Result<R>,
+    {
+        self.run_with_options(&TxOptions::default(), |tx, _| transaction(tx))
+    }
+
+    /*
+     * Like run, but the transaction can change what it captured. It's told
+     * how many attempts came before it, and everything it changed during
+     * those has to be undone or overwritten, since their results are gone.
+     */
+    pub fn run_mut<R, TX>(&self, transaction: TX) -> Result<R>
+    where
+        TX: FnMut(&mut Transaction, usize) -> Result<R>,
+    {
+        Ok(self.run_with_options(&TxOptions::default(), transaction)?.0)
+    }
+
+    /*
+     * Like run, but gives up with Error::TxTimedOut or Error::TxCancelled
+     * once the options say so, instead of retrying for as long as it takes.
+     */
+    pub fn run_with<R, TX>(&self, options: &TxOptions, transaction: TX) -> Result<R>
+    where
+        TX: Fn(&mut Transaction) -> Result<R>,
+    {
+        Ok(self.run_with_options(options, |tx, _| transaction(tx))?.0)
+    }
+
+    fn run_with_options<R, TX>(
+        &self,
+        options: &TxOptions,
+        mut transaction: TX,
+    ) -> Result<(R, CommitInfo)>
+    where
+        TX: FnMut(&mut Transaction, usize) -> Result<R>,
+    {
+        let mut stats = TxStats::default();
+        let ticket = self.ticket();
+        let deadline = Deadline::new(options);
+        let mut retries = 0;
+        loop {
+            deadline.check()?;
+            let (result, attempt) = self.attempt(|tx| transaction(tx, retries), ticket, &deadline);
+            stats.merge(&attempt);
+            match result {
+                Ok((result, info)) => return Ok((result, CommitInfo { stats, ..info })),
+                Err(Error::TxAborted { .. }) => retries += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /*
+     * Like run, but for transactions that await their reads instead of
+     * blocking on them, see Transaction::read_async. The closure returns a
+     * boxed future, which is free to borrow the transaction:
+     * |tx| Box::pin(async move { ... }).
+     */
+    pub async fn run_async<R, TX>(&self, transaction: TX) -> Result<R>
+    where
+        TX: for<'a, 'tx> Fn(&'a mut Transaction<'tx, 'data>) -> TxFuture<'a, R>,
+    {
+        let ticket = self.ticket();
+        let deadline = Deadline::new(&TxOptions::default());
+        loop {
+            let _active = self.quiesce.enter()?;
+
+            /* dropping the transaction, on a panic or when this future is dropped, aborts it */
+            let mut tx = self.begin(ticket, &deadline);
+            let result = transaction(&mut tx).await;
+            match self.finish(tx, result).0 {
+                Ok((result, _)) => return Ok(result),
+                Err(Error::TxAborted { .. }) => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /*
+     * Runs a read-only transaction against a snapshot of the store. There's
+     * nothing to commit and nothing to conflict with, so it runs only once.
+     */
+    pub fn run_read<R, TX>(&self, func: TX) -> Result<R>
+    where
+        TX: FnOnce(&ReadTransaction) -> Result<R>,
+    {
+        let _active = self.quiesce.enter()?;
+
+        let tx = self.begin_read();
+
+        let result = func(&tx);
+        self.stats.lock().merge(&tx.stats());
+
+        result
+    }
+
+    /*
+     * Like run_read, but sees the store as it was right after the commit
+     * of the given version, as reported by run_with_info(). Versions older
+     * than the history kept by the builder can fail with
+     * Error::VersionUnavailable, unless a snapshot still holds on to them.
+     */
+    pub fn run_at<R, TX>(&self, version: usize, func: TX) -> Result<R>
+    where
+        TX: FnOnce(&ReadTransaction) -> Result<R>,
+    {
+        let _active = self.quiesce.enter()?;
+
+        let mut tx = ReadTransaction::new_at(&self.las, &self.vos, self.root, version)?;
+        tx.directory_with(self.directory, self.policy.as_deref());
+
+        let result = func(&tx);
+        self.stats.lock().merge(&tx.stats());
+
+        result
+    }
+
+    /*
+     * Pins the current version of the store until the snapshot is dropped,
+     * no matter how many transactions commit in the meantime. Like any
+     * other running transaction, it holds up close().
+     */
+    pub fn snapshot(&self) -> Result<Snapshot<'_, 'data>> {
+        let active = self.quiesce.enter()?;
+
+        Ok(Snapshot {
+            librarius: self,
+            tx: self.begin_read(),
+            _active: active,
+        })
+    }
+}
+
+/*
+ * A read-only view of the store as it was when the snapshot was taken.
+ * Nothing it can see is folded, freed or reused while it's around, so it
+ * can be held for as long as a backup or a long scan takes.
+ */
+pub struct Snapshot<'a, 'data> {
+    librarius: &'a Librarius<'data>,
+    tx: ReadTransaction<'a, 'data>,
+    _active: ActiveTransaction<'a>,
+}
+
+impl<'a, 'data> Drop for Snapshot<'a, 'data> {
+    fn drop(&mut self) {
+        self.librarius.stats.lock().merge(&self.tx.stats());
+    }
+}
+
+impl<'a, 'data> Snapshot<'a, 'data> {
+    pub fn version(&self) -> usize {
+        self.tx.snapshot_version()
+    }
+
+    /* reads at the pinned version, as many times as needed */
+    pub fn run<R, TX>(&self, func: TX) -> Result<R>
+    where
+        TX: FnOnce(&ReadTransaction) -> Result<R>,
+    {
+        func(&self.tx)
+    }
+}
+
+/*
+ * Everything a transaction hands out borrows from that transaction, so no
+ * slice into the sources can outlive the run() that produced it. By the time
+ * the store is dropped there can be nothing in flight, and all that's left
+ * to do is a best-effort flush.
+ */
+impl<'data> Drop for Librarius<'data> {
+    fn drop(&mut self) {
+        if !self.quiesce.close() {
+            return;
+        }
+        debug_assert!(self.quiesce.is_idle());
+
+        /* there's no one to hand an error to, close() is the way to get it */
+        let result = self.shutdown();
+        debug_assert!(result.is_ok(), "flushing on drop failed: {:?}", result);
+    }
+}
+
+/* the type catalog is a run of entries, each a header and then the name */
+fn catalog_entries(mut data: &[u8]) -> Result<HashMap<&[u8], u64>> {
+    let mut entries = HashMap::new();
+    while !data.is_empty() {
+        if data.len() < CATALOG_HEADER_LEN {
+            return Err(Error::CorruptedCatalog {});
+        }
+        let (header, rest) = data.split_at(CATALOG_HEADER_LEN);
+        let (fingerprint, len) = header.split_at(size_of::<u64>());
+        let fingerprint =
+            u64::from_le_bytes(fingerprint.try_into().expect("fingerprint is 8 bytes"));
+        let len = u64::from_le_bytes(len.try_into().expect("length is 8 bytes")) as usize;
+        if rest.len() < len {
+            return Err(Error::CorruptedCatalog {});
+        }
+        let (name, rest) = rest.split_at(len);
+        entries.insert(name, fingerprint);
+        data = rest;
+    }
+    Ok(entries)
+}
+
+fn catalog_entry(name: &str, fingerprint: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(CATALOG_HEADER_LEN + name.len());
+    entry.extend_from_slice(&fingerprint.to_le_bytes());
+    entry.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    entry.extend_from_slice(name.as_bytes());
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ConflictReason;
+    use crate::las::{ByteLogicalSlice, LogicalAddress, LogicalSlice};
+    use crate::source::encrypted_source::tests::XorCipher;
+    use crate::source::faulty_source::FaultySource;
+    use crate::source::{
+        block_on, EncryptedSource, FileSource, IoOp, MemorySource, ReplaySource, TracingSource,
+    };
+    use crate::tx::CancelToken;
+    use std::collections::HashSet;
+    use std::future::Future;
+    use std::mem::{align_of, size_of};
+    use std::sync::Arc;
+
+    struct BasicRoot {
+        value: u64,
+    }
+    impl Persistent for BasicRoot {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<BasicRoot>())
+        }
+    }
+
+    #[test]
+    fn basic() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn realloc() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(8, 8), |data| {
+                data[8..].copy_from_slice(&[7; 8]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            let data = tx.realloc(root, ObjectSize::new(16, 12))?;
+            assert_eq!(data.len(), 28);
+            data[24..].copy_from_slice(&[9; 4]);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            let data = tx.read(root, &ObjectSize::new(16, 12))?;
+            assert!(data[..16].iter().all(|b| *b == 0));
+            assert_eq!(data[16..24], [7; 8]);
+            assert_eq!(data[24..], [9; 4]);
+
+            let data = tx.realloc(root, ObjectSize::new(0, 4))?;
+            assert_eq!(data, [7; 4]);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn set() -> Result<()> {
+        let size = ObjectSize::new(0, 16);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |data| {
+                data.copy_from_slice(&[1; 16]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let object = librarius.run_read(|tx| Ok(tx.root().address()))?;
+
+        librarius.run_read(|before| {
+            librarius.run(|tx| {
+                let root = tx.root();
+                tx.set(root, 2, &[2; 4])?;
+                tx.set(root, 4, &[3; 4])?;
+                assert!(matches!(
+                    tx.set(root, 14, &[0; 4]),
+                    Err(Error::OutOfBounds {})
+                ));
+                assert!(matches!(
+                    tx.set(root, usize::MAX, &[0; 4]),
+                    Err(Error::OutOfBounds {})
+                ));
+                Ok(())
+            })?;
+
+            let root = before.root();
+            assert_eq!(before.read(root, &size)?, [1; 16]);
+            assert_ne!(root.address(), object);
+            Ok(())
+        })?;
+
+        let expected = [1, 1, 2, 2, 3, 3, 3, 3, 1, 1, 1, 1, 1, 1, 1, 1];
+        librarius.run(|tx| {
+            let root = tx.root();
+            assert_eq!(root.address(), object);
+            assert_eq!(tx.read(root, &size)?, expected);
+
+            tx.write(root, &size)?;
+            tx.set(root, 0, &[4; 2])?;
+            Ok(())
+        })?;
+
+        let data = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert_eq!(data[..2], [4; 2]);
+        assert_eq!(data[2..], expected[2..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_own_writes() -> Result<()> {
+        let size = ObjectSize::new(0, 16);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |data| {
+                data.copy_from_slice(&[1; 16]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.set(root, 0, &[2; 4])?;
+            tx.set(root, 2, &[3; 4])?;
+            assert_eq!(tx.read(root, &size)?[..8], [2, 2, 3, 3, 3, 3, 1, 1]);
+            assert_eq!(
+                tx.read_for_write(root, &size)?[..8],
+                [2, 2, 3, 3, 3, 3, 1, 1]
+            );
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.write(root, &size)?.copy_from_slice(&[5; 16]);
+            assert_eq!(tx.read(root, &size)?, [5; 16]);
+            tx.set(root, 0, &[6; 2])?;
+            assert_eq!(tx.read(root, &size)?[..4], [6, 6, 5, 5]);
+            Ok(())
+        })?;
+
+        let data = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert_eq!(data[..4], [6, 6, 5, 5]);
+
+        /* pointers are only ever written whole */
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| -> [PersistentPointer<u64>; 1] {
+                [PersistentPointer::new_none()]
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let root = tx.root();
+            assert!(matches!(tx.set(root, 0, &[0]), Err(Error::OutOfBounds {})));
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_twice() -> Result<()> {
+        let size = ObjectSize::new(0, 16);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |data| {
+                data.copy_from_slice(&[1; 16]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            let first = tx.write(root, &size)?;
+            first[0] = 2;
+            let first = first.as_ptr();
+            let second = tx.write(root, &size)?;
+            assert_eq!(second.as_ptr(), first);
+            assert_eq!(second[0], 2);
+            second[1] = 3;
+            assert_eq!(tx.stats().objects_written, 1);
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.set(root, 2, &[4; 2])?;
+            tx.write(root, &size)?[4] = 5;
+            assert_eq!(tx.read(root, &size)?[..6], [2, 3, 4, 4, 5, 1]);
+            Ok(())
+        })?;
+
+        let data = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert_eq!(data[..6], [2, 3, 4, 4, 5, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_object() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-large-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let size = ObjectSize::new(0, 5 * 4096 + 100);
+        let mut expected = vec![0; size.total()];
+        for (i, b) in expected.iter_mut().enumerate() {
+            *b = (i % 251) as u8;
+        }
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with(size, |data| {
+                    data.copy_from_slice(&expected);
+                    Ok(())
+                })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+
+            librarius.run(|tx| {
+                let (_, data) = tx.alloc(size)?;
+                data[4096 * 3..4096 * 4].copy_from_slice(&[7; 4096]);
+
+                let root = tx.root();
+                assert!(tx.read(root, &size)? == expected);
+                Ok(())
+            })?;
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        let librarius = LibrariusBuilder::new()
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .open()?;
+        let data = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert!(data == expected);
+
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.write(root, &size)?[4096 * 2..4096 * 3].copy_from_slice(&[9; 4096]);
+            Ok(())
+        })?;
+        expected[4096 * 2..4096 * 3].copy_from_slice(&[9; 4096]);
+        let data = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert!(data == expected);
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn counter() -> Result<()> {
+        let root_size = ObjectSize::new_with_usize(0, std::mem::size_of::<usize>());
+
+        let librarius = LibrariusBuilder::new()
+            .create_with(root_size, |data| {
+                let counter: &mut usize = unsafe_utils::any_from_slice_mut(data);
+                *counter = 0;
+
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let nthreads = 10;
+
+        let librarius = Arc::new(librarius);
+
+        let mut threads = Vec::new();
+
+        for i in 0..nthreads {
             let lr = librarius.clone();
             threads.push(std::thread::spawn(move || {
                 let result = lr.run(|tx| {
                     let root = tx.root();
 
-                    let rootp = tx.write(root, &root_size)?;
-                    let counter: &mut usize = unsafe_utils::any_from_slice_mut(rootp);
+                    let rootp = tx.write(root, &root_size)?;
+                    let counter: &mut usize = unsafe_utils::any_from_slice_mut(rootp);
+
+                    *counter += 1;
+
+                    Ok(*counter)
+                })?;
+                Ok(())
+            }));
+        }
+
+        for th in threads {
+            th.join().unwrap()?;
+        }
+
+        librarius.run(|tx| {
+            let root = tx.root();
+
+            let rootp = tx.read(root, &root_size)?;
+            let counter: &usize = unsafe_utils::any_from_slice(rootp);
+            assert_eq!(*counter, nthreads);
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_commits() -> Result<()> {
+        type Counters = [PersistentPointer<u64>; 4];
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| [PersistentPointer::<u64>::NONE; 4])
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Counters>();
+            let mut counters = Vec::new();
+            for _ in 0..4 {
+                counters.push(tx.alloc_typed(|| 0u64)?);
+            }
+            for (slot, counter) in tx.write_typed(root)?.iter_mut().zip(counters) {
+                *slot = counter;
+            }
+            Ok(())
+        })?;
+
+        let librarius = Arc::new(librarius);
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let lr = librarius.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    for n in 1..=50 {
+                        let (_, info) = lr.run_with_info(|tx| {
+                            let root = tx.root_typed::<Counters>();
+                            let counters = tx.read_typed(root)?.get();
+                            *tx.write_typed(&counters[i])? += 1;
+                            Ok(())
+                        })?;
+
+                        /* whatever runs after a commit sees it, older ones or not */
+                        let (snapshot, value) = lr.run_read(|tx| {
+                            let counters = tx.read_typed(tx.root_typed::<Counters>())?;
+                            Ok((tx.snapshot_version(), *tx.read_typed(&counters[i])?))
+                        })?;
+                        assert!(snapshot >= info.version.unwrap());
+                        assert_eq!(value, n);
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn validation() -> Result<()> {
+        type Counters = [PersistentPointer<u64>; 3];
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| [PersistentPointer::<u64>::NONE; 3])
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Counters>();
+            let mut counters = Vec::new();
+            for _ in 0..3 {
+                counters.push(tx.alloc_typed(|| 0u64)?);
+            }
+            for (slot, counter) in tx.write_typed(root)?.iter_mut().zip(counters) {
+                *slot = counter;
+            }
+            Ok(())
+        })?;
+        let bump = |i: usize| {
+            librarius.run(|tx| {
+                let root = tx.root_typed::<Counters>();
+                let counters = tx.read_typed(root)?.get();
+                *tx.write_typed(&counters[i])? += 1;
+                Ok(())
+            })
+        };
+        /* copies the first counter into the second, with a bump of one in between */
+        let copy = |between: Option<usize>| {
+            let between = std::cell::Cell::new(between);
+            librarius.run_with_info(|tx| {
+                let root = tx.root_typed::<Counters>();
+                let counters = tx.read_typed(root)?.get();
+                let value = *tx.read_typed(&counters[0])?.mark(tx)?;
+                if let Some(i) = between.take() {
+                    bump(i)?;
+                }
+                *tx.write_typed(&counters[1])? = value;
+                Ok(())
+            })
+        };
+
+        /* nothing committed meanwhile, so there's nothing to check */
+        let (_, info) = copy(None)?;
+        assert_eq!(info.stats.reads_validated, 0);
+
+        let (_, info) = copy(Some(2))?;
+        assert!(info.stats.reads_validated > 0);
+        assert_eq!(info.stats.conflicts, 0);
+
+        let (_, info) = copy(Some(0))?;
+        assert_eq!(info.stats.conflicts, 1);
+        let copied = librarius.run_read(|tx| {
+            let counters = tx.read_typed(tx.root_typed::<Counters>())?;
+            Ok(*tx.read_typed(&counters[1])?)
+        })?;
+        assert_eq!(copied, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_commit() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-group-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let root_size = ObjectSize::new_with_usize(0, size_of::<usize>());
+
+        let source = TracingSource::new(FileSource::new(path, 1 << 20)?, 1 << 12);
+        let trace = source.trace();
+        let librarius = LibrariusBuilder::new()
+            .create_with(root_size, |data| {
+                data.copy_from_slice(&0usize.to_ne_bytes());
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(source)
+            .group_commit(Duration::from_millis(50))
+            .open()?;
+        trace.clear();
+
+        let nthreads = 8;
+        let librarius = Arc::new(librarius);
+        let barrier = Arc::new(std::sync::Barrier::new(nthreads));
+        let threads: Vec<_> = (0..nthreads)
+            .map(|_| {
+                let lr = librarius.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    lr.run(|tx| {
+                        let root = tx.root();
+                        let counter: &mut usize =
+                            unsafe_utils::any_from_slice_mut(tx.write(root, &root_size)?);
+                        *counter += 1;
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for th in threads {
+            th.join().unwrap()?;
+        }
+
+        let flushes = trace
+            .events()
+            .iter()
+            .filter(|e| matches!(e.op, IoOp::Flush | IoOp::Sync))
+            .count();
+        assert!(flushes != 0 && flushes < nthreads);
+
+        let counter = librarius.run_read(|tx| {
+            let counter: &usize = unsafe_utils::any_from_slice(tx.read(tx.root(), &root_size)?);
+            Ok(*counter)
+        })?;
+        assert_eq!(counter, nthreads);
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn group_commit_failed() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-undurable-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let root_size = ObjectSize::new_with_usize(0, size_of::<usize>());
+
+        let source = FaultySource::new(FileSource::new(path, 1 << 20)?);
+        let faults = source.injector();
+        let librarius = LibrariusBuilder::new()
+            .create_with(root_size, |data| {
+                data.copy_from_slice(&0usize.to_ne_bytes());
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(source)
+            .group_commit(Duration::from_millis(1))
+            .open()?;
+
+        /* committed all the same, it just can't be told to be durable */
+        faults.power_loss_at(0);
+        let result = librarius.run(|tx| {
+            let root = tx.root();
+            let counter: &mut usize = unsafe_utils::any_from_slice_mut(tx.write(root, &root_size)?);
+            *counter += 1;
+            Ok(())
+        });
+        assert!(matches!(result, Err(Error::CommittedNotDurable { .. })));
+        let counter = librarius.run_read(|tx| {
+            let counter: &usize = unsafe_utils::any_from_slice(tx.read(tx.root(), &root_size)?);
+            Ok(*counter)
+        })?;
+        assert_eq!(counter, 1);
+        /* the source is as good as gone, closing says so too */
+        let closed = librarius.close(Duration::from_secs(1));
+        assert!(matches!(closed, Err(Error::PowerLoss {})));
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn close() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.close(std::time::Duration::from_secs(1))?;
+
+        let result = librarius.run(|tx| Ok(()));
+        assert!(crate::is_enum_variant!(result.unwrap_err(), Error::Closed {}));
+
+        Ok(())
+    }
+
+    use crate::typed::{
+        PArray, Persistent, PersistentPointer, PersistentSlice, Tagged, TypedLibrariusBuilder,
+        TypedReadTransaction, TypedTransaction,
+    };
+    use crate::vos::PointerToken;
+
+    struct Tuple {
+        value: bool,
+    }
+    impl Persistent for Tuple {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Tuple>())
+        }
+    }
+    impl Tuple {
+        fn new(value: bool) -> Self {
+            Tuple { value }
+        }
+    }
+
+    const NTUPLES: usize = 10;
+    struct Root {
+        arr: PArray<Tuple, NTUPLES>,
+    }
+
+    impl Root {
+        fn new() -> Self {
+            Root {
+                arr: PArray::default(),
+            }
+        }
+    }
+
+    impl Persistent for Root {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(size_of::<Root>(), 0)
+        }
+    }
+
+    #[test]
+    fn switcharoo() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let nthreads = 10;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.write_typed(root)?;
+
+            for n in 0..NTUPLES {
+                rootp.arr[n] = tx.alloc_typed(|| Tuple::new(n % 2 == 0))?;
+            }
+
+            Ok(())
+        })?;
+
+        let librarius = Arc::new(librarius);
+
+        let mut threads = Vec::new();
+
+        for i in 0..nthreads {
+            let lr = librarius.clone();
+            threads.push(std::thread::spawn(move || {
+                let result = lr.run(|tx| {
+                    let root = tx.root_typed::<Root>();
+
+                    let rootp = tx.read_typed(root)?.get();
+
+                    let desired_value = i % 2 == 0;
+
+                    for n in 0..NTUPLES {
+                        let t = tx.write_typed(&rootp.arr[n])?;
+                        if t.value != desired_value {
+                            t.value = desired_value;
+                        }
+                    }
+
+                    Ok(())
+                });
+            }));
+        }
+
+        for th in threads {
+            th.join().unwrap()
+        }
+
+        let true_count = librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.read_typed(root)?.get();
+
+            let mut true_count = 0;
+            for n in 0..NTUPLES {
+                let t = tx.read_typed(&rootp.arr[n])?;
+                true_count += t.value as usize;
+            }
+
+            Ok(true_count)
+        })?;
+
+        assert!(true_count == NTUPLES || true_count == 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn alloc_near() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.write_typed(root)?;
+            rootp.arr[0] = tx.alloc_typed(|| Tuple::new(true))?;
+
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.read_typed(root)?.get();
+            let near = tx.alloc_typed_near(&rootp.arr[0], || Tuple::new(false))?;
+            assert_eq!(
+                near.as_raw().address() / 4096,
+                rootp.arr[0].as_raw().address() / 4096
+            );
+
+            Ok(())
+        })?;
+
+        assert_eq!(librarius.locality().near_hits, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn free() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            tx.write_typed(root)?.arr[0] = tx.alloc_typed(|| Tuple::new(true))?;
+            Ok(())
+        })?;
+
+        let aborted = librarius.run_once(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.read_typed(root)?.get();
+            tx.free_typed(&rootp.arr[0])?;
+            Err::<(), _>(Error::TxAborted {
+                reason: ConflictReason::User,
+            })
+        });
+        assert!(matches!(aborted, Err(Error::TxAborted { .. })));
+
+        let freed = librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.read_typed(root)?.get();
+            assert!(tx.read_typed(&rootp.arr[0])?.value);
+            tx.free_typed(&rootp.arr[0])?;
+            let freed = tx.to_token_typed(&rootp.arr[0]);
+            tx.write_typed(root)?.arr[0] = PersistentPointer::new_none();
+            Ok(freed)
+        })?;
+
+        librarius.run(|tx| {
+            let stale = tx.resolve_token_typed::<Tuple>(&freed)?;
+            assert!(matches!(tx.read_typed(stale), Err(Error::ObjectFreed {})));
+            let reused = tx.alloc_typed(|| Tuple::new(false))?;
+            assert_eq!(reused.as_raw().address(), stale.as_raw().address());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn abort_reclaims() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let mut leaked = None;
+        let aborted = librarius.run_once(|tx| {
+            let tuple = tx.alloc_typed(|| Tuple::new(true))?;
+            leaked = Some(tuple.as_raw().address());
+            Err::<(), _>(Error::OutOfBounds {})
+        });
+        assert!(matches!(aborted, Err(Error::OutOfBounds {})));
+
+        /* a reader from before the abort could still be looking at it */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            tx.write_typed(root)?.arr[1] = PersistentPointer::new_none();
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let reused = tx.alloc_typed(|| Tuple::new(false))?;
+            assert_eq!(Some(reused.as_raw().address()), leaked);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+    #[test]
+    fn pointer_token() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let token = librarius.run(|tx| {
+            let root = tx.root_typed::<Root>();
+            let rootp = tx.write_typed(root)?;
+            rootp.arr[0] = tx.alloc_typed(|| Tuple::new(true))?;
+
+            Ok(tx.to_token_typed(&rootp.arr[0]).to_string())
+        })?;
+
+        let token: PointerToken = token.parse()?;
+        let value = librarius.run(|tx| {
+            let tuple = tx.resolve_token_typed::<Tuple>(&token)?;
+            Ok(tx.read_typed(tuple)?.value)
+        })?;
+        assert!(value);
+
+        let other = LibrariusBuilder::new()
+            .create_with_typed(|| Root::new())
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let stale = other.run(|tx| tx.resolve_token_typed::<Tuple>(&token).map(|_| ()));
+        assert!(matches!(stale, Err(Error::StaleToken {})));
+
+        let mut mangled = token.to_string();
+        mangled.replace_range(20..21, if &mangled[20..21] == "0" { "1" } else { "0" });
+        assert!(matches!(
+            mangled.parse::<PointerToken>(),
+            Err(Error::InvalidToken {})
+        ));
+
+        Ok(())
+    }
+    #[test]
+    fn commit_info() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let ((), first) = librarius.run_with_info(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value += 1;
+            Ok(())
+        })?;
+        let first = first.version.unwrap();
+
+        let (snapshot, read) = librarius.run_with_info(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.read_typed(root)?;
+            Ok(tx.snapshot_version())
+        })?;
+        assert_eq!(read.version, None);
+        assert_eq!(read.snapshot, snapshot);
+        assert!(snapshot >= first);
+
+        let ((), second) = librarius.run_with_info(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value += 1;
+            Ok(())
+        })?;
+        assert!(second.version.unwrap() > first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn hooks() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let hook = |name: &'static str| {
+            let events = events.clone();
+            move || events.lock().push(name)
+        };
+        librarius.run(|tx| {
+            tx.on_commit(hook("commit"));
+            tx.on_abort(hook("abort"));
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value = 1;
+            tx.on_commit(hook("commit again"));
+            assert!(events.lock().is_empty());
+            Ok(())
+        })?;
+        assert_eq!(*events.lock(), ["commit", "commit again"]);
+
+        events.lock().clear();
+        let result: Result<()> = librarius.run(|tx| {
+            tx.on_commit(hook("commit"));
+            tx.on_abort(hook("abort"));
+            Err(Error::OutOfBounds {})
+        });
+        assert!(matches!(result, Err(Error::OutOfBounds {})));
+        assert_eq!(*events.lock(), ["abort"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_mut() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let mut attempts = Vec::new();
+        let mut seen = Vec::new();
+        let value = librarius.run_mut(|tx, retries| {
+            attempts.push(retries);
+            seen.clear();
+            let root = tx.root_typed::<BasicRoot>();
+            seen.push(tx.read_typed(root)?.value);
+            tx.write_typed(root)?.value = 1;
+            seen.push(tx.read_typed(root)?.value);
+            if retries == 0 {
+                return Err(Error::TxAborted {
+                    reason: ConflictReason::User,
+                });
+            }
+            Ok(seen.len())
+        })?;
+        assert_eq!(value, 2);
+        assert_eq!(attempts, [0, 1]);
+        assert_eq!(seen, [0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn conflict_reason() -> Result<()> {
+        let size = ObjectSize::new(0, 16);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |data| {
+                data.copy_from_slice(&[1; 16]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let mut written = 0;
+        let result = librarius.run_once(|tx| {
+            let root = tx.root();
+            tx.read_for_write(root, &size)?;
+            librarius.run(|tx| {
+                let root = tx.root();
+                tx.set(root, 0, &[2; 4])
+            })?;
+            written = root.address();
+            tx.alloc(size)?;
+            Ok(())
+        });
+
+        let reason = match result {
+            Err(Error::TxAborted { reason }) => reason,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(reason, ConflictReason::ReadValidation { address: written });
+        assert_eq!(
+            reason.to_string(),
+            format!("read validation failed at {:#x}", written)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn panic_rolls_back() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            librarius.run::<(), _>(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value = 1;
+                panic!("in the middle of a transaction");
+            })
+        }));
+        assert!(panicked.is_err());
+
+        let value =
+            librarius.run_read(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+        assert_eq!(value, 0);
+        librarius.run(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value = 2;
+            Ok(())
+        })?;
+        let value =
+            librarius.run_read(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+        assert_eq!(value, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_abort() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let attempts = std::cell::Cell::new(0);
+        let result: Result<()> = librarius.run(|tx| {
+            attempts.set(attempts.get() + 1);
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value = 1;
+            tx.abort_manually()
+        });
+        assert!(matches!(result, Err(Error::UserAborted {})));
+        assert_eq!(attempts.get(), 1);
+
+        let value =
+            librarius.run_read(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+        assert_eq!(value, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn none_pointer() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(Root::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Root>();
+            let first = &tx.read_typed(rootp)?.get().arr[0];
+            assert!(first.is_none());
+            assert!(first.as_option().is_none());
+            assert!(matches!(tx.read_typed(first), Err(Error::NullPointer {})));
+            assert!(matches!(tx.write_typed(first), Err(Error::NullPointer {})));
+
+            let root = tx.write_typed(rootp)?;
+            root.arr[0] = tx.alloc_typed(|| Tuple::new(true))?;
+            let first = root.arr[0].as_option().expect("just allocated");
+            assert!(tx.read_typed(first)?.value);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeout() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let options = TxOptions {
+            timeout: Some(Duration::from_millis(20)),
+            ..TxOptions::default()
+        };
+        let result: Result<()> = librarius.run_with(&options, |tx| {
+            Err(Error::TxAborted {
+                reason: ConflictReason::User,
+            })
+        });
+        assert!(matches!(result, Err(Error::TxTimedOut {})));
+
+        let token = CancelToken::new();
+        let options = TxOptions {
+            cancel_token: Some(token.clone()),
+            ..TxOptions::default()
+        };
+        let result = librarius.run_with(&options, |tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value = 1;
+            token.cancel();
+            tx.read_typed(root)?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(Error::TxCancelled {})));
+
+        let value =
+            librarius.run_read(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+        assert_eq!(value, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pessimistic() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .pessimistic_locking()
+            .open()?;
+        let (nthreads, increments) = (8, 50);
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..nthreads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..increments).try_for_each(|_| {
+                            librarius.run(|tx| {
+                                let root = tx.root_typed::<BasicRoot>();
+                                tx.write_typed(root)?.value += 1;
+                                Ok(())
+                            })
+                        })
+                    })
+                })
+                .collect();
+            threads.into_iter().try_for_each(|th| th.join().unwrap())
+        })?;
+
+        let value = librarius.run_read(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            Ok(tx.read_typed(root)?.value)
+        })?;
+        assert_eq!(value, nthreads * increments);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stats() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let interfered = std::cell::Cell::new(false);
+        let ((), info) = librarius.run_with_info(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.read_typed(root)?;
+            if !interfered.replace(true) {
+                librarius.run(|tx| {
+                    let root = tx.root_typed::<BasicRoot>();
+                    tx.write_typed(root)?.value += 1;
+                    Ok(())
+                })?;
+            }
+            tx.write_typed(root)?.value += 1;
+            tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            Ok(())
+        })?;
+
+        assert_eq!(info.stats.attempts, 2);
+        assert_eq!(info.stats.conflicts, 1);
+        assert_eq!(info.stats.objects_read, 3);
+        assert_eq!(info.stats.objects_written, 1);
+        assert!(info.stats.bytes_allocated > 2 * size_of::<BasicRoot>());
+        assert!(info.stats.pages_touched >= 1);
+
+        let global = librarius.stats();
+        assert_eq!(global.attempts, 3);
+        assert_eq!(global.conflicts, 1);
+        assert_eq!(global.objects_written, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_read() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let increment = || {
+            librarius.run(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value += 1;
+                Ok(())
+            })
+        };
+
+        increment()?;
+        librarius.run_read(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            assert_eq!(tx.read_typed(root)?.value, 1);
+
+            /* a newer version doesn't change what the snapshot sees */
+            increment()?;
+            assert_eq!(tx.read_typed(root)?.value, 1);
+            Ok(())
+        })?;
+
+        let value = librarius.run_read(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            Ok(tx.read_typed(root)?.value)
+        })?;
+        assert_eq!(value, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_at() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .history(4)
+            .open()?;
+        let value = |tx: &ReadTransaction| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value);
+
+        let mut versions = Vec::new();
+        for _ in 0..10 {
+            let (_, info) = librarius.run_with_info(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value += 1;
+                Ok(())
+            })?;
+            versions.push(info.version.unwrap());
+        }
+        /* collects whatever is out of the history */
+        librarius.run(|_| Ok(()))?;
+
+        for (n, version) in versions.iter().enumerate().skip(6) {
+            assert_eq!(librarius.run_at(*version, value)?, n as u64 + 1);
+        }
+        assert!(matches!(
+            librarius.run_at(versions[0], value),
+            Err(Error::VersionUnavailable { .. })
+        ));
+        assert!(matches!(
+            librarius.run_at(versions[9] + 1, value),
+            Err(Error::VersionUnavailable { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksums() -> Result<()> {
+        let size = ObjectSize::new(0, 64);
+        for mode in [
+            ChecksumMode::Always,
+            ChecksumMode::OnFetch,
+            ChecksumMode::Never,
+        ] {
+            let librarius = LibrariusBuilder::new()
+                .create_with(size, |_| Ok(()))
+                .source(MemorySource::new(1 << 20)?)
+                .checksums(mode)
+                .open()?;
+            librarius.run(|tx| {
+                let root = tx.root();
+                tx.write(root, &size)?.fill(7);
+                Ok(())
+            })?;
+
+            let address = librarius.run_read(|tx| Ok(tx.root().address()))?;
+            let slice = ByteLogicalSlice(LogicalSlice::new(address + 10, 1));
+            librarius.las.write(&slice)?[0] ^= 1;
+
+            let read = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?[10]));
+            match mode {
+                ChecksumMode::Always => {
+                    assert!(matches!(read, Err(Error::ChecksumMismatch { .. })))
+                }
+                /* nothing was fetched */
+                _ => assert_eq!(read?, 6),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksums_on_fetch() -> Result<()> {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let path = std::env::temp_dir().join(format!("librarius-crc-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let size = ObjectSize::new(0, 64);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |_| Ok(()))
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .checksums(ChecksumMode::OnFetch)
+            .group_commit(Duration::from_millis(1))
+            .open()?;
+        /* durable once it returns, and only pointed to on the file from then on */
+        librarius.run(|tx| {
+            let root = tx.root();
+            tx.write(root, &size)?.fill(0x5e);
+            Ok(())
+        })?;
+
+        /* bit rot in the middle of the object */
+        let image = std::fs::read(path).map_err(|err| Error::FileIO { err })?;
+        let at = image.windows(64).position(|w| w == [0x5e; 64]).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|err| Error::FileIO { err })?;
+        file.seek(SeekFrom::Start((at + 10) as u64))
+            .and_then(|_| file.write_all(&[0x5f]))
+            .map_err(|err| Error::FileIO { err })?;
+
+        let read = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?[10]));
+        assert!(matches!(read, Err(Error::ChecksumMismatch { .. })));
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
 
-                    *counter += 1;
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compression() -> Result<()> {
+        use crate::source::Lz4Codec;
+
+        let path = std::env::temp_dir().join(format!("librarius-lz4-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let size = ObjectSize::new(0, 16384);
+        let librarius = LibrariusBuilder::new()
+            .create_with(size, |_| Ok(()))
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .compression(Lz4Codec, 1024)
+            .checksums(ChecksumMode::OnFetch)
+            .group_commit(Duration::from_millis(1))
+            .open()?;
+        librarius.run(|tx| {
+            let root = tx.root();
+            for (i, byte) in tx.write(root, &size)?.iter_mut().enumerate() {
+                *byte = (i % 64) as u8;
+            }
+            Ok(())
+        })?;
+
+        /* the start of it might be in the compressed copy as is, but no more than that */
+        let pattern: Vec<u8> = (0..256).map(|i| (i % 64) as u8).collect();
+        let image = std::fs::read(path).map_err(|err| Error::FileIO { err })?;
+        assert!(image.windows(256).all(|w| w != &pattern[..]));
+
+        let read = librarius.run_read(|tx| Ok(tx.read(tx.root(), &size)?.to_vec()))?;
+        assert!(read.chunks(256).all(|chunk| chunk == &pattern[..]));
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn deferred_maintenance() -> Result<()> {
+        type Table = [PersistentPointer<[u8; 1024]>; 16];
+
+        let path = std::env::temp_dir().join(format!("librarius-deferred-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| -> Table {
+                std::array::from_fn(|_| PersistentPointer::new_none())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .buffer_limit(4096)
+            .group_commit(Duration::from_millis(1))
+            .deferred_maintenance()
+            .open()?;
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            for i in 0..16 {
+                let row = tx.alloc_typed(|| [i as u8; 1024])?;
+                tx.write_typed(table)?[i] = row;
+            }
+            Ok(())
+        })?;
+        let rows = || {
+            librarius.run_read(|tx| {
+                let table = tx.read_typed(tx.root_typed::<Table>())?;
+                table
+                    .iter()
+                    .map(|row| Ok(tx.read_typed(row)?[0]))
+                    .collect::<Result<Vec<_>>>()
+            })
+        };
+
+        /* the transactions leave the copies be */
+        assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+        assert!(librarius.buffer_stats().resident > 4096);
+        assert_eq!(librarius.buffer_stats().evicted, 0);
+
+        let maintenance = Maintenance::new(1000);
+        std::thread::scope(|scope| {
+            scope.spawn(|| librarius.run_maintenance(&maintenance));
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while librarius.buffer_stats().resident > 4096 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            maintenance.stop();
+        });
+        assert!(librarius.buffer_stats().resident <= 4096);
+        assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+
+        /* a paused one does nothing until it's resumed */
+        let maintenance = Maintenance::new(1000);
+        maintenance.pause();
+        std::thread::scope(|scope| {
+            scope.spawn(|| librarius.run_maintenance(&maintenance));
+            assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(librarius.buffer_stats().resident > 4096);
+
+            maintenance.resume();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while librarius.buffer_stats().resident > 4096 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            maintenance.stop();
+            Ok::<_, Error>(())
+        })?;
+        assert!(librarius.buffer_stats().resident <= 4096);
+
+        /* changed copies stay until there's a budget to write them back in */
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            for row in tx.read_typed(table)?.get() {
+                tx.read_typed(row)?;
+                tx.set(row.as_raw(), 1, &[1])?;
+            }
+            Ok(())
+        })?;
+        assert!(librarius.buffer_stats().resident > 4096);
+        let written_back = librarius.buffer_stats().written_back;
+        for maintenance in [
+            Maintenance::new(1000).io_budget(0),
+            Maintenance::new(1000).cpu_budget(Duration::ZERO),
+        ] {
+            std::thread::scope(|scope| {
+                scope.spawn(|| librarius.run_maintenance(&maintenance));
+                std::thread::sleep(Duration::from_millis(20));
+                maintenance.stop();
+            });
+            assert!(librarius.buffer_stats().resident > 4096);
+            assert_eq!(librarius.buffer_stats().written_back, written_back);
+        }
+
+        let maintenance = Maintenance::new(1000)
+            .io_budget(1 << 20)
+            .cpu_budget(Duration::from_millis(100))
+            .priority(MaintenanceWork::Evict, 1);
+        std::thread::scope(|scope| {
+            scope.spawn(|| librarius.run_maintenance(&maintenance));
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while librarius.buffer_stats().resident > 4096 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            maintenance.stop();
+        });
+        assert!(librarius.buffer_stats().resident <= 4096);
+        assert!(librarius.buffer_stats().written_back > written_back);
+        assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+
+        drop(librarius);
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn buffer_limit() -> Result<()> {
+        type Table = [PersistentPointer<[u8; 1024]>; 16];
+
+        let path = std::env::temp_dir().join(format!("librarius-buffer-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| -> Table {
+                std::array::from_fn(|_| PersistentPointer::new_none())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .buffer_limit(4096)
+            .group_commit(Duration::from_millis(1))
+            .open()?;
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            for i in 0..16 {
+                let row = tx.alloc_typed(|| [i as u8; 1024])?;
+                tx.write_typed(table)?[i] = row;
+            }
+            Ok(())
+        })?;
+        let rows = || {
+            librarius.run_read(|tx| {
+                let table = tx.read_typed(tx.root_typed::<Table>())?;
+                table
+                    .iter()
+                    .map(|row| Ok(tx.read_typed(row)?[0]))
+                    .collect::<Result<Vec<_>>>()
+            })
+        };
+
+        assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+        let stats = librarius.buffer_stats();
+        assert!(stats.resident <= 4096);
+        assert!(stats.evicted >= 12);
+
+        /* once the old table is reclaimed, the copies it pointed to aren't tracked through it */
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            tx.write_typed(table)?;
+            Ok(())
+        })?;
+        librarius.run(|_| Ok(()))?;
+        let replaced = librarius.buffer_stats();
+        assert!(replaced.resident < 1024);
+        assert_eq!(replaced.evicted, stats.evicted);
+        assert_eq!(rows()?, (0..16).collect::<Vec<u8>>());
+
+        /* folded once the transaction is done, before its group is written back */
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            let row = &tx.read_typed(table)?.get()[15];
+            tx.read_typed(row)?;
+            tx.set(row.as_raw(), 0, &[0xff])
+        })?;
+        let mut expected: Vec<u8> = (0..16).collect();
+        expected[15] = 0xff;
+        assert_eq!(rows()?, expected);
+        let stats = librarius.buffer_stats();
+        assert!(stats.resident <= 4096);
+        /* a redo record folded into a resident copy is written back with it */
+        let evicted = stats.evicted;
+        while librarius.buffer_stats().evicted < evicted + 16 {
+            assert_eq!(rows()?, expected);
+        }
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn evicted_while_read() -> Result<()> {
+        type Table = [PersistentPointer<[u8; 1024]>; 16];
+
+        let path = std::env::temp_dir().join(format!("librarius-evicted-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| -> Table {
+                std::array::from_fn(|_| PersistentPointer::new_none())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .buffer_limit(4096)
+            .group_commit(Duration::from_millis(1))
+            .open()?;
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            for i in 0..16 {
+                let row = tx.alloc_typed(|| [i as u8; 1024])?;
+                tx.write_typed(table)?[i] = row;
+            }
+            Ok(())
+        })?;
+        let rows = || {
+            librarius.run(|tx| {
+                let table = tx.root_typed::<Table>();
+                for row in tx.read_typed(table)?.get() {
+                    tx.read_typed(row)?;
+                }
+                let row = &tx.read_typed(table)?.get()[15];
+                tx.read_typed(row)?;
+                tx.set(row.as_raw(), 1, &[1])
+            })
+        };
+        rows()?;
+
+        /* a commit in the middle evicts what this one read, which is still checked */
+        let attempts = std::cell::Cell::new(0);
+        librarius.run(|tx| {
+            attempts.set(attempts.get() + 1);
+            let table = tx.root_typed::<Table>();
+            let row = &tx.read_typed(table)?.get()[0];
+            assert_eq!(tx.read_typed(row)?.mark(tx)?[0], 0);
+            if attempts.get() == 1 {
+                std::thread::scope(|scope| scope.spawn(rows).join().unwrap())?;
+                assert!(!row.as_raw().is_byte_addressable());
+            }
+            let row = &tx.read_typed(table)?.get()[14];
+            tx.read_typed(row)?;
+            tx.set(row.as_raw(), 2, &[2])
+        })?;
+        assert_eq!(attempts.get(), 1);
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn key_rotation() -> Result<()> {
+        type Table = [PersistentPointer<[u8; 1024]>; 16];
+
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| -> Table {
+                std::array::from_fn(|_| PersistentPointer::new_none())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(EncryptedSource::new(
+                ReplaySource::new(vec![0; 1 << 20], &[]),
+                XorCipher(vec![0x5a]),
+                4096,
+            )?)
+            .open()?;
+        librarius.run(|tx| {
+            let table = tx.root_typed::<Table>();
+            for i in 0..16 {
+                let row = tx.alloc_typed(|| [i as u8; 1024])?;
+                tx.write_typed(table)?[i] = row;
+            }
+            Ok(())
+        })?;
+        let unrotated = || librarius.usage()[1].unrotated;
+        assert_eq!(unrotated(), 0);
+        librarius.rotate_key(&[0xa5])?;
+        assert!(unrotated() > 0);
+
+        let maintenance = Maintenance::new(1000).io_budget(1 << 20);
+        std::thread::scope(|scope| {
+            scope.spawn(|| librarius.run_maintenance(&maintenance));
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while unrotated() > 0 && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            maintenance.stop();
+        });
+        assert_eq!(unrotated(), 0);
+        let rows = librarius.run_read(|tx| {
+            let table = tx.read_typed(tx.root_typed::<Table>())?;
+            table
+                .iter()
+                .map(|row| Ok(tx.read_typed(row)?[0]))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        assert_eq!(rows, (0..16).collect::<Vec<u8>>());
+
+        let memory = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 1 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        assert!(matches!(
+            memory.rotate_key(&[0xa5]),
+            Err(Error::CannotRotateKey {})
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 1 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let value = |tx: &ReadTransaction| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value);
+
+        let snapshot = librarius.snapshot()?;
+        for n in 2..5 {
+            librarius.run(|tx| {
+                let root = tx.root();
+                tx.set(root, 0, &(n as u64).to_ne_bytes())
+            })?;
+        }
+        librarius.run(|tx| Ok(()))?;
+
+        assert_eq!(snapshot.run(value)?, 1);
+        assert_eq!(librarius.run_read(value)?, 4);
+        assert!(librarius.run_read(|tx| Ok(tx.snapshot_version()))? > snapshot.version());
+
+        let result = librarius.close(Duration::from_millis(10));
+        assert!(matches!(result, Err(Error::ShutdownTimedOut {})));
+        assert_eq!(snapshot.run(value)?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reopen_versions() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-versions-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let open = || {
+            LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 0 })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()
+        };
+        let increment = |librarius: &Librarius| {
+            librarius.run_with_info(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value += 1;
+                Ok(())
+            })
+        };
+
+        let mut last = 0;
+        for round in 1..4 {
+            let librarius = open()?;
+            let value =
+                librarius.run_read(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+            assert_eq!(value, (round - 1) * 5);
+
+            for _ in 0..5 {
+                let (_, info) = increment(&librarius)?;
+                let version = info.version.unwrap();
+                /* nothing committed before the reopen looks newer than this */
+                assert!(version > last);
+                last = version;
+            }
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn read_only() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-ro-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 7 })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+            librarius.close(Duration::from_secs(1))?;
+        }
+        let image = std::fs::read(path).map_err(|err| Error::FileIO { err })?;
+
+        {
+            let librarius = LibrariusBuilder::new()
+                .read_only()
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+
+            let value = librarius.run(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                Ok(tx.read_typed(root)?.value)
+            })?;
+            assert_eq!(value, 7);
+
+            let result = librarius.run(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value = 8;
+                Ok(())
+            });
+            assert!(matches!(result, Err(Error::ReadOnly {})));
+            let result = librarius.run(|tx| tx.alloc_typed(|| BasicRoot { value: 9 }));
+            assert!(matches!(result, Err(Error::ReadOnly {})));
+
+            librarius.close(Duration::from_secs(1))?;
+        }
+        let untouched = std::fs::read(path).map_err(|err| Error::FileIO { err })?;
+        assert!(image == untouched);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn run_async() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-async-tx-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 7 })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        let librarius = LibrariusBuilder::new()
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .open()?;
+        let value = block_on(librarius.run_async(|tx| {
+            Box::pin(async move {
+                let root = tx.root();
+                let data = tx.read_async(root, &BasicRoot::size()).await?;
+                Ok(unsafe_utils::any_from_slice::<BasicRoot>(data).value)
+            })
+        }))?;
+        assert_eq!(value, 7);
+        drop(librarius);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn drop_async() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 7 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        let leaked = Arc::new(Mutex::new(None::<LogicalAddress>));
+        {
+            let mut pending = Box::pin(librarius.run_async(|tx| {
+                let leaked = leaked.clone();
+                Box::pin(async move {
+                    let object = tx.alloc_typed(|| BasicRoot { value: 1 })?;
+                    *leaked.lock() = Some(object.as_raw().address());
+                    let root = tx.root_typed::<BasicRoot>();
+                    tx.write_typed(root)?.value = 1;
+                    std::future::pending::<()>().await;
+                    Ok(())
+                })
+            }));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(pending.as_mut().poll(&mut cx).is_pending());
+        }
+        assert!(leaked.lock().is_some());
+
+        let value = librarius.run(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            Ok(tx.read_typed(root)?.value)
+        })?;
+        assert_eq!(value, 7);
+
+        /* a reader from before the drop could still be looking at it */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            tx.write_typed(root)?.value = 8;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let reused = tx.alloc_typed(|| BasicRoot { value: 2 })?;
+            assert_eq!(Some(reused.as_raw().address()), *leaked.lock());
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_source() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(4 * 4096)?)
+            .open()?;
+
+        let fill = |librarius: &Librarius| loop {
+            match librarius.run(|tx| tx.alloc_typed(|| BasicRoot { value: 1 })) {
+                Ok(_) => {}
+                Err(err) => return err,
+            }
+        };
+        assert!(matches!(fill(&librarius), Error::NoAvailableMemory {}));
+
+        librarius.add_source(MemorySource::new(1 << 20)?)?;
+        assert_eq!(librarius.usage().len(), 2);
+        librarius.run(|tx| tx.alloc_typed(|| BasicRoot { value: 1 }))?;
+
+        Ok(())
+    }
+
+    struct WideRoot {
+        value: u64,
+        doubled: u64,
+    }
+    impl Persistent for WideRoot {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<WideRoot>())
+        }
+    }
+
+    #[test]
+    fn migrate() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-migrate-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 21 })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        let mismatch = LibrariusBuilder::new()
+            .create_with_typed(|| WideRoot {
+                value: 0,
+                doubled: 0,
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open();
+        assert!(matches!(mismatch, Err(Error::LayoutMismatch { .. })));
+        drop(mismatch);
+
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| WideRoot {
+                value: 0,
+                doubled: 0,
+            })
+            .migrate::<BasicRoot, WideRoot, _>(|_, old| {
+                Ok(WideRoot {
+                    value: old.value,
+                    doubled: old.value * 2,
+                })
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open()?;
+        let (value, doubled) = librarius.run(|tx| {
+            let root = tx.root_typed::<WideRoot>();
+            let root = tx.read_typed(root)?.get();
+            Ok((root.value, root.doubled))
+        })?;
+        assert_eq!((value, doubled), (21, 42));
+        assert_eq!(librarius.root_fingerprint()?, WideRoot::fingerprint());
+        librarius.close(Duration::from_secs(1))?;
+        drop(librarius);
+
+        let librarius = LibrariusBuilder::new()
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open()?;
+        assert_eq!(librarius.root_fingerprint()?, WideRoot::fingerprint());
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn register_type() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-types-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 21 })
+                .register_type::<BasicRoot>("entry")
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 1 << 20)?)
+                .open()?;
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        let mismatch = LibrariusBuilder::new()
+            .register_type::<WideRoot>("entry")
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open();
+        assert!(matches!(
+            mismatch,
+            Err(Error::TypeLayoutMismatch { ref name, .. }) if name == "entry"
+        ));
+        drop(mismatch);
+
+        /* a new type is added next to the ones already in the catalog */
+        let librarius = LibrariusBuilder::new()
+            .register_type::<BasicRoot>("entry")
+            .register_type::<WideRoot>("wide")
+            .register_type::<WideRoot>("entry.wide")
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open()?;
+        librarius.close(Duration::from_secs(1))?;
+        drop(librarius);
+
+        /* names are told apart by all of their bytes */
+        let librarius = LibrariusBuilder::new()
+            .register_type::<BasicRoot>("entry")
+            .register_type::<WideRoot>("entry.wide")
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open()?;
+        librarius.close(Duration::from_secs(1))?;
+        drop(librarius);
+
+        let mismatch = LibrariusBuilder::new()
+            .register_type::<BasicRoot>("wide")
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open();
+        assert!(matches!(mismatch, Err(Error::TypeLayoutMismatch { .. })));
+        drop(mismatch);
+
+        let mut entry = catalog_entry("entry", 1);
+        entry.pop();
+        assert!(matches!(
+            catalog_entries(&entry),
+            Err(Error::CorruptedCatalog {})
+        ));
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn grow() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-grow-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with_typed(|| BasicRoot { value: 3 })
+                .source(MemorySource::new(1 << 20)?)
+                .source(FileSource::new(path, 4 * 4096)?)
+                .open()?;
+            assert!(matches!(
+                librarius.grow(0, 2 << 20),
+                Err(Error::CannotResize {})
+            ));
+
+            librarius.grow(1, 1 << 20)?;
+            assert_eq!(librarius.usage()[1].total, 1 << 20);
+            librarius.close(Duration::from_secs(1))?;
+        }
+
+        let librarius = LibrariusBuilder::new()
+            .source(MemorySource::new(1 << 20)?)
+            .open_file(path)
+            .open()?;
+        assert_eq!(librarius.usage()[1].total, 1 << 20);
+        let value = librarius.run(|tx| {
+            let root = tx.root_typed::<BasicRoot>();
+            Ok(tx.read_typed(root)?.value)
+        })?;
+        assert_eq!(value, 3);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[repr(u8)]
+    enum Shape {
+        Empty,
+        Circle(u32),
+        Rect { width: u32, height: u32 },
+    }
+    impl Persistent for Shape {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Shape>())
+        }
+    }
+    unsafe impl Tagged for Shape {
+        const VARIANTS: u8 = 3;
+    }
+
+    #[test]
+    fn tagged() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Shape::Empty)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Shape>();
+            assert!(matches!(*tx.read_tagged(rootp)?, Shape::Empty));
+            *tx.write_tagged(rootp)? = Shape::Circle(3);
+            Ok(())
+        })?;
 
-                    Ok(*counter)
-                })?;
-                Ok(())
-            }));
-        }
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Shape>();
+            match tx.write_tagged(rootp)? {
+                Shape::Circle(radius) => *radius *= 2,
+                _ => panic!("not a circle"),
+            }
+            Ok(())
+        })?;
 
-        for th in threads {
-            th.join().unwrap()?;
-        }
+        let area = librarius.run_read(|tx| {
+            Ok(match tx.read_tagged(tx.root_typed::<Shape>())? {
+                Shape::Empty => 0,
+                Shape::Circle(radius) => radius * radius * 3,
+                Shape::Rect { width, height } => width * height,
+            })
+        })?;
+        assert_eq!(area, 108);
 
+        /* a tag that isn't one of the variants never becomes a Shape */
         librarius.run(|tx| {
             let root = tx.root();
+            tx.write(root, &Shape::size())?[0] = 7;
+            Ok(())
+        })?;
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Shape>();
+            assert!(matches!(
+                tx.read_tagged(rootp),
+                Err(Error::InvalidTag { tag: 7 })
+            ));
+            Ok(())
+        })?;
 
-            let rootp = tx.read(root, &root_size)?;
-            let counter: &usize = unsafe_utils::any_from_slice(rootp);
-            assert_eq!(*counter, nthreads);
+        Ok(())
+    }
+
+    #[test]
+    fn named_roots() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 1 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            assert!(tx.root_named::<BasicRoot>("users")?.is_none());
+            tx.create_root_named("users", || BasicRoot { value: 2 })?;
+            let groups = tx.create_root_named("groups", || BasicRoot { value: 10 })?;
+            tx.write_typed(groups)?.value = 11;
+            assert!(matches!(
+                tx.create_root_named("users", || BasicRoot { value: 0 }),
+                Err(Error::NamedRootExists { .. })
+            ));
+            assert!(matches!(
+                tx.root_named::<BasicRoot>(&"x".repeat(33)),
+                Err(Error::InvalidRootName { .. })
+            ));
+            Ok(())
+        })?;
 
+        librarius.run(|tx| {
+            let users = tx.root_named::<BasicRoot>("users")?.expect("was created");
+            tx.write_typed(users)?.value += 1;
             Ok(())
         })?;
 
+        let values = librarius.run_read(|tx| {
+            let users = tx.root_named::<BasicRoot>("users")?.expect("was created");
+            let groups = tx.root_named::<BasicRoot>("groups")?.expect("was created");
+            assert!(tx.root_named::<BasicRoot>("other")?.is_none());
+            Ok((
+                tx.read_typed(tx.root_typed::<BasicRoot>())?.value,
+                tx.read_typed(users)?.value,
+                tx.read_typed(groups)?.value,
+            ))
+        })?;
+        assert_eq!(values, (1, 3, 11));
+
         Ok(())
     }
 
-    use crate::typed::{Persistent, PersistentPointer, TypedLibrariusBuilder, TypedTransaction};
+    #[test]
+    fn root_policy() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 1 })
+            .source(MemorySource::new(1 << 20)?)
+            .register_type::<BasicRoot>("basic")
+            .root_policy(|name, access| {
+                /* the type catalog isn't the tenants' business */
+                assert!(!name.starts_with("librarius."));
+                match access {
+                    RootAccess::Read => name != "secret",
+                    RootAccess::Write => name.starts_with("tenant-"),
+                    RootAccess::Create => name.starts_with("tenant-") || name == "catalog",
+                }
+            })
+            .open()?;
 
-    struct Tuple {
-        value: bool,
+        librarius.run(|tx| {
+            tx.create_root_named("tenant-a", || BasicRoot { value: 2 })?;
+            tx.create_root_named("catalog", || BasicRoot { value: 4 })?;
+            assert!(matches!(
+                tx.create_root_named("shared", || BasicRoot { value: 3 }),
+                Err(Error::RootAccessDenied {
+                    access: RootAccess::Create,
+                    ..
+                })
+            ));
+            let a = tx
+                .root_named::<BasicRoot>("tenant-a")?
+                .expect("was created");
+            tx.write_typed(a)?.value += 1;
+            Ok(())
+        })?;
+
+        librarius.run_read(|tx| {
+            let a = tx
+                .root_named::<BasicRoot>("tenant-a")?
+                .expect("was created");
+            assert_eq!(tx.read_typed(a)?.value, 3);
+            assert!(tx.root_named::<BasicRoot>("shared")?.is_none());
+            assert!(matches!(
+                tx.root_named::<BasicRoot>("librarius.types"),
+                Err(Error::ReservedRootName { .. })
+            ));
+            assert!(matches!(
+                tx.root_named::<BasicRoot>("secret"),
+                Err(Error::RootAccessDenied {
+                    access: RootAccess::Read,
+                    ..
+                })
+            ));
+            Ok(())
+        })?;
+
+        /* a root that can be read but not written, in a transaction that could */
+        librarius.run(|tx| {
+            let catalog = tx.root_named::<BasicRoot>("catalog")?.expect("was created");
+            assert_eq!(tx.read_typed(catalog)?.value, 4);
+            assert!(matches!(
+                tx.write_typed(catalog),
+                Err(Error::RootAccessDenied {
+                    access: RootAccess::Write,
+                    ..
+                })
+            ));
+            assert!(matches!(
+                tx.create_root_named("librarius.mine", || BasicRoot { value: 5 }),
+                Err(Error::ReservedRootName { .. })
+            ));
+            Ok(())
+        })?;
+
+        Ok(())
     }
-    impl Persistent for Tuple {
+
+    struct Slices {
+        values: PersistentSlice<BasicRoot>,
+        roots: PersistentSlice<UntypedPointer>,
+        updates: u64,
+    }
+    impl Persistent for Slices {
         fn size() -> ObjectSize {
-            ObjectSize::new_with_usize(0, size_of::<Tuple>())
+            ObjectSize::new_with_usize(2 * size_of::<UntypedPointer>(), size_of::<u64>())
         }
     }
-    impl Tuple {
-        fn new(value: bool) -> Self {
-            Tuple { value }
+    impl Slices {
+        fn new() -> Self {
+            Slices {
+                values: PersistentSlice::new_none(),
+                roots: PersistentSlice::new_none(),
+                updates: 0,
+            }
         }
     }
 
-    const NTUPLES: usize = 10;
-    struct Root {
-        arr: [PersistentPointer<Tuple>; NTUPLES],
+    struct Empty;
+    impl Persistent for Empty {
+        fn size() -> ObjectSize {
+            ObjectSize::new(0, 0)
+        }
     }
 
-    impl Root {
-        fn new() -> Self {
-            Root {
-                arr: [
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                    PersistentPointer::new_none(),
-                ],
+    #[test]
+    fn slices() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(Slices::new)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Slices>();
+            let root = tx.read_typed(rootp)?.get();
+            assert!(matches!(
+                tx.read_slice(&root.values),
+                Err(Error::NullPointer {})
+            ));
+            assert!(matches!(
+                tx.alloc_slice(2, |_| Slices::new()).map(|_| ()),
+                Err(Error::InvalidSliceElement {})
+            ));
+            assert!(matches!(
+                tx.alloc_slice(2, |_| Empty).map(|_| ()),
+                Err(Error::InvalidSliceElement {})
+            ));
+            let (pointer, _) = tx.alloc(ObjectSize::new(0, 8))?;
+            let empty = Box::leak(Box::new(PersistentSlice::<Empty>::from_raw(pointer)));
+            assert!(matches!(
+                tx.read_slice(empty),
+                Err(Error::InvalidSliceElement {})
+            ));
+            /* too big to fit the size of an object, rather than cut down to it */
+            let len = u32::MAX as usize / size_of::<BasicRoot>() + 1;
+            assert!(matches!(
+                tx.alloc_slice(len, |i| BasicRoot { value: i as u64 })
+                    .map(|_| ()),
+                Err(Error::AllocationTooLarge {})
+            ));
+            assert!(matches!(
+                tx.alloc_slice(usize::MAX, |i| BasicRoot { value: i as u64 })
+                    .map(|_| ()),
+                Err(Error::AllocationTooLarge {})
+            ));
+
+            let values = tx.alloc_slice(5, |i| BasicRoot { value: i as u64 })?;
+            let roots = tx.alloc_slice(3, |_| UntypedPointer::new_none())?;
+            let root = tx.write_typed(rootp)?;
+            root.values = values;
+            root.roots = roots;
+            Ok(())
+        })?;
+
+        librarius.run(|tx| {
+            let rootp = tx.root_typed::<Slices>();
+            let root = tx.read_typed(rootp)?.get();
+            for value in tx.write_slice(&root.values)? {
+                value.value *= 2;
             }
-        }
+            let (pointer, _) = tx.alloc(ObjectSize::new(0, 8))?;
+            tx.write_slice(&root.roots)?[1] = pointer;
+            tx.write_typed(rootp)?.updates += 1;
+            Ok(())
+        })?;
+
+        let (values, roots) = librarius.run_read(|tx| {
+            let root = tx.read_typed(tx.root_typed::<Slices>())?;
+            let values: Vec<_> = tx
+                .read_slice(&root.values)?
+                .iter()
+                .map(|v| v.value)
+                .collect();
+            let roots: Vec<_> = tx
+                .read_slice(&root.roots)?
+                .iter()
+                .map(|p| p.is_some())
+                .collect();
+            Ok((values, roots))
+        })?;
+        assert_eq!(values, [0, 2, 4, 6, 8]);
+        assert_eq!(roots, [false, true, false]);
+
+        Ok(())
     }
 
-    impl Persistent for Root {
+    #[repr(C)]
+    struct Pair {
+        left: PersistentPointer<BasicRoot>,
+        right: PersistentPointer<BasicRoot>,
+    }
+    impl Persistent for Pair {
         fn size() -> ObjectSize {
-            ObjectSize::new_with_usize(size_of::<Root>(), 0)
+            ObjectSize::new_with_usize(size_of::<Pair>(), 0)
         }
     }
 
     #[test]
-    fn switcharoo() -> Result<()> {
+    fn read_guards() -> Result<()> {
         let librarius = LibrariusBuilder::new()
-            .create_with_typed(|| Root::new())
+            .create_with_typed(|| Pair {
+                left: PersistentPointer::new_none(),
+                right: PersistentPointer::new_none(),
+            })
             .source(MemorySource::new(1 << 20)?)
             .open()?;
-        let nthreads = 10;
 
         librarius.run(|tx| {
-            let root = tx.root_typed::<Root>();
-            let rootp = tx.write_typed(root)?;
+            let root = tx.root_typed::<Pair>();
+            let pair = tx.write_typed(root)?;
+            pair.left = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            pair.right = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            Ok(())
+        })?;
 
-            for n in 0..NTUPLES {
-                rootp.arr[n] = tx.alloc_typed(|| Tuple::new(n % 2 == 0))?;
-            }
+        /* each side is set only if the other one isn't */
+        let skew = |mark: bool| {
+            librarius.run_once(|tx| {
+                let root = tx.root_typed::<Pair>();
+                let pair = tx.read_typed(root)?.get();
+                let left = tx.read_typed(&pair.left)?;
+                let left = match mark {
+                    true => left.mark(tx)?,
+                    false => left.get(),
+                };
+                librarius.run(|tx| {
+                    let root = tx.root_typed::<Pair>();
+                    let pair = tx.read_typed(root)?.get();
+                    let right = tx.read_typed(&pair.right)?;
+                    assert_eq!(right.mark(tx)?.value, 0);
+                    tx.write_typed(&pair.left)?.value = 1;
+                    Ok(())
+                })?;
+                if left.value == 0 {
+                    tx.write_typed(&pair.right)?.value = 1;
+                }
+                Ok(())
+            })
+        };
+
+        let reason = match skew(true) {
+            Err(Error::TxAborted { reason }) => reason,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert!(matches!(reason, ConflictReason::ReadValidation { .. }));
 
+        /* unmarked, both end up set */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let pair = tx.read_typed(root)?.get();
+            tx.write_typed(&pair.left)?.value = 0;
             Ok(())
         })?;
+        skew(false)?;
 
-        let librarius = Arc::new(librarius);
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let pair = tx.read_typed(root)?.get();
+            let right = tx.read_typed(&pair.right)?;
+            assert_eq!(right.value, 1);
+            right.upgrade(tx)?.value = 0;
+            Ok(())
+        })?;
+        let values = librarius.run_read(|tx| {
+            let pair = tx.read_typed(tx.root_typed::<Pair>())?;
+            Ok([
+                tx.read_typed(&pair.left)?.value,
+                tx.read_typed(&pair.right)?.value,
+            ])
+        })?;
+        assert_eq!(values, [1, 0]);
 
-        let mut threads = Vec::new();
+        Ok(())
+    }
 
-        for i in 0..nthreads {
-            let lr = librarius.clone();
-            threads.push(std::thread::spawn(move || {
-                let result = lr.run(|tx| {
-                    let root = tx.root_typed::<Root>();
+    #[repr(C, align(16))]
+    struct Wide {
+        value: u128,
+    }
+    impl Persistent for Wide {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(0, size_of::<Wide>())
+        }
+    }
 
-                    let rootp = tx.read_typed(root)?;
+    #[test]
+    fn alignment() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
 
-                    let desired_value = i % 2 == 0;
+        /* odd sizes don't push the objects after them out of alignment */
+        let pointers = librarius.run(|tx| {
+            let mut pointers = Vec::new();
+            for i in 0..3 {
+                tx.alloc_typed(|| Tuple { value: i % 2 == 0 })?;
+                pointers.push(tx.alloc_typed(|| BasicRoot { value: i })?);
+            }
+            Ok(pointers)
+        })?;
+        librarius.run_read(|tx| {
+            for (i, pointer) in pointers.iter().enumerate() {
+                let value = tx.read_typed(pointer)?;
+                assert_eq!(value.value, i as u64);
+                let address: *const BasicRoot = value;
+                assert_eq!(address.align_offset(align_of::<BasicRoot>()), 0);
+            }
+            Ok(())
+        })?;
 
-                    for n in 0..NTUPLES {
-                        let t = tx.write_typed(&rootp.arr[n])?;
-                        if t.value != desired_value {
-                            t.value = desired_value;
-                        }
-                    }
+        assert!(matches!(
+            librarius.run(|tx| {
+                tx.alloc_typed(|| Wide { value: 0 })?;
+                Ok(())
+            }),
+            Err(Error::UnsupportedAlignment { align: 16, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn usage_by_type() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Pair {
+                left: PersistentPointer::new_none(),
+                right: PersistentPointer::new_none(),
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let left = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            let right = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            let pair = tx.write_typed(root)?;
+            pair.left = left;
+            pair.right = right;
+            /* unreachable, so it's not counted */
+            tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            Ok(())
+        })?;
 
+        let object = size_of::<ObjectHeader>() + size_of::<BasicRoot>();
+        let usage = librarius.usage_by_type()?;
+        let basic = usage[&BasicRoot::fingerprint()];
+        assert_eq!(basic.objects, 2);
+        assert_eq!(basic.live_bytes, 2 * object);
+        assert_eq!(basic.dead_bytes, 0);
+        assert_eq!(usage[&Pair::fingerprint()].objects, 1);
+
+        /* the snapshot keeps the old version around */
+        let snapshot = librarius.snapshot()?;
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let pair = tx.read_typed(root)?.get();
+            tx.write_typed(&pair.left)?.value = 1;
+            Ok(())
+        })?;
+        let basic = librarius.usage_by_type()?[&BasicRoot::fingerprint()];
+        assert_eq!(basic.objects, 2);
+        assert_eq!(basic.dead_bytes, object);
+
+        drop(snapshot);
+        librarius.run(|_| Ok(()))?;
+        assert_eq!(
+            librarius.usage_by_type()?[&BasicRoot::fingerprint()].dead_bytes,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn scan() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Pair {
+                left: PersistentPointer::new_none(),
+                right: PersistentPointer::new_none(),
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let left = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            tx.write_typed(root)?.left = left;
+            tx.create_root_named("users", || BasicRoot { value: 2 })?;
+            Ok(())
+        })?;
+
+        let kinds = |librarius: &Librarius| {
+            librarius.run_read(|tx| {
+                let mut kinds = Vec::new();
+                tx.scan(|pointer| {
+                    kinds.push(tx.object_kind(pointer)?);
                     Ok(())
-                });
-            }));
+                })?;
+                Ok(kinds)
+            })
+        };
+        let kinds = kinds(&librarius)?;
+        let basic = BasicRoot::fingerprint();
+        assert_eq!(kinds.iter().filter(|kind| **kind == basic).count(), 2);
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|kind| **kind == Pair::fingerprint())
+                .count(),
+            1
+        );
+
+        let pointers = librarius.run_read(|tx| Ok(tx.pointers(tx.root())?.len()))?;
+        assert_eq!(pointers, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn type_tags() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| Pair {
+                left: PersistentPointer::new_none(),
+                right: PersistentPointer::new_none(),
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let left = tx.alloc_typed(|| BasicRoot { value: 0 })?;
+            let (right, _) = tx.alloc(BasicRoot::size())?;
+            let pair = tx.write_typed(root)?;
+            pair.left = left;
+            pair.right = PersistentPointer::from_raw(right);
+            Ok(())
+        })?;
+
+        /* a copy is still a BasicRoot, an untyped object can be anything */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Pair>();
+            let pair = tx.read_typed(root)?.get();
+            tx.write_typed(&pair.left)?.value = 1;
+            tx.write_typed(PersistentPointer::<Tuple>::from_raw_ref(
+                pair.right.as_raw(),
+            ))?
+            .value = true;
+            Ok(())
+        })?;
+        librarius.run_read(|tx| {
+            let pair = tx.read_typed(tx.root_typed::<Pair>())?;
+            assert_eq!(
+                tx.object_kind(pair.left.as_raw())?,
+                BasicRoot::fingerprint()
+            );
+            assert!(matches!(
+                tx.read_typed(PersistentPointer::<Tuple>::from_raw_ref(pair.left.as_raw())),
+                Err(Error::TypeMismatch { .. })
+            ));
+            assert!(matches!(
+                tx.read_typed(tx.root_typed::<BasicRoot>()),
+                Err(Error::TypeMismatch { .. })
+            ));
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn primitives() -> Result<()> {
+        type Counted = (PersistentPointer<[u16; 3]>, u64, u8);
+        assert_eq!(Counted::size().pointers, 8);
+
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| (PersistentPointer::<Counted>::new_none(), 7u32))
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+
+        librarius.run(|tx| {
+            let root = tx.root_typed::<(PersistentPointer<Counted>, u32)>();
+            let values = tx.alloc_typed(|| [1u16, 2, 3])?;
+            let counted = tx.alloc_typed(|| {
+                (
+                    PersistentPointer::from_raw(values.as_raw().clone()),
+                    42u64,
+                    1u8,
+                )
+            })?;
+            tx.write_typed(root)?.0 = counted;
+            Ok(())
+        })?;
+
+        let (count, values) = librarius.run_read(|tx| {
+            let root = tx.read_typed(tx.root_typed::<(PersistentPointer<Counted>, u32)>())?;
+            assert_eq!(root.1, 7);
+            let (values, count, set) = tx.read_typed(&root.0)?;
+            assert_eq!(*set, 1);
+            Ok((*count, *tx.read_typed(values)?))
+        })?;
+        assert_eq!(count, 42);
+        assert_eq!(values, [1, 2, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn superseded() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let write = |value| {
+            librarius.run(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value = value;
+                Ok(())
+            })?;
+            librarius.run_read(|tx| Ok(tx.root().address()))
+        };
+
+        /* with no one left to read them, old versions make room for new ones */
+        let mut addresses = HashSet::new();
+        for value in 1..100 {
+            addresses.insert(write(value)?);
         }
+        assert!(addresses.len() <= 3);
 
-        for th in threads {
-            th.join().unwrap()
+        /* but not while a snapshot can still see them */
+        let snapshot = librarius.snapshot()?;
+        for value in 100..110 {
+            write(value)?;
         }
+        let value = snapshot.run(|tx| Ok(tx.read_typed(tx.root_typed::<BasicRoot>())?.value))?;
+        assert_eq!(value, 99);
 
-        let true_count = librarius.run(|tx| {
-            let root = tx.root_typed::<Root>();
-            let rootp = tx.read_typed(root)?;
+        Ok(())
+    }
 
-            let mut true_count = 0;
-            for n in 0..NTUPLES {
-                let t = tx.read_typed(&rootp.arr[n])?;
-                true_count += t.value as usize;
-            }
+    #[test]
+    fn version_chains() -> Result<()> {
+        let librarius = LibrariusBuilder::new()
+            .create_with_typed(|| BasicRoot { value: 0 })
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        let write = |value| {
+            librarius.run(|tx| {
+                let root = tx.root_typed::<BasicRoot>();
+                tx.write_typed(root)?.value = value;
+                Ok(())
+            })
+        };
 
-            Ok(true_count)
+        let snapshot = librarius.snapshot()?;
+        for value in 1..=5 {
+            write(value)?;
+        }
+        let stats = snapshot.run(|tx| {
+            assert_eq!(tx.read_typed(tx.root_typed::<BasicRoot>())?.value, 0);
+            Ok(tx.stats())
         })?;
-
-        assert!(true_count == NTUPLES || true_count == 0);
+        assert_eq!(stats.versions_skipped, 5);
+        drop(snapshot);
+
+        /* once no one can see past it, the newest version is all there is */
+        write(6)?;
+        librarius.run(|_| Ok(()))?;
+        let reader = librarius.vos.new_versioned_reader(&librarius.las);
+        assert!(reader.header(librarius.root)?.other.is_none());
+        let stats = librarius.run_read(|tx| {
+            assert_eq!(tx.read_typed(tx.root_typed::<BasicRoot>())?.value, 6);
+            Ok(tx.stats())
+        })?;
+        assert_eq!(stats.versions_skipped, 0);
 
         Ok(())
     }
@@ -0,0 +1,652 @@
+use crate::error::{Error, Result};
+use crate::las::LogicalAddress;
+use crate::librarius::Librarius;
+use crate::tx::Transaction;
+use crate::utils::unsafe_utils;
+use crate::vos::{ObjectSize, UntypedPointer, UNTYPED};
+use parking_lot::Mutex;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/*
+ * How an object is reached: from the root, or from the named root with
+ * that name, and then through the pointers at the given indices. Unlike
+ * an address, it means the same thing in any store with the same graph.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KeyPath {
+    pub root: Option<String>,
+    pub hops: Vec<u32>,
+}
+
+impl KeyPath {
+    fn named(name: &str) -> Self {
+        KeyPath {
+            root: Some(name.to_string()),
+            hops: Vec::new(),
+        }
+    }
+
+    fn child(&self, hop: usize) -> Self {
+        let mut child = self.clone();
+        child.hops.push(hop as u32);
+        child
+    }
+}
+
+/* where a pointer in an object the transaction wrote or allocated points to */
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Link {
+    None,
+    /* the object as it was before the transaction */
+    Before(KeyPath),
+    /* the version of it the transaction wrote */
+    Written(KeyPath),
+    /* what the nth allocation of the transaction made */
+    Allocated(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecordedObject {
+    pub kind: u64,
+    pub pointers: Vec<Link>,
+    pub data: Vec<u8>,
+}
+
+impl RecordedObject {
+    fn size(&self) -> ObjectSize {
+        ObjectSize::new_with_usize(
+            self.pointers.len() * size_of::<UntypedPointer>(),
+            self.data.len(),
+        )
+    }
+}
+
+/*
+ * What a transaction did, as it was when it committed: the objects it
+ * read from before anything changed, with their kinds, the ones it
+ * allocated, named roots included, what it wrote and what it freed.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecordedTransaction {
+    pub reads: Vec<(KeyPath, u64)>,
+    pub allocs: Vec<(Option<String>, RecordedObject)>,
+    pub writes: Vec<(KeyPath, RecordedObject)>,
+    pub frees: Vec<KeyPath>,
+    /* false if it changed an object it got to some other way, e.g., through a PointerToken */
+    pub complete: bool,
+}
+
+/* the transactions that committed while recording, in the order they did */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Workload {
+    pub transactions: Vec<RecordedTransaction>,
+}
+
+pub(crate) struct Recording {
+    on: AtomicBool,
+    /* by the version they committed */
+    transactions: Mutex<Vec<(usize, RecordedTransaction)>>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Recording {
+            on: AtomicBool::new(false),
+            transactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Acquire)
+    }
+}
+
+/*
+ * Follows a transaction around, keeping the path of every object it
+ * gets to from a root, by address. The addresses of new versions are
+ * kept apart from those of objects as they were, so that a pointer can
+ * tell which of the two it points to.
+ */
+pub(crate) struct Recorder<'tx> {
+    recording: &'tx Recording,
+    before: HashMap<LogicalAddress, KeyPath>,
+    written: HashMap<LogicalAddress, KeyPath>,
+    /* with the newest version of each, in the order they were first written */
+    writes: Vec<(KeyPath, UntypedPointer)>,
+    allocs: Vec<(Option<String>, UntypedPointer)>,
+    reads: Vec<(KeyPath, u64)>,
+    frees: Vec<KeyPath>,
+    complete: bool,
+}
+
+impl<'tx> Recorder<'tx> {
+    pub fn new(recording: &'tx Recording) -> Self {
+        Recorder {
+            recording,
+            before: HashMap::new(),
+            written: HashMap::new(),
+            writes: Vec::new(),
+            allocs: Vec::new(),
+            reads: Vec::new(),
+            frees: Vec::new(),
+            complete: true,
+        }
+    }
+
+    fn path(&self, address: LogicalAddress) -> Option<&KeyPath> {
+        self.before
+            .get(&address)
+            .or_else(|| self.written.get(&address))
+    }
+
+    fn allocation(&self, address: LogicalAddress) -> Option<usize> {
+        self.allocs
+            .iter()
+            .position(|(_, pointer)| pointer.address() == address)
+    }
+
+    pub fn is_before(&self, pointer: &UntypedPointer) -> bool {
+        self.before.contains_key(&pointer.address())
+    }
+
+    pub fn root(&mut self, pointer: &UntypedPointer, name: Option<&str>) {
+        if pointer.is_some() && self.path(pointer.address()).is_none() {
+            let path = name.map_or_else(KeyPath::default, KeyPath::named);
+            self.before.insert(pointer.address(), path);
+        }
+    }
+
+    /* whatever the pointers in an object lead to is reached through it */
+    fn reached(&mut self, path: &KeyPath, data: &[u8], size: &ObjectSize) {
+        let pointers =
+            unsafe_utils::many_from_slice::<UntypedPointer>(&data[..size.pointers as usize]);
+        for (hop, pointer) in pointers.iter().enumerate() {
+            let address = pointer.address();
+            if pointer.is_some()
+                && self.path(address).is_none()
+                && self.allocation(address).is_none()
+            {
+                self.before.insert(address, path.child(hop));
+            }
+        }
+    }
+
+    /* the kind is there for objects as they were before the transaction */
+    pub fn read(
+        &mut self,
+        pointer: &UntypedPointer,
+        kind: Option<u64>,
+        data: &[u8],
+        size: &ObjectSize,
+    ) {
+        let path = match self.path(pointer.address()) {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        if let Some(kind) = kind.filter(|_| !self.reads.iter().any(|(read, _)| *read == path)) {
+            self.reads.push((path.clone(), kind));
+        }
+        self.reached(&path, data, size);
+    }
+
+    /* the pointer has been swung from the address to its new version by now */
+    pub fn wrote(
+        &mut self,
+        address: LogicalAddress,
+        pointer: &UntypedPointer,
+        data: Option<(&[u8], &ObjectSize)>,
+    ) {
+        if let Some(n) = self.allocation(address) {
+            self.allocs[n].1 = pointer.clone();
+            return;
+        }
+        let path = match self.path(address) {
+            Some(path) => path.clone(),
+            None => {
+                self.complete = false;
+                return;
+            }
+        };
+        self.written.insert(pointer.address(), path.clone());
+        match self.writes.iter_mut().find(|(written, _)| *written == path) {
+            Some((_, newest)) => *newest = pointer.clone(),
+            None => self.writes.push((path.clone(), pointer.clone())),
+        }
+        if let Some((data, size)) = data {
+            self.reached(&path, data, size);
+        }
+    }
+
+    pub fn allocated(&mut self, pointer: &UntypedPointer, name: Option<&str>) {
+        self.allocs
+            .push((name.map(str::to_string), pointer.clone()));
+    }
+
+    /* an object that was allocated and freed by the same transaction never was */
+    pub fn freed(&mut self, pointer: &UntypedPointer) {
+        let address = pointer.address();
+        match self.path(address) {
+            Some(path) => self.frees.push(path.clone()),
+            None if self.allocation(address).is_some() => {}
+            None => self.complete = false,
+        }
+    }
+
+    /* the objects are as the transaction left them, object() reads them */
+    pub fn transaction<F>(&self, object: F) -> Result<RecordedTransaction>
+    where
+        F: Fn(&UntypedPointer) -> Result<(ObjectSize, u64, &'tx [u8])>,
+    {
+        let mut complete = self.complete;
+        let mut recorded = |pointer: &UntypedPointer| -> Result<RecordedObject> {
+            let (size, kind, data) = object(pointer)?;
+            let (pointers, data) = data.split_at(size.pointers as usize);
+            let pointers = unsafe_utils::many_from_slice::<UntypedPointer>(pointers)
+                .iter()
+                .map(|pointer| {
+                    let address = pointer.address();
+                    if pointer.is_none() {
+                        Link::None
+                    } else if let Some(n) = self.allocation(address) {
+                        Link::Allocated(n)
+                    } else if let Some(path) = self.written.get(&address) {
+                        Link::Written(path.clone())
+                    } else if let Some(path) = self.before.get(&address) {
+                        Link::Before(path.clone())
+                    } else {
+                        complete = false;
+                        Link::None
+                    }
+                })
+                .collect();
+            Ok(RecordedObject {
+                kind,
+                pointers,
+                data: data.to_vec(),
+            })
+        };
+
+        let allocs = self
+            .allocs
+            .iter()
+            .map(|(name, pointer)| Ok((name.clone(), recorded(pointer)?)))
+            .collect::<Result<_>>()?;
+        let writes = self
+            .writes
+            .iter()
+            .map(|(path, pointer)| Ok((path.clone(), recorded(pointer)?)))
+            .collect::<Result<_>>()?;
+        Ok(RecordedTransaction {
+            reads: self.reads.clone(),
+            allocs,
+            writes,
+            frees: self.frees.clone(),
+            complete,
+        })
+    }
+
+    pub fn committed(&self, version: usize, transaction: RecordedTransaction) {
+        if self.recording.is_on() {
+            self.recording
+                .transactions
+                .lock()
+                .push((version, transaction));
+        }
+    }
+}
+
+/* none if there's nothing there, or the path leads nowhere */
+fn follow<'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    path: &KeyPath,
+) -> Result<Option<&'tx UntypedPointer>> {
+    let mut pointer = match &path.root {
+        None => tx.root(),
+        Some(name) => match tx.named_root(name)? {
+            Some(pointer) => pointer,
+            None => return Ok(None),
+        },
+    };
+    for hop in &path.hops {
+        if pointer.is_none() {
+            return Ok(None);
+        }
+        let size = tx.object_size(pointer)?;
+        let data = tx.read(pointer, &size)?;
+        let pointers =
+            unsafe_utils::many_from_slice::<UntypedPointer>(&data[..size.pointers as usize]);
+        pointer = match pointers.get(*hop as usize) {
+            Some(pointer) => pointer,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(pointer).filter(|pointer| pointer.is_some()))
+}
+
+/*
+ * Every path is followed before anything changes, since that's what
+ * they were recorded against. Written and allocated objects are filled
+ * in last, once all the pointers they can point to are there.
+ */
+fn replay<'tx>(
+    tx: &mut Transaction<'tx, '_>,
+    recorded: &RecordedTransaction,
+    transaction: usize,
+) -> Result<()> {
+    let diverged = |reason| Error::ReplayDiverged {
+        transaction,
+        reason,
+    };
+
+    let links = recorded
+        .allocs
+        .iter()
+        .map(|(_, object)| object)
+        .chain(recorded.writes.iter().map(|(_, object)| object))
+        .flat_map(|object| &object.pointers)
+        .filter_map(|link| match link {
+            Link::Before(path) | Link::Written(path) => Some(path),
+            _ => None,
+        });
+    let paths = recorded
+        .reads
+        .iter()
+        .map(|(path, _)| path)
+        .chain(recorded.writes.iter().map(|(path, _)| path))
+        .chain(&recorded.frees)
+        .chain(links);
+    /* where each path led, and what was there */
+    let mut before = HashMap::new();
+    for path in paths {
+        if !before.contains_key(path) {
+            let pointer = follow(tx, path)?.ok_or_else(|| diverged("a path led nowhere"))?;
+            before.insert(path, (pointer, pointer.clone()));
+        }
+    }
+    for (path, kind) in &recorded.reads {
+        if tx.object_kind(before[path].0)? != *kind {
+            return Err(diverged("an object read is of another kind"));
+        }
+    }
+
+    let mut objects = Vec::new();
+    let mut allocated = Vec::new();
+    for (name, object) in &recorded.allocs {
+        let (pointer, data) = match name {
+            Some(name) => {
+                let (pointer, data) = tx.alloc_named_root(name, object.size())?;
+                (pointer.clone(), data)
+            }
+            None => tx.alloc(object.size())?,
+        };
+        if object.kind != UNTYPED {
+            tx.retype(&pointer, object.kind)?;
+        }
+        allocated.push(pointer);
+        objects.push((object, data));
+    }
+
+    let mut written = HashMap::new();
+    for (path, object) in &recorded.writes {
+        let pointer = before[path].0;
+        let size = object.size();
+        let current = tx.object_size(pointer)?;
+        let data = if (current.pointers, current.data) == (size.pointers, size.data) {
+            tx.write(pointer, &size)?
+        } else {
+            tx.realloc(pointer, size)?
+        };
+        if tx.object_kind(pointer)? != object.kind {
+            tx.retype(pointer, object.kind)?;
+        }
+        written.insert(path, pointer.clone());
+        objects.push((object, data));
+    }
+
+    for (object, data) in objects {
+        let (pointers, data) = data.split_at_mut(object.size().pointers as usize);
+        data.copy_from_slice(&object.data);
+        let pointers = unsafe_utils::many_from_slice_mut::<UntypedPointer>(pointers);
+        for (pointer, link) in pointers.iter_mut().zip(&object.pointers) {
+            *pointer = match link {
+                Link::None => UntypedPointer::new_none(),
+                Link::Before(path) => before[path].1.clone(),
+                Link::Written(path) => written
+                    .get(path)
+                    .ok_or_else(|| diverged("a pointer to a version that wasn't written"))?
+                    .clone(),
+                Link::Allocated(n) => allocated
+                    .get(*n)
+                    .ok_or_else(|| diverged("a pointer to an allocation that wasn't made"))?
+                    .clone(),
+            };
+        }
+    }
+
+    for path in &recorded.frees {
+        tx.free(before[path].0)?;
+    }
+    Ok(())
+}
+
+impl<'data> Librarius<'data> {
+    /*
+     * From now on, every transaction that commits a change is recorded,
+     * until stop_recording() hands them over. Read-only transactions and
+     * read-only attempts change nothing, so they're left out.
+     */
+    pub fn start_recording(&self) {
+        self.recording.transactions.lock().clear();
+        self.recording.on.store(true, Ordering::Release);
+    }
+
+    pub fn stop_recording(&self) -> Workload {
+        self.recording.on.store(false, Ordering::Release);
+        let mut transactions = std::mem::take(&mut *self.recording.transactions.lock());
+        transactions.sort_by_key(|(version, _)| *version);
+        Workload {
+            transactions: transactions
+                .into_iter()
+                .map(|(_, transaction)| transaction)
+                .collect(),
+        }
+    }
+
+    /*
+     * Does what the recorded transactions did, one transaction each, in
+     * the same order, e.g., to a fresh store made like the one recorded.
+     * It stops at the first one that can't be done the way it was, with
+     * Error::ReplayDiverged.
+     */
+    pub fn replay(&self, workload: &Workload) -> Result<()> {
+        for (n, recorded) in workload.transactions.iter().enumerate() {
+            if !recorded.complete {
+                return Err(Error::ReplayDiverged {
+                    transaction: n,
+                    reason: "it wasn't recorded in full",
+                });
+            }
+            self.run(|tx| replay(tx, recorded, n))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::PVec;
+    use crate::source::MemorySource;
+    use crate::typed::{
+        Persistent, PersistentPointer, TypedLibrariusBuilder, TypedReadTransaction,
+        TypedTransaction,
+    };
+    use crate::LibrariusBuilder;
+
+    #[repr(C)]
+    struct Node {
+        next: PersistentPointer<Node>,
+        value: u64,
+    }
+
+    impl Persistent for Node {
+        fn size() -> ObjectSize {
+            ObjectSize::new_with_usize(size_of::<UntypedPointer>(), size_of::<u64>())
+        }
+    }
+
+    fn open<'data>() -> Result<Librarius<'data>> {
+        LibrariusBuilder::new()
+            .create_with_typed(|| Node {
+                next: PersistentPointer::new_none(),
+                value: 0,
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .open()
+    }
+
+    fn node(value: u64) -> impl Fn() -> Node {
+        move || Node {
+            next: PersistentPointer::new_none(),
+            value,
+        }
+    }
+
+    /* the values along the list from the root, and the ones in the vector */
+    fn contents(librarius: &Librarius) -> Result<(Vec<u64>, Vec<u64>)> {
+        librarius.run_read(|tx| {
+            let mut values = Vec::new();
+            let mut node = tx.root_typed::<Node>();
+            while !node.is_none() {
+                let read = tx.read_typed(node)?;
+                values.push(read.value);
+                node = &read.next;
+            }
+            let vec = tx.root_named::<PVec<u64>>("vec")?.expect("was created");
+            let elements = vec.read_all(tx)?.into_iter().copied().collect();
+            Ok((values, elements))
+        })
+    }
+
+    #[test]
+    fn record_and_replay() -> Result<()> {
+        let librarius = open()?;
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            tx.write_typed(root)?.value = 100;
+            Ok(())
+        })?;
+
+        librarius.start_recording();
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            tx.write_typed(root)?.value = 1;
+            let next = tx.alloc_typed(node(10))?;
+            tx.write_typed(root)?.next = next;
+            tx.create_root_named("vec", PVec::<u64>::new)?;
+            Ok(())
+        })?;
+        /* a new node between the root and the one after it */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            let after = PersistentPointer::from_raw(tx.read_typed(root)?.next.as_raw().clone());
+            let middle = tx.alloc_typed(node(5))?;
+            let written = tx.write_typed(root)?;
+            written.next = middle;
+            tx.write_typed(&written.next)?.next = after;
+            Ok(())
+        })?;
+        librarius.run(|tx| {
+            let vec = tx.root_named::<PVec<u64>>("vec")?.expect("was created");
+            for value in 0..20 {
+                vec.push(tx, value)?;
+            }
+            vec.pop(tx)?;
+            Ok(())
+        })?;
+        /* the last node goes */
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            let middle = &tx.read_typed(root)?.get().next;
+            let last = &tx.read_typed(middle)?.get().next;
+            tx.free_typed(last)?;
+            tx.write_typed(middle)?.next = PersistentPointer::new_none();
+            Ok(())
+        })?;
+        librarius.run_read(|tx| tx.read_typed(tx.root_typed::<Node>()).map(|_| ()))?;
+        let workload = librarius.stop_recording();
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            tx.write_typed(root)?.value = 2;
+            Ok(())
+        })?;
+
+        assert_eq!(workload.transactions.len(), 4);
+        assert!(workload.transactions.iter().all(|tx| tx.complete));
+        assert_eq!(workload.transactions[3].frees.len(), 1);
+
+        /* there are no addresses in it, it can go anywhere */
+        #[cfg(feature = "serde")]
+        let workload: Workload =
+            serde_json::from_slice(&serde_json::to_vec(&workload).unwrap()).unwrap();
+        let replayed = open()?;
+        replayed.replay(&workload)?;
+        let expected = (vec![1, 5], (0..19).collect::<Vec<u64>>());
+        assert_eq!(contents(&replayed)?, expected);
+        assert_eq!(contents(&librarius)?.1, expected.1);
+
+        let objects = |librarius: &Librarius| -> Result<Vec<(u64, usize)>> {
+            let mut usage: Vec<_> = librarius
+                .usage_by_type()?
+                .into_iter()
+                .map(|(kind, usage)| (kind, usage.objects))
+                .collect();
+            usage.sort_unstable();
+            Ok(usage)
+        };
+        assert_eq!(objects(&replayed)?, objects(&librarius)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_diverges() -> Result<()> {
+        let librarius = open()?;
+        librarius.start_recording();
+        librarius.run(|tx| {
+            let root = tx.root_typed::<Node>();
+            let value = tx.read_typed(root)?.value;
+            tx.write_typed(root)?.value = value + 1;
+            Ok(())
+        })?;
+        let workload = librarius.stop_recording();
+        assert_eq!(workload.transactions[0].reads.len(), 1);
+
+        let other = LibrariusBuilder::new()
+            .create_with_typed(|| 0u64)
+            .source(MemorySource::new(1 << 20)?)
+            .open()?;
+        assert!(matches!(
+            other.replay(&workload),
+            Err(Error::ReplayDiverged { transaction: 0, .. })
+        ));
+
+        let mut incomplete = workload.clone();
+        incomplete.transactions[0].complete = false;
+        assert!(matches!(
+            open()?.replay(&incomplete),
+            Err(Error::ReplayDiverged { transaction: 0, .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::{Error, Result};
+use parking_lot::Mutex;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle, Thread};
+
+pub type IoFuture<T> = Pin<Box<dyn Future<Output = Result<T>> + Send>>;
+
+/*
+ * Buffers are owned by the request rather than borrowed, so that an I/O can
+ * keep going after the caller moved on to something else. Operations issued
+ * on the same source complete in submission order.
+ */
+pub trait AsyncSource: Send + Sync {
+    fn read_async(&self, offset: usize, len: usize) -> IoFuture<Vec<u8>>;
+    fn write_async(&self, offset: usize, data: Vec<u8>) -> IoFuture<()>;
+    fn flush_async(&self) -> IoFuture<()>;
+}
+
+struct CompletionState<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+struct Completion<T> {
+    state: Mutex<CompletionState<T>>,
+}
+
+impl<T> Completion<T> {
+    fn complete(&self, result: Result<T>) {
+        let mut state = self.state.lock();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct CompletionFuture<T> {
+    completion: Arc<Completion<T>>,
+}
+
+impl<T> Future for CompletionFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<T>> {
+        let mut state = self.completion.state.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type IoJob = Box<dyn FnOnce() + Send>;
+
+/*
+ * A single worker thread that executes submitted operations in order. The
+ * thread exits once the queue is dropped and everything in flight is done.
+ */
+pub struct IoQueue {
+    jobs: Option<mpsc::Sender<IoJob>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl IoQueue {
+    pub fn new() -> Self {
+        let (jobs, pending) = mpsc::channel::<IoJob>();
+        let worker = thread::spawn(move || {
+            for job in pending {
+                job();
+            }
+        });
+
+        IoQueue {
+            jobs: Some(jobs),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn submit<T, F>(&self, op: F) -> IoFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        let completion = Arc::new(Completion {
+            state: Mutex::new(CompletionState {
+                result: None,
+                waker: None,
+            }),
+        });
+
+        let done = completion.clone();
+        let job: IoJob = Box::new(move || done.complete(op()));
+        match self.jobs.as_ref().map(|jobs| jobs.send(job)) {
+            Some(Ok(())) => Box::pin(CompletionFuture { completion }),
+            _ => Box::pin(std::future::ready(Err(Error::SourceError {}))),
+        }
+    }
+}
+
+impl Drop for IoQueue {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/*
+ * There's no executor in here, this is for callers that simply want to wait
+ * for an I/O they've issued earlier.
+ */
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(all(test, any(unix, windows)))]
+mod tests {
+    use super::*;
+    use crate::source::{FileSource, SourceAllocator, SyncMode};
+
+    #[test]
+    fn file_roundtrip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-async-{}", std::process::id()));
+        let source = FileSource::new(path.to_str().unwrap(), 1 << 16)?;
+        let allocator = SourceAllocator::new(
+            Box::new(source),
+            4096,
+            |_| 0,
+            false,
+            false,
+            SyncMode::default(),
+        )?;
+        let page = allocator.allocate_page()?;
+
+        let write = allocator.write_from_async(&page, 512, vec![7; 512]);
+        let read = allocator.read_into_async(&page, 512, 512);
+        block_on(write)?;
+        assert!(block_on(read)?.iter().all(|b| *b == 7));
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::{Error, Result};
+use crate::source::{lock_file, Source, SourceCapabilities};
+use crate::utils::math;
+use std::alloc::{self, Layout};
+use std::fs;
+use std::os::unix::fs::{FileExt, FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+
+/* _IOR(0x12, 114, size_t) and _IO(0x12, 119), missing from libc */
+const BLKGETSIZE64: libc::Ioctl = 0x80081272;
+const BLKDISCARD: libc::Ioctl = 0x1277;
+
+/* regular files don't have a sector size, this is safe for O_DIRECT anywhere */
+const FILE_ALIGNMENT: usize = 4096;
+
+struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize, align: usize) -> Result<Self> {
+        let layout = Layout::from_size_align(len, align).map_err(|_| Error::InvalidMemory {})?;
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(Error::NoAvailableMemory {});
+        }
+
+        Ok(AlignedBuffer { ptr, layout })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/* whether the kernel passes discards on to the device, going by sysfs */
+fn device_discards(rdev: u64) -> bool {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    let dev = format!("/sys/dev/block/{}:{}", major, minor);
+
+    /* partitions share the queue of the disk they're on */
+    ["queue", "../queue"]
+        .iter()
+        .find_map(|queue| fs::read_to_string(format!("{}/{}/discard_max_bytes", dev, queue)).ok())
+        .and_then(|max| max.trim().parse::<u64>().ok())
+        .is_some_and(|max| max > 0)
+}
+
+/*
+ * A raw block device (or a file) opened with O_DIRECT, bypassing the page
+ * cache. Requests that aren't sector aligned go through a bounce buffer,
+ * writes to partial sectors being read-modify-write.
+ */
+pub struct BlockDeviceSource {
+    file: fs::File,
+    len: usize,
+    sector: usize,
+    block_device: bool,
+    discards: bool,
+}
+
+impl BlockDeviceSource {
+    pub fn new(path: &str) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .map_err(|err| Error::FileIO { err })?;
+
+        let meta = file.metadata().map_err(|err| Error::FileIO { err })?;
+        let block_device = meta.file_type().is_block_device();
+        let (len, sector) = if block_device {
+            let mut len: u64 = 0;
+            let mut sector: libc::c_int = 0;
+            unsafe {
+                if libc::ioctl(file.as_raw_fd(), BLKGETSIZE64, &mut len) != 0
+                    || libc::ioctl(file.as_raw_fd(), libc::BLKSSZGET, &mut sector) != 0
+                {
+                    return Err(Error::FileIO {
+                        err: std::io::Error::last_os_error(),
+                    });
+                }
+            }
+            (len as usize, sector as usize)
+        } else {
+            (meta.len() as usize, FILE_ALIGNMENT)
+        };
+
+        Ok(BlockDeviceSource {
+            file,
+            len,
+            sector,
+            block_device,
+            discards: !block_device || device_discards(meta.rdev()),
+        })
+    }
+
+    fn bounce(&self, offset: usize, len: usize) -> Result<(usize, AlignedBuffer)> {
+        if offset + len > self.len {
+            return Err(Error::InvalidMemory {});
+        }
+        let start = math::align_down(offset, self.sector);
+        let end = math::align_up(offset + len, self.sector);
+
+        Ok((start, AlignedBuffer::new(end - start, self.sector)?))
+    }
+
+    fn read_aligned(&self, start: usize, buf: &mut AlignedBuffer) -> Result<()> {
+        self.file
+            .read_exact_at(buf.as_mut_slice(), start as u64)
+            .map_err(|err| Error::FileIO { err })
+    }
+}
+
+impl Source for BlockDeviceSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        let mut capabilities = SourceCapabilities::PERSISTENT;
+        capabilities.set(SourceCapabilities::DISCARD, self.discards);
+        /* the device writes whole sectors or nothing, a file system may not */
+        capabilities.set(SourceCapabilities::ATOMIC_SECTOR_WRITES, self.block_device);
+        capabilities
+    }
+
+    fn perf_level(&self) -> usize {
+        0
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.len)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let (start, mut buf) = self.bounce(offset, data.len())?;
+        self.read_aligned(start, &mut buf)?;
+
+        let skip = offset - start;
+        data.copy_from_slice(&buf.as_slice()[skip..skip + data.len()]);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        let (start, mut buf) = self.bounce(offset, data.len())?;
+        if start != offset || buf.as_slice().len() != data.len() {
+            self.read_aligned(start, &mut buf)?;
+        }
+
+        let skip = offset - start;
+        buf.as_mut_slice()[skip..skip + data.len()].copy_from_slice(data);
+
+        self.file
+            .write_all_at(buf.as_slice(), start as u64)
+            .map_err(|err| Error::FileIO { err })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.sync_data().map_err(|err| Error::FileIO { err })
+    }
+
+    /*
+     * SSDs get TRIMmed, files have holes punched into them. Only whole
+     * sectors can go, whatever partial ones are at the ends stay.
+     */
+    fn discard(&mut self, offset: usize, len: usize) -> Result<()> {
+        let start = math::align_up(offset, self.sector);
+        let end = math::align_down(offset + len, self.sector);
+        if !self.discards || start >= end {
+            return Ok(());
+        }
+
+        let fd = self.file.as_raw_fd();
+        let ret = unsafe {
+            if self.block_device {
+                let range: [u64; 2] = [start as u64, (end - start) as u64];
+                libc::ioctl(fd, BLKDISCARD, &range)
+            } else {
+                libc::fallocate(
+                    fd,
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    start as libc::off_t,
+                    (end - start) as libc::off_t,
+                )
+            }
+        };
+        if ret != 0 {
+            return Err(Error::FileIO {
+                err: std::io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        lock_file(&self.file, exclusive)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unaligned_io() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-blockdev-{}", std::process::id()));
+        fs::File::create(&path)
+            .and_then(|file| file.set_len(1 << 16))
+            .map_err(|err| Error::FileIO { err })?;
+
+        /* tmpfs and friends refuse O_DIRECT */
+        let mut source = match BlockDeviceSource::new(path.to_str().unwrap()) {
+            Ok(source) => source,
+            Err(_) => return fs::remove_file(path).map_err(|err| Error::FileIO { err }),
+        };
+
+        source.write(4000, &[9; 200])?;
+        source.flush()?;
+
+        let mut data = [0u8; 300];
+        source.read(3950, &mut data)?;
+        assert!(data[..50].iter().all(|b| *b == 0));
+        assert!(data[50..250].iter().all(|b| *b == 9));
+        assert!(data[250..].iter().all(|b| *b == 0));
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn discarded_sectors() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-trim-{}", std::process::id()));
+        fs::File::create(&path)
+            .and_then(|file| file.set_len(1 << 16))
+            .map_err(|err| Error::FileIO { err })?;
+
+        let mut source = match BlockDeviceSource::new(path.to_str().unwrap()) {
+            Ok(source) => source,
+            Err(_) => return fs::remove_file(path).map_err(|err| Error::FileIO { err }),
+        };
+        assert!(source.capabilities().contains(SourceCapabilities::DISCARD));
+
+        source.write(0, &[3; 3 * 4096])?;
+        source.discard(100, 2 * 4096)?;
+
+        let mut data = vec![0u8; 3 * 4096];
+        source.read(0, &mut data)?;
+        assert!(data[..4096].iter().all(|b| *b == 3));
+        assert!(data[4096..2 * 4096].iter().all(|b| *b == 0));
+        assert!(data[2 * 4096..].iter().all(|b| *b == 3));
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
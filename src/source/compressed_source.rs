@@ -0,0 +1,348 @@
+use crate::error::{Error, Result};
+use crate::source::{page_spans, Source, SourceCapabilities, SyncMode};
+use crate::utils::{math, unsafe_utils};
+use std::collections::BTreeMap;
+use std::mem::size_of;
+
+pub trait PageCodec: Send + Sync {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decompress(&self, data: &[u8], out: &mut [u8]) -> Result<()>;
+}
+
+#[cfg(feature = "lz4")]
+pub struct Lz4Codec;
+
+#[cfg(feature = "lz4")]
+impl PageCodec for Lz4Codec {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(lz4_flex::block::compress(data))
+    }
+
+    fn decompress(&self, data: &[u8], out: &mut [u8]) -> Result<()> {
+        match lz4_flex::block::decompress_into(data, out) {
+            Ok(n) if n == out.len() => Ok(()),
+            _ => Err(Error::CorruptedPage {}),
+        }
+    }
+}
+
+/* extents are handed out in sectors, so small rewrites can usually stay put */
+const SECTOR: usize = 512;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct PageEntry {
+    offset: u64,
+    len: u32,
+    compressed: u32,
+}
+
+impl PageEntry {
+    fn extent(&self) -> usize {
+        math::align_up(self.len as usize, SECTOR)
+    }
+}
+
+/*
+ * The inner source starts with a table holding the location and stored size
+ * of every page, followed by the extents the pages were packed into. The free
+ * extents aren't persisted, they're whatever the table doesn't reference.
+ * A rewritten page always goes to a new extent and the old one is released
+ * only once the table pointing elsewhere has been flushed, until then a crash
+ * could still leave the old entry in place.
+ */
+pub struct CompressedSource<S: Source, C: PageCodec> {
+    inner: S,
+    codec: C,
+    pagesize: usize,
+    entries: Vec<PageEntry>,
+    free: BTreeMap<usize, usize>,
+    /* extents of rewritten pages, reused after the next flush */
+    released: Vec<(usize, usize)>,
+}
+
+impl<S: Source, C: PageCodec> CompressedSource<S, C> {
+    pub fn new(inner: S, codec: C, pagesize: usize, len: usize) -> Result<Self> {
+        let npages = len / pagesize;
+        let table_len = math::align_up(npages * size_of::<PageEntry>(), SECTOR);
+        let inner_len = math::align_down(inner.length()?, SECTOR);
+        if table_len > inner_len {
+            return Err(Error::InvalidSource {});
+        }
+
+        let mut table = vec![0; npages * size_of::<PageEntry>()];
+        inner.read(0, &mut table)?;
+        let entries: Vec<PageEntry> = table
+            .chunks(size_of::<PageEntry>())
+            .map(|entry| *unsafe_utils::any_from_slice::<PageEntry>(entry))
+            .collect();
+
+        let mut used: Vec<(usize, usize)> = entries
+            .iter()
+            .filter(|e| e.len != 0)
+            .map(|e| (e.offset as usize, e.extent()))
+            .collect();
+        used.sort_unstable();
+
+        let mut free = BTreeMap::new();
+        let mut next = table_len;
+        for (offset, len) in used.into_iter().chain(std::iter::once((inner_len, 0))) {
+            if offset > next {
+                free.insert(next, offset - next);
+            }
+            next = std::cmp::max(next, offset + len);
+        }
+
+        Ok(CompressedSource {
+            inner,
+            codec,
+            pagesize,
+            entries,
+            free,
+            released: Vec::new(),
+        })
+    }
+
+    fn alloc_extent(&mut self, len: usize) -> Result<usize> {
+        let (offset, available) = self
+            .free
+            .iter()
+            .find(|(_, available)| **available >= len)
+            .map(|(offset, available)| (*offset, *available))
+            .ok_or(Error::NoAvailableMemory {})?;
+
+        self.free.remove(&offset);
+        if available > len {
+            self.free.insert(offset + len, available - len);
+        }
+        Ok(offset)
+    }
+
+    fn free_extent(&mut self, mut offset: usize, mut len: usize) {
+        if let Some((&prev, &prev_len)) = self.free.range(..offset).next_back() {
+            if prev + prev_len == offset {
+                self.free.remove(&prev);
+                offset = prev;
+                len += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(offset + len)) {
+            len += next_len;
+        }
+        self.free.insert(offset, len);
+    }
+
+    fn reuse_released(&mut self) {
+        for (offset, len) in std::mem::take(&mut self.released) {
+            self.free_extent(offset, len);
+        }
+    }
+
+    fn read_page(&self, page: usize, data: &mut [u8]) -> Result<()> {
+        let entry = self.entries[page];
+        if entry.len == 0 {
+            data.iter_mut().for_each(|b| *b = 0);
+            return Ok(());
+        }
+
+        let mut stored = vec![0; entry.len as usize];
+        self.inner.read(entry.offset as usize, &mut stored)?;
+        if entry.compressed != 0 {
+            self.codec.decompress(&stored, data)
+        } else {
+            data.copy_from_slice(&stored);
+            Ok(())
+        }
+    }
+
+    fn write_page(&mut self, page: usize, data: &[u8]) -> Result<()> {
+        let compressed = self.codec.compress(data)?;
+        let (stored, is_compressed) = if compressed.len() < data.len() {
+            (&compressed[..], 1)
+        } else {
+            (data, 0)
+        };
+
+        let old = self.entries[page];
+        let entry = PageEntry {
+            offset: self.alloc_extent(math::align_up(stored.len(), SECTOR))? as u64,
+            len: stored.len() as u32,
+            compressed: is_compressed,
+        };
+
+        self.inner.write(entry.offset as usize, stored)?;
+        self.inner.write(
+            page * size_of::<PageEntry>(),
+            unsafe_utils::any_as_slice(&entry),
+        )?;
+        self.entries[page] = entry;
+
+        if old.len != 0 {
+            self.released.push((old.offset as usize, old.extent()));
+        }
+
+        Ok(())
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<()> {
+        if offset + len > self.entries.len() * self.pagesize {
+            return Err(Error::InvalidMemory {});
+        }
+        Ok(())
+    }
+
+    pub fn stored_len(&self) -> usize {
+        self.entries.iter().map(|e| e.extent()).sum()
+    }
+}
+
+impl<S: Source, C: PageCodec> Source for CompressedSource<S, C> {
+    fn capabilities(&self) -> SourceCapabilities {
+        self.inner.capabilities() & SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        self.inner.perf_level()
+    }
+
+    fn close(&mut self) {
+        self.inner.close()
+    }
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.entries.len() * self.pagesize)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        self.check(offset, data.len())?;
+
+        let mut buf = vec![0; self.pagesize];
+        for (page, in_page, in_data) in page_spans(offset, data.len(), self.pagesize) {
+            self.read_page(page, &mut buf)?;
+            data[in_data].copy_from_slice(&buf[in_page]);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.check(offset, data.len())?;
+
+        let mut buf = vec![0; self.pagesize];
+        for (page, in_page, in_data) in page_spans(offset, data.len(), self.pagesize) {
+            if in_page.len() != self.pagesize {
+                self.read_page(page, &mut buf)?;
+            }
+            buf[in_page].copy_from_slice(&data[in_data]);
+            self.write_page(page, &buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        self.reuse_released();
+        Ok(())
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        self.inner.sync(mode)?;
+        if mode != SyncMode::None {
+            self.reuse_released();
+        }
+        Ok(())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.inner.lock(exclusive)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod tests {
+    use super::*;
+    use crate::source::ReplaySource;
+
+    #[test]
+    fn packed_pages() -> Result<()> {
+        /* twice the logical capacity of the backing image */
+        let mut source = CompressedSource::new(
+            ReplaySource::new(vec![0; 1 << 16], &[]),
+            Lz4Codec,
+            4096,
+            1 << 17,
+        )?;
+
+        for page in 0..32 {
+            source.write(page * 4096 + 100, &[page as u8 + 1; 1000])?;
+        }
+        source.write(5 * 4096, &[42; 4096])?;
+        assert!(source.stored_len() < 32 * 1024);
+
+        let mut data = [0u8; 1200];
+        source.read(5 * 4096, &mut data)?;
+        assert!(data.iter().all(|b| *b == 42));
+
+        source.flush()?;
+        let image = {
+            let mut image = vec![0; 1 << 16];
+            source.inner.read(0, &mut image)?;
+            image
+        };
+        let reopened =
+            CompressedSource::new(ReplaySource::new(image, &[]), Lz4Codec, 4096, 1 << 17)?;
+        reopened.read(31 * 4096, &mut data)?;
+        assert!(data[..100].iter().all(|b| *b == 0));
+        assert!(data[100..1100].iter().all(|b| *b == 32));
+        assert_eq!(reopened.free, source.free);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_keeps_old_extent() -> Result<()> {
+        let mut source = CompressedSource::new(
+            ReplaySource::new(vec![0; 1 << 16], &[]),
+            Lz4Codec,
+            4096,
+            1 << 16,
+        )?;
+        let is_free = |source: &CompressedSource<_, _>, offset: usize| {
+            source
+                .free
+                .range(..=offset)
+                .next_back()
+                .map_or(false, |(start, len)| offset < start + len)
+        };
+
+        source.write(0, &[1; 4096])?;
+        source.flush()?;
+        let old = source.entries[0].offset as usize;
+
+        source.write(0, &[2; 4096])?;
+        assert_ne!(source.entries[0].offset as usize, old);
+        assert!(!is_free(&source, old));
+
+        source.flush()?;
+        assert!(is_free(&source, old));
+
+        Ok(())
+    }
+}
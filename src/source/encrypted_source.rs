@@ -0,0 +1,562 @@
+use crate::error::{Error, Result};
+use crate::source::{page_spans, Source, SourceCapabilities, SyncMode};
+use crate::utils::unsafe_utils;
+
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+/*
+ * An AEAD cipher keyed by the application, e.g., AES-256-GCM. Pages are
+ * encrypted in place and the tag is kept separately. Keys have versions,
+ * starting at 0, the application keeps them by version and has to know
+ * every one that pages are still sealed with when it opens the source.
+ */
+pub trait PageCipher: Send + Sync {
+    fn seal(
+        &self,
+        key: u32,
+        nonce: &[u8; NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+    ) -> Result<[u8; TAG_LEN]>;
+    fn open(
+        &self,
+        key: u32,
+        nonce: &[u8; NONCE_LEN],
+        aad: &[u8],
+        data: &mut [u8],
+        tag: &[u8; TAG_LEN],
+    ) -> Result<()>;
+
+    /* for ciphers that can rotate keys, the key of the given version */
+    fn add_key(&mut self, _version: u32, _key: &[u8]) -> Result<()> {
+        Err(Error::CannotRotateKey {})
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct Seal {
+    generation: u64,
+    key: u64,
+    tag: [u8; TAG_LEN],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct PageEntry {
+    seal: Seal,
+    /* the newest generation the page was sealed with, it may never have been written */
+    sealed: u64,
+    /* how the same data was sealed before it was reencrypted, in case that didn't make it */
+    previous: Seal,
+}
+
+/*
+ * The inner source holds the encrypted pages followed by a table of per-page
+ * entries, the epoch and the version of the key new writes are sealed with.
+ * Pages sealed with an older key are rewritten by reencrypt(), which makes
+ * the entry durable first, so that a crash in the middle leaves a page the
+ * previous seal still opens, as long as the inner source didn't tear the
+ * page itself. Nonces are made from the page number and a generation,
+ * which is the epoch followed by a count of the writes to the page. A
+ * generation is taken before anything is sealed with it, and the epoch is
+ * bumped on the source before the first write after opening, so that not
+ * even writes lost in a crash get their nonce reused under the same key.
+ * The page number is also authenticated, pages can't be swapped around.
+ */
+pub struct EncryptedSource<S: Source, C: PageCipher> {
+    inner: S,
+    cipher: C,
+    pagesize: usize,
+    entries: Vec<PageEntry>,
+    epoch: u64,
+    epoch_bumped: bool,
+    key: u32,
+}
+
+impl<S: Source, C: PageCipher> EncryptedSource<S, C> {
+    pub fn new(inner: S, cipher: C, pagesize: usize) -> Result<Self> {
+        let entry_size = std::mem::size_of::<PageEntry>();
+        let npages = inner.length()?.saturating_sub(entry_size) / (pagesize + entry_size);
+
+        let mut table = vec![0; (npages + 1) * entry_size];
+        inner.read(npages * pagesize, &mut table)?;
+        let (table, trailer) = table.split_at(npages * entry_size);
+        let entries = table
+            .chunks(entry_size)
+            .map(|entry| *unsafe_utils::any_from_slice::<PageEntry>(entry))
+            .collect();
+        let (epoch, key) = trailer.split_at(std::mem::size_of::<u64>());
+        let epoch = *unsafe_utils::any_from_slice::<u64>(epoch);
+        let key = *unsafe_utils::any_from_slice::<u64>(key);
+
+        Ok(EncryptedSource {
+            inner,
+            cipher,
+            pagesize,
+            entries,
+            epoch: u64::from_le(epoch),
+            epoch_bumped: false,
+            key: u64::from_le(key) as u32,
+        })
+    }
+
+    /*
+     * Pages sealed with an older key, and the ones reencrypted before the
+     * source was last opened, whose rewrite may not have made it.
+     */
+    fn is_stale(&self, page: usize) -> bool {
+        let entry = &self.entries[page];
+        entry.seal.generation != 0
+            && (entry.seal.key != self.key as u64 || entry.previous.generation != 0)
+    }
+
+    fn entry_offset(&self, page: usize) -> usize {
+        self.entries.len() * self.pagesize + page * std::mem::size_of::<PageEntry>()
+    }
+
+    fn bump_epoch(&mut self) -> Result<()> {
+        let epoch = self.epoch + 1;
+        self.inner
+            .write(self.entry_offset(self.entries.len()), &epoch.to_le_bytes())?;
+        self.inner.flush()?;
+        self.epoch = epoch;
+        self.epoch_bumped = true;
+        Ok(())
+    }
+
+    /* a generation no data of the page was ever sealed with */
+    fn next_generation(&mut self, page: usize) -> Result<u64> {
+        if !self.epoch_bumped {
+            self.bump_epoch()?;
+        }
+        let entry = &self.entries[page];
+        let mut generation = std::cmp::max(entry.seal.generation, entry.sealed) + 1;
+        generation = std::cmp::max(generation, self.epoch << 32);
+        /* the count ran out within the epoch */
+        if generation >> 32 != self.epoch {
+            self.bump_epoch()?;
+            generation = self.epoch << 32;
+        }
+
+        self.entries[page].sealed = generation;
+        Ok(generation)
+    }
+
+    fn nonce(page: usize, generation: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = [0; NONCE_LEN];
+        nonce[..4].copy_from_slice(&(page as u32).to_le_bytes());
+        nonce[4..].copy_from_slice(&generation.to_le_bytes());
+        nonce
+    }
+
+    fn open(&self, page: usize, seal: &Seal, data: &mut [u8]) -> Result<()> {
+        self.cipher.open(
+            seal.key as u32,
+            &Self::nonce(page, seal.generation),
+            &(page as u64).to_le_bytes(),
+            data,
+            &seal.tag,
+        )
+    }
+
+    /* the seal that opened the page */
+    fn open_page(&self, page: usize, data: &mut [u8]) -> Result<Seal> {
+        let entry = self.entries[page];
+        self.inner.read(page * self.pagesize, data)?;
+        if entry.previous.generation == 0 {
+            return self.open(page, &entry.seal, data).map(|_| entry.seal);
+        }
+
+        let sealed = data.to_vec();
+        match self.open(page, &entry.seal, data) {
+            Ok(()) => Ok(entry.seal),
+            Err(_) => {
+                data.copy_from_slice(&sealed);
+                self.open(page, &entry.previous, data)
+                    .map(|_| entry.previous)
+            }
+        }
+    }
+
+    fn read_page(&self, page: usize, data: &mut [u8]) -> Result<()> {
+        if self.entries[page].seal.generation == 0 {
+            data.iter_mut().for_each(|b| *b = 0);
+            return Ok(());
+        }
+
+        self.open_page(page, data).map(|_| ())
+    }
+
+    /* previous is the seal of the same data, if it's only being reencrypted */
+    fn write_page(&mut self, page: usize, data: &mut [u8], previous: Seal) -> Result<()> {
+        let generation = self.next_generation(page)?;
+        let mut entry = self.entries[page];
+        entry.seal = Seal {
+            generation,
+            key: self.key as u64,
+            tag: self.cipher.seal(
+                self.key,
+                &Self::nonce(page, generation),
+                &(page as u64).to_le_bytes(),
+                data,
+            )?,
+        };
+        entry.previous = previous;
+
+        let entry_offset = self.entry_offset(page);
+        if previous.generation == 0 {
+            self.inner.write(page * self.pagesize, data)?;
+            self.inner
+                .write(entry_offset, unsafe_utils::any_as_slice(&entry))?;
+        } else {
+            self.inner
+                .write(entry_offset, unsafe_utils::any_as_slice(&entry))?;
+            self.inner.flush()?;
+            self.inner.write(page * self.pagesize, data)?;
+        }
+        /* until it's opened again, the page is known to have made it */
+        entry.previous = Seal::default();
+        self.entries[page] = entry;
+
+        Ok(())
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result<()> {
+        if offset + len > self.entries.len() * self.pagesize {
+            return Err(Error::InvalidMemory {});
+        }
+        Ok(())
+    }
+}
+
+impl<S: Source, C: PageCipher> Source for EncryptedSource<S, C> {
+    fn capabilities(&self) -> SourceCapabilities {
+        self.inner.capabilities() & SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        self.inner.perf_level()
+    }
+
+    fn close(&mut self) {
+        self.inner.close()
+    }
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.entries.len() * self.pagesize)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        self.check(offset, data.len())?;
+
+        let mut buf = vec![0; self.pagesize];
+        for (page, in_page, in_data) in page_spans(offset, data.len(), self.pagesize) {
+            self.read_page(page, &mut buf)?;
+            data[in_data].copy_from_slice(&buf[in_page]);
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.check(offset, data.len())?;
+
+        let mut buf = vec![0; self.pagesize];
+        for (page, in_page, in_data) in page_spans(offset, data.len(), self.pagesize) {
+            if in_page.len() != self.pagesize {
+                self.read_page(page, &mut buf)?;
+            }
+            buf[in_page].copy_from_slice(&data[in_data]);
+            self.write_page(page, &mut buf, Seal::default())?;
+        }
+
+        Ok(())
+    }
+
+    fn rotate_key(&mut self, key: &[u8]) -> Result<()> {
+        let version = self.key.checked_add(1).ok_or(Error::CannotRotateKey {})?;
+        self.cipher.add_key(version, key)?;
+        let offset = self.entry_offset(self.entries.len()) + std::mem::size_of::<u64>();
+        self.inner.write(offset, &(version as u64).to_le_bytes())?;
+        self.inner.flush()?;
+        self.key = version;
+        Ok(())
+    }
+
+    fn reencrypt(&mut self, budget: usize) -> Result<usize> {
+        let mut buf = vec![0; self.pagesize];
+        let mut written = 0;
+        for page in 0..self.entries.len() {
+            if written + self.pagesize > budget {
+                break;
+            }
+            if !self.is_stale(page) {
+                continue;
+            }
+
+            let seal = self.open_page(page, &mut buf)?;
+            if seal.key == self.key as u64 {
+                let mut entry = self.entries[page];
+                entry.seal = seal;
+                entry.previous = Seal::default();
+                self.inner
+                    .write(self.entry_offset(page), unsafe_utils::any_as_slice(&entry))?;
+                self.entries[page] = entry;
+            } else {
+                self.write_page(page, &mut buf, seal)?;
+            }
+            written += self.pagesize;
+        }
+
+        Ok(written)
+    }
+
+    fn unrotated(&self) -> usize {
+        (0..self.entries.len())
+            .filter(|page| self.is_stale(*page))
+            .count()
+            * self.pagesize
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        self.inner.sync(mode)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.inner.lock(exclusive)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::source::faulty_source::FaultySource;
+    use crate::source::ReplaySource;
+    use crate::utils::crc_slice;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    /* not a cipher, just enough to exercise the plumbing, with a key byte per version */
+    pub(crate) struct XorCipher(pub Vec<u8>);
+
+    impl XorCipher {
+        fn tag(&self, key: u8, nonce: &[u8; NONCE_LEN], aad: &[u8], data: &[u8]) -> [u8; TAG_LEN] {
+            let mut tag = [0; TAG_LEN];
+            tag[..4].copy_from_slice(&crc_slice(data).to_le_bytes());
+            tag[4..8].copy_from_slice(&crc_slice(nonce).to_le_bytes());
+            tag[8..12].copy_from_slice(&crc_slice(aad).to_le_bytes());
+            tag[12] = key;
+            tag
+        }
+
+        fn key(&self, version: u32) -> Result<u8> {
+            let key = self.0.get(version as usize);
+            key.copied().ok_or(Error::DecryptionFailed {})
+        }
+    }
+
+    impl PageCipher for XorCipher {
+        fn seal(
+            &self,
+            key: u32,
+            nonce: &[u8; NONCE_LEN],
+            aad: &[u8],
+            data: &mut [u8],
+        ) -> Result<[u8; TAG_LEN]> {
+            let key = self.key(key)?;
+            let tag = self.tag(key, nonce, aad, data);
+            data.iter_mut().for_each(|b| *b ^= key ^ nonce[4]);
+            Ok(tag)
+        }
+
+        fn open(
+            &self,
+            key: u32,
+            nonce: &[u8; NONCE_LEN],
+            aad: &[u8],
+            data: &mut [u8],
+            tag: &[u8; TAG_LEN],
+        ) -> Result<()> {
+            let key = self.key(key)?;
+            data.iter_mut().for_each(|b| *b ^= key ^ nonce[4]);
+            if self.tag(key, nonce, aad, data) != *tag {
+                return Err(Error::DecryptionFailed {});
+            }
+            Ok(())
+        }
+
+        fn add_key(&mut self, version: u32, key: &[u8]) -> Result<()> {
+            assert_eq!(version as usize, self.0.len());
+            self.0.push(key[0]);
+            Ok(())
+        }
+    }
+
+    /* remembers every nonce anything was sealed with */
+    #[derive(Clone, Default)]
+    struct NonceLog(Arc<Mutex<Vec<[u8; NONCE_LEN]>>>);
+
+    impl PageCipher for NonceLog {
+        fn seal(
+            &self,
+            key: u32,
+            nonce: &[u8; NONCE_LEN],
+            aad: &[u8],
+            data: &mut [u8],
+        ) -> Result<[u8; TAG_LEN]> {
+            self.0.lock().push(*nonce);
+            XorCipher(vec![0x5a]).seal(key, nonce, aad, data)
+        }
+
+        fn open(
+            &self,
+            key: u32,
+            nonce: &[u8; NONCE_LEN],
+            aad: &[u8],
+            data: &mut [u8],
+            tag: &[u8; TAG_LEN],
+        ) -> Result<()> {
+            XorCipher(vec![0x5a]).open(key, nonce, aad, data, tag)
+        }
+    }
+
+    #[test]
+    fn nonces_after_failed_writes() -> Result<()> {
+        let log = NonceLog::default();
+        let faulty = FaultySource::new(ReplaySource::new(vec![0; 1 << 16], &[]));
+        let faults = faulty.injector();
+        let mut source = EncryptedSource::new(faulty, log.clone(), 4096)?;
+        source.write(4096, &[1; 4096])?;
+        source.flush()?;
+
+        /* the data goes out, the entry doesn't, and the same source goes on */
+        faults.power_loss_at(1);
+        assert!(source.write(4096, &[2; 4096]).is_err());
+        faults.restore_power();
+        source.write(4096, &[3; 4096])?;
+        source.flush()?;
+
+        /* and again, but the source is opened anew, like after a crash */
+        faults.power_loss_at(1);
+        assert!(source.write(4096, &[4; 4096]).is_err());
+        faults.restore_power();
+        let mut source = EncryptedSource::new(source.inner, log.clone(), 4096)?;
+        let mut data = [0u8; 4096];
+        source.read(4096, &mut data)?;
+        assert!(data.iter().all(|b| *b == 3));
+        source.write(4096, &[5; 4096])?;
+
+        let nonces = log.0.lock();
+        assert_eq!(nonces.len(), 5);
+        let unique: std::collections::HashSet<_> = nonces.iter().collect();
+        assert_eq!(unique.len(), nonces.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_pages() -> Result<()> {
+        let mut source = EncryptedSource::new(
+            ReplaySource::new(vec![0; 1 << 16], &[]),
+            XorCipher(vec![0x5a]),
+            4096,
+        )?;
+        assert_eq!(source.length()?, 15 * 4096);
+
+        source.write(4000, &[7; 200])?;
+        let mut data = [0u8; 300];
+        source.read(3950, &mut data)?;
+        assert!(data[..50].iter().all(|b| *b == 0));
+        assert!(data[50..250].iter().all(|b| *b == 7));
+
+        source.inner.write(4096, &[0; 1])?;
+        assert!(matches!(
+            source.read(4096, &mut data),
+            Err(Error::DecryptionFailed {})
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_rotation() -> Result<()> {
+        let mut source = EncryptedSource::new(
+            ReplaySource::new(vec![0; 1 << 16], &[]),
+            XorCipher(vec![0x5a]),
+            4096,
+        )?;
+        source.write(0, &[1; 3 * 4096])?;
+        source.rotate_key(&[0xa5])?;
+        assert_eq!(source.unrotated(), 3 * 4096);
+
+        /* new writes are already sealed with the new key */
+        source.write(4096, &[2; 4096])?;
+        assert_eq!(source.unrotated(), 2 * 4096);
+        assert_eq!(source.reencrypt(4096 + 1)?, 4096);
+        assert_eq!(source.unrotated(), 4096);
+        assert_eq!(source.reencrypt(usize::MAX)?, 4096);
+        assert_eq!(source.unrotated(), 0);
+        source.flush()?;
+
+        /* reencrypted pages get checked once after reopening, with no old key around */
+        let mut source = EncryptedSource::new(source.inner, XorCipher(vec![0, 0xa5]), 4096)?;
+        assert_eq!(source.unrotated(), 2 * 4096);
+        assert_eq!(source.reencrypt(usize::MAX)?, 2 * 4096);
+        assert_eq!(source.unrotated(), 0);
+        let mut data = vec![0u8; 3 * 4096];
+        source.read(0, &mut data)?;
+        assert!(data[..4096].iter().all(|b| *b == 1));
+        assert!(data[4096..2 * 4096].iter().all(|b| *b == 2));
+        assert!(data[2 * 4096..].iter().all(|b| *b == 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn crash_while_reencrypting() -> Result<()> {
+        let faulty = FaultySource::new(ReplaySource::new(vec![0; 1 << 16], &[]));
+        let faults = faulty.injector();
+        let mut source = EncryptedSource::new(faulty, XorCipher(vec![0x5a]), 4096)?;
+        source.write(0, &[1; 4096])?;
+        source.rotate_key(&[0xa5])?;
+        source.flush()?;
+
+        /* the entry is flushed, the page itself doesn't make it */
+        faults.power_loss_at(1);
+        assert!(source.reencrypt(usize::MAX).is_err());
+        faults.restore_power();
+
+        let mut source = EncryptedSource::new(source.inner, XorCipher(vec![0x5a, 0xa5]), 4096)?;
+        let mut data = [0u8; 4096];
+        source.read(0, &mut data)?;
+        assert!(data.iter().all(|b| *b == 1));
+        assert_eq!(source.unrotated(), 4096);
+        assert_eq!(source.reencrypt(usize::MAX)?, 4096);
+        source.read(0, &mut data)?;
+        assert!(data.iter().all(|b| *b == 1));
+
+        Ok(())
+    }
+}
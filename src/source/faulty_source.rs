@@ -0,0 +1,439 @@
+use crate::error::{Error, Result};
+use crate::source::async_source::{block_on, AsyncSource, IoFuture};
+use crate::source::{Source, SourceCapabilities, SyncMode};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Default)]
+struct FaultState {
+    reads: usize,
+    writes: usize,
+    short_read: Option<usize>,
+    torn_write: Option<(usize, usize)>,
+    power_loss: Option<usize>,
+    write_delay: Duration,
+    powered_off: bool,
+}
+
+/*
+ * Arms the faults of a FaultySource. It's cheap to clone and stays with the
+ * test when the source itself is handed over to a store. Operations are
+ * counted from the moment a fault is armed, 0 being the very next one.
+ */
+#[derive(Clone, Default)]
+pub struct FaultInjector(Arc<Mutex<FaultState>>);
+
+impl FaultInjector {
+    /* the nth read only fills the start of its buffer and fails */
+    pub fn short_read_at(&self, n: usize) {
+        let mut state = self.0.lock();
+        state.short_read = Some(state.reads + n);
+    }
+
+    /* every write takes at least this long */
+    pub fn delay_writes(&self, delay: Duration) {
+        self.0.lock().write_delay = delay;
+    }
+
+    /* the nth write only gets its first keep bytes out before power is lost */
+    pub fn tear_write_at(&self, n: usize, keep: usize) {
+        let mut state = self.0.lock();
+        state.torn_write = Some((state.writes + n, keep));
+    }
+
+    /* power is lost right before the nth write */
+    pub fn power_loss_at(&self, n: usize) {
+        let mut state = self.0.lock();
+        state.power_loss = Some(state.writes + n);
+    }
+
+    pub fn is_powered_off(&self) -> bool {
+        self.0.lock().powered_off
+    }
+
+    /* like rebooting the machine, the source works again and all faults are gone */
+    pub fn restore_power(&self) {
+        let mut state = self.0.lock();
+        *state = FaultState {
+            reads: state.reads,
+            writes: state.writes,
+            ..FaultState::default()
+        };
+    }
+}
+
+/*
+ * Wraps a source and breaks it on purpose, so that whatever sits on top can
+ * be checked for how it recovers. Writes reach the inner source right away,
+ * but the old contents are kept until the next flush. A power loss puts
+ * them back, the inner source then holds exactly what a real device would
+ * have after the crash.
+ */
+pub struct FaultySource<S: Source> {
+    inner: S,
+    faults: FaultInjector,
+    /* shared with the flushes in flight, which clear it once they're done */
+    undo: Arc<Mutex<Vec<(usize, Vec<u8>)>>>,
+}
+
+impl<S: Source> FaultySource<S> {
+    pub fn new(inner: S) -> Self {
+        FaultySource {
+            inner,
+            faults: FaultInjector::default(),
+            undo: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn injector(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+
+    /* the inner source, as it would be found after a reboot */
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /* throws away everything written since the last flush */
+    fn lose_power(&mut self, state: &mut FaultState) -> Result<()> {
+        state.powered_off = true;
+        let undo = std::mem::take(&mut *self.undo.lock());
+        for (offset, old) in undo.into_iter().rev() {
+            self.inner.write(offset, &old)?;
+        }
+        Ok(())
+    }
+
+    fn write_undoable(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.save(offset, data.len())?;
+        self.inner.write(offset, data)
+    }
+
+    /* what's about to be overwritten, for a power loss to put back */
+    fn save(&self, offset: usize, len: usize) -> Result<()> {
+        let mut old = vec![0; len];
+        self.inner.read(offset, &mut old)?;
+        self.undo.lock().push((offset, old));
+        Ok(())
+    }
+
+    fn inner_async(&self) -> &dyn AsyncSource {
+        self.inner
+            .as_async()
+            .expect("only handed out when the inner source is async")
+    }
+}
+
+impl<S: Source> Source for FaultySource<S> {
+    /* direct access to the memory would bypass all the faults */
+    fn capabilities(&self) -> SourceCapabilities {
+        self.inner.capabilities()
+            & (SourceCapabilities::PERSISTENT
+                | SourceCapabilities::DISCARD
+                | SourceCapabilities::ASYNC)
+    }
+
+    fn perf_level(&self) -> usize {
+        self.inner.perf_level()
+    }
+
+    fn close(&mut self) {
+        self.inner.close()
+    }
+
+    fn length(&self) -> Result<usize> {
+        self.inner.length()
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let mut state = self.faults.0.lock();
+        if state.powered_off {
+            return Err(Error::PowerLoss {});
+        }
+
+        let n = state.reads;
+        state.reads += 1;
+        if state.short_read == Some(n) {
+            let half = data.len() / 2;
+            self.inner.read(offset, &mut data[..half])?;
+            return Err(Error::PartialIO {});
+        }
+
+        self.inner.read(offset, data)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        let faults = self.faults.clone();
+        let mut state = faults.0.lock();
+        if state.powered_off {
+            return Err(Error::PowerLoss {});
+        }
+        std::thread::sleep(state.write_delay);
+
+        let n = state.writes;
+        state.writes += 1;
+        if state.power_loss == Some(n) {
+            self.lose_power(&mut state)?;
+            return Err(Error::PowerLoss {});
+        }
+        if let Some((torn, keep)) = state.torn_write {
+            if torn == n {
+                self.lose_power(&mut state)?;
+                let keep = std::cmp::min(keep, data.len());
+                self.inner.write(offset, &data[..keep])?;
+                return Err(Error::PowerLoss {});
+            }
+        }
+
+        self.write_undoable(offset, data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.faults.is_powered_off() {
+            return Err(Error::PowerLoss {});
+        }
+
+        let undone = self.undo.lock().len();
+        self.inner.flush()?;
+        durable(&self.undo, undone);
+        Ok(())
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        if self.faults.is_powered_off() {
+            return Err(Error::PowerLoss {});
+        }
+
+        let undone = self.undo.lock().len();
+        self.inner.sync(mode)?;
+        if mode != SyncMode::None {
+            durable(&self.undo, undone);
+        }
+        Ok(())
+    }
+
+    /* the discarded contents come back with a power loss, like written ones */
+    fn discard(&mut self, offset: usize, len: usize) -> Result<()> {
+        if self.faults.is_powered_off() {
+            return Err(Error::PowerLoss {});
+        }
+
+        self.save(offset, len)?;
+        self.inner.discard(offset, len)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.inner.lock(exclusive)
+    }
+
+    fn resize(&mut self, len: usize) -> Result<()> {
+        self.inner.resize(len)
+    }
+
+    fn as_async(&self) -> Option<&dyn AsyncSource> {
+        self.inner.as_async().map(|_| self as &dyn AsyncSource)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+/*
+ * Faults hit asynchronous I/O when it's issued, in the same order as the
+ * blocking kind. What a power loss puts back is written synchronously.
+ */
+impl<S: Source> AsyncSource for FaultySource<S> {
+    fn read_async(&self, offset: usize, len: usize) -> IoFuture<Vec<u8>> {
+        let mut state = self.faults.0.lock();
+        if state.powered_off {
+            return Box::pin(std::future::ready(Err(Error::PowerLoss {})));
+        }
+
+        let n = state.reads;
+        state.reads += 1;
+        if state.short_read == Some(n) {
+            return Box::pin(std::future::ready(Err(Error::PartialIO {})));
+        }
+
+        self.inner_async().read_async(offset, len)
+    }
+
+    fn write_async(&self, offset: usize, data: Vec<u8>) -> IoFuture<()> {
+        let inner = self.inner_async();
+        let mut state = self.faults.0.lock();
+        if state.powered_off {
+            return Box::pin(std::future::ready(Err(Error::PowerLoss {})));
+        }
+        std::thread::sleep(state.write_delay);
+
+        let n = state.writes;
+        state.writes += 1;
+        let torn = match state.torn_write {
+            Some((torn, keep)) if torn == n => Some(keep),
+            _ => None,
+        };
+        if state.power_loss == Some(n) || torn.is_some() {
+            state.powered_off = true;
+            let undo = std::mem::take(&mut *self.undo.lock());
+            let mut lost = undo.into_iter().rev().collect::<Vec<_>>();
+            if let Some(keep) = torn {
+                let keep = std::cmp::min(keep, data.len());
+                lost.push((offset, data[..keep].to_vec()));
+            }
+            let restored = lost
+                .into_iter()
+                .try_for_each(|(offset, old)| block_on(inner.write_async(offset, old)));
+            return Box::pin(std::future::ready(restored.and(Err(Error::PowerLoss {}))));
+        }
+
+        match self.save(offset, data.len()) {
+            Ok(()) => inner.write_async(offset, data),
+            Err(err) => Box::pin(std::future::ready(Err(err))),
+        }
+    }
+
+    fn flush_async(&self) -> IoFuture<()> {
+        if self.faults.is_powered_off() {
+            return Box::pin(std::future::ready(Err(Error::PowerLoss {})));
+        }
+
+        let undone = self.undo.lock().len();
+        let undo = self.undo.clone();
+        let flush = self.inner_async().flush_async();
+        Box::pin(async move {
+            flush.await?;
+            durable(&undo, undone);
+            Ok(())
+        })
+    }
+}
+
+/* the writes that came before a flush are durable, there's nothing to undo anymore */
+fn durable(undo: &Mutex<Vec<(usize, Vec<u8>)>>, undone: usize) {
+    let mut undo = undo.lock();
+    let undone = std::cmp::min(undone, undo.len());
+    undo.drain(..undone);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{Page, ReplaySource, SourceAllocator};
+
+    fn contents(source: &impl Source, offset: usize) -> Result<[u8; 512]> {
+        let mut data = [0; 512];
+        source.read(offset, &mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn crashes() -> Result<()> {
+        let mut source = FaultySource::new(ReplaySource::new(vec![0; 1 << 16], &[]));
+        let faults = source.injector();
+
+        source.write(0, &[1; 512])?;
+        source.flush()?;
+        source.write(512, &[2; 512])?;
+        faults.tear_write_at(1, 100);
+        source.write(1024, &[3; 512])?;
+        assert!(matches!(
+            source.write(2048, &[4; 512]),
+            Err(Error::PowerLoss {})
+        ));
+        assert!(matches!(
+            source.read(0, &mut [0; 512]),
+            Err(Error::PowerLoss {})
+        ));
+
+        let image = source.into_inner();
+        assert_eq!(contents(&image, 0)?, [1; 512]);
+        assert_eq!(contents(&image, 512)?, [0; 512]);
+        assert_eq!(contents(&image, 1024)?, [0; 512]);
+        assert!(contents(&image, 2048)?[..100].iter().all(|b| *b == 4));
+        assert!(contents(&image, 2048)?[100..].iter().all(|b| *b == 0));
+
+        let source = FaultySource::new(image);
+        let faults = source.injector();
+        faults.short_read_at(1);
+        source.read(0, &mut [0; 512])?;
+        assert!(matches!(
+            source.read(0, &mut [0; 512]),
+            Err(Error::PartialIO {})
+        ));
+
+        let allocator = SourceAllocator::new(
+            Box::new(source),
+            4096,
+            |_| 0,
+            false,
+            false,
+            SyncMode::default(),
+        )?;
+        let page: Page = allocator.allocate_page()?;
+        faults.power_loss_at(0);
+        assert!(matches!(
+            allocator.write_from(&page, 0, &[5; 512]),
+            Err(Error::PowerLoss {})
+        ));
+
+        /* the allocator doesn't try again, even though the source would work */
+        faults.restore_power();
+        assert!(matches!(
+            allocator.write_from(&page, 0, &[5; 512]),
+            Err(Error::PowerLoss {})
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn passes_through() -> Result<()> {
+        use crate::source::FileSource;
+
+        let path = std::env::temp_dir().join(format!("librarius-faulty-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut source = FaultySource::new(FileSource::new(path, 1 << 16)?);
+        let faults = source.injector();
+        assert!(source.capabilities().contains(SourceCapabilities::ASYNC));
+
+        /* only a sync that asks for durability keeps a write from being undone */
+        source.write(0, &[1; 512])?;
+        source.sync(SyncMode::None)?;
+        source.write(512, &[2; 512])?;
+        source.sync(SyncMode::Fsync)?;
+        block_on(source.as_async().unwrap().write_async(1024, vec![3; 512]))?;
+        source.discard(0, 512)?;
+        faults.power_loss_at(0);
+        assert!(matches!(
+            source.write(2048, &[4; 512]),
+            Err(Error::PowerLoss {})
+        ));
+        assert!(matches!(
+            block_on(source.as_async().unwrap().read_async(0, 512)),
+            Err(Error::PowerLoss {})
+        ));
+
+        let image = source.into_inner();
+        assert_eq!(contents(&image, 0)?, [1; 512]);
+        assert_eq!(contents(&image, 512)?, [2; 512]);
+        assert_eq!(contents(&image, 1024)?, [0; 512]);
+        drop(image);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
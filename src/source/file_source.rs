@@ -1,9 +1,71 @@
 use crate::error::{Error, Result};
-use std::{fs, io::{prelude::*, SeekFrom}};
-use crate::source::Source;
+use crate::source::{lock_file, Source, SourceCapabilities, SyncMode};
+#[cfg(any(unix, windows))]
+use crate::source::{AsyncSource, IoFuture, IoQueue};
+use std::fs;
+#[cfg(not(any(unix, windows)))]
+use std::io::{prelude::*, SeekFrom};
+#[cfg(all(feature = "vectored", target_os = "linux"))]
+use std::io::{IoSlice, IoSliceMut};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(all(
+    any(feature = "punch", feature = "prealloc", feature = "vectored"),
+    target_os = "linux"
+))]
+use std::os::unix::io::AsRawFd;
+#[cfg(any(unix, windows))]
+use std::sync::Arc;
+#[cfg(windows)]
+use std::{io, os::windows::fs::FileExt};
+
+/* UIO_MAXIOV, the most iovecs a single preadv or pwritev takes */
+#[cfg(all(feature = "vectored", target_os = "linux"))]
+const IOV_MAX: usize = 1024;
+
+/*
+ * Windows only has seek_read and seek_write, which can transfer less than
+ * was asked for, so they have to be retried until the whole buffer is done.
+ */
+#[cfg(windows)]
+trait PositionalExt {
+    fn read_exact_at(&self, data: &mut [u8], offset: u64) -> io::Result<()>;
+    fn write_all_at(&self, data: &[u8], offset: u64) -> io::Result<()>;
+}
+
+#[cfg(windows)]
+impl PositionalExt for fs::File {
+    fn read_exact_at(&self, mut data: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !data.is_empty() {
+            match self.seek_read(data, offset)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => {
+                    data = &mut data[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+        while !data.is_empty() {
+            match self.seek_write(data, offset)? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n => {
+                    data = &data[n..];
+                    offset += n as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 pub struct FileSource {
     file: std::fs::File,
+    #[cfg(any(unix, windows))]
+    io: (Arc<fs::File>, IoQueue),
 }
 
 impl FileSource {
@@ -14,27 +76,237 @@ impl FileSource {
             .create(true)
             .truncate(false)
             .open(path)
-            .map_err(|err| Error::SourceError { })?;
+            .map_err(|err| Error::SourceError {})?;
 
         file.set_len(len as u64)
             .map_err(|err| Error::FileIO { err })?;
 
-        Ok(FileSource { file })
+        Self::from_file(file)
+    }
+
+    /*
+     * Like new(), but also reserves all the blocks of the file up front, so
+     * running out of space can only happen here and never in the middle of
+     * a transaction. A file created by this is removed again on failure.
+     */
+    pub fn new_preallocated(path: &str, len: usize) -> Result<Self> {
+        let created = !std::path::Path::new(path).exists();
+        let mut source = Self::new(path, len)?;
+
+        if let Err(err) = source.preallocate(len) {
+            if created {
+                let _ = fs::remove_file(path);
+            }
+            return Err(err);
+        }
+
+        Ok(source)
+    }
+
+    #[cfg(all(feature = "prealloc", target_os = "linux"))]
+    fn preallocate(&mut self, len: usize) -> Result<()> {
+        let fd = self.file.as_raw_fd();
+        match unsafe { libc::posix_fallocate(fd, 0, len as libc::off_t) } {
+            0 => Ok(()),
+            libc::ENOSPC => Err(Error::NoSpaceOnDevice {}),
+            errno => Err(Error::FileIO {
+                err: std::io::Error::from_raw_os_error(errno),
+            }),
+        }
+    }
+
+    /*
+     * Writing every block back as it is makes the filesystem allocate the
+     * holes without touching any data that's already in the file.
+     */
+    #[cfg(not(all(feature = "prealloc", target_os = "linux")))]
+    fn preallocate(&mut self, len: usize) -> Result<()> {
+        const CHUNK: usize = 1 << 20;
+
+        let mut data = vec![0; CHUNK];
+        for offset in (0..len).step_by(CHUNK) {
+            let chunk = &mut data[..std::cmp::min(CHUNK, len - offset)];
+            self.read(offset, chunk)?;
+            self.write(offset, chunk).map_err(|err| match err {
+                Error::FileIO { err } if err.kind() == std::io::ErrorKind::StorageFull => {
+                    Error::NoSpaceOnDevice {}
+                }
+                err => err,
+            })?;
+        }
+        self.file.sync_data().map_err(|err| Error::FileIO { err })
+    }
+
+    /*
+     * Runs preadv or pwritev until all of iov is transferred, picking up
+     * after short transfers in the middle of a buffer.
+     */
+    #[cfg(all(feature = "vectored", target_os = "linux"))]
+    fn transfer_vectored<F>(&self, mut offset: usize, iov: &mut [libc::iovec], op: F) -> Result<()>
+    where
+        F: Fn(libc::c_int, *const libc::iovec, libc::c_int, libc::off_t) -> libc::ssize_t,
+    {
+        let fd = self.file.as_raw_fd();
+        let mut start = 0;
+        while start < iov.len() {
+            let count = std::cmp::min(iov.len() - start, IOV_MAX);
+            let ret = op(
+                fd,
+                iov[start..].as_ptr(),
+                count as libc::c_int,
+                offset as libc::off_t,
+            );
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(Error::FileIO { err });
+            }
+            if ret == 0 {
+                return Err(Error::PartialIO {});
+            }
+
+            let mut done = ret as usize;
+            offset += done;
+            while start < iov.len() && done >= iov[start].iov_len {
+                done -= iov[start].iov_len;
+                start += 1;
+            }
+            if done > 0 {
+                let base = iov[start].iov_base as *mut u8;
+                iov[start].iov_base = unsafe { base.add(done) } as *mut libc::c_void;
+                iov[start].iov_len -= done;
+            }
+        }
+        Ok(())
+    }
+
+    /* opens a file that already exists, as big as it is */
+    pub fn open(path: &str) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|err| Error::FileIO { err })?;
+
+        Self::from_file(file)
+    }
+
+    fn from_file(file: fs::File) -> Result<Self> {
+        Ok(FileSource {
+            #[cfg(any(unix, windows))]
+            io: (
+                Arc::new(file.try_clone().map_err(|err| Error::FileIO { err })?),
+                IoQueue::new(),
+            ),
+            file,
+        })
+    }
+}
+
+/*
+ * The async path runs on its own handle, so its requests never wait for
+ * the synchronous ones, or the other way around.
+ */
+#[cfg(any(unix, windows))]
+impl AsyncSource for FileSource {
+    fn read_async(&self, offset: usize, len: usize) -> IoFuture<Vec<u8>> {
+        let file = self.io.0.clone();
+        self.io.1.submit(move || {
+            let mut data = vec![0; len];
+            file.read_exact_at(&mut data, offset as u64)
+                .map_err(|err| Error::FileIO { err })?;
+            Ok(data)
+        })
+    }
+
+    fn write_async(&self, offset: usize, data: Vec<u8>) -> IoFuture<()> {
+        let file = self.io.0.clone();
+        self.io.1.submit(move || {
+            file.write_all_at(&data, offset as u64)
+                .map_err(|err| Error::FileIO { err })
+        })
+    }
+
+    fn flush_async(&self) -> IoFuture<()> {
+        let file = self.io.0.clone();
+        self.io
+            .1
+            .submit(move || file.sync_data().map_err(|err| Error::FileIO { err }))
     }
 }
 
 impl Source for FileSource {
-    fn is_byte_addressable(&self) -> bool {
-        false
+    fn capabilities(&self) -> SourceCapabilities {
+        let mut capabilities = SourceCapabilities::PERSISTENT;
+        capabilities.set(SourceCapabilities::ASYNC, cfg!(any(unix, windows)));
+        capabilities.set(
+            SourceCapabilities::DISCARD,
+            cfg!(all(feature = "punch", target_os = "linux")),
+        );
+        capabilities
     }
 
     fn close(&mut self) {}
 
-    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<()> {
+    /*
+     * Positional I/O doesn't move a shared file position, so any number of
+     * readers, and the async path, can use the file at the same time.
+     */
+    #[cfg(any(unix, windows))]
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
         self.file
-            .seek(SeekFrom::Start(offset as u64))
+            .read_exact_at(data, offset as u64)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::UnexpectedEof => Error::PartialIO {},
+                _ => Error::FileIO { err },
+            })
+    }
+
+    #[cfg(any(unix, windows))]
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.file
+            .write_all_at(data, offset as u64)
+            .map_err(|err| Error::FileIO { err })
+    }
+
+    #[cfg(all(feature = "vectored", target_os = "linux"))]
+    fn read_vectored(&self, offset: usize, bufs: &mut [IoSliceMut<'_>]) -> Result<()> {
+        let mut iov: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .filter(|buf| !buf.is_empty())
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        self.transfer_vectored(offset, &mut iov, |fd, iov, count, offset| unsafe {
+            libc::preadv(fd, iov, count, offset)
+        })
+    }
+
+    #[cfg(all(feature = "vectored", target_os = "linux"))]
+    fn write_vectored(&mut self, offset: usize, bufs: &[IoSlice<'_>]) -> Result<()> {
+        let mut iov: Vec<libc::iovec> = bufs
+            .iter()
+            .filter(|buf| !buf.is_empty())
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        self.transfer_vectored(offset, &mut iov, |fd, iov, count, offset| unsafe {
+            libc::pwritev(fd, iov, count, offset)
+        })
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset as u64))
             .map_err(|err| Error::FileIO { err })?;
-        let n = self.file.read(data).map_err(|err| Error::FileIO { err })?;
+        let n = file.read(data).map_err(|err| Error::FileIO { err })?;
         if n == data.len() {
             Ok(())
         } else {
@@ -42,6 +314,7 @@ impl Source for FileSource {
         }
     }
 
+    #[cfg(not(any(unix, windows)))]
     fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
         self.file
             .seek(SeekFrom::Start(offset as u64))
@@ -55,7 +328,17 @@ impl Source for FileSource {
     }
 
     fn flush(&mut self) -> Result<()> {
-        self.file.flush().map_err(|err| Error::FileIO { err })
+        self.file.sync_data().map_err(|err| Error::FileIO { err })
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        match mode {
+            SyncMode::None => Ok(()),
+            SyncMode::Fdatasync => self.flush(),
+            SyncMode::Fsync | SyncMode::OSync => {
+                self.file.sync_all().map_err(|err| Error::FileIO { err })
+            }
+        }
     }
 
     fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
@@ -75,9 +358,40 @@ impl Source for FileSource {
         0
     }
 
-    fn is_persistent(&self) -> bool {
-        true
+    #[cfg(all(feature = "punch", target_os = "linux"))]
+    fn discard(&mut self, offset: usize, len: usize) -> Result<()> {
+        let ret = unsafe {
+            libc::fallocate(
+                self.file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                len as libc::off_t,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::FileIO {
+                err: std::io::Error::last_os_error(),
+            })
+        }
+    }
+
+    fn resize(&mut self, len: usize) -> Result<()> {
+        self.file
+            .set_len(len as u64)
+            .map_err(|err| Error::FileIO { err })
     }
+
+    #[cfg(any(unix, windows))]
+    fn as_async(&self) -> Option<&dyn AsyncSource> {
+        Some(self)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        lock_file(&self.file, exclusive)
+    }
+
     fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
         Err(Error::NotByteAddressable {})
     }
@@ -85,3 +399,166 @@ impl Source for FileSource {
         Err(Error::NotByteAddressable {})
     }
 }
+
+#[cfg(all(test, feature = "punch", target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::source::{IoOp, MemorySource, SourceAllocator, TracingSource};
+    use crate::{LibrariusBuilder, ObjectSize};
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn punched_free_pages() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-punch-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let blocks = || -> Result<u64> {
+            let meta = fs::metadata(path).map_err(|err| Error::FileIO { err })?;
+            Ok(meta.blocks())
+        };
+        fs::write(path, vec![0; 1 << 20]).map_err(|err| Error::FileIO { err })?;
+        let written = blocks()?;
+
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&[5; 8]);
+                Ok(())
+            })
+            .punch_holes()
+            .source(MemorySource::new(1 << 20)?)
+            .source(FileSource::new(path, 1 << 20)?)
+            .open()?;
+        librarius.close(std::time::Duration::from_secs(1))?;
+        assert!(blocks()? < written / 2);
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn preallocated() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-prealloc-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let source = FileSource::new_preallocated(path, 1 << 20)?;
+        assert_eq!(source.length()?, 1 << 20);
+        let meta = fs::metadata(path).map_err(|err| Error::FileIO { err })?;
+        assert!(meta.blocks() * 512 >= 1 << 20);
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn sync_modes() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-sync-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        for (mode, flushes) in [(SyncMode::Fdatasync, 1), (SyncMode::OSync, 2)] {
+            let source = TracingSource::new(FileSource::new(path, 1 << 16)?, 64);
+            let trace = source.trace();
+            let allocator =
+                SourceAllocator::new(Box::new(source), 4096, |_| 0, false, false, mode)?;
+            let pages = [allocator.allocate_page()?, allocator.allocate_page()?];
+
+            trace.clear();
+            allocator.write_batch(&[(pages[0], &[1; 4096]), (pages[1], &[2; 4096])])?;
+            let events = trace.events();
+            assert_eq!(
+                events.iter().filter(|e| e.op == IoOp::Sync).count(),
+                flushes
+            );
+        }
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn concurrent_reads() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-pread-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let source = FileSource::new(path, 1 << 16)?;
+        let allocator =
+            SourceAllocator::new(Box::new(source), 4096, |_| 0, false, false, SyncMode::None)?;
+        let pages: Vec<_> = (0..4u8)
+            .map(|n| {
+                let page = allocator.allocate_page()?;
+                allocator.write_from(&page, 0, &[n; 4096])?;
+                Ok((page, n))
+            })
+            .collect::<Result<_>>()?;
+
+        std::thread::scope(|scope| {
+            for (page, n) in &pages {
+                let allocator = &allocator;
+                scope.spawn(move || {
+                    let mut data = [0; 4096];
+                    for _ in 0..100 {
+                        allocator.read_into(page, 0, &mut data).unwrap();
+                        assert!(data.iter().all(|b| b == n));
+                    }
+                });
+            }
+        });
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn vectored_batches() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-iov-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut source = FileSource::new(path, 1 << 16)?;
+        let pages: Vec<Vec<u8>> = (1..5u8).map(|n| vec![n; 4096]).collect();
+        let offsets = [0, 4096, 8192, 5 * 4096];
+        let writes: Vec<(usize, &[u8])> = offsets
+            .iter()
+            .zip(&pages)
+            .map(|(offset, page)| (*offset, page.as_slice()))
+            .collect();
+        source.write_batch(&writes)?;
+
+        let mut data = vec![vec![0; 4096]; 4];
+        let mut reads: Vec<(usize, &mut [u8])> = offsets
+            .iter()
+            .zip(data.iter_mut())
+            .map(|(offset, page)| (*offset, page.as_mut_slice()))
+            .collect();
+        source.read_batch(&mut reads)?;
+        assert_eq!(data, pages);
+
+        let mut gap = [0xff; 4096];
+        source.read(3 * 4096, &mut gap)?;
+        assert!(gap.iter().all(|b| *b == 0));
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+
+    #[test]
+    fn locked_by_another_opener() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-lock-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let open = |read_only| {
+            let source = FileSource::new(path, 1 << 16)?;
+            SourceAllocator::new(
+                Box::new(source),
+                4096,
+                |_| 0,
+                read_only,
+                false,
+                SyncMode::default(),
+            )
+        };
+
+        let writer = open(false)?;
+        assert!(matches!(open(false), Err(Error::PoolBusy {})));
+        assert!(matches!(open(true), Err(Error::PoolBusy {})));
+        drop(writer);
+
+        let readers = [open(true)?, open(true)?];
+        assert!(matches!(open(false), Err(Error::PoolBusy {})));
+        drop(readers);
+        open(false)?;
+
+        fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
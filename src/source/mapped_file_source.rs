@@ -0,0 +1,154 @@
+use crate::error::{Error, Result};
+use crate::source::memory_source::MemoryMap;
+use crate::source::{lock_file, Source, SourceCapabilities};
+use crate::utils::math;
+use std::fs;
+
+/*
+ * Objects in a mapped file are accessed in place, and made durable by
+ * msync'ing the pages they live on. Slower than DRAM, but it saves the
+ * fetch/flush copies a block source needs.
+ */
+pub struct MappedFileSource {
+    map: MemoryMap<'static>,
+    file: fs::File,
+}
+
+impl MappedFileSource {
+    pub fn new(path: &str, len: usize) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| Error::SourceError {})?;
+
+        file.set_len(len as u64)
+            .map_err(|err| Error::FileIO { err })?;
+
+        let map = MemoryMap::from_file(&file, len)?;
+
+        Ok(MappedFileSource { map, file })
+    }
+
+    fn msync(&self, offset: usize, len: usize) -> Result<()> {
+        let ospage = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let start = math::align_down(offset, ospage);
+        let end = std::cmp::min(math::align_up(offset + len, ospage), self.map.len());
+        let data = self
+            .map
+            .at(start, end - start)
+            .ok_or(Error::InvalidMemory {})?;
+
+        let ret = unsafe {
+            libc::msync(
+                data.as_ptr() as *mut libc::c_void,
+                data.len(),
+                libc::MS_SYNC,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::FileIO {
+                err: std::io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Source for MappedFileSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities::BYTE_ADDRESSABLE | SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        50
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.map.len())
+    }
+
+    fn read(&self, offset: usize, dst: &mut [u8]) -> Result<()> {
+        let src = self
+            .map
+            .at(offset, dst.len())
+            .ok_or(Error::InvalidMemory {})?;
+
+        dst.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, src: &[u8]) -> Result<()> {
+        let dst = self
+            .map
+            .at_mut(offset, src.len())
+            .ok_or(Error::InvalidMemory {})?;
+
+        dst.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.msync(0, self.map.len())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        lock_file(&self.file, exclusive)
+    }
+
+    fn at(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.map.at(offset, len).ok_or(Error::InvalidMemory {})
+    }
+
+    fn at_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.map.at_mut(offset, len).ok_or(Error::InvalidMemory {})
+    }
+
+    fn offset(&mut self, ptr: *const u8) -> Result<usize> {
+        let off = self.map.offset(ptr);
+        if off >= 0 && (off as usize) < self.map.len() {
+            Ok(off as usize)
+        } else {
+            Err(Error::InvalidMemory {})
+        }
+    }
+
+    fn flush_slice(&self, slice: &[u8]) -> Result<()> {
+        let off = self.map.offset(slice.as_ptr());
+        if off < 0 || off as usize + slice.len() > self.map.len() {
+            return Err(Error::InvalidMemory {});
+        }
+
+        self.msync(off as usize, slice.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reopen() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-mapped-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let mut source = MappedFileSource::new(path, 1 << 16)?;
+            let data = source.at_mut(8192, 64)?;
+            data.copy_from_slice(&[3; 64]);
+            let data = source.at(8192, 64)?;
+            source.flush_slice(data)?;
+        }
+
+        let source = MappedFileSource::new(path, 1 << 16)?;
+        assert!(source.at(8192, 64)?.iter().all(|b| *b == 3));
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
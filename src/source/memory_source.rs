@@ -1,11 +1,36 @@
 use crate::error::{Error, Result};
-use crate::source::Source;
-use errno;
-use libc;
-use std::ptr;
+use crate::source::{Source, SourceCapabilities};
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+use crate::utils::math;
+#[cfg(all(feature = "mmap", unix))]
+use std::{fs, os::unix::io::AsRawFd, ptr};
 
-struct MemoryMap<'a> {
+#[cfg(windows)]
+mod win32 {
+    use std::ffi::c_void;
+
+    pub const MEM_COMMIT: u32 = 0x1000;
+    pub const MEM_RESERVE: u32 = 0x2000;
+    pub const MEM_RELEASE: u32 = 0x8000;
+    pub const PAGE_READWRITE: u32 = 0x04;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn VirtualAlloc(
+            address: *mut c_void,
+            size: usize,
+            allocation_type: u32,
+            protect: u32,
+        ) -> *mut c_void;
+        pub fn VirtualFree(address: *mut c_void, size: usize, free_type: u32) -> i32;
+    }
+}
+
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+pub(crate) struct MemoryMap<'a> {
     data: &'a mut [u8],
+    owned: bool,
 }
 
 unsafe impl<'a> Send for MemoryMap<'a> {}
@@ -13,17 +38,122 @@ unsafe impl<'a> Sync for MemoryMap<'a> {}
 
 impl<'a> MemoryMap<'a> {
     fn from_existing(data: &'a mut [u8]) -> Self {
-        MemoryMap { data }
+        MemoryMap { data, owned: false }
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    fn new(len: usize) -> Result<Self> {
+        Self::map(len, libc::MAP_ANONYMOUS | libc::MAP_SHARED, -1)
     }
 
+    /* committed memory on Windows is zeroed just like an anonymous mapping */
+    #[cfg(windows)]
     fn new(len: usize) -> Result<Self> {
+        let ptr = unsafe {
+            win32::VirtualAlloc(
+                std::ptr::null_mut(),
+                len,
+                win32::MEM_RESERVE | win32::MEM_COMMIT,
+                win32::PAGE_READWRITE,
+            )
+        };
+
+        if ptr.is_null() {
+            Err(Error::VirtualAlloc {
+                err: std::io::Error::last_os_error(),
+            })
+        } else {
+            Ok(MemoryMap {
+                data: unsafe { std::slice::from_raw_parts_mut(ptr as *mut u8, len) },
+                owned: true,
+            })
+        }
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    pub(crate) fn from_file(file: &fs::File, len: usize) -> Result<Self> {
+        Self::map(len, libc::MAP_SHARED, file.as_raw_fd())
+    }
+
+    /*
+     * Huge pages have to be reserved up front by the administrator, if
+     * there are none to spare this falls back to regular pages and asks for
+     * transparent huge pages instead.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    fn new_hugepages(len: usize) -> Result<Self> {
+        let flags = libc::MAP_ANONYMOUS | libc::MAP_SHARED;
+        if let Ok(map) = Self::map(len, flags | libc::MAP_HUGETLB, -1) {
+            return Ok(map);
+        }
+
+        let map = Self::map(len, flags, -1)?;
+        unsafe {
+            libc::madvise(
+                map.data.as_ptr() as *mut libc::c_void,
+                len,
+                libc::MADV_HUGEPAGE,
+            );
+        }
+        Ok(map)
+    }
+
+    /*
+     * The policy is set before anything touches the memory, so every page
+     * gets faulted in on the requested node.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    fn new_on_node(len: usize, node: usize) -> Result<Self> {
+        const MPOL_BIND: libc::c_int = 2;
+
+        let map = Self::new(len)?;
+
+        let bits = 8 * std::mem::size_of::<libc::c_ulong>();
+        let mut nodemask = vec![0 as libc::c_ulong; node / bits + 1];
+        nodemask[node / bits] |= 1 << (node % bits);
+
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mbind,
+                map.data.as_ptr(),
+                len,
+                MPOL_BIND,
+                nodemask.as_ptr(),
+                nodemask.len() * bits,
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::MemoryAlloc {
+                errno: errno::errno(),
+            });
+        }
+
+        Ok(map)
+    }
+
+    /*
+     * MAP_SYNC only succeeds on DAX, where it guarantees that flushing the
+     * CPU caches is all it takes to make a store durable.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    pub(crate) fn from_file_sync(file: &fs::File, len: usize) -> Result<Self> {
+        Self::map(
+            len,
+            libc::MAP_SHARED_VALIDATE | libc::MAP_SYNC,
+            file.as_raw_fd(),
+        )
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    fn map(len: usize, flags: libc::c_int, fd: libc::c_int) -> Result<Self> {
         let ptr = unsafe {
             libc::mmap(
                 ptr::null_mut(),
                 len as libc::size_t,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_ANONYMOUS | libc::MAP_SHARED,
-                -1,
+                flags,
+                fd,
                 0,
             )
         };
@@ -35,11 +165,22 @@ impl<'a> MemoryMap<'a> {
         } else {
             Ok(MemoryMap {
                 data: unsafe { std::slice::from_raw_parts_mut(ptr as *mut u8, len) },
+                owned: true,
             })
         }
     }
 
-    fn at(&self, offset: usize, len: usize) -> Option<&[u8]> {
+    /*
+     * Without mmap (e.g., on wasm32) the memory comes from the global
+     * allocator instead. It's released in drop by rebuilding the box.
+     */
+    #[cfg(not(any(all(feature = "mmap", unix), windows)))]
+    fn new(len: usize) -> Result<Self> {
+        let data = Box::leak(vec![0u8; len].into_boxed_slice());
+        Ok(MemoryMap { data, owned: true })
+    }
+
+    pub(crate) fn at(&self, offset: usize, len: usize) -> Option<&[u8]> {
         if offset + len > self.data.len() {
             return None;
         }
@@ -48,7 +189,7 @@ impl<'a> MemoryMap<'a> {
         Some(&self.data[offset..end])
     }
 
-    fn at_mut(&mut self, offset: usize, len: usize) -> Option<&mut [u8]> {
+    pub(crate) fn at_mut(&mut self, offset: usize, len: usize) -> Option<&mut [u8]> {
         if offset + len > self.data.len() {
             return None;
         }
@@ -57,19 +198,68 @@ impl<'a> MemoryMap<'a> {
         Some(&mut self.data[offset..end])
     }
 
-    fn offset(&self, ptr: *const u8) -> isize {
+    pub(crate) fn offset(&self, ptr: *const u8) -> isize {
         let base = self.data.as_ptr() as isize;
         let offptr = ptr as isize;
         offptr - base
     }
 
-    fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         self.data.len()
     }
 }
 
+/* the nodes listed in a sysfs node list, e.g., "0-1,4" */
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+fn parse_nodes(list: &str) -> Option<Vec<usize>> {
+    let mut nodes = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (first, last): (usize, usize) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => (range.parse().ok()?, range.parse().ok()?),
+        };
+        nodes.extend(first..=last);
+    }
+    Some(nodes)
+}
+
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub(crate) fn online_nodes() -> Result<Vec<usize>> {
+    let list = std::fs::read_to_string("/sys/devices/system/node/online")
+        .map_err(|err| Error::FileIO { err })?;
+    parse_nodes(&list).ok_or(Error::InvalidSource {})
+}
+
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub(crate) fn current_node() -> Option<usize> {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu as *mut libc::c_uint,
+            &mut node as *mut libc::c_uint,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if ret == 0 {
+        Some(node as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(all(feature = "mmap", target_os = "linux")))]
+pub(crate) fn current_node() -> Option<usize> {
+    None
+}
+
 impl<'a> Drop for MemoryMap<'a> {
+    #[cfg(all(feature = "mmap", unix))]
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
         unsafe {
             libc::munmap(
                 self.data.as_mut_ptr() as *mut core::ffi::c_void,
@@ -77,11 +267,33 @@ impl<'a> Drop for MemoryMap<'a> {
             );
         }
     }
+
+    #[cfg(windows)]
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        unsafe {
+            win32::VirtualFree(self.data.as_mut_ptr() as *mut _, 0, win32::MEM_RELEASE);
+        }
+    }
+
+    #[cfg(not(any(all(feature = "mmap", unix), windows)))]
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        let data = std::mem::take(&mut self.data);
+        unsafe {
+            drop(Box::from_raw(data as *mut [u8]));
+        }
+    }
 }
 
 pub struct MemorySource<'a> {
     map: MemoryMap<'a>,
     persistent: bool,
+    node: Option<usize>,
 }
 
 impl<'a> MemorySource<'a> {
@@ -90,28 +302,75 @@ impl<'a> MemorySource<'a> {
         Ok(MemorySource {
             map,
             persistent: false,
+            node: None,
+        })
+    }
+
+    /*
+     * Uses memory owned by the caller, e.g., a preallocated arena. Whatever
+     * was in it is cleared, leftovers could otherwise pass for live objects.
+     */
+    pub fn from_slice(data: &'a mut [u8]) -> Self {
+        data.fill(0);
+        MemorySource {
+            map: MemoryMap::from_existing(data),
+            persistent: false,
+            node: None,
+        }
+    }
+
+    /*
+     * Memory that's physically on the given NUMA node. Librarius prefers
+     * allocating from the sources that are local to the calling thread.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    pub fn new_on_node(len: usize, node: usize) -> Result<Self> {
+        let map = MemoryMap::new_on_node(len, node)?;
+        Ok(MemorySource {
+            map,
+            persistent: false,
+            node: Some(node),
+        })
+    }
+
+    /*
+     * For large DRAM caches, ideally together with a pagesize of
+     * HUGE_PAGE_SIZE. The length is rounded up to whole huge pages.
+     */
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    pub fn new_hugepages(len: usize) -> Result<Self> {
+        let map = MemoryMap::new_hugepages(math::align_up(len, HUGE_PAGE_SIZE))?;
+        Ok(MemorySource {
+            map,
+            persistent: false,
+            node: None,
         })
     }
 }
 
 impl<'a> Source for MemorySource<'a> {
-    fn is_byte_addressable(&self) -> bool {
-        true
-    }
-    fn is_persistent(&self) -> bool {
-        self.persistent
+    fn capabilities(&self) -> SourceCapabilities {
+        if self.persistent {
+            SourceCapabilities::BYTE_ADDRESSABLE | SourceCapabilities::PERSISTENT
+        } else {
+            SourceCapabilities::BYTE_ADDRESSABLE
+        }
     }
     fn perf_level(&self) -> usize {
         100
     }
 
+    fn numa_node(&self) -> Option<usize> {
+        self.node
+    }
+
     fn close(&mut self) {}
 
     fn length(&self) -> Result<usize> {
         Ok(self.map.len() as usize)
     }
 
-    fn read(&mut self, offset: usize, dst: &mut [u8]) -> Result<()> {
+    fn read(&self, offset: usize, dst: &mut [u8]) -> Result<()> {
         let len = dst.len();
         let src = self.map.at(offset, len).ok_or(Error::InvalidMemory {})?;
 
@@ -157,3 +416,66 @@ impl<'a> Source for MemorySource<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LibrariusBuilder, ObjectSize};
+
+    #[test]
+    fn caller_buffer() -> Result<()> {
+        let mut arena = vec![0xff; 1 << 20];
+        {
+            let librarius = LibrariusBuilder::new()
+                .create_with(ObjectSize::new(0, 8), |data| {
+                    data.copy_from_slice(&[5; 8]);
+                    Ok(())
+                })
+                .source(MemorySource::from_slice(&mut arena))
+                .open()?;
+            librarius.run(|tx| tx.alloc(ObjectSize::new(0, 8)).map(|_| ()))?;
+        }
+        assert!(arena.iter().any(|b| *b == 5));
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn hugepages() -> Result<()> {
+        let source = MemorySource::new_hugepages(7 << 20)?;
+        assert_eq!(source.length()?, 4 * HUGE_PAGE_SIZE);
+
+        let librarius = LibrariusBuilder::new()
+            .pagesize(HUGE_PAGE_SIZE)
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&[5; 8]);
+                Ok(())
+            })
+            .source(source)
+            .open()?;
+        librarius.run(|tx| tx.alloc(ObjectSize::new(0, 1 << 20)).map(|_| ()))
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn numa_nodes() -> Result<()> {
+        assert_eq!(parse_nodes("0-1,4\n"), Some(vec![0, 1, 4]));
+
+        let nodes = online_nodes()?;
+        assert!(nodes.contains(&current_node().unwrap()));
+
+        let source = MemorySource::new_on_node(1 << 20, nodes[0])?;
+        assert_eq!(source.numa_node(), Some(nodes[0]));
+
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&[5; 8]);
+                Ok(())
+            })
+            .numa_memory(1 << 20)
+            .open()?;
+        assert_eq!(librarius.usage().len(), nodes.len());
+        librarius.run(|tx| tx.alloc(ObjectSize::new(0, 8)).map(|_| ()))
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::{Error, Result};
+use crate::source::{Source, SourceCapabilities, SyncMode};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/* how much resync copies from the survivor at a time */
+const RESYNC_CHUNK: usize = 1 << 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MirrorHealth {
+    Healthy,
+    /* the replica with this index failed and no longer gets any I/O */
+    Degraded { failed: usize },
+    Failed,
+}
+
+/*
+ * Keeps two copies of every page on independent sources. Writes go to both
+ * replicas, reads are served by the faster one. A replica that returns an
+ * error is taken out of service and the mirror keeps running on the other
+ * one until resync() brings it back.
+ */
+pub struct MirroredSource<A: Source, B: Source> {
+    primary: A,
+    secondary: B,
+    failed: [AtomicBool; 2],
+}
+
+impl<A: Source, B: Source> MirroredSource<A, B> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        MirroredSource {
+            primary,
+            secondary,
+            failed: [AtomicBool::new(false), AtomicBool::new(false)],
+        }
+    }
+
+    pub fn health(&self) -> MirrorHealth {
+        match [self.is_failed(0), self.is_failed(1)] {
+            [false, false] => MirrorHealth::Healthy,
+            [true, false] => MirrorHealth::Degraded { failed: 0 },
+            [false, true] => MirrorHealth::Degraded { failed: 1 },
+            [true, true] => MirrorHealth::Failed,
+        }
+    }
+
+    fn is_failed(&self, n: usize) -> bool {
+        self.failed[n].load(Ordering::Acquire)
+    }
+
+    fn set_failed(&self, n: usize, failed: bool) {
+        self.failed[n].store(failed, Ordering::Release)
+    }
+
+    fn replica(&self, n: usize) -> &dyn Source {
+        if n == 0 {
+            &self.primary
+        } else {
+            &self.secondary
+        }
+    }
+
+    fn replica_mut(&mut self, n: usize) -> &mut dyn Source {
+        if n == 0 {
+            &mut self.primary
+        } else {
+            &mut self.secondary
+        }
+    }
+
+    fn healthy(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..2).filter(move |n| !self.is_failed(*n))
+    }
+
+    /* healthy replicas, the one to read from first */
+    fn read_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = self.healthy().collect();
+        if self.secondary.perf_level() > self.primary.perf_level() {
+            order.reverse();
+        }
+        order
+    }
+
+    /*
+     * Runs op on every healthy replica. It's enough for one of them to
+     * succeed, the others are marked as failed.
+     */
+    fn for_each_healthy<F>(&mut self, mut op: F) -> Result<()>
+    where
+        F: FnMut(&mut dyn Source) -> Result<()>,
+    {
+        let mut result = Err(Error::SourceError {});
+        for n in self.healthy().collect::<Vec<_>>() {
+            match op(self.replica_mut(n)) {
+                Ok(()) => result = Ok(()),
+                Err(err) => {
+                    self.set_failed(n, true);
+                    if result.is_err() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /*
+     * Copies the contents of the surviving replica over the failed one and
+     * puts it back into service. If the copy fails, the replica stays out.
+     */
+    pub fn resync(&mut self) -> Result<()> {
+        let (failed, survivor) = match self.health() {
+            MirrorHealth::Healthy => return Ok(()),
+            MirrorHealth::Degraded { failed } => (failed, 1 - failed),
+            MirrorHealth::Failed => return Err(Error::SourceError {}),
+        };
+
+        let len = self.length()?;
+        let mut data = vec![0; std::cmp::min(RESYNC_CHUNK, len)];
+        for offset in (0..len).step_by(RESYNC_CHUNK) {
+            let chunk = &mut data[..std::cmp::min(RESYNC_CHUNK, len - offset)];
+            if let Err(err) = self.replica(survivor).read(offset, chunk) {
+                self.set_failed(survivor, true);
+                return Err(err);
+            }
+            self.replica_mut(failed).write(offset, chunk)?;
+        }
+        self.replica_mut(failed).flush()?;
+
+        self.set_failed(failed, false);
+
+        Ok(())
+    }
+}
+
+impl<A: Source, B: Source> Source for MirroredSource<A, B> {
+    fn capabilities(&self) -> SourceCapabilities {
+        if self.primary.is_persistent() || self.secondary.is_persistent() {
+            SourceCapabilities::PERSISTENT
+        } else {
+            SourceCapabilities::empty()
+        }
+    }
+
+    fn perf_level(&self) -> usize {
+        std::cmp::max(self.primary.perf_level(), self.secondary.perf_level())
+    }
+
+    fn close(&mut self) {
+        self.primary.close();
+        self.secondary.close();
+    }
+
+    fn length(&self) -> Result<usize> {
+        Ok(std::cmp::min(
+            self.primary.length()?,
+            self.secondary.length()?,
+        ))
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let mut result = Err(Error::SourceError {});
+        for n in self.read_order() {
+            result = self.replica(n).read(offset, data);
+            match result {
+                Ok(()) => break,
+                Err(_) => self.set_failed(n, true),
+            }
+        }
+        result
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.for_each_healthy(|replica| replica.write(offset, data))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.for_each_healthy(|replica| replica.flush())
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        self.for_each_healthy(|replica| replica.sync(mode))
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.primary.lock(exclusive)?;
+        self.secondary.lock(exclusive)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ReplaySource;
+    use std::sync::Arc;
+
+    struct FaultySource {
+        inner: ReplaySource,
+        broken: Arc<AtomicBool>,
+    }
+
+    impl FaultySource {
+        fn check(&self) -> Result<()> {
+            if self.broken.load(Ordering::SeqCst) {
+                Err(Error::SourceError {})
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Source for FaultySource {
+        fn capabilities(&self) -> SourceCapabilities {
+            SourceCapabilities::PERSISTENT
+        }
+        fn perf_level(&self) -> usize {
+            10
+        }
+        fn close(&mut self) {}
+        fn length(&self) -> Result<usize> {
+            self.inner.length()
+        }
+        fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+            self.check()?;
+            self.inner.read(offset, data)
+        }
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+            self.check()?;
+            self.inner.write(offset, data)
+        }
+        fn flush(&mut self) -> Result<()> {
+            self.check()
+        }
+        fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+            Err(Error::NotByteAddressable {})
+        }
+        fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+            Err(Error::NotByteAddressable {})
+        }
+        fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+            Err(Error::NotByteAddressable {})
+        }
+        fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+            Err(Error::NotByteAddressable {})
+        }
+    }
+
+    #[test]
+    fn degraded_mirror() -> Result<()> {
+        let broken = Arc::new(AtomicBool::new(false));
+        let faulty = FaultySource {
+            inner: ReplaySource::new(vec![0; 1 << 16], &[]),
+            broken: broken.clone(),
+        };
+        let mut mirror = MirroredSource::new(faulty, ReplaySource::new(vec![0; 1 << 16], &[]));
+
+        mirror.write(0, &[1; 512])?;
+        broken.store(true, Ordering::SeqCst);
+
+        let mut data = [0; 512];
+        mirror.read(0, &mut data)?;
+        assert_eq!(data, [1; 512]);
+        assert_eq!(mirror.health(), MirrorHealth::Degraded { failed: 0 });
+
+        mirror.write(512, &[2; 512])?;
+        assert!(mirror.resync().is_err());
+
+        broken.store(false, Ordering::SeqCst);
+        mirror.resync()?;
+        assert_eq!(mirror.health(), MirrorHealth::Healthy);
+
+        mirror.secondary = ReplaySource::new(vec![0; 1 << 16], &[]);
+        mirror.read(512, &mut data)?;
+        assert_eq!(data, [2; 512]);
+
+        Ok(())
+    }
+}
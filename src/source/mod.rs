@@ -4,23 +4,156 @@ use crate::{
 };
 use parking_lot::RwLock;
 use std::collections::VecDeque;
-
+use std::future;
+use std::io::{IoSlice, IoSliceMut};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub mod async_source;
+#[cfg(all(
+    feature = "blockdev",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod block_device_source;
+pub mod compressed_source;
+pub mod encrypted_source;
+#[cfg(any(test, feature = "faults"))]
+pub mod faulty_source;
 pub mod file_source;
+#[cfg(all(feature = "mmap", unix))]
+pub mod mapped_file_source;
 pub mod memory_source;
-
+pub mod mirrored_source;
+pub mod object_store_source;
+#[cfg(all(feature = "mmap", target_os = "linux", target_arch = "x86_64"))]
+pub mod pmem_source;
+pub mod remote_source;
+pub mod replay_source;
+pub mod striped_source;
+pub mod tracing_source;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub mod uring_file_source;
+
+pub use async_source::{block_on, AsyncSource, IoFuture, IoQueue};
+#[cfg(all(
+    feature = "blockdev",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use block_device_source::BlockDeviceSource;
+#[cfg(feature = "lz4")]
+pub use compressed_source::Lz4Codec;
+pub use compressed_source::{CompressedSource, PageCodec};
+pub use encrypted_source::{EncryptedSource, PageCipher};
+#[cfg(feature = "faults")]
+pub use faulty_source::{FaultInjector, FaultySource};
 pub use file_source::FileSource;
-pub use memory_source::MemorySource;
+#[cfg(all(feature = "mmap", unix))]
+pub use mapped_file_source::MappedFileSource;
+pub use memory_source::{MemorySource, HUGE_PAGE_SIZE};
+pub use mirrored_source::{MirrorHealth, MirroredSource};
+pub use object_store_source::{DirectoryStore, ObjectStore, ObjectStoreSource};
+#[cfg(all(feature = "mmap", target_os = "linux", target_arch = "x86_64"))]
+pub use pmem_source::PmemSource;
+pub use remote_source::RemoteSource;
+pub use replay_source::ReplaySource;
+pub use striped_source::StripedSource;
+pub use tracing_source::{IoEvent, IoOp, IoTrace, TracingSource};
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub use uring_file_source::IoUringFileSource;
+
+/*
+ * How far SourceAllocator goes to make a write durable before reporting it
+ * as done. Only sources with something like a page cache tell these apart.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /* leaves it to the OS, a crash can lose committed transactions */
+    None,
+    /* file data, and only the metadata needed to read it back */
+    #[default]
+    Fdatasync,
+    /* file data and all of its metadata */
+    Fsync,
+    /* like Fsync, but after every single write instead of once per batch */
+    OSync,
+}
+
+/*
+ * What a source can do beyond plain reads and writes. New features get a
+ * flag of their own, so sources that don't know about them keep compiling.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceCapabilities(u32);
+
+impl SourceCapabilities {
+    /* at() and at_mut() hand out the memory itself */
+    pub const BYTE_ADDRESSABLE: Self = SourceCapabilities(1 << 0);
+    /* the contents survive a restart */
+    pub const PERSISTENT: Self = SourceCapabilities(1 << 1);
+    /* discard() actually releases the storage */
+    pub const DISCARD: Self = SourceCapabilities(1 << 2);
+    /* as_async() returns the source */
+    pub const ASYNC: Self = SourceCapabilities(1 << 3);
+    /* a write of a whole sector is never torn by a crash */
+    pub const ATOMIC_SECTOR_WRITES: Self = SourceCapabilities(1 << 4);
+
+    pub const fn empty() -> Self {
+        SourceCapabilities(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl std::ops::BitOr for SourceCapabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        SourceCapabilities(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitAnd for SourceCapabilities {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        SourceCapabilities(self.0 & other.0)
+    }
+}
 
 pub trait Source: Send + Sync {
-    fn is_byte_addressable(&self) -> bool;
-    fn is_persistent(&self) -> bool;
+    fn capabilities(&self) -> SourceCapabilities;
     fn perf_level(&self) -> usize;
 
+    fn is_byte_addressable(&self) -> bool {
+        self.capabilities()
+            .contains(SourceCapabilities::BYTE_ADDRESSABLE)
+    }
+
+    fn is_persistent(&self) -> bool {
+        self.capabilities().contains(SourceCapabilities::PERSISTENT)
+    }
+
     fn close(&mut self);
 
     fn length(&self) -> Result<usize>;
 
-    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<()>;
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()>;
     fn write(&mut self, offset: usize, data: &[u8]) -> Result<()>;
     fn flush(&mut self) -> Result<()>;
 
@@ -29,6 +162,112 @@ pub trait Source: Send + Sync {
 
     fn offset(&mut self, ptr: *const u8) -> Result<usize>;
     fn flush_slice(&self, slice: &[u8]) -> Result<()>;
+
+    /* reads the range that starts at offset into bufs, one after another */
+    fn read_vectored(&self, offset: usize, bufs: &mut [IoSliceMut<'_>]) -> Result<()> {
+        let mut offset = offset;
+        for buf in bufs.iter_mut() {
+            self.read(offset, buf)?;
+            offset += buf.len();
+        }
+        Ok(())
+    }
+
+    fn write_vectored(&mut self, offset: usize, bufs: &[IoSlice<'_>]) -> Result<()> {
+        let mut offset = offset;
+        for buf in bufs {
+            self.write(offset, buf)?;
+            offset += buf.len();
+        }
+        Ok(())
+    }
+
+    /* requests that pick up where the previous one ended go out together */
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<()> {
+        let mut rest = reqs;
+        while !rest.is_empty() {
+            let n = contiguous_run(rest.iter().map(|(offset, data)| (*offset, data.len())));
+            let (run, tail) = std::mem::take(&mut rest).split_at_mut(n);
+            let offset = run[0].0;
+            let mut bufs: Vec<IoSliceMut> = run
+                .iter_mut()
+                .map(|(_, data)| IoSliceMut::new(data))
+                .collect();
+            self.read_vectored(offset, &mut bufs)?;
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self, reqs: &[(usize, &[u8])]) -> Result<()> {
+        let mut rest = reqs;
+        while !rest.is_empty() {
+            let n = contiguous_run(rest.iter().map(|(offset, data)| (*offset, data.len())));
+            let (run, tail) = rest.split_at(n);
+            let bufs: Vec<IoSlice> = run.iter().map(|(_, data)| IoSlice::new(data)).collect();
+            self.write_vectored(run[0].0, &bufs)?;
+            rest = tail;
+        }
+        Ok(())
+    }
+
+    /*
+     * Tells the source that the range holds nothing of value anymore, e.g.,
+     * so that a file can give the disk space back or an SSD can TRIM it.
+     * Only called on sources with SourceCapabilities::DISCARD.
+     */
+    fn discard(&mut self, _offset: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /* the NUMA node the memory of a byte addressable source is on */
+    fn numa_node(&self) -> Option<usize> {
+        None
+    }
+
+    /*
+     * Keeps other processes from using the same storage at the same time.
+     * Readers can share it, a writer needs it for itself. The lock is held
+     * for as long as the source is open.
+     */
+    fn lock(&self, _exclusive: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /* sources that can only ever grow, and only if nothing maps them */
+    fn resize(&mut self, _len: usize) -> Result<()> {
+        Err(Error::CannotResize {})
+    }
+
+    /* makes everything written so far durable, as far as mode asks for */
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        match mode {
+            SyncMode::None => Ok(()),
+            _ => self.flush(),
+        }
+    }
+
+    fn as_async(&self) -> Option<&dyn AsyncSource> {
+        None
+    }
+
+    /*
+     * For sources that encrypt pages, seals everything written from now on
+     * with the new key. What was sealed before is left to reencrypt().
+     */
+    fn rotate_key(&mut self, _key: &[u8]) -> Result<()> {
+        Err(Error::CannotRotateKey {})
+    }
+
+    /* rewrites pages under the current key, up to budget bytes of them */
+    fn reencrypt(&mut self, _budget: usize) -> Result<usize> {
+        Ok(0)
+    }
+
+    /* bytes of pages that reencrypt() has yet to get to */
+    fn unrotated(&self) -> usize {
+        0
+    }
 }
 
 const SOURCE_HEADER_MAGIC: u64 = 0xDEADBEEF;
@@ -63,6 +302,71 @@ impl SourceHeader {
     }
 }
 
+/*
+ * Splits a byte range into the pages it touches, yielding the page number,
+ * the range within that page and the range within the caller's buffer.
+ */
+pub(crate) fn page_spans(
+    offset: usize,
+    len: usize,
+    pagesize: usize,
+) -> impl Iterator<Item = (usize, Range<usize>, Range<usize>)> {
+    (offset / pagesize..(offset + len).div_ceil(pagesize)).map(move |page| {
+        let start = std::cmp::max(offset, page * pagesize);
+        let end = std::cmp::min(offset + len, (page + 1) * pagesize);
+        (
+            page,
+            start - page * pagesize..end - page * pagesize,
+            start - offset..end - offset,
+        )
+    })
+}
+
+/* an advisory lock on the whole file, failing right away if it's taken */
+pub(crate) fn lock_file(file: &std::fs::File, exclusive: bool) -> Result<()> {
+    let result = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+    result.map_err(|err| match err {
+        std::fs::TryLockError::WouldBlock => Error::PoolBusy {},
+        std::fs::TryLockError::Error(err) => Error::FileIO { err },
+    })
+}
+
+/* how many of the leading (offset, len) requests form one contiguous range */
+fn contiguous_run(mut reqs: impl Iterator<Item = (usize, usize)>) -> usize {
+    let mut end = match reqs.next() {
+        Some((offset, len)) => offset + len,
+        None => return 0,
+    };
+    1 + reqs
+        .take_while(|(offset, len)| {
+            let next = *offset == end;
+            end = offset + len;
+            next
+        })
+        .count()
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SourceUsage {
+    pub perf_level: usize,
+    pub persistent: bool,
+    pub byte_addressable: bool,
+    pub total: usize,
+    pub free: usize,
+    /* still sealed with a key older than the one the source was rotated to */
+    pub unrotated: usize,
+}
+
+impl SourceUsage {
+    pub fn used(&self) -> usize {
+        self.total - self.free
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Page {
     offset: usize,
@@ -95,12 +399,20 @@ impl Page {
     }
 }
 
-// This is *very* ugly. Source trait needs to be changed to allow asynchronous
-// I/O, and this implementation should follow.
+/*
+ * Reads only need the read lock, so they run concurrently with each other.
+ * Sources that implement AsyncSource can be driven through the *_async
+ * methods, which only hold the lock long enough to submit the request.
+ * Writes and flushes otherwise still do their I/O under the write lock.
+ */
 pub struct SourceAllocator<'data> {
     source: RwLock<Box<dyn Source + 'data>>,
     freelist: RwLock<VecDeque<Page>>,
     pagesize: usize,
+    read_only: bool,
+    punch_holes: bool,
+    sync_mode: SyncMode,
+    lost_power: AtomicBool,
 }
 
 impl<'data> SourceAllocator<'data> {
@@ -111,14 +423,18 @@ impl<'data> SourceAllocator<'data> {
         let source = self.source.get_mut();
 
         source.write(0, data)?;
-        source.flush()?;
+        source.sync(self.sync_mode)?;
 
         Ok(())
     }
 
+    /*
+     * valid tells how many bytes from the start of a page are in use, which
+     * for the first page of a run covers the pages that follow it as well.
+     */
     fn initialize<F>(&mut self, valid: F) -> Result<()>
     where
-        F: Fn(&[u8]) -> bool,
+        F: Fn(&[u8]) -> usize,
     {
         let mut data = vec![0; self.pagesize];
 
@@ -127,6 +443,9 @@ impl<'data> SourceAllocator<'data> {
         {
             let hdrp: &SourceHeader = unsafe_utils::any_from_slice(&data);
             if !hdrp.is_valid() {
+                if self.read_only {
+                    return Err(Error::OpenOnUninitialized {});
+                }
                 self.create()?;
             } else {
                 if hdrp.pagesize() != self.pagesize {
@@ -138,34 +457,79 @@ impl<'data> SourceAllocator<'data> {
             }
         }
 
+        /* nothing will ever be allocated from a source that can't be written */
+        if self.read_only {
+            return Ok(());
+        }
+
         let mut base_offset = math::align_up(std::mem::size_of::<SourceHeader>(), self.pagesize);
         base_offset += self.pagesize; // metapage
 
-        let base_size = self.length() - base_offset;
+        let length = self.length();
+        let base_size = length - base_offset;
 
         let npages = base_size / self.pagesize;
 
+        /* runs of free pages are discarded together, not page by page */
+        let mut run = 0..0;
+        let mut used = 0;
         for n in 0..npages {
             let offset = base_offset + (n * self.pagesize);
+            if offset < used {
+                continue;
+            }
 
             self.source.get_mut().read(offset, &mut data)?;
 
-            if !valid(data.as_slice()) {
-                self.free_page(Page::new(offset, self.pagesize))?;
+            let len = valid(data.as_slice());
+            if len != 0 {
+                /* nothing that runs past the end can be an object, the page is just in use */
+                used = match offset + len {
+                    end if end <= length => end,
+                    _ => offset + self.pagesize,
+                };
+            } else {
+                self.freelist
+                    .get_mut()
+                    .push_back(Page::new(offset, self.pagesize));
+                if run.end != offset {
+                    self.discard(run)?;
+                    run = offset..offset;
+                }
+                run.end = offset + self.pagesize;
             }
         }
 
-        Ok(())
+        self.discard(run)
     }
 
-    pub fn new<F>(source: Box<dyn Source + 'data>, pagesize: usize, valid: F) -> Result<Self>
+    /*
+     * In read-only mode persistent sources are left exactly as they were
+     * found, volatile ones are still set up so that they can cache data.
+     */
+    pub fn new<F>(
+        source: Box<dyn Source + 'data>,
+        pagesize: usize,
+        valid: F,
+        read_only: bool,
+        punch_holes: bool,
+        sync_mode: SyncMode,
+    ) -> Result<Self>
     where
-        F: Fn(&[u8]) -> bool,
+        F: Fn(&[u8]) -> usize,
     {
+        let read_only = read_only && source.is_persistent();
+        source.lock(!read_only)?;
+        let punch_holes =
+            punch_holes && source.capabilities().contains(SourceCapabilities::DISCARD);
         let mut allocator = SourceAllocator {
             source: RwLock::new(source),
             freelist: RwLock::new(VecDeque::new()),
             pagesize,
+            read_only,
+            punch_holes,
+            sync_mode,
+            lost_power: AtomicBool::new(false),
         };
 
         allocator.initialize(valid)?;
@@ -178,20 +542,51 @@ impl<'data> SourceAllocator<'data> {
     }
 
     pub fn allocate_page(&self) -> Result<Page> {
+        self.allocate_run(self.pagesize)
+    }
+
+    /*
+     * First fit for a run of contiguous pages. Free pages are kept one by
+     * one, so only when nothing is long enough are neighbours merged.
+     */
+    pub fn allocate_run(&self, len: usize) -> Result<Page> {
+        let len = math::align_up(len, self.pagesize);
         let mut freelist = self.freelist.write();
 
-        let mut page = freelist.pop_front().ok_or(Error::NoAvailableMemory {})?;
-        let allocated = page
-            .split(self.pagesize)
-            .ok_or(Error::NoAvailableMemory {})?;
+        let index = match freelist.iter().position(|page| page.len >= len) {
+            Some(index) => index,
+            None => {
+                Self::coalesce(&mut freelist);
+                freelist
+                    .iter()
+                    .position(|page| page.len >= len)
+                    .ok_or(Error::NoAvailableMemory {})?
+            }
+        };
+
+        let mut page = freelist.remove(index).unwrap();
+        let allocated = page.split(len).unwrap();
 
         if page.len != 0 {
-            freelist.push_front(page);
+            freelist.insert(index, page);
         }
 
         Ok(allocated)
     }
 
+    fn coalesce(freelist: &mut VecDeque<Page>) {
+        freelist.make_contiguous().sort_by_key(|page| page.offset);
+
+        let mut merged: VecDeque<Page> = VecDeque::with_capacity(freelist.len());
+        for page in freelist.drain(..) {
+            match merged.back_mut() {
+                Some(last) if last.offset + last.len == page.offset => last.len += page.len,
+                _ => merged.push_back(page),
+            }
+        }
+        *freelist = merged;
+    }
+
     pub fn get_bytes(&self, page: &Page) -> Result<Option<&'data [u8]>> {
         let source = self.source.read();
 
@@ -221,32 +616,216 @@ impl<'data> SourceAllocator<'data> {
     }
 
     pub fn read_into(&self, page: &Page, offset: usize, data: &mut [u8]) -> Result<()> {
-        assert!(page.len >= data.len());
+        assert!(page.len >= offset + data.len());
 
-        self.source.write().read(page.offset, data)
+        self.source.read().read(page.offset + offset, data)
     }
 
     pub fn write_from(&self, page: &Page, offset: usize, data: &[u8]) -> Result<()> {
-        assert!(page.len >= data.len());
+        assert!(page.len >= offset + data.len());
+        self.check_writable()?;
         let mut src = self.source.write();
 
-        src.write(page.offset, data)?;
-        src.flush()
+        let result = src
+            .write(page.offset + offset, data)
+            .and_then(|_| src.sync(self.sync_mode));
+        self.track(result)
+    }
+
+    pub fn read_batch(&self, reqs: &mut [(Page, usize, &mut [u8])]) -> Result<()> {
+        let mut batch: Vec<(usize, &mut [u8])> = reqs
+            .iter_mut()
+            .map(|(page, offset, data)| {
+                assert!(page.len >= *offset + data.len());
+                (page.offset + *offset, &mut **data)
+            })
+            .collect();
+        /* in address order, neighbouring pages become a single request */
+        batch.sort_by_key(|(offset, _)| *offset);
+
+        self.source.read().read_batch(&mut batch)
+    }
+
+    pub fn write_batch(&self, reqs: &[(Page, &[u8])]) -> Result<()> {
+        self.write_pages(reqs, true)
+    }
+
+    /* the writes only become durable with the next flush(), unless it's OSync */
+    pub fn write_batch_unsynced(&self, reqs: &[(Page, &[u8])]) -> Result<()> {
+        self.write_pages(reqs, false)
+    }
+
+    fn write_pages(&self, reqs: &[(Page, &[u8])], sync: bool) -> Result<()> {
+        let mut batch: Vec<(usize, &[u8])> = reqs
+            .iter()
+            .map(|(page, data)| {
+                assert!(page.len >= data.len());
+                (page.offset, *data)
+            })
+            .collect();
+        batch.sort_by_key(|(offset, _)| *offset);
+        self.check_writable()?;
+
+        let result = self.write_sorted(&mut **self.source.write(), &batch, sync);
+        self.track(result)
+    }
+
+    fn write_sorted(
+        &self,
+        src: &mut dyn Source,
+        batch: &[(usize, &[u8])],
+        sync: bool,
+    ) -> Result<()> {
+        if self.sync_mode == SyncMode::OSync {
+            for (offset, data) in batch {
+                src.write(*offset, data)?;
+                src.sync(self.sync_mode)?;
+            }
+            return Ok(());
+        }
+
+        src.write_batch(batch)?;
+        match sync {
+            true => src.sync(self.sync_mode),
+            false => Ok(()),
+        }
     }
 
     pub fn flush(&self) -> Result<()> {
-        self.source.write().flush()
+        let result = self.source.write().sync(self.sync_mode);
+        self.track(result)
+    }
+
+    pub fn read_into_async(&self, page: &Page, offset: usize, len: usize) -> IoFuture<Vec<u8>> {
+        assert!(page.len >= offset + len);
+
+        if let Some(source) = self.source.read().as_async() {
+            return source.read_async(page.offset + offset, len);
+        }
+
+        let mut data = vec![0; len];
+        let result = self
+            .source
+            .read()
+            .read(page.offset + offset, &mut data)
+            .map(|_| data);
+        Box::pin(future::ready(result))
+    }
+
+    pub fn write_from_async(&self, page: &Page, offset: usize, data: Vec<u8>) -> IoFuture<()> {
+        assert!(page.len >= offset + data.len());
+        if let Err(err) = self.check_writable() {
+            return Box::pin(future::ready(Err(err)));
+        }
+
+        /* the async flush of a source is an fdatasync, nothing else */
+        if self.sync_mode != SyncMode::Fdatasync {
+            return Box::pin(future::ready(self.write_from(page, offset, &data)));
+        }
+
+        if let Some(source) = self.source.read().as_async() {
+            let write = source.write_async(page.offset + offset, data);
+            let flush = source.flush_async();
+            return Box::pin(async move {
+                write.await?;
+                flush.await
+            });
+        }
+
+        Box::pin(future::ready(self.write_from(page, offset, &data)))
+    }
+
+    pub fn flush_async(&self) -> IoFuture<()> {
+        if self.sync_mode != SyncMode::Fdatasync {
+            return Box::pin(future::ready(self.flush()));
+        }
+
+        if let Some(source) = self.source.read().as_async() {
+            return source.flush_async();
+        }
+
+        Box::pin(future::ready(self.flush()))
+    }
+
+    pub fn close(&self) {
+        self.source.write().close()
     }
 
     pub fn flush_partial(&self, data: &[u8]) -> Result<()> {
         self.source.write().flush_slice(data)
     }
 
+    /* resizes the source and makes everything past its old end allocatable */
+    pub fn grow(&self, len: usize) -> Result<()> {
+        self.check_writable()?;
+
+        let old = self.length();
+        if len < old {
+            return Err(Error::CannotResize {});
+        }
+        self.source.write().resize(len)?;
+
+        let new = self.length();
+        if new > old {
+            self.free_page(Page::new(old, new - old))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn rotate_key(&self, key: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.track(self.source.write().rotate_key(key))
+    }
+
+    pub fn reencrypt(&self, budget: usize) -> Result<usize> {
+        self.check_writable()?;
+        self.track(self.source.write().reencrypt(budget))
+    }
+
     pub fn free_page(&self, page: Page) -> Result<()> {
+        self.discard(page.offset..page.offset + page.len)?;
         self.freelist.write().push_back(page);
         Ok(())
     }
 
+    /* lets the source drop the storage behind pages nobody uses anymore */
+    fn discard(&self, range: Range<usize>) -> Result<()> {
+        if !self.punch_holes || range.is_empty() {
+            return Ok(());
+        }
+        self.source.write().discard(range.start, range.len())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnly {})
+        } else if self.lost_power.load(Ordering::Acquire) {
+            Err(Error::PowerLoss {})
+        } else {
+            Ok(())
+        }
+    }
+
+    /*
+     * Whatever a source wrote after its last flush is gone once it lost
+     * power, so from then on it isn't asked to do anything anymore.
+     */
+    fn track<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(Error::PowerLoss {}) = result {
+            self.lost_power.store(true, Ordering::Release);
+        }
+        result
+    }
+
+    pub fn perf_level(&self) -> usize {
+        self.source.read().perf_level()
+    }
+
+    pub fn numa_node(&self) -> Option<usize> {
+        self.source.read().numa_node()
+    }
+
     pub fn is_byte_addressable(&self) -> bool {
         self.source.read().is_byte_addressable()
     }
@@ -258,4 +837,18 @@ impl<'data> SourceAllocator<'data> {
     pub fn length(&self) -> usize {
         math::align_down(self.source.read().length().unwrap(), self.pagesize)
     }
+
+    pub fn usage(&self) -> SourceUsage {
+        let source = self.source.read();
+        let free = self.freelist.read().iter().map(|page| page.len()).sum();
+
+        SourceUsage {
+            perf_level: source.perf_level(),
+            persistent: source.is_persistent(),
+            byte_addressable: source.is_byte_addressable(),
+            total: math::align_down(source.length().unwrap(), self.pagesize),
+            free,
+            unrotated: source.unrotated(),
+        }
+    }
 }
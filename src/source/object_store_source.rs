@@ -0,0 +1,228 @@
+use crate::error::{Error, Result};
+use crate::source::{Source, SourceCapabilities};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/*
+ * The bits of an S3-like bucket that a source needs. Objects that were
+ * never written read back as None and are treated as zeroed.
+ */
+pub trait ObjectStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    fn get_many(&self, keys: &[String]) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    fn put_many(&self, objects: &[(String, &[u8])]) -> Result<()> {
+        for (key, data) in objects {
+            self.put(key, data)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Keeps every object as a file in a local directory. Handy for tests and for
+ * anything that mounts a bucket as a filesystem.
+ */
+pub struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root).map_err(|err| Error::FileIO { err })?;
+        Ok(DirectoryStore { root })
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Error::FileIO { err }),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let tmp = self.root.join(format!("{}.tmp", key));
+        fs::write(&tmp, data).map_err(|err| Error::FileIO { err })?;
+        fs::rename(tmp, self.root.join(key)).map_err(|err| Error::FileIO { err })
+    }
+}
+
+/*
+ * Maps fixed size ranges of the source onto objects in a bucket. Writes are
+ * buffered locally and only uploaded, as one batch, on flush.
+ */
+pub struct ObjectStoreSource {
+    store: Box<dyn ObjectStore>,
+    prefix: String,
+    object_size: usize,
+    len: usize,
+    dirty: BTreeMap<usize, Vec<u8>>,
+}
+
+impl ObjectStoreSource {
+    pub fn new(
+        store: impl ObjectStore + 'static,
+        prefix: &str,
+        object_size: usize,
+        len: usize,
+    ) -> Self {
+        ObjectStoreSource {
+            store: Box::new(store),
+            prefix: prefix.to_string(),
+            object_size,
+            len,
+            dirty: BTreeMap::new(),
+        }
+    }
+
+    fn key(&self, index: usize) -> String {
+        format!("{}{:016x}", self.prefix, index)
+    }
+
+    fn objects(&self, offset: usize, len: usize) -> Result<std::ops::Range<usize>> {
+        if offset + len > self.len {
+            return Err(Error::InvalidMemory {});
+        }
+        Ok(offset / self.object_size..(offset + len).div_ceil(self.object_size))
+    }
+
+    /* objects missing from the write buffer are fetched with a single batch */
+    fn load(&self, indices: std::ops::Range<usize>) -> Result<BTreeMap<usize, Vec<u8>>> {
+        let missing: Vec<usize> = indices
+            .clone()
+            .filter(|n| !self.dirty.contains_key(n))
+            .collect();
+        let keys: Vec<String> = missing.iter().map(|n| self.key(*n)).collect();
+        let fetched = self.store.get_many(&keys)?;
+
+        let mut objects = BTreeMap::new();
+        for (n, data) in missing.into_iter().zip(fetched) {
+            let mut data = data.unwrap_or_default();
+            data.resize(self.object_size, 0);
+            objects.insert(n, data);
+        }
+        for n in indices {
+            if let Some(data) = self.dirty.get(&n) {
+                objects.insert(n, data.clone());
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+impl Source for ObjectStoreSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        0
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.len)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let objects = self.load(self.objects(offset, data.len())?)?;
+
+        let mut pos = offset;
+        for (n, object) in objects {
+            let start = pos - n * self.object_size;
+            let len = std::cmp::min(self.object_size - start, offset + data.len() - pos);
+            data[pos - offset..pos - offset + len].copy_from_slice(&object[start..start + len]);
+            pos += len;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        let objects = self.load(self.objects(offset, data.len())?)?;
+
+        let mut pos = offset;
+        for (n, mut object) in objects {
+            let start = pos - n * self.object_size;
+            let len = std::cmp::min(self.object_size - start, offset + data.len() - pos);
+            object[start..start + len].copy_from_slice(&data[pos - offset..pos - offset + len]);
+            self.dirty.insert(n, object);
+            pos += len;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let objects: Vec<(String, &[u8])> = self
+            .dirty
+            .iter()
+            .map(|(n, data)| (self.key(*n), data.as_slice()))
+            .collect();
+        self.store.put_many(&objects)?;
+        self.dirty.clear();
+
+        Ok(())
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_objects() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("librarius-objects-{}", std::process::id()));
+        let object_size = 4096;
+
+        let mut source =
+            ObjectStoreSource::new(DirectoryStore::new(&dir)?, "pool-", object_size, 1 << 16);
+        source.write(4000, &[4; 200])?;
+
+        let mut data = [0u8; 200];
+        source.read(4000, &mut data)?;
+        assert_eq!(data, [4; 200]);
+        assert!(fs::read_dir(&dir)
+            .map_err(|err| Error::FileIO { err })?
+            .next()
+            .is_none());
+
+        source.flush()?;
+
+        let source =
+            ObjectStoreSource::new(DirectoryStore::new(&dir)?, "pool-", object_size, 1 << 16);
+        data = [0; 200];
+        source.read(4000, &mut data)?;
+        assert_eq!(data, [4; 200]);
+
+        fs::remove_dir_all(dir).map_err(|err| Error::FileIO { err })
+    }
+}
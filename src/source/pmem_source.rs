@@ -0,0 +1,227 @@
+use crate::error::{Error, Result};
+use crate::source::memory_source::MemoryMap;
+use crate::source::{lock_file, Source, SourceCapabilities};
+use crate::utils::math;
+use std::arch::asm;
+use std::arch::x86_64::{__cpuid, __cpuid_count, _mm_sfence};
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+const CACHELINE_SIZE: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CacheFlush {
+    /* eADR, the caches are already in the persistence domain */
+    None,
+    Clwb,
+    ClflushOpt,
+    Clflush,
+}
+
+impl CacheFlush {
+    fn detect() -> Self {
+        if Self::cpu_cache_persistent() {
+            return CacheFlush::None;
+        }
+
+        let leaf7 = if __cpuid(0).eax >= 7 {
+            __cpuid_count(7, 0).ebx
+        } else {
+            0
+        };
+        if leaf7 & (1 << 24) != 0 {
+            CacheFlush::Clwb
+        } else if leaf7 & (1 << 23) != 0 {
+            CacheFlush::ClflushOpt
+        } else {
+            CacheFlush::Clflush
+        }
+    }
+
+    /*
+     * The kernel reports "cpu_cache" as the persistence domain of regions on
+     * platforms that flush the caches on power loss.
+     */
+    fn cpu_cache_persistent() -> bool {
+        let regions = match fs::read_dir("/sys/bus/nd/devices") {
+            Ok(regions) => regions,
+            Err(_) => return false,
+        };
+
+        let mut found = false;
+        for region in regions.flatten() {
+            let domain = region.path().join("persistence_domain");
+            match fs::read_to_string(domain) {
+                Ok(domain) if domain.trim() == "cpu_cache" => found = true,
+                Ok(_) => return false,
+                Err(_) => {}
+            }
+        }
+        found
+    }
+
+    fn flush(&self, data: &[u8]) {
+        let start = math::align_down(data.as_ptr() as usize, CACHELINE_SIZE);
+        let end = data.as_ptr() as usize + data.len();
+
+        for line in (start..end).step_by(CACHELINE_SIZE) {
+            unsafe {
+                match self {
+                    CacheFlush::None => {}
+                    CacheFlush::Clwb => asm!("clwb [{}]", in(reg) line, options(nostack)),
+                    CacheFlush::ClflushOpt => {
+                        asm!("clflushopt [{}]", in(reg) line, options(nostack))
+                    }
+                    CacheFlush::Clflush => asm!("clflush [{}]", in(reg) line, options(nostack)),
+                }
+            }
+        }
+        unsafe { _mm_sfence() };
+    }
+}
+
+/*
+ * Persistent memory, either a /dev/dax character device or a file on a
+ * filesystem mounted with -o dax. Stores are made durable straight from
+ * user space by flushing the cache lines they touched.
+ */
+pub struct PmemSource {
+    map: MemoryMap<'static>,
+    flush: CacheFlush,
+    file: fs::File,
+}
+
+impl PmemSource {
+    pub fn new(path: &str, len: usize) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| Error::SourceError {})?;
+
+        let meta = file.metadata().map_err(|err| Error::FileIO { err })?;
+        if meta.file_type().is_char_device() {
+            if Self::devdax_size(meta.rdev())? < len {
+                return Err(Error::InvalidSource {});
+            }
+        } else {
+            file.set_len(len as u64)
+                .map_err(|err| Error::FileIO { err })?;
+        }
+
+        let map = MemoryMap::from_file_sync(&file, len)?;
+
+        Ok(PmemSource {
+            map,
+            flush: CacheFlush::detect(),
+            file,
+        })
+    }
+
+    /* device dax doesn't report its length through stat */
+    fn devdax_size(rdev: u64) -> Result<usize> {
+        let path = format!(
+            "/sys/dev/char/{}:{}/size",
+            libc::major(rdev),
+            libc::minor(rdev)
+        );
+        fs::read_to_string(path)
+            .map_err(|err| Error::FileIO { err })?
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidSource {})
+    }
+}
+
+impl Source for PmemSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities::BYTE_ADDRESSABLE | SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        75
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.map.len())
+    }
+
+    fn read(&self, offset: usize, dst: &mut [u8]) -> Result<()> {
+        let src = self
+            .map
+            .at(offset, dst.len())
+            .ok_or(Error::InvalidMemory {})?;
+
+        dst.copy_from_slice(src);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, src: &[u8]) -> Result<()> {
+        let dst = self
+            .map
+            .at_mut(offset, src.len())
+            .ok_or(Error::InvalidMemory {})?;
+
+        dst.copy_from_slice(src);
+        self.flush.flush(dst);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        unsafe { _mm_sfence() };
+        Ok(())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        lock_file(&self.file, exclusive)
+    }
+
+    fn at(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.map.at(offset, len).ok_or(Error::InvalidMemory {})
+    }
+
+    fn at_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.map.at_mut(offset, len).ok_or(Error::InvalidMemory {})
+    }
+
+    fn offset(&mut self, ptr: *const u8) -> Result<usize> {
+        let off = self.map.offset(ptr);
+        if off >= 0 && (off as usize) < self.map.len() {
+            Ok(off as usize)
+        } else {
+            Err(Error::InvalidMemory {})
+        }
+    }
+
+    fn flush_slice(&self, slice: &[u8]) -> Result<()> {
+        let off = self.map.offset(slice.as_ptr());
+        if off < 0 || off as usize + slice.len() > self.map.len() {
+            return Err(Error::InvalidMemory {});
+        }
+
+        self.flush.flush(slice);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_flush() {
+        let data = vec![1u8; 4 * CACHELINE_SIZE + 3];
+        let detected = CacheFlush::detect();
+
+        for flush in &[CacheFlush::None, CacheFlush::Clflush, detected] {
+            flush.flush(&data[1..]);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::error::{Error, Result};
+use crate::source::{Source, SourceCapabilities};
+use parking_lot::Mutex;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/*
+ * Requests are an opcode byte followed by big-endian offset and length,
+ * writes carry their data right after. Every reply starts with a status
+ * byte, successful reads and info requests are followed by their payload.
+ */
+const OP_READ: u8 = 0;
+const OP_WRITE: u8 = 1;
+const OP_FLUSH: u8 = 2;
+const OP_INFO: u8 = 3;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+
+/* keeps a broken or hostile client from making the server allocate at will */
+const MAX_REQUEST_LEN: usize = 64 << 20;
+
+fn io_err(err: io::Error) -> Error {
+    Error::FileIO { err }
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+struct Connection {
+    rx: BufReader<TcpStream>,
+    tx: BufWriter<TcpStream>,
+}
+
+/*
+ * A block source living on another machine, meant to sit behind a DRAM
+ * cache as the slowest tier. Requests take turns on a single connection.
+ */
+pub struct RemoteSource {
+    conn: Mutex<Connection>,
+    len: usize,
+    persistent: bool,
+}
+
+impl RemoteSource {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).map_err(io_err)?;
+        stream.set_nodelay(true).map_err(io_err)?;
+
+        let mut source = RemoteSource {
+            conn: Mutex::new(Connection {
+                rx: BufReader::new(stream.try_clone().map_err(io_err)?),
+                tx: BufWriter::new(stream),
+            }),
+            len: 0,
+            persistent: false,
+        };
+
+        let mut info = [0; 9];
+        source.request(OP_INFO, 0, 0, &[], &mut info)?;
+        source.len = read_u64(&mut &info[..8]).map_err(io_err)? as usize;
+        source.persistent = info[8] != 0;
+
+        Ok(source)
+    }
+
+    /*
+     * Serves a single client connection until it disconnects, exporting the
+     * source over the protocol RemoteSource speaks.
+     */
+    pub fn serve(source: &mut dyn Source, stream: TcpStream) -> Result<()> {
+        let mut rx = BufReader::new(stream.try_clone().map_err(io_err)?);
+        let mut tx = BufWriter::new(stream);
+
+        loop {
+            let op = match read_u8(&mut rx) {
+                Ok(op) => op,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(io_err(err)),
+            };
+            let offset = read_u64(&mut rx).map_err(io_err)? as usize;
+            let len = read_u64(&mut rx).map_err(io_err)? as usize;
+            if len > MAX_REQUEST_LEN {
+                return Err(Error::InvalidSource {});
+            }
+
+            let mut data = vec![0; len];
+            let (result, payload) = match op {
+                OP_READ => (source.read(offset, &mut data), &data[..]),
+                OP_WRITE => {
+                    rx.read_exact(&mut data).map_err(io_err)?;
+                    (source.write(offset, &data), &[][..])
+                }
+                OP_FLUSH => (source.flush(), &[][..]),
+                OP_INFO => {
+                    data = source.length()?.to_be_bytes().to_vec();
+                    data.push(source.is_persistent() as u8);
+                    (Ok(()), &data[..])
+                }
+                _ => return Err(Error::InvalidSource {}),
+            };
+
+            match result {
+                Ok(()) => {
+                    tx.write_all(&[STATUS_OK]).map_err(io_err)?;
+                    tx.write_all(payload).map_err(io_err)?;
+                }
+                Err(_) => tx.write_all(&[STATUS_ERR]).map_err(io_err)?,
+            }
+            tx.flush().map_err(io_err)?;
+        }
+    }
+
+    /* sends a request and fills reply with the payload of its answer */
+    fn request(
+        &self,
+        op: u8,
+        offset: usize,
+        len: usize,
+        data: &[u8],
+        reply: &mut [u8],
+    ) -> Result<()> {
+        let mut conn = self.conn.lock();
+
+        conn.tx.write_all(&[op]).map_err(io_err)?;
+        conn.tx
+            .write_all(&(offset as u64).to_be_bytes())
+            .map_err(io_err)?;
+        conn.tx
+            .write_all(&(len as u64).to_be_bytes())
+            .map_err(io_err)?;
+        conn.tx.write_all(data).map_err(io_err)?;
+        conn.tx.flush().map_err(io_err)?;
+
+        match read_u8(&mut conn.rx).map_err(io_err)? {
+            STATUS_OK => conn.rx.read_exact(reply).map_err(io_err),
+            _ => Err(Error::SourceError {}),
+        }
+    }
+}
+
+impl Source for RemoteSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        if self.persistent {
+            SourceCapabilities::PERSISTENT
+        } else {
+            SourceCapabilities::empty()
+        }
+    }
+
+    fn perf_level(&self) -> usize {
+        0
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.len)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        self.request(OP_READ, offset, data.len(), &[], data)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.request(OP_WRITE, offset, data.len(), data, &mut [])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.request(OP_FLUSH, 0, 0, &[], &mut [])
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{MemorySource, ReplaySource};
+    use crate::{LibrariusBuilder, ObjectSize};
+    use std::net::TcpListener;
+
+    #[test]
+    fn remote_tier() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(io_err)?;
+        let addr = listener.local_addr().map_err(io_err)?;
+        let server = std::thread::spawn(move || -> Result<()> {
+            let mut image = ReplaySource::new(vec![0; 1 << 20], &[]);
+            let (stream, _) = listener.accept().map_err(io_err)?;
+            RemoteSource::serve(&mut image, stream)
+        });
+
+        {
+            let remote = RemoteSource::connect(addr)?;
+            assert_eq!(remote.length()?, 1 << 20);
+            assert!(remote.is_persistent());
+
+            let librarius = LibrariusBuilder::new()
+                .create_with(ObjectSize::new(0, 8), |data| {
+                    data.copy_from_slice(&[5; 8]);
+                    Ok(())
+                })
+                .source(MemorySource::new(1 << 20)?)
+                .source(remote)
+                .open()?;
+            librarius.close(std::time::Duration::from_secs(1))?;
+        }
+
+        server.join().unwrap()
+    }
+}
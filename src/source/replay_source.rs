@@ -0,0 +1,169 @@
+use crate::error::{Error, Result};
+use crate::source::{IoEvent, IoOp, Source, SourceCapabilities};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::time::{Duration, Instant};
+
+struct LatencyModel {
+    recorded: HashMap<IoOp, VecDeque<Duration>>,
+    average: HashMap<IoOp, Duration>,
+}
+
+impl LatencyModel {
+    fn new(trace: &[IoEvent]) -> Self {
+        let mut recorded: HashMap<IoOp, VecDeque<Duration>> = HashMap::new();
+        for event in trace {
+            recorded
+                .entry(event.op)
+                .or_default()
+                .push_back(event.latency);
+        }
+
+        let average = recorded
+            .iter()
+            .map(|(op, latencies)| {
+                let total: Duration = latencies.iter().sum();
+                (*op, total / latencies.len() as u32)
+            })
+            .collect();
+
+        LatencyModel { recorded, average }
+    }
+
+    /*
+     * Operations consume the recorded latencies of their kind in order. Once
+     * the trace runs out, the average of that kind is used instead so that
+     * longer runs still behave like the recorded device.
+     */
+    fn next(&mut self, op: IoOp) -> Duration {
+        self.recorded
+            .get_mut(&op)
+            .and_then(|latencies| latencies.pop_front())
+            .or_else(|| self.average.get(&op).copied())
+            .unwrap_or_default()
+    }
+}
+
+pub struct ReplaySource {
+    image: Vec<u8>,
+    latency: Mutex<LatencyModel>,
+}
+
+impl ReplaySource {
+    pub fn new(image: Vec<u8>, trace: &[IoEvent]) -> Self {
+        ReplaySource {
+            image,
+            latency: Mutex::new(LatencyModel::new(trace)),
+        }
+    }
+
+    pub fn from_file(path: &str, trace: &[IoEvent]) -> Result<Self> {
+        let image = fs::read(path).map_err(|err| Error::FileIO { err })?;
+        Ok(Self::new(image, trace))
+    }
+
+    fn enforce(&self, op: IoOp, start: Instant) {
+        let target = self.latency.lock().next(op);
+        while start.elapsed() < target {
+            std::thread::yield_now();
+        }
+    }
+
+    fn range(&self, offset: usize, len: usize) -> Result<std::ops::Range<usize>> {
+        if offset + len > self.image.len() {
+            return Err(Error::InvalidMemory {});
+        }
+        Ok(offset..offset + len)
+    }
+}
+
+impl Source for ReplaySource {
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        0
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        Ok(self.image.len())
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let start = Instant::now();
+        let range = self.range(offset, data.len())?;
+        data.copy_from_slice(&self.image[range]);
+        self.enforce(IoOp::Read, start);
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        let start = Instant::now();
+        let range = self.range(offset, data.len())?;
+        self.image[range].copy_from_slice(data);
+        self.enforce(IoOp::Write, start);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let start = Instant::now();
+        self.enforce(IoOp::Flush, start);
+
+        Ok(())
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{IoTrace, TracingSource};
+
+    #[test]
+    fn trace_roundtrip() -> Result<()> {
+        let mut source = TracingSource::new(ReplaySource::new(vec![0; 1 << 16], &[]), 16);
+        let trace = source.trace();
+
+        let mut data = [1u8; 512];
+        source.write(4096, &data)?;
+        source.flush()?;
+        data = [0; 512];
+        source.read(4096, &mut data)?;
+        assert!(data.iter().all(|b| *b == 1));
+
+        let mut csv = Vec::new();
+        trace.write_csv(&mut csv)?;
+        let events = IoTrace::read_csv(csv.as_slice())?;
+
+        let ops: Vec<IoOp> = events.iter().map(|e| e.op).collect();
+        assert_eq!(ops, vec![IoOp::Write, IoOp::Flush, IoOp::Read]);
+        assert_eq!(events[2].offset, 4096);
+        assert_eq!(events[2].len, 512);
+
+        let replay = ReplaySource::new(vec![0; 1 << 16], &events);
+        replay.read(0, &mut data)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{Error, Result};
+use crate::source::{page_spans, Source, SourceCapabilities, SyncMode};
+use std::ops::Range;
+use std::thread;
+
+/*
+ * Interleaves stripes across a set of member sources, RAID0-style: stripe n
+ * lives on member n % members, so large requests are spread over all of
+ * them. Whenever a request touches more than one member, each member does
+ * its share on a separate thread.
+ */
+pub struct StripedSource<'a> {
+    members: Vec<Box<dyn Source + 'a>>,
+    stripe: usize,
+}
+
+impl<'a> StripedSource<'a> {
+    pub fn new(members: Vec<Box<dyn Source + 'a>>, stripe: usize) -> Result<Self> {
+        if members.is_empty() || stripe == 0 {
+            return Err(Error::InvalidSource {});
+        }
+
+        Ok(StripedSource { members, stripe })
+    }
+
+    /* which member each piece of the range is on, and where on that member */
+    fn spans(
+        &self,
+        offset: usize,
+        len: usize,
+    ) -> impl Iterator<Item = (usize, usize, Range<usize>)> {
+        let (n, stripe) = (self.members.len(), self.stripe);
+        page_spans(offset, len, stripe).map(move |(nstripe, within, buf)| {
+            let member_offset = (nstripe / n) * stripe + within.start;
+            (nstripe % n, member_offset, buf)
+        })
+    }
+}
+
+/*
+ * Runs op on every member that has work in its batch. Members are handed out
+ * as shared references for reads and as exclusive ones for everything else.
+ */
+fn dispatch<M, T, F>(members: impl Iterator<Item = M>, batches: Vec<Vec<T>>, op: F) -> Result<()>
+where
+    M: Send,
+    T: Send,
+    F: Fn(M, &mut [T]) -> Result<()> + Sync,
+{
+    let work: Vec<_> = members
+        .zip(batches)
+        .filter(|(_, batch)| !batch.is_empty())
+        .collect();
+
+    if work.len() <= 1 {
+        for (member, mut batch) in work {
+            op(member, &mut batch)?;
+        }
+        return Ok(());
+    }
+
+    let op = &op;
+    thread::scope(|scope| {
+        let running: Vec<_> = work
+            .into_iter()
+            .map(|(member, mut batch)| scope.spawn(move || op(member, &mut batch)))
+            .collect();
+
+        running
+            .into_iter()
+            .try_for_each(|member| member.join().unwrap_or(Err(Error::SourceError {})))
+    })
+}
+
+impl<'a> Source for StripedSource<'a> {
+    fn capabilities(&self) -> SourceCapabilities {
+        let persistent = self.members.iter().all(|member| member.is_persistent());
+        if persistent {
+            SourceCapabilities::PERSISTENT
+        } else {
+            SourceCapabilities::empty()
+        }
+    }
+
+    fn perf_level(&self) -> usize {
+        self.members
+            .iter()
+            .map(|member| member.perf_level())
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn close(&mut self) {
+        self.members.iter_mut().for_each(|member| member.close());
+    }
+
+    /* only whole rows of stripes are usable */
+    fn length(&self) -> Result<usize> {
+        let mut shortest = usize::MAX;
+        for member in &self.members {
+            shortest = std::cmp::min(shortest, member.length()?);
+        }
+        Ok(shortest / self.stripe * self.stripe * self.members.len())
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        self.read_batch(&mut [(offset, data)])
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.write_batch(&[(offset, data)])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let batches = self.members.iter().map(|_| vec![()]).collect();
+        dispatch(self.members.iter_mut(), batches, |member, _| member.flush())
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        let batches = self.members.iter().map(|_| vec![()]).collect();
+        dispatch(self.members.iter_mut(), batches, |member, _| {
+            member.sync(mode)
+        })
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.members
+            .iter()
+            .try_for_each(|member| member.lock(exclusive))
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<()> {
+        let mut batches: Vec<Vec<(usize, &mut [u8])>> =
+            self.members.iter().map(|_| Vec::new()).collect();
+
+        for (offset, data) in reqs.iter_mut() {
+            let mut rest: &mut [u8] = data;
+            for (member, member_offset, buf) in self.spans(*offset, rest.len()) {
+                let (piece, tail) = std::mem::take(&mut rest).split_at_mut(buf.len());
+                batches[member].push((member_offset, piece));
+                rest = tail;
+            }
+        }
+
+        dispatch(self.members.iter(), batches, |member, batch| {
+            member.read_batch(batch)
+        })
+    }
+
+    fn write_batch(&mut self, reqs: &[(usize, &[u8])]) -> Result<()> {
+        let mut batches: Vec<Vec<(usize, &[u8])>> =
+            self.members.iter().map(|_| Vec::new()).collect();
+
+        for (offset, data) in reqs {
+            for (member, member_offset, buf) in self.spans(*offset, data.len()) {
+                batches[member].push((member_offset, &data[buf]));
+            }
+        }
+
+        dispatch(self.members.iter_mut(), batches, |member, batch| {
+            member.write_batch(batch)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{MemorySource, ReplaySource};
+    use crate::{LibrariusBuilder, ObjectSize};
+
+    #[test]
+    fn interleaved() -> Result<()> {
+        let members: Vec<Box<dyn Source>> = (0..3)
+            .map(|_| Box::new(ReplaySource::new(vec![0; 1 << 20], &[])) as Box<dyn Source>)
+            .collect();
+        let mut striped = StripedSource::new(members, 4096)?;
+        assert_eq!(striped.length()?, 3 << 20);
+
+        let data: Vec<u8> = (0..5 * 4096 + 100).map(|i| (i / 4096) as u8).collect();
+        striped.write(2048, &data)?;
+
+        let mut stripe = vec![0; 4096];
+        striped.members[1].read(0, &mut stripe)?;
+        assert!(stripe[..2048].iter().all(|b| *b == 0));
+        assert!(stripe[2048..].iter().all(|b| *b == 1));
+
+        let mut back = vec![0; data.len()];
+        striped.read(2048, &mut back)?;
+        assert!(back == data);
+
+        let librarius = LibrariusBuilder::new()
+            .create_with(ObjectSize::new(0, 8), |data| {
+                data.copy_from_slice(&[5; 8]);
+                Ok(())
+            })
+            .source(MemorySource::new(1 << 20)?)
+            .source(striped)
+            .open()?;
+        librarius.close(std::time::Duration::from_secs(1))
+    }
+}
@@ -0,0 +1,353 @@
+use crate::error::{Error, Result};
+use crate::source::async_source::{AsyncSource, IoFuture};
+use crate::source::{Source, SourceCapabilities, SyncMode};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IoOp {
+    Read,
+    Write,
+    Flush,
+    FlushSlice,
+    Sync,
+    Discard,
+    Resize,
+}
+
+impl IoOp {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IoOp::Read => "read",
+            IoOp::Write => "write",
+            IoOp::Flush => "flush",
+            IoOp::FlushSlice => "flush_slice",
+            IoOp::Sync => "sync",
+            IoOp::Discard => "discard",
+            IoOp::Resize => "resize",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<IoOp> {
+        match name {
+            "read" => Some(IoOp::Read),
+            "write" => Some(IoOp::Write),
+            "flush" => Some(IoOp::Flush),
+            "flush_slice" => Some(IoOp::FlushSlice),
+            "sync" => Some(IoOp::Sync),
+            "discard" => Some(IoOp::Discard),
+            "resize" => Some(IoOp::Resize),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct IoEvent {
+    pub op: IoOp,
+    pub offset: usize,
+    pub len: usize,
+    pub start: Duration,
+    pub latency: Duration,
+}
+
+impl IoEvent {
+    fn from_csv(line: &str) -> Option<IoEvent> {
+        let mut fields = line.split(',');
+        let op = IoOp::from_name(fields.next()?)?;
+        let mut number = || fields.next()?.trim().parse::<u64>().ok();
+
+        Some(IoEvent {
+            op,
+            offset: number()? as usize,
+            len: number()? as usize,
+            start: Duration::from_nanos(number()?),
+            latency: Duration::from_nanos(number()?),
+        })
+    }
+}
+
+pub struct IoTrace {
+    events: Mutex<VecDeque<IoEvent>>,
+    capacity: usize,
+    epoch: Instant,
+}
+
+impl IoTrace {
+    pub fn new(capacity: usize) -> Self {
+        IoTrace {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            epoch: Instant::now(),
+        }
+    }
+
+    fn record(&self, op: IoOp, offset: usize, len: usize, start: Instant) {
+        let event = IoEvent {
+            op,
+            offset,
+            len,
+            start: start.duration_since(self.epoch),
+            latency: start.elapsed(),
+        };
+
+        let mut events = self.events.lock();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub fn events(&self) -> Vec<IoEvent> {
+        self.events.lock().iter().copied().collect()
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().clear()
+    }
+
+    pub fn write_csv(&self, mut out: impl Write) -> Result<()> {
+        writeln!(out, "op,offset,len,start_ns,latency_ns").map_err(|err| Error::FileIO { err })?;
+        for e in self.events.lock().iter() {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                e.op.name(),
+                e.offset,
+                e.len,
+                e.start.as_nanos(),
+                e.latency.as_nanos()
+            )
+            .map_err(|err| Error::FileIO { err })?;
+        }
+        Ok(())
+    }
+
+    pub fn read_csv(input: impl BufRead) -> Result<Vec<IoEvent>> {
+        let mut events = Vec::new();
+        for line in input.lines().skip(1) {
+            let line = line.map_err(|err| Error::FileIO { err })?;
+            if line.is_empty() {
+                continue;
+            }
+            events.push(IoEvent::from_csv(&line).ok_or(Error::InvalidTrace {})?);
+        }
+        Ok(events)
+    }
+
+    pub fn write_json(&self, mut out: impl Write) -> Result<()> {
+        let events = self.events.lock();
+        write!(out, "[").map_err(|err| Error::FileIO { err })?;
+        for (n, e) in events.iter().enumerate() {
+            if n != 0 {
+                write!(out, ",").map_err(|err| Error::FileIO { err })?;
+            }
+            write!(
+                out,
+                "{{\"op\":\"{}\",\"offset\":{},\"len\":{},\"start_ns\":{},\"latency_ns\":{}}}",
+                e.op.name(),
+                e.offset,
+                e.len,
+                e.start.as_nanos(),
+                e.latency.as_nanos()
+            )
+            .map_err(|err| Error::FileIO { err })?;
+        }
+        writeln!(out, "]").map_err(|err| Error::FileIO { err })
+    }
+}
+
+pub struct TracingSource<S: Source> {
+    inner: S,
+    trace: Arc<IoTrace>,
+}
+
+impl<S: Source> TracingSource<S> {
+    pub fn new(inner: S, capacity: usize) -> Self {
+        TracingSource {
+            inner,
+            trace: Arc::new(IoTrace::new(capacity)),
+        }
+    }
+
+    pub fn trace(&self) -> Arc<IoTrace> {
+        self.trace.clone()
+    }
+
+    /* asynchronous I/O is recorded once it completes, from when it was issued */
+    fn traced<T: Send + 'static>(
+        &self,
+        op: IoOp,
+        offset: usize,
+        len: usize,
+        io: IoFuture<T>,
+    ) -> IoFuture<T> {
+        let trace = self.trace.clone();
+        let start = Instant::now();
+        Box::pin(async move {
+            let result = io.await;
+            trace.record(op, offset, len, start);
+            result
+        })
+    }
+
+    fn inner_async(&self) -> &dyn AsyncSource {
+        self.inner
+            .as_async()
+            .expect("only handed out when the inner source is async")
+    }
+}
+
+impl<S: Source> Source for TracingSource<S> {
+    fn capabilities(&self) -> SourceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn perf_level(&self) -> usize {
+        self.inner.perf_level()
+    }
+
+    fn close(&mut self) {
+        self.inner.close()
+    }
+
+    fn length(&self) -> Result<usize> {
+        self.inner.length()
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.read(offset, data);
+        self.trace.record(IoOp::Read, offset, data.len(), start);
+        result
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.write(offset, data);
+        self.trace.record(IoOp::Write, offset, data.len(), start);
+        result
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.flush();
+        self.trace.record(IoOp::Flush, 0, 0, start);
+        result
+    }
+
+    fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.sync(mode);
+        self.trace.record(IoOp::Sync, 0, 0, start);
+        result
+    }
+
+    fn discard(&mut self, offset: usize, len: usize) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.discard(offset, len);
+        self.trace.record(IoOp::Discard, offset, len, start);
+        result
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        self.inner.lock(exclusive)
+    }
+
+    fn resize(&mut self, len: usize) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.resize(len);
+        self.trace.record(IoOp::Resize, 0, len, start);
+        result
+    }
+
+    fn as_async(&self) -> Option<&dyn AsyncSource> {
+        self.inner.as_async().map(|_| self as &dyn AsyncSource)
+    }
+
+    fn at(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.inner.at(offset, len)
+    }
+
+    fn at_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.inner.at_mut(offset, len)
+    }
+
+    fn offset(&mut self, ptr: *const u8) -> Result<usize> {
+        self.inner.offset(ptr)
+    }
+
+    fn flush_slice(&self, slice: &[u8]) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.flush_slice(slice);
+        /* Source::offset needs &mut, so only the length is recorded here */
+        self.trace.record(IoOp::FlushSlice, 0, slice.len(), start);
+        result
+    }
+}
+
+impl<S: Source> AsyncSource for TracingSource<S> {
+    fn read_async(&self, offset: usize, len: usize) -> IoFuture<Vec<u8>> {
+        let read = self.inner_async().read_async(offset, len);
+        self.traced(IoOp::Read, offset, len, read)
+    }
+
+    fn write_async(&self, offset: usize, data: Vec<u8>) -> IoFuture<()> {
+        let len = data.len();
+        let write = self.inner_async().write_async(offset, data);
+        self.traced(IoOp::Write, offset, len, write)
+    }
+
+    fn flush_async(&self) -> IoFuture<()> {
+        let flush = self.inner_async().flush_async();
+        self.traced(IoOp::Flush, 0, 0, flush)
+    }
+}
+
+#[cfg(all(test, any(unix, windows)))]
+mod tests {
+    use super::*;
+    use crate::source::async_source::block_on;
+    use crate::source::FileSource;
+
+    #[test]
+    fn passes_through() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-tracing-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut source = TracingSource::new(FileSource::new(path, 1 << 16)?, 16);
+        let trace = source.trace();
+        assert!(source.capabilities().contains(SourceCapabilities::ASYNC));
+
+        block_on(source.as_async().unwrap().write_async(512, vec![1; 512]))?;
+        source.sync(SyncMode::Fsync)?;
+        source.discard(0, 512)?;
+        source.resize(1 << 17)?;
+        assert_eq!(source.length()?, 1 << 17);
+        assert_eq!(
+            block_on(source.as_async().unwrap().read_async(512, 512))?,
+            vec![1; 512]
+        );
+
+        let ops: Vec<_> = trace
+            .events()
+            .iter()
+            .map(|e| (e.op, e.offset, e.len))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                (IoOp::Write, 512, 512),
+                (IoOp::Sync, 0, 0),
+                (IoOp::Discard, 0, 512),
+                (IoOp::Resize, 0, 1 << 17),
+                (IoOp::Read, 512, 512),
+            ]
+        );
+        drop(source);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
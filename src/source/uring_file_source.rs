@@ -0,0 +1,417 @@
+use crate::error::{Error, Result};
+use crate::source::{lock_file, Source, SourceCapabilities};
+use parking_lot::Mutex;
+use std::fs;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const IORING_FSYNC_DATASYNC: u32 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RingParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+fn last_os_error() -> Error {
+    Error::FileIO {
+        err: io::Error::last_os_error(),
+    }
+}
+
+struct RingMapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl RingMapping {
+    fn new(fd: &fs::File, len: usize, offset: libc::off_t) -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(last_os_error());
+        }
+
+        Ok(RingMapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*self.at::<AtomicU32>(offset) }
+    }
+}
+
+impl Drop for RingMapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/*
+ * Only the bits of io_uring this source needs: no polling threads, no
+ * registered buffers. Submission and completion both happen under &mut self,
+ * so there's only ever one thread touching the rings.
+ */
+struct Ring {
+    params: RingParams,
+    sq: RingMapping,
+    cq: RingMapping,
+    sqes: RingMapping,
+    fd: fs::File,
+}
+
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn new(depth: u32) -> Result<Self> {
+        let mut params = RingParams::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                depth,
+                &mut params as *mut RingParams,
+            )
+        };
+        if fd < 0 {
+            return Err(last_os_error());
+        }
+        let fd = unsafe { fs::File::from_raw_fd(fd as i32) };
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * size_of::<Sqe>();
+
+        Ok(Ring {
+            sq: RingMapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+            cq: RingMapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: RingMapping::new(&fd, sqes_len, IORING_OFF_SQES)?,
+            params,
+            fd,
+        })
+    }
+
+    fn push(&mut self, sqes: &[Sqe], base: usize) {
+        let tail = self.sq.atomic(self.params.sq_off.tail);
+        let mask = unsafe { *self.sq.at::<u32>(self.params.sq_off.ring_mask) };
+        let array = self.sq.at::<u32>(self.params.sq_off.array);
+        let entries = self.sqes.at::<Sqe>(0);
+
+        let mut next = tail.load(Ordering::Relaxed);
+        for (n, sqe) in sqes.iter().enumerate() {
+            let index = next & mask;
+            let mut sqe = *sqe;
+            sqe.user_data = (base + n) as u64;
+            unsafe {
+                entries.add(index as usize).write(sqe);
+                array.add(index as usize).write(index);
+            }
+            next = next.wrapping_add(1);
+        }
+        tail.store(next, Ordering::Release);
+    }
+
+    fn reap(&mut self, results: &mut [i32]) -> usize {
+        let head = self.cq.atomic(self.params.cq_off.head);
+        let tail = self.cq.atomic(self.params.cq_off.tail);
+        let mask = unsafe { *self.cq.at::<u32>(self.params.cq_off.ring_mask) };
+        let cqes = self.cq.at::<Cqe>(self.params.cq_off.cqes);
+
+        let mut next = head.load(Ordering::Relaxed);
+        let last = tail.load(Ordering::Acquire);
+        let mut reaped = 0;
+        while next != last {
+            let cqe = unsafe { &*cqes.add((next & mask) as usize) };
+            results[cqe.user_data as usize] = cqe.res;
+            next = next.wrapping_add(1);
+            reaped += 1;
+        }
+        head.store(next, Ordering::Release);
+
+        reaped
+    }
+
+    /*
+     * Batches larger than the submission queue are split up, but each chunk
+     * still costs a single io_uring_enter in the common case.
+     */
+    fn submit_and_wait(&mut self, sqes: &[Sqe]) -> Result<Vec<i32>> {
+        let mut results = vec![0; sqes.len()];
+        let depth = self.params.sq_entries as usize;
+
+        for (n, chunk) in sqes.chunks(depth).enumerate() {
+            self.push(chunk, n * depth);
+
+            let mut submitted = 0;
+            let mut completed = 0;
+            while completed < chunk.len() {
+                let ret = unsafe {
+                    libc::syscall(
+                        libc::SYS_io_uring_enter,
+                        self.fd.as_raw_fd(),
+                        (chunk.len() - submitted) as u32,
+                        (chunk.len() - completed) as u32,
+                        IORING_ENTER_GETEVENTS,
+                        ptr::null::<libc::sigset_t>(),
+                        0usize,
+                    )
+                };
+                if ret < 0 {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(Error::FileIO { err });
+                }
+                submitted += ret as usize;
+                completed += self.reap(&mut results);
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/* the ring only ever has one batch in flight, submitters take turns */
+pub struct IoUringFileSource {
+    file: fs::File,
+    ring: Mutex<Ring>,
+}
+
+impl IoUringFileSource {
+    pub fn new(path: &str, len: usize, queue_depth: u32) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| Error::SourceError {})?;
+
+        file.set_len(len as u64)
+            .map_err(|err| Error::FileIO { err })?;
+
+        Ok(IoUringFileSource {
+            file,
+            ring: Mutex::new(Ring::new(queue_depth)?),
+        })
+    }
+
+    fn sqe(&self, opcode: u8, offset: usize, addr: *const u8, len: usize) -> Sqe {
+        Sqe {
+            opcode,
+            fd: self.file.as_raw_fd(),
+            off: offset as u64,
+            addr: addr as u64,
+            len: len as u32,
+            ..Default::default()
+        }
+    }
+
+    fn submit(&self, sqes: &[Sqe]) -> Result<()> {
+        let results = self.ring.lock().submit_and_wait(sqes)?;
+        for (sqe, res) in sqes.iter().zip(results) {
+            if res < 0 {
+                return Err(Error::FileIO {
+                    err: io::Error::from_raw_os_error(-res),
+                });
+            }
+            if sqe.opcode != IORING_OP_FSYNC && res as u32 != sqe.len {
+                return Err(Error::PartialIO {});
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Source for IoUringFileSource {
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities::PERSISTENT
+    }
+
+    fn perf_level(&self) -> usize {
+        0
+    }
+
+    fn close(&mut self) {}
+
+    fn length(&self) -> Result<usize> {
+        let m = self.file.metadata().map_err(|err| Error::FileIO { err })?;
+        Ok(m.len() as usize)
+    }
+
+    fn read(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        self.read_batch(&mut [(offset, data)])
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.write_batch(&[(offset, data)])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut sqe = self.sqe(IORING_OP_FSYNC, 0, ptr::null(), 0);
+        sqe.op_flags = IORING_FSYNC_DATASYNC;
+        self.submit(&[sqe])
+    }
+
+    fn read_batch(&self, reqs: &mut [(usize, &mut [u8])]) -> Result<()> {
+        let sqes: Vec<Sqe> = reqs
+            .iter()
+            .map(|(offset, data)| self.sqe(IORING_OP_READ, *offset, data.as_ptr(), data.len()))
+            .collect();
+        self.submit(&sqes)
+    }
+
+    fn write_batch(&mut self, reqs: &[(usize, &[u8])]) -> Result<()> {
+        let sqes: Vec<Sqe> = reqs
+            .iter()
+            .map(|(offset, data)| self.sqe(IORING_OP_WRITE, *offset, data.as_ptr(), data.len()))
+            .collect();
+        self.submit(&sqes)
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<()> {
+        lock_file(&self.file, exclusive)
+    }
+
+    fn at(&self, _offset: usize, _len: usize) -> Result<&[u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn at_mut(&mut self, _offset: usize, _len: usize) -> Result<&mut [u8]> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn offset(&mut self, _ptr: *const u8) -> Result<usize> {
+        Err(Error::NotByteAddressable {})
+    }
+
+    fn flush_slice(&self, _slice: &[u8]) -> Result<()> {
+        Err(Error::NotByteAddressable {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_roundtrip() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("librarius-uring-{}", std::process::id()));
+        /* io_uring is commonly disabled in containers */
+        let mut source = match IoUringFileSource::new(path.to_str().unwrap(), 1 << 16, 2) {
+            Err(Error::FileIO { .. }) => return Ok(()),
+            source => source?,
+        };
+
+        let pages: Vec<Vec<u8>> = (0..5u8).map(|n| vec![n + 1; 4096]).collect();
+        let writes: Vec<(usize, &[u8])> = pages
+            .iter()
+            .enumerate()
+            .map(|(n, page)| (n * 8192, page.as_slice()))
+            .collect();
+        source.write_batch(&writes)?;
+        source.flush()?;
+
+        let mut data = vec![vec![0u8; 4096]; 5];
+        let mut reads: Vec<(usize, &mut [u8])> = data
+            .iter_mut()
+            .enumerate()
+            .map(|(n, page)| (n * 8192, page.as_mut_slice()))
+            .collect();
+        source.read_batch(&mut reads)?;
+        assert_eq!(data, pages);
+
+        std::fs::remove_file(path).map_err(|err| Error::FileIO { err })
+    }
+}
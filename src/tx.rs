@@ -1,35 +1,232 @@
-use crate::error::{Error, Result};
-use crate::las::{LogicalAddress, LogicalAddressSpace, StoredLogicalSlice};
+use crate::error::{ConflictReason, Error, Result};
+use crate::las::{LogicalAddress, LogicalAddressSpace, LogicalMutRef, StoredLogicalSlice};
+use crate::record::{Recorder, Recording};
 use crate::utils::unsafe_utils;
 use crate::vos::{
-    TransactionalLogAllocator, TransactionalObjectAllocator, UntypedPointer, Version,
-    VersionedObjectStore, VersionedReader, ObjectSize
+    IndirectVersion, ObjectSize, PointerToken, TransactionalLogAllocator,
+    TransactionalObjectAllocator, UntypedPointer, Version, VersionedObjectStore, VersionedReader,
+    UNTYPED,
 };
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::mem::size_of;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 struct TransactionWrite<'tx> {
     dst: &'tx UntypedPointer,
-    current: LogicalAddress,
+    /* the pointer exactly as it was read, whatever its type */
+    current: UntypedPointer,
     new: UntypedPointer,
 }
 
 impl<'tx> TransactionWrite<'tx> {
-    pub fn new(dst: &'tx UntypedPointer, current: LogicalAddress, new: UntypedPointer) -> Self {
+    pub fn new(dst: &'tx UntypedPointer, current: UntypedPointer, new: UntypedPointer) -> Self {
         TransactionWrite { dst, current, new }
     }
 
     pub fn perform(&self) -> bool {
-        self.dst.compare_and_swap(
-            UntypedPointer::new_byte(self.current),
-            self.new.clone(),
-        )
+        self.dst
+            .compare_and_swap(self.current.clone(), self.new.clone())
     }
 
-    pub fn rollback(&self) {
-        let success = self.dst.compare_and_swap(
-            self.new.clone(),
-            UntypedPointer::new_byte(self.current),
-        );
-        assert!(success)
+    /* fails when the pointer was moved since, which only a discarded copy can do */
+    pub fn rollback(&self) -> bool {
+        self.dst
+            .compare_and_swap(self.new.clone(), self.current.clone())
+    }
+}
+
+/*
+ * Named roots are listed in a directory, pointers to their entries first
+ * and then their names, in the same order. Each entry is an object that
+ * holds nothing but the pointer to its root, so that the pointer stays
+ * where it is when the directory grows.
+ */
+const ROOT_NAME_LEN: usize = 32;
+const ROOT_ENTRY_SIZE: ObjectSize = ObjectSize {
+    pointers: size_of::<UntypedPointer>() as u32,
+    data: 0,
+};
+
+/* what a transaction is about to do with a named root, for the root policy */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RootAccess {
+    Read,
+    /* written through, after a transaction looked it up */
+    Write,
+    Create,
+}
+
+/* roots the store keeps for itself, no policy or lookup gets to see them */
+pub(crate) const RESERVED_ROOT_PREFIX: &str = "librarius.";
+
+/* whether a named root can be accessed, the host decides per name */
+pub(crate) type RootPolicy<'a> = dyn Fn(&str, RootAccess) -> bool + Send + Sync + 'a;
+
+fn allowed(policy: Option<&RootPolicy>, name: &str, access: RootAccess) -> Result<()> {
+    if name.starts_with(RESERVED_ROOT_PREFIX) {
+        return Err(Error::ReservedRootName {
+            name: name.to_string(),
+        });
+    }
+    match policy {
+        Some(policy) if !policy(name, access) => Err(Error::RootAccessDenied {
+            name: name.to_string(),
+            access,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn root_name(name: &str) -> Result<[u8; ROOT_NAME_LEN]> {
+    if name.is_empty() || name.len() > ROOT_NAME_LEN {
+        return Err(Error::InvalidRootName {
+            name: name.to_string(),
+        });
+    }
+    let mut key = [0; ROOT_NAME_LEN];
+    key[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(key)
+}
+
+fn find_root<'a>(
+    directory: &'a [u8],
+    size: &ObjectSize,
+    name: &[u8; ROOT_NAME_LEN],
+) -> Option<&'a UntypedPointer> {
+    let (pointers, names) = directory.split_at(size.pointers as usize);
+    unsafe_utils::many_from_slice::<UntypedPointer>(pointers)
+        .iter()
+        .zip(names.chunks_exact(ROOT_NAME_LEN))
+        .find(|(_, other)| *other == name)
+        .map(|(entry, _)| entry)
+}
+
+/* copies as much of src as fits, whatever is left of dst is zeroed */
+fn copy_prefix(dst: &mut [u8], src: &[u8]) {
+    let len = std::cmp::min(dst.len(), src.len());
+    dst[..len].copy_from_slice(&src[..len]);
+    dst[len..].iter_mut().for_each(|b| *b = 0);
+}
+
+/*
+ * Write locks of the pessimistic mode, kept in a side table keyed by the
+ * address of the pointer that a write swings. Conflicts are settled with
+ * wait-die: a transaction older than the holder waits for it, a younger
+ * one aborts right away. Retries keep their ticket, so every transaction
+ * eventually becomes the oldest and can't be starved.
+ */
+pub(crate) struct ObjectLocks {
+    held: Mutex<HashMap<usize, u64>>,
+    released: Condvar,
+    tickets: AtomicU64,
+}
+
+impl ObjectLocks {
+    pub fn new() -> Self {
+        ObjectLocks {
+            held: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+            tickets: AtomicU64::new(0),
+        }
+    }
+
+    /* lower tickets are older */
+    pub fn ticket(&self) -> u64 {
+        self.tickets.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn acquire(&self, key: usize, ticket: u64, address: LogicalAddress) -> Result<()> {
+        let mut held = self.held.lock();
+        loop {
+            match held.get(&key) {
+                None => {
+                    held.insert(key, ticket);
+                    return Ok(());
+                }
+                Some(owner) if *owner == ticket => return Ok(()),
+                Some(owner) if ticket < *owner => self.released.wait(&mut held),
+                Some(_) => {
+                    return Err(Error::TxAborted {
+                        reason: ConflictReason::Locked { address },
+                    })
+                }
+            }
+        }
+    }
+
+    fn release(&self, keys: &[usize]) {
+        if keys.is_empty() {
+            return;
+        }
+        let mut held = self.held.lock();
+        for key in keys {
+            held.remove(key);
+        }
+        self.released.notify_all();
+    }
+}
+
+/* what the closure of Librarius::run_async returns */
+pub type TxFuture<'a, R> = Pin<Box<dyn Future<Output = Result<R>> + 'a>>;
+
+/* cancels every transaction it was given to, clones share the same state */
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/*
+ * Limits for Librarius::run_with. Both are checked cooperatively, between
+ * attempts and whenever the transaction reads or writes, so a transaction
+ * stuck in code of its own is never interrupted.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct TxOptions {
+    /* for all attempts together */
+    pub timeout: Option<Duration>,
+    pub cancel_token: Option<CancelToken>,
+}
+
+pub(crate) struct Deadline {
+    at: Option<Instant>,
+    cancel_token: Option<CancelToken>,
+}
+
+impl Deadline {
+    pub fn new(options: &TxOptions) -> Self {
+        Deadline {
+            at: options.timeout.map(|timeout| Instant::now() + timeout),
+            cancel_token: options.cancel_token.clone(),
+        }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.cancel_token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            return Err(Error::TxCancelled {});
+        }
+        match self.at {
+            Some(at) if Instant::now() >= at => Err(Error::TxTimedOut {}),
+            _ => Ok(()),
+        }
     }
 }
 
@@ -43,18 +240,89 @@ impl<'tx> TransactionRead<'tx> {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TxStats {
+    pub attempts: usize,
+    /* attempts that ended in TxAborted */
+    pub conflicts: usize,
+    pub objects_read: usize,
+    /* newer versions passed over on the way to the ones in the snapshot */
+    pub versions_skipped: usize,
+    pub objects_written: usize,
+    /* readset entries checked at commit, none if nothing committed since the snapshot */
+    pub reads_validated: usize,
+    /* object headers included */
+    pub bytes_allocated: usize,
+    /* distinct pages read from or allocated in */
+    pub pages_touched: usize,
+}
+
+impl TxStats {
+    pub(crate) fn merge(&mut self, other: &TxStats) {
+        self.attempts += other.attempts;
+        self.conflicts += other.conflicts;
+        self.objects_read += other.objects_read;
+        self.versions_skipped += other.versions_skipped;
+        self.objects_written += other.objects_written;
+        self.reads_validated += other.reads_validated;
+        self.bytes_allocated += other.bytes_allocated;
+        self.pages_touched += other.pages_touched;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub snapshot: usize,
+    /* None for transactions that didn't write anything */
+    pub version: Option<usize>,
+    /* covers every attempt it took to commit */
+    pub stats: TxStats,
+}
+
 pub struct Transaction<'tx, 'data: 'tx> {
     las: &'tx LogicalAddressSpace<'data>,
     vos: &'tx VersionedObjectStore<'data>,
     root: &'tx UntypedPointer,
+    /* none in stores made before there were named roots */
+    directory: Option<&'tx UntypedPointer>,
+    policy: Option<&'tx RootPolicy<'tx>>,
+    /* named roots looked up so far, by the address of their pointer */
+    named: Vec<(usize, String)>,
 
-    object_allocator: TransactionalObjectAllocator<'tx>,
-    log_allocator: TransactionalLogAllocator<'tx>,
+    object_allocator: TransactionalObjectAllocator<'tx, 'data>,
+    log_allocator: TransactionalLogAllocator<'tx, 'data>,
     reader: VersionedReader<'tx, 'data>,
-    version: Option<Version>,
+    version: Option<IndirectVersion<'data>>,
 
     writeset: Vec<TransactionWrite<'tx>>,
     readset: Vec<TransactionRead<'tx>>,
+    /* objects this transaction frees, header included */
+    freeset: Vec<LogicalMutRef<'data>>,
+    /* owners written through set, with the latest redo record of each */
+    logged: Vec<(&'tx UntypedPointer, UntypedPointer)>,
+    validated: usize,
+    /* committed or aborted, anything else is aborted when it's dropped */
+    finished: bool,
+
+    locks: Option<(&'tx ObjectLocks, u64)>,
+    locked: Vec<usize>,
+    deadline: Option<&'tx Deadline>,
+
+    on_commit: Vec<Box<dyn FnOnce() + 'tx>>,
+    on_abort: Vec<Box<dyn FnOnce() + 'tx>>,
+
+    recorder: Option<Recorder<'tx>>,
+}
+
+impl<'tx, 'data: 'tx> Drop for Transaction<'tx, 'data> {
+    /* a panic, or a future dropped before it's done, mustn't leave uncommitted versions behind */
+    fn drop(&mut self) {
+        self.abort();
+        if let Some((locks, _)) = self.locks {
+            locks.release(&self.locked);
+        }
+        self.vos.unpin(&self.reader);
+    }
 }
 
 impl<'tx, 'data: 'tx> Transaction<'tx, 'data> {
@@ -65,23 +333,158 @@ impl<'tx, 'data: 'tx> Transaction<'tx, 'data> {
     ) -> Self {
         let object_allocator = vos.new_object_allocator(las.boxed_page_alloc());
         let log_allocator = vos.new_log_allocator(las.boxed_page_alloc());
-        let reader = vos.new_versioned_reader(las);
+        let reader = vos.new_pinned_reader(las);
 
         Transaction {
             vos,
             las,
             root,
+            directory: None,
+            policy: None,
+            named: Vec::new(),
             object_allocator,
             log_allocator,
             reader,
             version: None,
             writeset: Vec::new(),
             readset: Vec::new(),
+            freeset: Vec::new(),
+            logged: Vec::new(),
+            validated: 0,
+            finished: false,
+            locks: None,
+            locked: Vec::new(),
+            deadline: None,
+            on_commit: Vec::new(),
+            on_abort: Vec::new(),
+            recorder: None,
         }
     }
 
+    /*
+     * Side effects that should only happen once the outcome is known. Each
+     * attempt of a retried transaction registers its own, and whichever of
+     * them matches how the attempt ended runs exactly once, in order.
+     */
+    pub fn on_commit(&mut self, f: impl FnOnce() + 'tx) {
+        self.on_commit.push(Box::new(f));
+    }
+
+    pub fn on_abort(&mut self, f: impl FnOnce() + 'tx) {
+        self.on_abort.push(Box::new(f));
+    }
+
+    /* writes lock their objects until the transaction is gone */
+    pub(crate) fn lock_with(&mut self, locks: &'tx ObjectLocks, ticket: u64) {
+        self.locks = Some((locks, ticket));
+    }
+
+    pub(crate) fn limit_with(&mut self, deadline: &'tx Deadline) {
+        self.deadline = Some(deadline);
+    }
+
+    pub(crate) fn record_with(&mut self, recording: &'tx Recording) {
+        self.recorder = Some(Recorder::new(recording));
+    }
+
+    pub(crate) fn directory_with(
+        &mut self,
+        directory: Option<&'tx UntypedPointer>,
+        policy: Option<&'tx RootPolicy<'tx>>,
+    ) {
+        self.directory = directory;
+        self.policy = policy;
+    }
+
+    fn check(&self) -> Result<()> {
+        self.deadline.map_or(Ok(()), |deadline| deadline.check())
+    }
+
+    /* everything that writes through a pointer locks it first */
+    fn lock(&mut self, pointer: &UntypedPointer) -> Result<()> {
+        let key = pointer as *const UntypedPointer as usize;
+        if let Some((_, name)) = self.named.iter().find(|(named, _)| *named == key) {
+            allowed(self.policy, name, RootAccess::Write)?;
+        }
+        if let Some((locks, ticket)) = self.locks {
+            if !self.locked.contains(&key) {
+                locks.acquire(key, ticket, pointer.address())?;
+                self.locked.push(key);
+            }
+        }
+        Ok(())
+    }
+
     pub fn read(&mut self, pointer: &'tx UntypedPointer, size: &ObjectSize) -> Result<&'tx [u8]> {
-        Ok(self.reader.read(pointer, size, false)?.0)
+        self.check()?;
+        let data = match self.own_version(pointer)? {
+            Some(own) => self.reader.read_own(own, size)?,
+            None => self.reader.read(pointer, size, false)?.0,
+        };
+        self.record_read(pointer, size, data)?;
+        Ok(data)
+    }
+
+    fn record_read(
+        &mut self,
+        pointer: &UntypedPointer,
+        size: &ObjectSize,
+        data: &[u8],
+    ) -> Result<()> {
+        let kind = match &self.recorder {
+            None => return Ok(()),
+            Some(recorder) if recorder.is_before(pointer) => Some(self.object_kind(pointer)?),
+            Some(_) => None,
+        };
+        if let Some(recorder) = &mut self.recorder {
+            recorder.read(pointer, kind, data, size);
+        }
+        Ok(())
+    }
+
+    /*
+     * The newest version of the object written by this transaction, if any,
+     * or the object itself when this transaction allocated it. A write only
+     * counts while its pointer is still where it was swung, a copy made by
+     * this transaction is free to move pointers around.
+     */
+    fn own_version<'a>(
+        &'a self,
+        pointer: &'a UntypedPointer,
+    ) -> Result<Option<&'a UntypedPointer>> {
+        if let Some(w) = self.written(pointer) {
+            return Ok(Some(&w.new));
+        }
+        match &self.version {
+            Some(version)
+                if pointer.is_some() && self.reader.owned_by(pointer, &version.version())? =>
+            {
+                Ok(Some(pointer))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn written(&self, pointer: &UntypedPointer) -> Option<&TransactionWrite<'tx>> {
+        self.writeset
+            .iter()
+            .rev()
+            .find(|w| std::ptr::eq(w.dst, pointer) && pointer.address() == w.new.address())
+    }
+
+    /* like read, but an object on a block source is fetched without blocking */
+    pub async fn read_async(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        size: &ObjectSize,
+    ) -> Result<&'tx [u8]> {
+        self.check()?;
+        let data = match self.own_version(pointer)? {
+            Some(own) => self.reader.read_own(own, size)?,
+            None => self.reader.read_async(pointer, size, false).await?.0,
+        };
+        self.record_read(pointer, size, data)?;
+        Ok(data)
     }
 
     pub fn read_for_write(
@@ -89,83 +492,732 @@ impl<'tx, 'data: 'tx> Transaction<'tx, 'data> {
         pointer: &'tx UntypedPointer,
         size: &ObjectSize,
     ) -> Result<&'tx [u8]> {
-        self.readset.push(TransactionRead::new(pointer));
-        Ok(self.reader.read(pointer, size, true)?.0)
+        self.check()?;
+        let data = match self.own_version(pointer)? {
+            Some(own) => self.reader.read_own(own, size)?,
+            None => {
+                self.readset.push(TransactionRead::new(pointer));
+                self.reader.read(pointer, size, true)?.0
+            }
+        };
+        self.record_read(pointer, size, data)?;
+        Ok(data)
+    }
+
+    pub fn snapshot_version(&self) -> usize {
+        self.reader.version()
+    }
+
+    /* what this attempt did so far */
+    pub fn stats(&self) -> TxStats {
+        let mut pages = self.reader.pages();
+        pages.extend(self.object_allocator.pages());
+
+        TxStats {
+            attempts: 1,
+            conflicts: 0,
+            objects_read: self.reader.objects_read(),
+            versions_skipped: self.reader.versions_skipped(),
+            objects_written: self.writeset.len(),
+            reads_validated: self.validated,
+            bytes_allocated: self.object_allocator.bytes_allocated(),
+            pages_touched: pages.len(),
+        }
     }
 
     pub fn root(&mut self) -> &'tx UntypedPointer {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.root(self.root, None);
+        }
         self.root
     }
 
-    pub fn write(&mut self, pointer: &'tx UntypedPointer, size: &ObjectSize) -> Result<&'tx mut [u8]> {
+    /* the size the object was allocated with, for walking objects without their types */
+    pub fn object_size(&self, pointer: &UntypedPointer) -> Result<ObjectSize> {
+        match self.own_version(pointer)? {
+            Some(own) => Ok(self.reader.header(own)?.size),
+            None => self.reader.size(pointer),
+        }
+    }
+
+    /* the fingerprint of the type the object was allocated as, or UNTYPED */
+    pub fn object_kind(&self, pointer: &UntypedPointer) -> Result<u64> {
+        match self.own_version(pointer)? {
+            Some(own) => Ok(self.reader.header(own)?.kind),
+            None => self.reader.kind(pointer),
+        }
+    }
+
+    /* the pointer to the root with the given name, if there's one */
+    pub fn named_root(&mut self, name: &str) -> Result<Option<&'tx UntypedPointer>> {
+        allowed(self.policy, name, RootAccess::Read)?;
+        let root = self.find_named_root(name)?;
+        if let Some(root) = root {
+            let key = root as *const UntypedPointer as usize;
+            self.named.push((key, name.to_string()));
+            if let Some(recorder) = &mut self.recorder {
+                recorder.root(root, Some(name));
+            }
+        }
+        Ok(root)
+    }
+
+    /* like named_root, but for reserved names too, and without asking the policy */
+    pub(crate) fn find_named_root(&mut self, name: &str) -> Result<Option<&'tx UntypedPointer>> {
+        let name = root_name(name)?;
+        let directory = self.directory.ok_or(Error::NoRootDirectory {})?;
+        let size = self.object_size(directory)?;
+        let data = self.read(directory, &size)?;
+        match find_root(data, &size, &name) {
+            Some(entry) => {
+                let entry = self.read(entry, &ROOT_ENTRY_SIZE)?;
+                Ok(Some(unsafe_utils::any_from_slice(entry)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /*
+     * Allocates a root that is found by its name from then on. Like any
+     * other allocation, it's gone if the transaction doesn't commit.
+     */
+    pub fn alloc_named_root(
+        &mut self,
+        name: &str,
+        size: ObjectSize,
+    ) -> Result<(&'tx UntypedPointer, &'tx mut [u8])> {
+        allowed(self.policy, name, RootAccess::Create)?;
+        let (root, data) = self.create_named_root(name, size)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.allocated(root, Some(name));
+        }
+        Ok((root, data))
+    }
+
+    pub(crate) fn create_named_root(
+        &mut self,
+        name: &str,
+        size: ObjectSize,
+    ) -> Result<(&'tx UntypedPointer, &'tx mut [u8])> {
+        let key = root_name(name)?;
+        let directory = self.directory.ok_or(Error::NoRootDirectory {})?;
+        let old_size = self.object_size(directory)?;
+        if find_root(self.read(directory, &old_size)?, &old_size, &key).is_some() {
+            return Err(Error::NamedRootExists {
+                name: name.to_string(),
+            });
+        }
+
+        let (root, data) = self.alloc_object(size)?;
+        let (entry, slot) = self.alloc_object(ROOT_ENTRY_SIZE)?;
+        *unsafe_utils::any_from_slice_mut::<UntypedPointer>(slot) = root;
+        let slot: &'tx [u8] = slot;
+
+        /* the directory only grows, the new entry goes at the end */
+        let n = old_size.pointers as usize / size_of::<UntypedPointer>();
+        let size = ObjectSize::new_with_usize(
+            (n + 1) * size_of::<UntypedPointer>(),
+            (n + 1) * ROOT_NAME_LEN,
+        );
+        let (pointers, names) = self
+            .resize(directory, size)?
+            .split_at_mut(size.pointers as usize);
+        unsafe_utils::many_from_slice_mut::<UntypedPointer>(pointers)[n] = entry;
+        names[n * ROOT_NAME_LEN..].copy_from_slice(&key);
+
+        Ok((unsafe_utils::any_from_slice(slot), data))
+    }
+
+    pub fn write(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        size: &ObjectSize,
+    ) -> Result<&'tx mut [u8]> {
+        let address = pointer.address();
+        let data = self.write_object(pointer, size)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.wrote(address, pointer, Some((data, size)));
+        }
+        Ok(data)
+    }
+
+    fn write_object(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        size: &ObjectSize,
+    ) -> Result<&'tx mut [u8]> {
+        self.lock(pointer)?;
+        let current = pointer.clone();
         let read_pointer = pointer.clone();
-        let address = read_pointer.address();
 
         let version = self.write_version()?;
 
-        let (src, hdr) = self.reader.read(&read_pointer, size, true)?;
-        let (dstptr, dst) = self.object_allocator.alloc(*size, version, read_pointer)?;
+        /* there's already a copy to write to, unless it's only redo records */
+        let src = match self.own_version(pointer)? {
+            Some(own) if !own.is_log() => return self.reader.write_own(own, size),
+            Some(own) => self.reader.read_own(own, size)?,
+            None => self.reader.read(&read_pointer, size, true)?.0,
+        };
+        let kind = self.reader.header(&read_pointer)?.kind;
+        let (dstptr, dst) = self
+            .object_allocator
+            .alloc(*size, kind, version, read_pointer)?;
 
         dst.copy_from_slice(src);
 
-        let write = TransactionWrite::new(pointer, address, dstptr);
+        self.swing(pointer, current, dstptr)?;
+        Ok(dst)
+    }
+
+    /*
+     * Like write, but the new version has a different size. Pointers and
+     * data are resized separately, each keeping the prefix that still fits,
+     * and anything added is zeroed.
+     */
+    pub fn realloc(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        size: ObjectSize,
+    ) -> Result<&'tx mut [u8]> {
+        let address = pointer.address();
+        let data = self.resize(pointer, size)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.wrote(address, pointer, Some((data, &size)));
+        }
+        Ok(data)
+    }
+
+    fn resize(&mut self, pointer: &'tx UntypedPointer, size: ObjectSize) -> Result<&'tx mut [u8]> {
+        self.lock(pointer)?;
+        let current = pointer.clone();
+        let read_pointer = pointer.clone();
+
+        let version = self.write_version()?;
+
+        let header = self.reader.header(&read_pointer)?;
+        let (old_size, kind) = (header.size, header.kind);
+        let src = match self.own_version(pointer)? {
+            Some(own) => self.reader.read_own(own, &old_size)?,
+            None => self.reader.read(&read_pointer, &old_size, true)?.0,
+        };
+        let (dstptr, dst) = self
+            .object_allocator
+            .alloc(size, kind, version, read_pointer)?;
+
+        let (src_pointers, src_data) = src.split_at(old_size.pointers as usize);
+        let (dst_pointers, dst_data) = dst.split_at_mut(size.pointers as usize);
+        copy_prefix(dst_pointers, src_pointers);
+        copy_prefix(dst_data, src_data);
+
+        self.swing(pointer, current, dstptr)?;
+        Ok(dst)
+    }
+
+    /* points the object at its new version, for as long as nobody else did first */
+    fn swing(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        current: UntypedPointer,
+        new: UntypedPointer,
+    ) -> Result<()> {
+        let address = current.address();
+        let new = new.with_refcount(current.refcount());
+        let write = TransactionWrite::new(pointer, current, new);
 
         if !write.perform() {
-            Err(Error::TxAborted {})
+            Err(Error::TxAborted {
+                reason: ConflictReason::WriteRace { address },
+            })
         } else {
             self.writeset.push(write);
 
-            Ok(dst)
+            Ok(())
         }
     }
 
+    /*
+     * Changes the count in the spare bits of the pointer. The object is
+     * written first, as write would, so that two transactions can't both
+     * change the count, and an abort puts the old one back.
+     */
+    pub(crate) fn set_refcount<F>(
+        &mut self,
+        pointer: &'tx UntypedPointer,
+        size: &ObjectSize,
+        f: F,
+    ) -> Result<u8>
+    where
+        F: FnOnce(u8) -> Result<u8>,
+    {
+        self.write(pointer, size)?;
+        let current = pointer.clone();
+        let count = f(current.refcount())?;
+        let new = current.with_refcount(count);
+        if !pointer.compare_and_swap(current.clone(), new.clone()) {
+            return Err(Error::TxAborted {
+                reason: ConflictReason::WriteRace {
+                    address: current.address(),
+                },
+            });
+        }
+        if let Some(w) = self
+            .writeset
+            .iter_mut()
+            .rev()
+            .find(|w| std::ptr::eq(w.dst, pointer) && w.new.address() == new.address())
+        {
+            w.new = new;
+        }
+        Ok(count)
+    }
+
     fn write_version(&mut self) -> Result<Version> {
-        if self.version.is_some() {
-            Ok(self.version.clone().unwrap())
+        if self.las.is_read_only() {
+            return Err(Error::ReadOnly {});
+        }
+        self.check()?;
+        if let Some(version) = &self.version {
+            Ok(version.version())
         } else {
-            self.version = Some(self.log_allocator.new_indirect_version()?);
+            self.version = Some(self.vos.new_indirect_version(self.las)?);
             self.write_version()
         }
     }
 
     pub fn alloc(&mut self, size: ObjectSize) -> Result<(UntypedPointer, &'tx mut [u8])> {
+        let (pointer, data) = self.alloc_object(size)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.allocated(&pointer, None);
+        }
+        Ok((pointer, data))
+    }
+
+    fn alloc_object(&mut self, size: ObjectSize) -> Result<(UntypedPointer, &'tx mut [u8])> {
+        let version = self.write_version()?;
+        self.object_allocator.alloc_new(size, UNTYPED, version)
+    }
+
+    pub fn alloc_near(
+        &mut self,
+        near: &UntypedPointer,
+        size: ObjectSize,
+    ) -> Result<(UntypedPointer, &'tx mut [u8])> {
+        let version = self.write_version()?;
+        let (pointer, data) =
+            self.object_allocator
+                .alloc_near(near.address(), size, UNTYPED, version)?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.allocated(&pointer, None);
+        }
+        Ok((pointer, data))
+    }
+
+    /*
+     * Records the type an object was allocated as, for typed reads to
+     * check. Only objects this transaction made, or copied, can change it.
+     */
+    pub(crate) fn retype(&mut self, pointer: &UntypedPointer, kind: u64) -> Result<()> {
+        match self.own_version(pointer)? {
+            Some(own) if !own.is_log() => self.reader.retype(own, kind),
+            _ => Err(Error::InvalidLogicalAddress {}),
+        }
+    }
+
+    /*
+     * The object is gone for every transaction that starts after this one
+     * commits, its space is reused once the older ones are done with it.
+     * Pointers to it are not touched, it's up to the caller to unlink it.
+     */
+    pub fn free(&mut self, pointer: &'tx UntypedPointer) -> Result<()> {
         let version = self.write_version()?;
-        self.object_allocator.alloc_new(size, version)
+        self.lock(pointer)?;
+
+        /* a copy made by this transaction goes along with what it replaced */
+        let replaced = self
+            .written(pointer)
+            .map(|w| w.current.clone())
+            .filter(|current| current.is_byte_addressable());
+        if let Some(replaced) = replaced {
+            let object = self.reader.tombstone(&replaced, &version)?;
+            self.freeset.push(self.las.write_ref(&object)?);
+        }
+
+        let object = self.reader.tombstone(pointer, &version)?;
+        self.freeset.push(self.las.write_ref(&object)?);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.freed(pointer);
+        }
+
+        Ok(())
     }
 
-    pub fn set(&mut self, owner: &UntypedPointer, offset: usize, src: &'tx [u8]) -> Result<()> {
-        todo!()
+    pub fn to_token(&self, pointer: &UntypedPointer) -> PointerToken {
+        pointer.to_token(self.vos.epoch())
+    }
+
+    /*
+     * The resolved pointer is a private copy living in the transaction's log,
+     * it can be read through but writes to it won't be visible to anyone.
+     */
+    pub fn resolve_token(&mut self, token: &PointerToken) -> Result<&'tx UntypedPointer> {
+        let pointer = UntypedPointer::from_token(token, self.vos.epoch())?;
+        self.log_allocator.alloc_pointer(pointer)
+    }
+
+    /*
+     * Overwrites part of an object without copying the rest of it. The
+     * bytes go to a redo record in the transaction's log, which the owner
+     * points to until the bytes get applied to the object in place, once
+     * no running transaction can still need the object as it was.
+     */
+    pub fn set(&mut self, owner: &'tx UntypedPointer, offset: usize, src: &[u8]) -> Result<()> {
+        self.lock(owner)?;
+        let version = self.write_version()?;
+
+        /* only the data, a pointer has to be written as a whole, with write() */
+        let size = self.reader.header(owner)?.size;
+        let end = offset.checked_add(src.len());
+        if offset < size.pointers as usize || end.is_none_or(|end| end > size.total()) {
+            return Err(Error::OutOfBounds {});
+        }
+
+        let owned = self.reader.owned_by(owner, &version)?;
+        if owned && owner.is_byte_addressable() {
+            /* a copy made by this transaction, no one else can see it yet */
+            return self.reader.patch(owner, offset, src);
+        }
+        if !owned {
+            /* checks for conflicts, and brings the object into memory */
+            self.reader.read(owner, &size, true)?;
+        }
+
+        let current = owner.clone();
+        let entry = self
+            .log_allocator
+            .alloc_entry(version, current.clone(), offset, src)?;
+        self.swing(owner, current.clone(), entry.clone())?;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.wrote(current.address(), owner, None);
+        }
+
+        let logged = self
+            .logged
+            .iter_mut()
+            .find(|(logged, _)| std::ptr::eq(*logged, owner));
+        match logged {
+            Some((_, last)) => *last = entry,
+            None => self.logged.push((owner, entry)),
+        }
+
+        Ok(())
     }
 
     pub fn abort(&mut self) {
-        for w in &self.writeset {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        for w in self.writeset.iter().rev() {
             w.rollback();
         }
+        for object in self.freeset.drain(..) {
+            self.reader.revive(&object);
+        }
+        /* space that can't be resolved anymore is left unused, a rollback can't fail */
+        let objects = self
+            .object_allocator
+            .take_objects()
+            .iter()
+            .filter_map(|object| self.las.write_ref(object).ok())
+            .collect();
+        self.vos.discard(objects);
+        self.on_commit.clear();
+        for f in self.on_abort.drain(..) {
+            f();
+        }
     }
 
-    pub fn commit(&mut self) -> Result<()> {
-        if let Some(version) = &self.version {
+    /*
+     * Rolls back on purpose. The error is meant to be returned from the
+     * transaction, it's passed on to the caller and never retried.
+     */
+    pub fn abort_manually<R>(&mut self) -> Result<R> {
+        Err(Error::UserAborted {})
+    }
+
+    fn committed(&mut self) {
+        self.finished = true;
+        self.on_abort.clear();
+        for f in self.on_commit.drain(..) {
+            f();
+        }
+    }
+
+    /*
+     * The versions this transaction's writes replaced. Nothing points to them
+     * once it commits, only the older snapshots can still get to them through
+     * the new versions, so they're freed along with what the transaction
+     * freed. Redo records are left alone, they go once they're folded.
+     */
+    fn superseded(&self) -> Result<Vec<LogicalMutRef<'data>>> {
+        let mut superseded: Vec<LogicalMutRef<'data>> = Vec::new();
+        for w in &self.writeset {
+            if w.current.is_none() || !w.current.is_byte_addressable() {
+                continue;
+            }
+            let object = self.reader.extent(&w.current)?;
+            let address = object.0.address();
             if self
-                .vos
-                .commit_version(version, self.las, || {
-                    for read in &self.readset {
-                        let other = self.reader.read_version(read.pointer)?;
-                        if other.newer(version, self.las)? {
-                            return Err(Error::TxAborted {});
-                        }
-                    }
-                    Ok(())
-                })
-                .is_err()
+                .freeset
+                .iter()
+                .chain(superseded.iter())
+                .all(|freed| freed.address() != address)
             {
-                println!("validate failed");
+                superseded.push(self.las.write_ref(&object)?);
+            }
+        }
+        Ok(superseded)
+    }
+
+    /* everything the transaction made is final by now, and not yet visible to anyone */
+    fn seal(&self) -> Result<()> {
+        for object in self.object_allocator.objects() {
+            self.reader.seal(object)?;
+        }
+        Ok(())
+    }
+
+    /* a version this transaction made, as it is, for the recorder */
+    fn own_object(&self, pointer: &UntypedPointer) -> Result<(ObjectSize, u64, &'tx [u8])> {
+        let header = self.reader.header(pointer)?;
+        let data = self.reader.read_own(pointer, &header.size)?;
+        Ok((header.size, header.kind, data))
+    }
+
+    pub fn commit(&mut self) -> Result<CommitInfo> {
+        /* nothing can fail once the version is published, the commit can't be taken back */
+        let superseded = match self
+            .check()
+            .and_then(|_| self.seal())
+            .and_then(|_| self.superseded())
+        {
+            Ok(superseded) => superseded,
+            Err(err) => {
                 self.abort();
-                Err(Error::TxAborted {})
-            } else {
+                return Err(err);
+            }
+        };
+        let snapshot = self.snapshot_version();
+        let mut validated = 0;
+        /* a transaction can't fail for not being recorded */
+        let recorded = match (&self.recorder, &self.version) {
+            (Some(recorder), Some(_)) => recorder
+                .transaction(|pointer| self.own_object(pointer))
+                .ok(),
+            _ => None,
+        };
+        if let Some(version) = &self.version {
+            match self.vos.commit_version(version, snapshot, || {
+                for read in &self.readset {
+                    /* a write of its own already checked it wasn't stale */
+                    if self.own_version(read.pointer)?.is_some() {
+                        continue;
+                    }
+                    validated += 1;
+                    if self.reader.changed(read.pointer)? {
+                        let address = read.pointer.address();
+                        return Err(Error::TxAborted {
+                            reason: ConflictReason::ReadValidation { address },
+                        });
+                    }
+                }
                 Ok(())
+            }) {
+                Ok(committed) => {
+                    self.validated = validated;
+                    if let (Some(recorder), Some(recorded)) = (&self.recorder, recorded) {
+                        recorder.committed(committed, recorded);
+                    }
+                    self.vos
+                        .release(std::mem::take(&mut self.freeset), committed);
+                    self.vos.release(superseded, committed);
+                    for (owner, entry) in self.logged.drain(..) {
+                        self.vos.defer_fold(self.las, committed, owner, entry);
+                    }
+                    for w in &self.writeset {
+                        /* only the newest copy of each object, the others are superseded */
+                        if w.new.is_byte_addressable() && w.dst.address() == w.new.address() {
+                            self.vos.defer_trim(committed, w.new.clone());
+                        }
+                    }
+                    self.committed();
+                    Ok(CommitInfo {
+                        snapshot,
+                        version: Some(committed),
+                        stats: self.stats(),
+                    })
+                }
+                Err(err) => {
+                    self.abort();
+                    Err(err)
+                }
             }
         } else {
-            Ok(())
+            self.committed();
+            Ok(CommitInfo {
+                snapshot,
+                version: None,
+                stats: self.stats(),
+            })
         }
     }
 }
+
+/*
+ * A transaction that only ever reads. It sees the store as it was when it
+ * started and, having nothing to validate, it never aborts.
+ */
+pub struct ReadTransaction<'tx, 'data: 'tx> {
+    vos: &'tx VersionedObjectStore<'data>,
+    root: &'tx UntypedPointer,
+    directory: Option<&'tx UntypedPointer>,
+    policy: Option<&'tx RootPolicy<'tx>>,
+    reader: VersionedReader<'tx, 'data>,
+}
+
+impl<'tx, 'data: 'tx> Drop for ReadTransaction<'tx, 'data> {
+    fn drop(&mut self) {
+        self.vos.unpin(&self.reader);
+    }
+}
+
+impl<'tx, 'data: 'tx> ReadTransaction<'tx, 'data> {
+    pub fn new(
+        las: &'tx LogicalAddressSpace<'data>,
+        vos: &'tx VersionedObjectStore<'data>,
+        root: &'tx UntypedPointer,
+    ) -> Self {
+        ReadTransaction {
+            vos,
+            root,
+            directory: None,
+            policy: None,
+            reader: vos.new_pinned_reader(las),
+        }
+    }
+
+    /* reads the store as it was right after the given version committed */
+    pub fn new_at(
+        las: &'tx LogicalAddressSpace<'data>,
+        vos: &'tx VersionedObjectStore<'data>,
+        root: &'tx UntypedPointer,
+        version: usize,
+    ) -> Result<Self> {
+        Ok(ReadTransaction {
+            vos,
+            root,
+            directory: None,
+            policy: None,
+            reader: vos.new_pinned_reader_at(las, version)?,
+        })
+    }
+
+    pub fn read(&self, pointer: &'tx UntypedPointer, size: &ObjectSize) -> Result<&'tx [u8]> {
+        Ok(self.reader.read(pointer, size, false)?.0)
+    }
+
+    pub async fn read_async(
+        &self,
+        pointer: &'tx UntypedPointer,
+        size: &ObjectSize,
+    ) -> Result<&'tx [u8]> {
+        Ok(self.reader.read_async(pointer, size, false).await?.0)
+    }
+
+    pub fn snapshot_version(&self) -> usize {
+        self.reader.version()
+    }
+
+    pub fn stats(&self) -> TxStats {
+        TxStats {
+            attempts: 1,
+            objects_read: self.reader.objects_read(),
+            versions_skipped: self.reader.versions_skipped(),
+            pages_touched: self.reader.pages().len(),
+            ..TxStats::default()
+        }
+    }
+
+    pub fn root(&self) -> &'tx UntypedPointer {
+        self.root
+    }
+
+    pub fn object_size(&self, pointer: &UntypedPointer) -> Result<ObjectSize> {
+        self.reader.size(pointer)
+    }
+
+    pub fn object_kind(&self, pointer: &UntypedPointer) -> Result<u64> {
+        self.reader.kind(pointer)
+    }
+
+    /* the pointers an object starts with */
+    pub fn pointers(&self, pointer: &'tx UntypedPointer) -> Result<&'tx [UntypedPointer]> {
+        let size = self.object_size(pointer)?;
+        let pointers = &self.read(pointer, &size)?[..size.pointers as usize];
+        Ok(unsafe_utils::many_from_slice(pointers))
+    }
+
+    /*
+     * Hands over every object reachable from the root, each once. Named
+     * roots are followed too, unless a policy decides which of them can be
+     * read at all.
+     */
+    pub fn scan<F>(&self, mut func: F) -> Result<()>
+    where
+        F: FnMut(&'tx UntypedPointer) -> Result<()>,
+    {
+        let mut pending = vec![self.root];
+        if self.policy.is_none() {
+            pending.extend(self.directory);
+        }
+        let mut seen = HashSet::new();
+        while let Some(pointer) = pending.pop() {
+            if pointer.is_none() || !seen.insert(pointer.address()) {
+                continue;
+            }
+            func(pointer)?;
+            pending.extend(self.pointers(pointer)?);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn directory_with(
+        &mut self,
+        directory: Option<&'tx UntypedPointer>,
+        policy: Option<&'tx RootPolicy<'tx>>,
+    ) {
+        self.directory = directory;
+        self.policy = policy;
+    }
+
+    pub fn named_root(&self, name: &str) -> Result<Option<&'tx UntypedPointer>> {
+        allowed(self.policy, name, RootAccess::Read)?;
+        self.find_named_root(name)
+    }
+
+    pub(crate) fn find_named_root(&self, name: &str) -> Result<Option<&'tx UntypedPointer>> {
+        let name = root_name(name)?;
+        let directory = self.directory.ok_or(Error::NoRootDirectory {})?;
+        let size = self.object_size(directory)?;
+        match find_root(self.read(directory, &size)?, &size, &name) {
+            Some(entry) => {
+                let entry = self.read(entry, &ROOT_ENTRY_SIZE)?;
+                Ok(Some(unsafe_utils::any_from_slice(entry)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn to_token(&self, pointer: &UntypedPointer) -> PointerToken {
+        pointer.to_token(self.vos.epoch())
+    }
+}
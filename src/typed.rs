@@ -1,13 +1,26 @@
+use crate::error::Error;
+use crate::librarius::Migration;
 use crate::utils::unsafe_utils;
-use crate::vos::{ObjectSize, UntypedPointer};
+use crate::vos::{ObjectSize, PointerToken, UntypedPointer, OBJECT_ALIGN, UNTYPED};
 use crate::Result;
-use crate::Transaction;
 use crate::{Librarius, LibrariusBuilder};
+use crate::{ReadTransaction, Transaction};
 use std::marker::PhantomData;
-use std::mem::size_of;
+use std::mem::{align_of, size_of};
+use std::ops::{Deref, DerefMut};
 
 pub trait Persistent {
     fn size() -> ObjectSize;
+
+    /*
+     * Tells layouts apart, a store remembers the one of its root. Only the
+     * size goes into it by default, #[derive(Persistent)] also mixes in the
+     * names and types of the fields.
+     */
+    fn fingerprint() -> u64 {
+        let size = Self::size();
+        (size.pointers as u64) << 32 | size.data as u64
+    }
 }
 
 impl Persistent for UntypedPointer {
@@ -16,39 +29,454 @@ impl Persistent for UntypedPointer {
     }
 }
 
+/**
+ * Plain data, without pointers. A #[derive(Persistent)] struct is Pod
+ * when all its fields are.
+ *
+ * # Safety
+ *
+ * Any bytes at all have to be a valid value of the type, and it can't hold
+ * pointers, neither persistent nor volatile ones.
+ */
+pub unsafe trait Pod {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl Pod for $t {}
+            impl_data!($t);
+        )*
+    };
+}
+
+/* plain values, so that they can be allocated on their own */
+macro_rules! impl_data {
+    ($t:ty) => {
+        impl Persistent for $t {
+            fn size() -> ObjectSize {
+                ObjectSize::new_with_usize(0, size_of::<$t>())
+            }
+        }
+
+        unsafe impl Layout for $t {
+            const POINTERS: usize = 0;
+        }
+    };
+}
+
+/*
+ * There's no bool, not every byte is one and typed reads can't check it
+ * before there's a reference. A u8 that's 0 or 1 does the same.
+ */
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+/*
+ * The size of a type from its layout, like #[derive(Persistent)] does it.
+ * Anything with a Layout, pointers included, can be an element of these.
+ */
+fn laid_out<T: Layout>() -> ObjectSize {
+    ObjectSize::new_with_usize(T::POINTERS, size_of::<T>() - T::POINTERS)
+}
+
+impl<T: Layout, const N: usize> Persistent for [T; N] {
+    fn size() -> ObjectSize {
+        laid_out::<Self>()
+    }
+}
+
+/* the fields of a tuple can be in any order, as long as pointers end up first */
+macro_rules! impl_tuple {
+    ($($t:ident $i:tt),*) => {
+        unsafe impl<$($t: Layout),*> Layout for ($($t,)*) {
+            const POINTERS: usize = layout::compose(&[
+                $((std::mem::offset_of!(Self, $i), $t::POINTERS, size_of::<$t>()),)*
+            ]);
+        }
+
+        impl<$($t: Layout),*> Persistent for ($($t,)*) {
+            fn size() -> ObjectSize {
+                laid_out::<Self>()
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+
+/**
+ * The bytes at the start of a type that are pointers, known at compile
+ * time. It's how #[derive(Persistent)] adds up the fields of a struct,
+ * whatever they are, and checks that all of its pointers still come first,
+ * where writing back an object looks for them.
+ *
+ * # Safety
+ *
+ * The first POINTERS bytes have to be persistent pointers, and none of
+ * the bytes after them.
+ */
+pub unsafe trait Layout {
+    const POINTERS: usize;
+}
+
+/* an array repeats the layout of its elements, so they can't mix the two */
+unsafe impl<T: Layout, const N: usize> Layout for [T; N] {
+    const POINTERS: usize = match T::POINTERS {
+        0 => 0,
+        pointers if pointers == size_of::<T>() => pointers * N,
+        _ => panic!("the elements of an array can't mix pointers and data"),
+    };
+}
+
+unsafe impl Layout for UntypedPointer {
+    const POINTERS: usize = size_of::<UntypedPointer>();
+}
+
+/* what the derive puts together layouts with */
+pub mod layout {
+    /*
+     * The offset, pointers and size of each field. They're taken in the
+     * order they are in memory, tuples don't keep the one they're declared in.
+     */
+    pub const fn compose(fields: &[(usize, usize, usize)]) -> usize {
+        let mut end = 0;
+        let mut data = false;
+        let mut previous = None;
+        let mut n = 0;
+        while n < fields.len() {
+            let next = next_field(fields, previous);
+            let (offset, pointers, size) = fields[next];
+            if pointers != 0 {
+                if data || offset != end {
+                    panic!("pointers have to come first in an object, before any data or padding");
+                }
+                end = offset + pointers;
+            }
+            if pointers != size {
+                data = true;
+            }
+            previous = Some(next);
+            n += 1;
+        }
+        end
+    }
+
+    /* the field with the lowest offset after the previous one, fields that share it by index */
+    const fn next_field(fields: &[(usize, usize, usize)], previous: Option<usize>) -> usize {
+        let mut next: Option<usize> = None;
+        let mut i = 0;
+        while i < fields.len() {
+            let after = match previous {
+                Some(j) => fields[i].0 > fields[j].0 || (fields[i].0 == fields[j].0 && i > j),
+                None => true,
+            };
+            let lower = match next {
+                Some(k) => fields[i].0 < fields[k].0,
+                None => true,
+            };
+            if after && lower {
+                next = Some(i);
+            }
+            i += 1;
+        }
+        match next {
+            Some(next) => next,
+            None => panic!("every field is taken once"),
+        }
+    }
+}
+
+/**
+ * A #[repr(u8)] enum, with plain data in its variants. Not every byte is a
+ * valid tag, so these are read with read_tagged and write_tagged, which
+ * check the tag before there's a reference to the enum.
+ *
+ * # Safety
+ *
+ * The tags have to be 0 up to VARIANTS, in the first byte, and all the
+ * fields of the variants have to be Pod.
+ */
+pub unsafe trait Tagged: Persistent {
+    const VARIANTS: u8;
+}
+
+/* objects aren't aligned to more than OBJECT_ALIGN, so neither can T be */
+fn aligned<T: Persistent>() -> Result<()> {
+    match align_of::<T>() {
+        align if align > OBJECT_ALIGN => Err(Error::UnsupportedAlignment {
+            align,
+            max: OBJECT_ALIGN,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Whether the object was allocated as a T, as far as fingerprints tell
+ * types apart. Untyped objects pass. It's a look at the header for every
+ * read, so only debug builds and the checked feature pay for it.
+ */
+fn checked<T: Persistent>(kind: impl FnOnce() -> Result<u64>) -> Result<()> {
+    if !cfg!(any(debug_assertions, feature = "checked")) {
+        return Ok(());
+    }
+    match kind()? {
+        UNTYPED => Ok(()),
+        found if found == T::fingerprint() => Ok(()),
+        found => Err(Error::TypeMismatch {
+            expected: T::fingerprint(),
+            found,
+        }),
+    }
+}
+
+fn tagged<T: Tagged>(data: &[u8]) -> Result<()> {
+    match data.first() {
+        Some(tag) if *tag < T::VARIANTS => Ok(()),
+        Some(tag) => Err(Error::InvalidTag { tag: *tag }),
+        None => Err(Error::InvalidTag { tag: 0 }),
+    }
+}
+
 pub struct PersistentPointer<T: Persistent> {
     raw: UntypedPointer,
     phantom: PhantomData<T>,
 }
 
+unsafe impl<T: Persistent> Layout for PersistentPointer<T> {
+    const POINTERS: usize = size_of::<UntypedPointer>();
+}
+
 impl<T: Persistent> PersistentPointer<T> {
-    fn from_raw(raw: UntypedPointer) -> Self {
+    /* each use is a pointer of its own, so that arrays of them can be filled with it */
+    #[allow(clippy::declare_interior_mutable_const)]
+    pub const NONE: Self = Self::new_none();
+
+    pub(crate) fn from_raw(raw: UntypedPointer) -> Self {
         PersistentPointer {
             raw,
             phantom: PhantomData,
         }
     }
 
-    fn from_raw_ref(raw: &UntypedPointer) -> &Self {
+    pub(crate) fn from_raw_ref(raw: &UntypedPointer) -> &Self {
         unsafe { std::mem::transmute(raw) }
     }
 
-    fn as_raw(&self) -> &UntypedPointer {
+    pub(crate) fn as_raw(&self) -> &UntypedPointer {
         unsafe { std::mem::transmute(self) }
     }
 
-    pub fn new_none() -> Self {
+    pub const fn new_none() -> Self {
         PersistentPointer {
             raw: UntypedPointer::new_none(),
             phantom: PhantomData,
         }
     }
+
+    pub fn is_some(&self) -> bool {
+        self.raw.is_some()
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.raw.is_none()
+    }
+
+    /* so that a none pointer can be matched on, or mapped over */
+    pub fn as_option(&self) -> Option<&Self> {
+        if self.is_some() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    /* the raw pointer, for as long as there's an object to dereference */
+    fn dereferenceable(&self) -> Result<&UntypedPointer> {
+        if self.is_none() {
+            return Err(Error::NullPointer {});
+        }
+        aligned::<T>()?;
+        Ok(self.as_raw())
+    }
+}
+
+/* a fixed number of pointers to T, all of them none to begin with */
+#[repr(transparent)]
+pub struct PArray<T: Persistent, const N: usize>([PersistentPointer<T>; N]);
+
+impl<T: Persistent, const N: usize> Persistent for PArray<T, N> {
+    fn size() -> ObjectSize {
+        ObjectSize::new_with_usize(size_of::<Self>(), 0)
+    }
+}
+
+unsafe impl<T: Persistent, const N: usize> Layout for PArray<T, N> {
+    const POINTERS: usize = size_of::<Self>();
+}
+
+impl<T: Persistent, const N: usize> Default for PArray<T, N> {
+    fn default() -> Self {
+        PArray([PersistentPointer::NONE; N])
+    }
+}
+
+impl<T: Persistent, const N: usize> Deref for PArray<T, N> {
+    type Target = [PersistentPointer<T>; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: Persistent, const N: usize> DerefMut for PArray<T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/*
+ * What read_typed gives out in a read-write transaction. It's a snapshot
+ * read, nothing checks it at commit, so a decision made on it can race
+ * with other writers. Marking it puts the object in the readset instead,
+ * and the transaction aborts if someone else writes it before the commit.
+ */
+pub struct ReadGuard<'tx, T: Persistent> {
+    pointer: &'tx PersistentPointer<T>,
+    value: &'tx T,
+}
+
+impl<'tx, T: Persistent> ReadGuard<'tx, T> {
+    /* the value, unvalidated, for when it doesn't decide anything */
+    pub fn get(&self) -> &'tx T {
+        self.value
+    }
+
+    pub fn mark(self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx T> {
+        let data = tx.read_for_write(self.pointer.as_raw(), &T::size())?;
+        Ok(unsafe_utils::any_from_slice(data))
+    }
+
+    /* marks it, and then writes it, as write_typed would */
+    pub fn upgrade(self, tx: &mut Transaction<'tx, '_>) -> Result<&'tx mut T> {
+        self.mark(tx)?;
+        tx.write_typed(self.pointer)
+    }
+}
+
+impl<T: Persistent> Clone for ReadGuard<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Persistent> Copy for ReadGuard<'_, T> {}
+
+impl<T: Persistent> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+/*
+ * Points to as many T as there were when it was allocated, the number is
+ * only in the header of the object. The elements can't mix pointers and
+ * data, or the pointers of all but the first one would be in the middle of
+ * the data.
+ */
+pub struct PersistentSlice<T: Persistent> {
+    raw: UntypedPointer,
+    phantom: PhantomData<T>,
+}
+
+unsafe impl<T: Persistent> Layout for PersistentSlice<T> {
+    const POINTERS: usize = size_of::<UntypedPointer>();
+}
+
+impl<T: Persistent> PersistentSlice<T> {
+    pub(crate) fn from_raw(raw: UntypedPointer) -> Self {
+        PersistentSlice {
+            raw,
+            phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn as_raw(&self) -> &UntypedPointer {
+        &self.raw
+    }
+
+    pub fn new_none() -> Self {
+        Self::from_raw(UntypedPointer::new_none())
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.raw.is_some()
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.raw.is_none()
+    }
+
+    fn dereferenceable(&self) -> Result<&UntypedPointer> {
+        if self.is_none() {
+            return Err(Error::NullPointer {});
+        }
+        Self::element()?;
+        Ok(&self.raw)
+    }
+
+    /* elements are all pointers or all data, and take up some space */
+    fn element() -> Result<ObjectSize> {
+        aligned::<T>()?;
+        let element = T::size();
+        if size_of::<T>() == 0
+            || (element.pointers != 0 && element.data != 0)
+            || element.total() != size_of::<T>()
+        {
+            return Err(Error::InvalidSliceElement {});
+        }
+        Ok(element)
+    }
+
+    fn size(len: usize) -> Result<ObjectSize> {
+        let element = Self::element()?;
+        /* the sizes of an object are u32s, as is what they add up to */
+        let bytes = len
+            .checked_mul(size_of::<T>())
+            .filter(|bytes| *bytes <= u32::MAX as usize)
+            .ok_or(Error::AllocationTooLarge {})?;
+        Ok(match element.pointers {
+            0 => ObjectSize::new_with_usize(0, bytes),
+            _ => ObjectSize::new_with_usize(bytes, 0),
+        })
+    }
 }
 
 pub trait TypedLibrariusBuilder<'root> {
     fn create_with_typed<T: Persistent, TC>(self, f: TC) -> Self
     where
         TC: Fn() -> T + 'root;
+
+    /*
+     * Turns a root laid out as Old into a New one, once, when the store is
+     * opened. Migrations chain, so that a store that's a few layouts behind
+     * goes through all of them.
+     */
+    fn migrate<Old: Persistent + 'static, New: Persistent + 'static, F>(self, f: F) -> Self
+    where
+        F: for<'tx, 'data> Fn(&mut Transaction<'tx, 'data>, &'tx Old) -> Result<New> + 'root;
+
+    /*
+     * Records the layout of a type kept anywhere in the store. Opening the
+     * store fails if the type was stored with a different layout before.
+     */
+    fn register_type<T: Persistent>(self, name: &str) -> Self;
 }
 
 impl<'data, 'root> TypedLibrariusBuilder<'root> for LibrariusBuilder<'data, 'root> {
@@ -57,10 +485,37 @@ impl<'data, 'root> TypedLibrariusBuilder<'root> for LibrariusBuilder<'data, 'roo
         TC: Fn() -> T + 'root,
     {
         self.create_with(T::size(), move |data| {
+            aligned::<T>()?;
             let typed = unsafe_utils::any_from_slice_mut(data);
             *typed = tc();
             Ok(())
         })
+        .fingerprint(T::fingerprint())
+    }
+
+    fn migrate<Old: Persistent + 'static, New: Persistent + 'static, F>(self, f: F) -> Self
+    where
+        F: for<'tx, 'd> Fn(&mut Transaction<'tx, 'd>, &'tx Old) -> Result<New> + 'root,
+    {
+        self.migration(Migration {
+            from: Old::fingerprint(),
+            to: New::fingerprint(),
+            run: Box::new(move |tx| {
+                let root = tx.root();
+                let old = tx
+                    .read_typed(PersistentPointer::<Old>::from_raw_ref(root))?
+                    .get();
+                let new = f(tx, old)?;
+                aligned::<New>()?;
+                let data = tx.realloc(root, New::size())?;
+                unsafe { std::ptr::write(unsafe_utils::any_from_slice_mut(data), new) };
+                tx.retype(root, New::fingerprint())
+            }),
+        })
+    }
+
+    fn register_type<T: Persistent>(self, name: &str) -> Self {
+        self.register(name, T::fingerprint())
     }
 }
 
@@ -69,11 +524,54 @@ pub trait TypedTransaction<'tx> {
         &mut self,
         pointer: &'tx PersistentPointer<T>,
     ) -> Result<&'tx mut T>;
-    fn read_typed<T: Persistent>(&mut self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T>;
+    fn read_typed<T: Persistent>(
+        &mut self,
+        pointer: &'tx PersistentPointer<T>,
+    ) -> Result<ReadGuard<'tx, T>>;
+    #[allow(clippy::mut_from_ref)]
+    fn write_tagged<T: Tagged>(&mut self, pointer: &'tx PersistentPointer<T>)
+        -> Result<&'tx mut T>;
+    fn read_tagged<T: Tagged>(
+        &mut self,
+        pointer: &'tx PersistentPointer<T>,
+    ) -> Result<ReadGuard<'tx, T>>;
     fn root_typed<T: Persistent>(&mut self) -> &'tx PersistentPointer<T>;
+    fn root_named<T: Persistent>(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<&'tx PersistentPointer<T>>>;
+    fn create_root_named<T: Persistent, F>(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> Result<&'tx PersistentPointer<T>>
+    where
+        F: Fn() -> T;
     fn alloc_typed<T: Persistent, F>(&mut self, f: F) -> Result<PersistentPointer<T>>
     where
         F: Fn() -> T;
+    fn alloc_typed_near<T: Persistent, U: Persistent, F>(
+        &mut self,
+        near: &PersistentPointer<U>,
+        f: F,
+    ) -> Result<PersistentPointer<T>>
+    where
+        F: Fn() -> T;
+    fn free_typed<T: Persistent>(&mut self, pointer: &'tx PersistentPointer<T>) -> Result<()>;
+    fn alloc_slice<T: Persistent, F>(&mut self, len: usize, f: F) -> Result<PersistentSlice<T>>
+    where
+        F: Fn(usize) -> T;
+    fn read_slice<T: Persistent>(&mut self, pointer: &'tx PersistentSlice<T>) -> Result<&'tx [T]>;
+    #[allow(clippy::mut_from_ref)]
+    fn write_slice<T: Persistent>(
+        &mut self,
+        pointer: &'tx PersistentSlice<T>,
+    ) -> Result<&'tx mut [T]>;
+    fn to_token_typed<T: Persistent>(&self, pointer: &PersistentPointer<T>) -> PointerToken;
+    fn resolve_token_typed<T: Persistent>(
+        &mut self,
+        token: &PointerToken,
+    ) -> Result<&'tx PersistentPointer<T>>;
 }
 
 impl<'tx, 'data> TypedTransaction<'tx> for Transaction<'tx, 'data> {
@@ -81,13 +579,48 @@ impl<'tx, 'data> TypedTransaction<'tx> for Transaction<'tx, 'data> {
         &mut self,
         pointer: &'tx PersistentPointer<T>,
     ) -> Result<&'tx mut T> {
-        let data = self.write(pointer.as_raw(), &T::size())?;
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.write(raw, &T::size())?;
         Ok(unsafe_utils::any_from_slice_mut(data))
     }
 
-    fn read_typed<T: Persistent>(&mut self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T> {
-        let data = self.read(pointer.as_raw(), &T::size())?;
-        Ok(unsafe_utils::any_from_slice(data))
+    fn read_typed<T: Persistent>(
+        &mut self,
+        pointer: &'tx PersistentPointer<T>,
+    ) -> Result<ReadGuard<'tx, T>> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.read(raw, &T::size())?;
+        Ok(ReadGuard {
+            pointer,
+            value: unsafe_utils::any_from_slice(data),
+        })
+    }
+
+    fn write_tagged<T: Tagged>(
+        &mut self,
+        pointer: &'tx PersistentPointer<T>,
+    ) -> Result<&'tx mut T> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.write(raw, &T::size())?;
+        tagged::<T>(data)?;
+        Ok(unsafe_utils::any_from_slice_mut(data))
+    }
+
+    fn read_tagged<T: Tagged>(
+        &mut self,
+        pointer: &'tx PersistentPointer<T>,
+    ) -> Result<ReadGuard<'tx, T>> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.read(raw, &T::size())?;
+        tagged::<T>(data)?;
+        Ok(ReadGuard {
+            pointer,
+            value: unsafe_utils::any_from_slice(data),
+        })
     }
 
     fn root_typed<T: Persistent>(&mut self) -> &'tx PersistentPointer<T> {
@@ -95,17 +628,154 @@ impl<'tx, 'data> TypedTransaction<'tx> for Transaction<'tx, 'data> {
         PersistentPointer::from_raw_ref(raw)
     }
 
+    fn root_named<T: Persistent>(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<&'tx PersistentPointer<T>>> {
+        let raw = self.named_root(name)?;
+        Ok(raw.map(PersistentPointer::from_raw_ref))
+    }
+
+    fn create_root_named<T: Persistent, F>(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> Result<&'tx PersistentPointer<T>>
+    where
+        F: Fn() -> T,
+    {
+        aligned::<T>()?;
+        let (raw, data) = self.alloc_named_root(name, T::size())?;
+
+        let data = unsafe_utils::any_from_slice_mut(data);
+        *data = f();
+        self.retype(raw, T::fingerprint())?;
+
+        Ok(PersistentPointer::from_raw_ref(raw))
+    }
+
     fn alloc_typed<T: Persistent, F>(&mut self, f: F) -> Result<PersistentPointer<T>>
     where
         F: Fn() -> T,
     {
+        aligned::<T>()?;
         let (raw, data) = self.alloc(T::size())?;
 
         let data = unsafe_utils::any_from_slice_mut(data);
         *data = f();
+        self.retype(&raw, T::fingerprint())?;
+
+        Ok(PersistentPointer::from_raw(raw))
+    }
+
+    fn alloc_typed_near<T: Persistent, U: Persistent, F>(
+        &mut self,
+        near: &PersistentPointer<U>,
+        f: F,
+    ) -> Result<PersistentPointer<T>>
+    where
+        F: Fn() -> T,
+    {
+        aligned::<T>()?;
+        let (raw, data) = self.alloc_near(near.as_raw(), T::size())?;
+
+        let data = unsafe_utils::any_from_slice_mut(data);
+        *data = f();
+        self.retype(&raw, T::fingerprint())?;
 
         Ok(PersistentPointer::from_raw(raw))
     }
+
+    fn free_typed<T: Persistent>(&mut self, pointer: &'tx PersistentPointer<T>) -> Result<()> {
+        self.free(pointer.as_raw())
+    }
+
+    fn alloc_slice<T: Persistent, F>(&mut self, len: usize, f: F) -> Result<PersistentSlice<T>>
+    where
+        F: Fn(usize) -> T,
+    {
+        let (raw, data) = self.alloc(PersistentSlice::<T>::size(len)?)?;
+
+        let elements = data.as_mut_ptr() as *mut T;
+        for i in 0..len {
+            unsafe { std::ptr::write(elements.add(i), f(i)) };
+        }
+        self.retype(&raw, T::fingerprint())?;
+
+        Ok(PersistentSlice::from_raw(raw))
+    }
+
+    fn read_slice<T: Persistent>(&mut self, pointer: &'tx PersistentSlice<T>) -> Result<&'tx [T]> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let size = self.object_size(raw)?;
+        let data = self.read(raw, &size)?;
+        Ok(unsafe_utils::many_from_slice(data))
+    }
+
+    fn write_slice<T: Persistent>(
+        &mut self,
+        pointer: &'tx PersistentSlice<T>,
+    ) -> Result<&'tx mut [T]> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let size = self.object_size(raw)?;
+        let data = self.write(raw, &size)?;
+        Ok(unsafe_utils::many_from_slice_mut(data))
+    }
+
+    fn to_token_typed<T: Persistent>(&self, pointer: &PersistentPointer<T>) -> PointerToken {
+        self.to_token(pointer.as_raw())
+    }
+
+    fn resolve_token_typed<T: Persistent>(
+        &mut self,
+        token: &PointerToken,
+    ) -> Result<&'tx PersistentPointer<T>> {
+        let raw = self.resolve_token(token)?;
+        Ok(PersistentPointer::from_raw_ref(raw))
+    }
+}
+
+pub trait TypedReadTransaction<'tx> {
+    fn read_typed<T: Persistent>(&self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T>;
+    fn read_tagged<T: Tagged>(&self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T>;
+    fn root_typed<T: Persistent>(&self) -> &'tx PersistentPointer<T>;
+    fn root_named<T: Persistent>(&self, name: &str) -> Result<Option<&'tx PersistentPointer<T>>>;
+    fn read_slice<T: Persistent>(&self, pointer: &'tx PersistentSlice<T>) -> Result<&'tx [T]>;
+}
+
+impl<'tx, 'data> TypedReadTransaction<'tx> for ReadTransaction<'tx, 'data> {
+    fn read_typed<T: Persistent>(&self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.read(raw, &T::size())?;
+        Ok(unsafe_utils::any_from_slice(data))
+    }
+
+    fn read_tagged<T: Tagged>(&self, pointer: &'tx PersistentPointer<T>) -> Result<&'tx T> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let data = self.read(raw, &T::size())?;
+        tagged::<T>(data)?;
+        Ok(unsafe_utils::any_from_slice(data))
+    }
+
+    fn root_typed<T: Persistent>(&self) -> &'tx PersistentPointer<T> {
+        PersistentPointer::from_raw_ref(self.root())
+    }
+
+    fn root_named<T: Persistent>(&self, name: &str) -> Result<Option<&'tx PersistentPointer<T>>> {
+        let raw = self.named_root(name)?;
+        Ok(raw.map(PersistentPointer::from_raw_ref))
+    }
+
+    fn read_slice<T: Persistent>(&self, pointer: &'tx PersistentSlice<T>) -> Result<&'tx [T]> {
+        let raw = pointer.dereferenceable()?;
+        checked::<T>(|| self.object_kind(raw))?;
+        let size = self.object_size(raw)?;
+        Ok(unsafe_utils::many_from_slice(self.read(raw, &size)?))
+    }
 }
 
 pub fn deserialize<'tx, T: Persistent + 'tx>(data: &'tx [u8]) -> &'tx T {
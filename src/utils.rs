@@ -7,12 +7,24 @@ pub mod unsafe_utils {
     }
 
     pub fn any_from_slice_mut<'a, T>(data: &'a mut [u8]) -> &'a mut T {
+        debug_assert!(data.as_ptr().align_offset(mem::align_of::<T>()) == 0);
         unsafe { mem::transmute(data.as_mut_ptr()) }
     }
 
     pub fn any_from_slice<'a, T>(data: &'a [u8]) -> &'a T {
+        debug_assert!(data.as_ptr().align_offset(mem::align_of::<T>()) == 0);
         unsafe { mem::transmute(data.as_ptr()) }
     }
+
+    pub fn many_from_slice<T>(data: &[u8]) -> &[T] {
+        let len = data.len() / mem::size_of::<T>();
+        unsafe { slice::from_raw_parts(data.as_ptr() as *const T, len) }
+    }
+
+    pub fn many_from_slice_mut<T>(data: &mut [u8]) -> &mut [T] {
+        let len = data.len() / mem::size_of::<T>();
+        unsafe { slice::from_raw_parts_mut(data.as_mut_ptr() as *mut T, len) }
+    }
 }
 pub mod math {
     use core::ops::{Add, BitAnd, Not, Sub};
@@ -1,50 +1,81 @@
-use crate::error::{Error, Result};
+use crate::error::{ConflictReason, Error, Result};
 use crate::las::{
     BlockLogicalSlice, ByteLogicalSlice, LogicalAddress, LogicalAddressSpace, LogicalMutRef,
     LogicalSlice, PageAlloc, StoredLogicalSlice,
 };
-use crate::utils::{unsafe_utils, OptionExt};
-use parking_lot::RwLock;
+use crate::source::PageCodec;
+use crate::utils::{self, math, unsafe_utils, OptionExt};
+use parking_lot::{Condvar, Mutex, RwLock};
+use std::collections::btree_map::Entry;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
 use std::mem::size_of;
-use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/*
+ * Pointers and versions are stored in objects, so they're kept little-endian
+ * whatever the machine, which puts the tag bits into the same bytes of an
+ * image everywhere. Everything else works with native values.
+ */
+#[cfg(target_endian = "little")]
+const fn stored(raw: u64) -> u64 {
+    raw
+}
+
+#[cfg(target_endian = "big")]
+const fn stored(raw: u64) -> u64 {
+    raw.swap_bytes()
+}
 
+/*
+ * Swinging a pointer publishes what it points to. The object is written
+ * in full before the release half of the swap, and whoever loads the
+ * pointer with acquire sees all of it. Nothing needs a single order over
+ * all pointers, commits are put in order by the versions they reserve.
+ */
 #[derive(Debug)]
 pub struct UntypedPointer {
-    address: AtomicUsize,
+    address: AtomicU64,
 }
 
 impl Clone for UntypedPointer {
     fn clone(&self) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(self.address_internal()),
+            address: AtomicU64::new(stored(self.address_internal())),
         }
     }
 }
 
 impl UntypedPointer {
-    const POINTER_TYPE_MASK: usize = 0b11 << 54;
-    const POINTER_REFCOUNT_MASK: usize = 0b11111111 << 56;
+    const POINTER_TYPE_MASK: u64 = 0b11 << 54;
+    const POINTER_REFCOUNT_MASK: u64 = 0b11111111 << 56;
 
-    const POINTER_BYTE_ADDRESSABLE: usize = 0b00 << 54;
-    const POINTER_BLOCK: usize = 0b01 << 54;
-    const POINTER_LOG: usize = 0b10 << 54;
+    const POINTER_BYTE_ADDRESSABLE: u64 = 0b00 << 54;
+    const POINTER_BLOCK: u64 = 0b01 << 54;
+    const POINTER_LOG: u64 = 0b10 << 54;
+    /* a compressed copy of the object on a block source */
+    const POINTER_PACKED: u64 = 0b11 << 54;
 
-    const POINTER_ADDRESS_MASK: usize = !(Self::POINTER_TYPE_MASK | Self::POINTER_REFCOUNT_MASK);
+    const POINTER_ADDRESS_MASK: u64 = !(Self::POINTER_TYPE_MASK | Self::POINTER_REFCOUNT_MASK);
 
-    fn type_bytes(&self) -> usize {
+    fn type_bytes(&self) -> u64 {
         self.address_internal() & Self::POINTER_TYPE_MASK
     }
 
-    fn from_raw(data: usize) -> Self {
+    fn from_raw(data: u64) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(data),
+            address: AtomicU64::new(stored(data)),
         }
     }
 
     fn internal_clone(&self) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(self.address_internal()),
+            address: AtomicU64::new(stored(self.address_internal())),
         }
     }
 
@@ -65,7 +96,7 @@ impl UntypedPointer {
 
     pub(crate) fn new_byte(address: LogicalAddress) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(address | Self::POINTER_BYTE_ADDRESSABLE),
+            address: AtomicU64::new(stored(address as u64 | Self::POINTER_BYTE_ADDRESSABLE)),
         }
     }
 
@@ -79,23 +110,29 @@ impl UntypedPointer {
 
     fn new_block(address: LogicalAddress) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(address | Self::POINTER_BLOCK),
+            address: AtomicU64::new(stored(address as u64 | Self::POINTER_BLOCK)),
         }
     }
 
     fn new_log(address: LogicalAddress) -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(address | Self::POINTER_LOG),
+            address: AtomicU64::new(stored(address as u64 | Self::POINTER_LOG)),
+        }
+    }
+
+    fn new_packed(address: LogicalAddress) -> Self {
+        UntypedPointer {
+            address: AtomicU64::new(stored(address as u64 | Self::POINTER_PACKED)),
         }
     }
 
-    pub(crate) fn new_none() -> Self {
+    pub(crate) const fn new_none() -> Self {
         UntypedPointer {
-            address: AtomicUsize::new(0),
+            address: AtomicU64::new(stored(0)),
         }
     }
 
-    fn is_byte_addressable(&self) -> bool {
+    pub(crate) fn is_byte_addressable(&self) -> bool {
         self.type_bytes() == Self::POINTER_BYTE_ADDRESSABLE
     }
 
@@ -103,12 +140,21 @@ impl UntypedPointer {
         self.type_bytes() == Self::POINTER_BLOCK
     }
 
-    fn address_internal(&self) -> usize {
-        self.address.load(Ordering::SeqCst)
+    pub(crate) fn is_log(&self) -> bool {
+        self.type_bytes() == Self::POINTER_LOG
+    }
+
+    fn is_packed(&self) -> bool {
+        self.type_bytes() == Self::POINTER_PACKED
+    }
+
+    fn address_internal(&self) -> u64 {
+        stored(self.address.load(Ordering::Acquire))
     }
 
-    pub(crate) fn address(&self) -> LogicalAddress {
-        self.address_internal() & Self::POINTER_ADDRESS_MASK
+    /* where the object is, the same one an error about it reports */
+    pub fn address(&self) -> LogicalAddress {
+        (self.address_internal() & Self::POINTER_ADDRESS_MASK) as LogicalAddress
     }
 
     fn into_stored_slice(&self, len: usize) -> StoredLogicalSlice {
@@ -121,69 +167,152 @@ impl UntypedPointer {
         StoredLogicalSlice::new(slice, self.is_byte_addressable())
     }
 
-    pub fn refcount(&self) -> &AtomicU8 {
-        let bytes = unsafe {
-            let data = std::mem::transmute(&self.address);
-            std::slice::from_raw_parts(data, size_of::<AtomicUsize>())
-        };
+    /* kept in the top byte, only PArc counts anything in it */
+    pub fn refcount(&self) -> u8 {
+        ((self.address_internal() & Self::POINTER_REFCOUNT_MASK) >> 56) as u8
+    }
 
-        &bytes[0]
+    pub(crate) fn with_refcount(&self, count: u8) -> Self {
+        let raw = self.address_internal() & !Self::POINTER_REFCOUNT_MASK;
+        Self::from_raw(raw | (count as u64) << 56)
     }
 
     pub fn compare_and_swap(&self, current: UntypedPointer, new: UntypedPointer) -> bool {
-        let current = current.address_internal();
-        let new = new.address_internal();
+        let current = stored(current.address_internal());
+        let new = stored(new.address_internal());
+
+        self.address
+            .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub(crate) fn to_token(&self, epoch: u64) -> PointerToken {
+        PointerToken {
+            epoch,
+            raw: self.address_internal() & !Self::POINTER_REFCOUNT_MASK,
+        }
+    }
+
+    pub(crate) fn from_token(token: &PointerToken, epoch: u64) -> Result<Self> {
+        if token.epoch != epoch {
+            return Err(Error::StaleToken {});
+        }
+        let ptr = Self::from_raw(token.raw);
+        if ptr.is_none() || token.raw & Self::POINTER_REFCOUNT_MASK != 0 {
+            return Err(Error::InvalidToken {});
+        }
+
+        Ok(ptr)
+    }
+}
+
+/*
+ * Logical addresses are only meaningful for as long as the store that handed
+ * them out is open, so tokens carry the epoch of that store and are refused
+ * by any other. The checksum only catches mangled tokens, it's not a MAC.
+ */
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PointerToken {
+    epoch: u64,
+    raw: u64,
+}
+
+impl fmt::Display for PointerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}{:016x}{:08x}",
+            self.epoch,
+            self.raw,
+            utils::crc(self)
+        )
+    }
+}
+
+impl FromStr for PointerToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 40 || !s.is_ascii() {
+            return Err(Error::InvalidToken {});
+        }
+        let field = |range: std::ops::Range<usize>| {
+            u64::from_str_radix(&s[range], 16).map_err(|_| Error::InvalidToken {})
+        };
+
+        let token = PointerToken {
+            epoch: field(0..16)?,
+            raw: field(16..32)?,
+        };
+        if field(32..40)? != utils::crc(&token) as u64 {
+            return Err(Error::InvalidToken {});
+        }
 
-        let old = self
-            .address
-            .compare_and_swap(current, new, Ordering::SeqCst);
-        old == current
+        Ok(token)
     }
 }
 
+/*
+ * A version is committed with a release store, after every copy the
+ * transaction made is complete, so a reader that loads it with acquire
+ * and finds it committed can read those copies.
+ */
 pub struct Version {
-    version: AtomicUsize,
+    version: AtomicU64,
 }
 
 impl Clone for Version {
     fn clone(&self) -> Self {
         Version {
-            version: AtomicUsize::new(self.version.load(Ordering::SeqCst)),
+            version: AtomicU64::new(self.version.load(Ordering::Acquire)),
         }
     }
 }
 
 impl Version {
-    const VERSION_TYPE_MASK: usize = 0b1 << 63;
-    const VERSION_DATA_MASK: usize = !(Self::VERSION_TYPE_MASK);
+    const VERSION_TYPE_MASK: u64 = 0b1 << 63;
+    const VERSION_DATA_MASK: u64 = !(Self::VERSION_TYPE_MASK);
 
-    const VERSION_TYPE_DIRECT: usize = 0b0 << 63;
-    const VERSION_TYPE_INDIRECT: usize = 0b1 << 63;
+    const VERSION_TYPE_DIRECT: u64 = 0b0 << 63;
+    const VERSION_TYPE_INDIRECT: u64 = 0b1 << 63;
 
     pub fn new() -> Self {
         Version {
-            version: AtomicUsize::new(0),
+            version: AtomicU64::new(stored(0)),
         }
     }
 
     pub fn new_base() -> Self {
         Version {
-            version: AtomicUsize::new(1),
+            version: AtomicU64::new(stored(1)),
         }
     }
 
-    fn type_bytes(&self) -> usize {
-        self.version.load(Ordering::SeqCst) & Self::VERSION_TYPE_MASK
+    fn load(&self) -> u64 {
+        stored(self.version.load(Ordering::Acquire))
+    }
+
+    fn store(&self, version: u64) {
+        self.version.store(stored(version), Ordering::Release);
+    }
+
+    fn type_bytes(&self) -> u64 {
+        self.load() & Self::VERSION_TYPE_MASK
     }
 
-    fn data_bytes(&self) -> usize {
-        self.version.load(Ordering::SeqCst) & Self::VERSION_DATA_MASK
+    fn data_bytes(&self) -> u64 {
+        self.load() & Self::VERSION_DATA_MASK
+    }
+
+    fn commit_direct(&self, new_version: usize) {
+        assert_eq!(self.type_bytes(), Self::VERSION_TYPE_DIRECT);
+        self.store(new_version as u64 | Self::VERSION_TYPE_DIRECT);
     }
 
     fn commit(&self, new_version: usize, las: &LogicalAddressSpace) -> Result<()> {
         if self.type_bytes() == Self::VERSION_TYPE_DIRECT {
-            self.version
-                .store(new_version | Self::VERSION_TYPE_DIRECT, Ordering::SeqCst);
+            self.store(new_version as u64 | Self::VERSION_TYPE_DIRECT);
 
             Ok(())
         } else {
@@ -200,13 +329,48 @@ impl Version {
         }
     }
 
+    /*
+     * An object's freed version stays 0 for as long as it's live. Freeing
+     * stores the, possibly not yet committed, version of the transaction
+     * into it, which fails if another transaction got there first.
+     */
+    fn same(&self, other: &Version) -> bool {
+        self.load() == other.load()
+    }
+
+    fn tombstone(&self, freed: &Version) -> bool {
+        let freed = stored(freed.load());
+        self.version
+            .compare_exchange(stored(0), freed, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    fn revive(&self) {
+        self.store(0);
+    }
+
+    /*
+     * Replaces a committed indirect version with the number in its slot.
+     * Slots are never written back, only direct versions survive a reopen.
+     */
+    fn settle(&self, las: &LogicalAddressSpace) -> Result<usize> {
+        if self.type_bytes() == Self::VERSION_TYPE_DIRECT {
+            return Ok(self.data_bytes() as usize);
+        }
+        let version = self.read(las)?;
+        if version != 0 {
+            self.store(version as u64 | Self::VERSION_TYPE_DIRECT);
+        }
+        Ok(version)
+    }
+
     fn new_indirect(real_version: UntypedPointer) -> Self {
         assert_eq!(real_version.address_internal() & Self::VERSION_TYPE_MASK, 0);
 
         Version {
-            version: AtomicUsize::new(
+            version: AtomicU64::new(stored(
                 real_version.address_internal() | Self::VERSION_TYPE_INDIRECT,
-            ),
+            )),
         }
     }
 
@@ -220,7 +384,7 @@ impl Version {
         let data = self.data_bytes();
 
         if self.type_bytes() == Self::VERSION_TYPE_DIRECT {
-            Ok(data)
+            Ok(data as usize)
         } else {
             let ptr = UntypedPointer::from_raw(data);
             let slice = ptr.into_stored_slice(size_of::<Version>());
@@ -233,42 +397,57 @@ impl Version {
     }
 }
 
-struct GenericAllocator<'tx> {
-    active: Option<LogicalMutRef<'tx>>,
-    page_alloc: PageAlloc<'tx>,
+struct GenericAllocator<'tx, 'data> {
+    active: Option<LogicalMutRef<'data>>,
+    page_alloc: PageAlloc<'tx, 'data>,
 }
 
-impl<'tx> GenericAllocator<'tx> {
-    fn new(page_alloc: PageAlloc<'tx>) -> Self {
+impl<'tx, 'data> GenericAllocator<'tx, 'data> {
+    fn new(page_alloc: PageAlloc<'tx, 'data>) -> Self {
         GenericAllocator {
             active: None,
             page_alloc,
         }
     }
 
-    pub fn alloc(&mut self, size: usize) -> Result<(LogicalSlice, &'tx mut [u8])> {
-        let mut page_full = false;
-        Ok(loop {
-            if self.active.is_none() {
-                self.active = Some((self.page_alloc)()?);
-                page_full = true;
-            }
-            let mref = self.active.as_mut().unwrap();
+    /*
+     * Anything bigger than a page starts a run of pages, its tail stays
+     * active. The space is rounded up, so that whatever comes next is
+     * aligned too.
+     */
+    pub fn alloc(&mut self, size: usize) -> Result<(LogicalSlice, &'data mut [u8])> {
+        let size = math::align_up(size, OBJECT_ALIGN);
+        if let Some(it) = self
+            .active
+            .as_mut()
+            .and_then(|active| active.try_consume_bytes(size, size))
+        {
+            return Ok(it);
+        }
 
-            match mref.try_consume_bytes(size, size) {
-                Some(it) => break it,
-                _ => {
-                    if page_full {
-                        return Err(Error::AllocationTooLarge {});
-                    }
-                    self.active = None;
-                    continue;
-                }
-            }
-        })
+        let mut fresh = (self.page_alloc)(size)?;
+        let placed = fresh
+            .try_consume_bytes(size, size)
+            .ok_or(Error::AllocationTooLarge {})?;
+        self.active = Some(fresh);
+
+        Ok(placed)
     }
 }
 
+/*
+ * What every object, and everything else in a page, is aligned to. A page
+ * starts with an object, and headers are a multiple of it, so there's no
+ * room to align the data of an object any further.
+ */
+pub const OBJECT_ALIGN: usize = 8;
+
+const _: () = assert!(size_of::<ObjectHeader>().is_multiple_of(OBJECT_ALIGN));
+const _: () = assert!(LOG_ENTRY_OVERHEAD.is_multiple_of(OBJECT_ALIGN));
+
+/* the kind of an object allocated without a type */
+pub const UNTYPED: u64 = 0;
+
 #[derive(Copy, Clone, Debug)]
 pub struct ObjectSize {
     pub pointers: u32,
@@ -293,18 +472,46 @@ impl ObjectSize {
     }
 }
 
+/*
+ * How often the checksums of objects are checked on read. Objects only get
+ * one when they're committed while it isn't Never, the others always pass.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /* on every read, which catches corruption in memory too */
+    Always,
+    /* only on copies fetched from a block source */
+    OnFetch,
+    #[default]
+    Never,
+}
+
+/* set next to the crc, so that a checksum of zero isn't mistaken for none */
+const CHECKSUMMED: u64 = 1 << 32;
+
 pub struct ObjectHeader {
     pub size: ObjectSize,
+    /* the fingerprint of the type it was allocated as, if it was typed */
+    pub kind: u64,
+    checksum: u64,
+    /* the length of the payload while it's compressed, zero when it isn't */
+    packed: u64,
     version: Version,
+    freed: Version,
     parent: UntypedPointer,
-    other: UntypedPointer,
+    /* the version this one replaced, until no snapshot can need it anymore */
+    pub(crate) other: UntypedPointer,
 }
 
 impl ObjectHeader {
-    fn new(size: ObjectSize, version: Version, other: UntypedPointer) -> Self {
+    fn new(size: ObjectSize, kind: u64, version: Version, other: UntypedPointer) -> Self {
         ObjectHeader {
             size,
+            kind,
+            checksum: 0,
+            packed: 0,
             version,
+            freed: Version::new(),
             parent: UntypedPointer::new_none(),
             other,
         }
@@ -317,70 +524,374 @@ impl ObjectHeader {
     fn from_slice_mut(data: &mut [u8]) -> &mut Self {
         unsafe_utils::any_from_slice_mut(data)
     }
+
+    /*
+     * Covers the data of the object only, its pointers are swizzled and
+     * recounted in place. None when the payload isn't all there.
+     */
+    fn checksum_of(&self, payload: &[u8]) -> Option<u64> {
+        let start = self.size.pointers as usize;
+        payload
+            .get(start..start + self.size.data as usize)
+            .map(|data| CHECKSUMMED | utils::crc_slice(data) as u64)
+    }
+
+    /* the object is the header followed by its payload */
+    fn seal(object: &mut [u8]) {
+        let (hdr, payload) = object.split_at_mut(size_of::<ObjectHeader>());
+        let hdrp = ObjectHeader::from_slice_mut(hdr);
+        hdrp.checksum = hdrp.checksum_of(payload).unwrap_or(0);
+    }
+
+    fn verify(object: &[u8], address: LogicalAddress) -> Result<()> {
+        let (hdr, payload) = object.split_at(size_of::<ObjectHeader>());
+        let hdrp = ObjectHeader::from_slice(hdr);
+        match hdrp.checksum_of(payload) {
+            Some(checksum) if hdrp.checksum != 0 && hdrp.checksum != checksum => {
+                Err(Error::ChecksumMismatch { address })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/*
+ * Transactions allocate objects by carving up a page of their own. Whatever
+ * is left of that page once the transaction is done is kept here, indexed by
+ * page, so that later transactions can continue filling it and so that new
+ * objects can be placed next to an existing one.
+ */
+pub struct OpenPages<'data> {
+    pagesize: usize,
+    pages: Mutex<BTreeMap<LogicalAddress, LogicalMutRef<'data>>>,
+}
+
+impl<'data> OpenPages<'data> {
+    const MIN_TAIL: usize = size_of::<ObjectHeader>() + size_of::<UntypedPointer>();
+
+    fn new(pagesize: usize) -> Self {
+        OpenPages {
+            pagesize,
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn page_of(&self, address: LogicalAddress) -> LogicalAddress {
+        math::align_down(address, self.pagesize)
+    }
+
+    fn retire(&self, tail: LogicalMutRef<'data>) {
+        if tail.len() >= Self::MIN_TAIL {
+            self.pages.lock().insert(self.page_of(tail.address()), tail);
+        }
+    }
+
+    fn take(&self, page: LogicalAddress) -> Option<LogicalMutRef<'data>> {
+        self.pages.lock().remove(&page)
+    }
+
+    fn take_any(&self, len: usize) -> Option<LogicalMutRef<'data>> {
+        let mut pages = self.pages.lock();
+        let page = pages
+            .iter()
+            .find(|(_, tail)| tail.len() >= len)
+            .map(|(page, _)| *page)?;
+        pages.remove(&page)
+    }
+}
+
+/*
+ * Space of freed objects, to be reused for new ones in the same page. A
+ * transaction that started before the object was freed can still read it,
+ * so the space first waits in limbo, tagged with the version that freed
+ * it, until the oldest running transaction is at least that recent.
+ */
+pub struct FreeList<'data> {
+    pagesize: usize,
+    limbo: Mutex<Vec<(usize, LogicalMutRef<'data>)>>,
+    pages: Mutex<BTreeMap<LogicalAddress, Vec<LogicalMutRef<'data>>>>,
+}
+
+impl<'data> FreeList<'data> {
+    fn new(pagesize: usize) -> Self {
+        FreeList {
+            pagesize,
+            limbo: Mutex::new(Vec::new()),
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn release(&self, freed: usize, chunk: LogicalMutRef<'data>) {
+        self.limbo.lock().push((freed, chunk));
+    }
+
+    fn reclaim(&self, oldest: usize) {
+        let mut limbo = self.limbo.lock();
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *limbo)
+            .into_iter()
+            .partition(|(freed, _)| *freed <= oldest);
+        *limbo = waiting;
+
+        let mut pages = self.pages.lock();
+        for (_, chunk) in ready {
+            let page = math::align_down(chunk.address(), self.pagesize);
+            pages.entry(page).or_default().push(chunk);
+        }
+    }
+
+    /*
+     * First fit, either in the given page or in any page at all. Space of a
+     * freed run of pages is only reused across pages from the start of the
+     * run, an object never begins in the middle of a page and ends in another.
+     */
+    fn take(
+        &self,
+        page: Option<LogicalAddress>,
+        len: usize,
+    ) -> Option<(LogicalSlice, &'data mut [u8])> {
+        let mut pages = self.pages.lock();
+        let pagesize = self.pagesize;
+        let fits = |chunks: &Vec<LogicalMutRef<'data>>| {
+            chunks.iter().position(|c| {
+                let start = c.address();
+                c.len() >= len
+                    && (start % pagesize == 0
+                        || math::align_down(start, pagesize)
+                            == math::align_down(start + len - 1, pagesize))
+            })
+        };
+        let (page, index) = match page {
+            Some(page) => (page, fits(pages.get(&page)?)?),
+            None => pages
+                .iter()
+                .find_map(|(page, chunks)| Some((*page, fits(chunks)?)))?,
+        };
+
+        let chunks = pages.get_mut(&page).unwrap();
+        let mut chunk = chunks.swap_remove(index);
+        let placed = chunk.try_consume_bytes(len, len);
+        if chunk.len() >= OpenPages::MIN_TAIL {
+            chunks.push(chunk);
+        }
+        if chunks.is_empty() {
+            pages.remove(&page);
+        }
+
+        placed
+    }
+}
+
+#[derive(Default)]
+struct LocalityCounters {
+    near_hits: AtomicUsize,
+    near_misses: AtomicUsize,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AllocLocality {
+    pub near_hits: usize,
+    pub near_misses: usize,
+}
+
+pub struct TransactionalObjectAllocator<'tx, 'data> {
+    generic: GenericAllocator<'tx, 'data>,
+    open_pages: &'tx OpenPages<'data>,
+    free_list: &'tx FreeList<'data>,
+    locality: &'tx LocalityCounters,
+    bytes_allocated: usize,
+    pages: HashSet<LogicalAddress>,
+    /* every object placed so far, header included */
+    objects: Vec<ByteLogicalSlice>,
 }
 
-pub struct TransactionalObjectAllocator<'tx> {
-    generic: GenericAllocator<'tx>,
+impl<'tx, 'data> Drop for TransactionalObjectAllocator<'tx, 'data> {
+    fn drop(&mut self) {
+        if let Some(tail) = self.generic.active.take() {
+            self.open_pages.retire(tail);
+        }
+    }
 }
 
-impl<'tx> TransactionalObjectAllocator<'tx> {
-    fn new(page_alloc: PageAlloc<'tx>) -> Self {
+impl<'tx, 'data> TransactionalObjectAllocator<'tx, 'data> {
+    fn new(
+        page_alloc: PageAlloc<'tx, 'data>,
+        open_pages: &'tx OpenPages<'data>,
+        free_list: &'tx FreeList<'data>,
+        locality: &'tx LocalityCounters,
+    ) -> Self {
+        let page_alloc = Box::new(move |len| match open_pages.take_any(len) {
+            Some(tail) => Ok(tail),
+            None => page_alloc(len),
+        });
+
         TransactionalObjectAllocator {
             generic: GenericAllocator::new(page_alloc),
+            open_pages,
+            free_list,
+            locality,
+            bytes_allocated: 0,
+            pages: HashSet::new(),
+            objects: Vec::new(),
         }
     }
 
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /* the pages this allocator placed objects in */
+    pub fn pages(&self) -> &HashSet<LogicalAddress> {
+        &self.pages
+    }
+
+    pub fn objects(&self) -> &[ByteLogicalSlice] {
+        &self.objects
+    }
+
+    /* the objects placed so far, which the allocator forgets about */
+    pub fn take_objects(&mut self) -> Vec<ByteLogicalSlice> {
+        std::mem::take(&mut self.objects)
+    }
+
     pub fn alloc_new(
         &mut self,
         size: ObjectSize,
+        kind: u64,
+        version: Version,
+    ) -> Result<(UntypedPointer, &'data mut [u8])> {
+        self.alloc(size, kind, version, UntypedPointer::new_none())
+    }
+
+    pub fn alloc_near(
+        &mut self,
+        near: LogicalAddress,
+        size: ObjectSize,
+        kind: u64,
         version: Version,
-    ) -> Result<(UntypedPointer, &'tx mut [u8])> {
-        self.alloc(size, version, UntypedPointer::new_none())
+    ) -> Result<(UntypedPointer, &'data mut [u8])> {
+        let page = self.open_pages.page_of(near);
+        let total = math::align_up(size.total() + size_of::<ObjectHeader>(), OBJECT_ALIGN);
+
+        let active_page = self
+            .generic
+            .active
+            .as_ref()
+            .map(|active| self.open_pages.page_of(active.address()));
+
+        let placed = if active_page == Some(page) {
+            self.generic
+                .active
+                .as_mut()
+                .and_then(|active| active.try_consume_bytes(total, total))
+        } else if let Some(mut tail) = self.open_pages.take(page) {
+            let placed = tail.try_consume_bytes(total, total);
+            self.open_pages.retire(tail);
+            placed
+        } else {
+            None
+        };
+        let placed = placed.or_else(|| self.free_list.take(Some(page), total));
+
+        match placed {
+            Some((slice, data)) => {
+                self.locality.near_hits.fetch_add(1, Ordering::Relaxed);
+                Ok(self.place(slice, data, size, kind, version, UntypedPointer::new_none()))
+            }
+            None => {
+                self.locality.near_misses.fetch_add(1, Ordering::Relaxed);
+                self.alloc_new(size, kind, version)
+            }
+        }
     }
 
     pub fn init_object(
         &mut self,
-        data: &'tx mut [u8],
+        data: &'data mut [u8],
         size: ObjectSize,
+        kind: u64,
         version: Version,
         other: UntypedPointer,
-    ) -> &'tx mut [u8] {
+    ) -> &'data mut [u8] {
         let (hdr, userdata) = data.split_at_mut(size_of::<ObjectHeader>());
 
         let hdrp = ObjectHeader::from_slice_mut(hdr);
 
-        *hdrp = ObjectHeader::new(size, version, other);
+        *hdrp = ObjectHeader::new(size, kind, version, other);
 
-        userdata
+        /* without the padding */
+        &mut userdata[..size.total()]
     }
 
-    pub fn alloc(
+    fn place(
         &mut self,
+        slice: LogicalSlice,
+        data: &'data mut [u8],
         size: ObjectSize,
+        kind: u64,
         version: Version,
         other: UntypedPointer,
-    ) -> Result<(UntypedPointer, &'tx mut [u8])> {
-        let (slice, data) = self
-            .generic
-            .alloc(size.total() + size_of::<ObjectHeader>())?;
-
-        let userdata = self.init_object(data, size, version, other);
+    ) -> (UntypedPointer, &'data mut [u8]) {
+        self.bytes_allocated += slice.len();
+        self.pages.insert(self.open_pages.page_of(slice.address()));
+        self.objects.push(ByteLogicalSlice(slice));
+        let userdata = self.init_object(data, size, kind, version, other);
 
         let (_, userslice) = slice.split_at(size_of::<ObjectHeader>());
 
-        Ok((UntypedPointer::new_byte(userslice.address()), userdata))
+        (UntypedPointer::new_byte(userslice.address()), userdata)
+    }
+
+    pub fn alloc(
+        &mut self,
+        size: ObjectSize,
+        kind: u64,
+        version: Version,
+        other: UntypedPointer,
+    ) -> Result<(UntypedPointer, &'data mut [u8])> {
+        let total = math::align_up(size.total() + size_of::<ObjectHeader>(), OBJECT_ALIGN);
+        let (slice, data) = match self.free_list.take(None, total) {
+            Some(it) => it,
+            None => self.generic.alloc(total)?,
+        };
+
+        Ok(self.place(slice, data, size, kind, version, other))
     }
 }
 
 struct LogSegmentHeader {}
 
+/*
+ * A redo record of a partial write, followed by the new bytes. The owner of
+ * the object points to it with a log pointer, and it points on to whatever
+ * the owner pointed to before, either the object or an older record.
+ */
 struct LogEntryHeader {
+    version: Version,
+    base: UntypedPointer,
+    /* where in the object's data the bytes go */
     slice: LogicalSlice,
 }
 
 impl LogEntryHeader {
-    pub fn new(slice: LogicalSlice) -> Self {
-        LogEntryHeader { slice }
+    pub fn new(version: Version, base: UntypedPointer, slice: LogicalSlice) -> Self {
+        LogEntryHeader {
+            version,
+            base,
+            slice,
+        }
+    }
+
+    fn read<'data>(
+        las: &LogicalAddressSpace<'data>,
+        ptr: &UntypedPointer,
+    ) -> Result<(&'data Self, &'data [u8])> {
+        let slice = ByteLogicalSlice(LogicalSlice::new(ptr.address(), LOG_ENTRY_OVERHEAD));
+        let hdr = Self::from_slice(las.read(&slice)?);
+
+        let slice = LogicalSlice::new(ptr.address() + LOG_ENTRY_OVERHEAD, hdr.slice.len());
+        let data = las.read(&ByteLogicalSlice(slice))?;
+
+        Ok((hdr, data))
     }
 
     fn from_slice(data: &[u8]) -> &Self {
@@ -394,12 +905,12 @@ impl LogEntryHeader {
 
 const LOG_ENTRY_OVERHEAD: usize = size_of::<LogEntryHeader>();
 
-pub struct TransactionalLogAllocator<'tx> {
-    generic: GenericAllocator<'tx>,
+pub struct TransactionalLogAllocator<'tx, 'data> {
+    generic: GenericAllocator<'tx, 'data>,
 }
 
-impl<'tx> TransactionalLogAllocator<'tx> {
-    fn new(page_alloc: PageAlloc<'tx>) -> Self {
+impl<'tx, 'data> TransactionalLogAllocator<'tx, 'data> {
+    fn new(page_alloc: PageAlloc<'tx, 'data>) -> Self {
         TransactionalLogAllocator {
             generic: GenericAllocator::new(page_alloc),
         }
@@ -415,142 +926,995 @@ impl<'tx> TransactionalLogAllocator<'tx> {
 
         Ok(Version::new_indirect(ptr))
     }
+
+    pub fn alloc_entry(
+        &mut self,
+        version: Version,
+        base: UntypedPointer,
+        offset: usize,
+        src: &[u8],
+    ) -> Result<UntypedPointer> {
+        let (slice, data) = self.generic.alloc(LOG_ENTRY_OVERHEAD + src.len())?;
+
+        let (hdr, bytes) = data.split_at_mut(LOG_ENTRY_OVERHEAD);
+        *LogEntryHeader::from_slice_mut(hdr) =
+            LogEntryHeader::new(version, base, LogicalSlice::new(offset, src.len()));
+        bytes[..src.len()].copy_from_slice(src);
+
+        Ok(UntypedPointer::new_log(slice.address()))
+    }
+
+    pub fn alloc_pointer(&mut self, ptr: UntypedPointer) -> Result<&'data UntypedPointer> {
+        let (_, data) = self.generic.alloc(size_of::<UntypedPointer>())?;
+
+        let slot = unsafe_utils::any_from_slice_mut::<UntypedPointer>(data);
+        *slot = ptr;
+
+        Ok(slot)
+    }
+}
+
+/*
+ * Objects with a payload of at least `above` bytes are written back as a
+ * compressed copy instead, packed with other such copies into pages of
+ * their own, so that the block source only stores what they compress to.
+ */
+pub struct Compression<'data> {
+    codec: Box<dyn PageCodec>,
+    above: usize,
+    pages: Mutex<Option<LogicalMutRef<'data>>>,
+}
+
+impl<'data> Compression<'data> {
+    pub fn new(codec: Box<dyn PageCodec>, above: usize) -> Self {
+        Compression {
+            codec,
+            above,
+            pages: Mutex::new(None),
+        }
+    }
+
+    /* the copies are never freed, they're only pointed to from the block source */
+    fn alloc(
+        &self,
+        las: &LogicalAddressSpace<'data>,
+        len: usize,
+    ) -> Result<(LogicalSlice, &'data mut [u8])> {
+        let len = math::align_up(len, OBJECT_ALIGN);
+        let mut active = self.pages.lock();
+        let mut fresh = false;
+
+        loop {
+            let page = active.get_or_insert_with_result(|| {
+                fresh = true;
+                las.alloc()
+            })?;
+            match page.try_consume_bytes(len, len) {
+                Some(it) => return Ok(it),
+                None if fresh => break,
+                None => *active = None,
+            }
+        }
+
+        las.alloc_run(len)?
+            .try_consume_bytes(len, len)
+            .ok_or(Error::AllocationTooLarge {})
+    }
 }
 
 pub struct VersionedReader<'tx, 'data> {
     version: usize,
     las: &'tx LogicalAddressSpace<'data>,
+    objects_read: AtomicUsize,
+    versions_skipped: AtomicUsize,
+    pages: Mutex<HashSet<LogicalAddress>>,
+    /* fetched copies that lost the race to be pointed to */
+    discarded: Mutex<Vec<ByteLogicalSlice>>,
+    /* and the ones that won it */
+    fetched: Mutex<Vec<Resident>>,
+    checksums: ChecksumMode,
+    compression: Option<Arc<Compression<'data>>>,
     phantom: PhantomData<&'tx u8>,
 }
 
 impl<'tx, 'data> VersionedReader<'tx, 'data> {
-    pub fn new(version: usize, las: &'tx LogicalAddressSpace<'data>) -> Self {
+    pub fn new(
+        version: usize,
+        las: &'tx LogicalAddressSpace<'data>,
+        checksums: ChecksumMode,
+        compression: Option<Arc<Compression<'data>>>,
+    ) -> Self {
         VersionedReader {
             version,
             las,
+            objects_read: AtomicUsize::new(0),
+            versions_skipped: AtomicUsize::new(0),
+            pages: Mutex::new(HashSet::new()),
+            discarded: Mutex::new(Vec::new()),
+            fetched: Mutex::new(Vec::new()),
+            checksums,
+            compression,
             phantom: PhantomData,
         }
     }
 
-    pub fn read_version(&self, ptr: &UntypedPointer) -> Result<&Version> {
-        let slice = ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>());
-        if let StoredLogicalSlice::Block(block) = slice {
-            todo!()
-        }
+    pub fn version(&self) -> usize {
+        self.version
+    }
 
-        let slice = slice.unwrap_byte();
+    pub fn objects_read(&self) -> usize {
+        self.objects_read.load(Ordering::Relaxed)
+    }
 
-        let hdr = self.las.read(&slice)?;
+    pub fn versions_skipped(&self) -> usize {
+        self.versions_skipped.load(Ordering::Relaxed)
+    }
 
-        let hdrp = ObjectHeader::from_slice(hdr);
+    pub fn take_discarded(&self) -> Vec<ByteLogicalSlice> {
+        std::mem::take(&mut *self.discarded.lock())
+    }
 
-        Ok(&hdrp.version)
+    fn take_fetched(&self) -> Vec<Resident> {
+        std::mem::take(&mut *self.fetched.lock())
     }
 
-    pub fn flush(&self, ptr: &UntypedPointer) -> Result<()> {
-        let slice = ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>());
-        if let StoredLogicalSlice::Block(block) = slice {
-            return Ok(());
-        }
+    /* the pages holding the object versions this reader ended up reading */
+    pub fn pages(&self) -> HashSet<LogicalAddress> {
+        self.pages.lock().clone()
+    }
 
-        let slice = slice.unwrap_byte();
+    /* whether someone else wrote the object since the snapshot, or is writing it */
+    pub fn changed(&self, ptr: &UntypedPointer) -> Result<bool> {
+        let version = self.read_version(ptr)?.read(self.las)?;
+        Ok(version == 0 || version > self.version)
+    }
+
+    pub fn read_version(&self, ptr: &UntypedPointer) -> Result<&Version> {
+        if ptr.is_log() {
+            return Ok(&LogEntryHeader::read(self.las, ptr)?.0.version);
+        }
+        /* it may have been evicted since it was read */
+        let slice = self.header_slice(ptr)?;
 
         let hdr = self.las.read(&slice)?;
 
         let hdrp = ObjectHeader::from_slice(hdr);
-        let npointers = hdrp.size.pointers as usize / size_of::<UntypedPointer>();
-
-        let slice = ptr
-            .into_stored_slice(hdrp.size.pointers as usize)
-            .unwrap_byte();
 
-        let data = self.las.read(&slice)?.as_ptr() as *const UntypedPointer;
-        let pointers: &[UntypedPointer] = unsafe { std::slice::from_raw_parts(data, npointers) };
+        Ok(&hdrp.version)
+    }
 
-        for p in pointers.iter().filter(|p| p.is_some()) {
-            let oldptr = p.internal_clone();
-            if p.is_byte_addressable() {
-                let stored_slice = p.into_stored_slice(1).unwrap_byte();
-                let mut backing = self.las.get_backing(&stored_slice)?;
-                let backing = backing.get_or_insert_with_result(|| {
-                    self.las.flush(&stored_slice)?;
-                    Ok(self.las.get_backing(&stored_slice)?.unwrap())
-                })?;
-                let newptr = UntypedPointer::new_from_stored(backing);
-                if !p.compare_and_swap(oldptr, newptr) { /* XXX: leaking memory... */ }
+    /* a header on a block source is fetched on its own, just to be looked at */
+    fn header_slice(&self, ptr: &UntypedPointer) -> Result<ByteLogicalSlice> {
+        let slice = ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>());
+        match slice {
+            StoredLogicalSlice::Block(_) => {
+                let copy = self.las.fetch(&slice)?;
+                self.discarded.lock().push(copy);
+                Ok(copy)
             }
+            StoredLogicalSlice::Byte(slice) => Ok(slice),
+        }
+    }
+
+    /* the object underneath any redo records */
+    fn resolve(&self, ptr: &UntypedPointer) -> Result<UntypedPointer> {
+        let mut ptr = ptr.internal_clone();
+        while ptr.is_log() {
+            let (entry, _) = LogEntryHeader::read(self.las, &ptr)?;
+            ptr = entry.base.internal_clone();
         }
+        Ok(ptr)
+    }
 
-        self.las.flush(&slice)?;
+    pub fn header(&self, ptr: &UntypedPointer) -> Result<&'tx ObjectHeader> {
+        let ptr = self.resolve(ptr)?;
+        let slice = self.header_slice(&ptr)?;
 
-        Ok(())
+        Ok(ObjectHeader::from_slice(self.las.read(&slice)?))
     }
 
-    pub fn read(
-        &self,
-        ptr: &UntypedPointer,
-        size: &ObjectSize,
-        abort_on_conflict: bool,
-    ) -> Result<(&'tx [u8], &ObjectHeader)> {
+    /* the size of the version this reader sees, a realloc makes newer ones differ */
+    pub fn size(&self, ptr: &UntypedPointer) -> Result<ObjectSize> {
         if !ptr.is_some() {
             return Err(Error::InvalidLogicalAddress {});
         }
+        let header = self.header(ptr)?;
+        let version = header.version.read(self.las)?;
+        if version == 0 || version > self.version {
+            return self.size(&header.other);
+        }
+        Ok(header.size)
+    }
 
-        let oldptr = ptr.internal_clone();
-        let slice = oldptr.into_stored_slice_offset(size.total(), size_of::<ObjectHeader>());
-        if let StoredLogicalSlice::Block(block) = slice {
-            let slice = oldptr.into_stored_slice(size.total());
+    /* the kind of the version this reader sees, like its size */
+    pub fn kind(&self, ptr: &UntypedPointer) -> Result<u64> {
+        if !ptr.is_some() {
+            return Err(Error::InvalidLogicalAddress {});
+        }
+        let header = self.header(ptr)?;
+        let version = header.version.read(self.las)?;
+        if version == 0 || version > self.version {
+            return self.kind(&header.other);
+        }
+        Ok(header.kind)
+    }
+
+    /* changes the kind of an object that no one else can see yet */
+    pub fn retype(&self, ptr: &UntypedPointer, kind: u64) -> Result<()> {
+        let slice = LogicalSlice::new(
+            ptr.address() - size_of::<ObjectHeader>(),
+            size_of::<ObjectHeader>(),
+        );
+        ObjectHeader::from_slice_mut(self.las.write(&ByteLogicalSlice(slice))?).kind = kind;
+        Ok(())
+    }
 
-            let bytes = self.las.fetch(&slice)?;
-            let newptr = UntypedPointer::new_from_byte(&bytes);
-            if !ptr.compare_and_swap(oldptr, newptr) { /* XXX: leaking memory... */ }
-            return self.read(ptr, size, abort_on_conflict);
+    /* checksums an object made by the reading transaction, once it's final */
+    pub fn seal(&self, object: &ByteLogicalSlice) -> Result<()> {
+        if self.checksums != ChecksumMode::Never {
+            ObjectHeader::seal(self.las.write(object)?);
         }
+        Ok(())
+    }
 
-        let slice = slice.unwrap_byte();
+    /*
+     * Marks the object as freed by the given version and returns everything
+     * it occupies, header included. Freeing an object that is being written
+     * or freed by someone else aborts, just like writing it would.
+     */
+    pub fn tombstone(&self, ptr: &UntypedPointer, version: &Version) -> Result<ByteLogicalSlice> {
+        let header = self.header(ptr)?;
+        let size = header.size;
+        let hdr = match header.version.same(version) {
+            /* allocated by the same transaction, no one else can see it */
+            true => header,
+            false => self.read(ptr, &size, true)?.1,
+        };
+        if !hdr.freed.tombstone(version) {
+            return Err(Error::TxAborted {
+                reason: ConflictReason::FreeRace {
+                    address: ptr.address(),
+                },
+            });
+        }
 
-        let data = self.las.read(&slice)?;
+        self.extent(ptr)
+    }
 
-        let (hdr, userdata) = data.split_at(size_of::<ObjectHeader>());
+    /* everything the object occupies, the header and padding go along with it */
+    pub fn extent(&self, ptr: &UntypedPointer) -> Result<ByteLogicalSlice> {
+        let size = self.header(ptr)?.size;
+        Ok(self
+            .resolve(ptr)?
+            .into_stored_slice_offset(
+                math::align_up(size.total(), OBJECT_ALIGN),
+                size_of::<ObjectHeader>(),
+            )
+            .unwrap_byte())
+    }
 
-        let hdrp = ObjectHeader::from_slice(hdr);
-        let version = hdrp.version.read(self.las)?;
+    /* whether the object, or its latest redo record, was made by the given version */
+    pub fn owned_by(&self, ptr: &UntypedPointer, version: &Version) -> Result<bool> {
+        if ptr.is_log() {
+            return Ok(LogEntryHeader::read(self.las, ptr)?.0.version.same(version));
+        }
+        if !ptr.is_byte_addressable() {
+            return Ok(false);
+        }
+        Ok(self.header(ptr)?.version.same(version))
+    }
+
+    /*
+     * Reads a new version made by the reading transaction itself, which it
+     * has to see even though it isn't committed, along with any redo
+     * records the transaction stacked on top of an older version.
+     */
+    pub fn read_own(&self, ptr: &UntypedPointer, size: &ObjectSize) -> Result<&'tx [u8]> {
+        if ptr.is_log() {
+            let (entry, bytes) = LogEntryHeader::read(self.las, ptr)?;
+            let data = match self.owned_by(&entry.base, &entry.version)? {
+                true => self.read_own(&entry.base, size)?,
+                false => self.read(&entry.base, size, false)?.0,
+            };
+            let copy = self.las.alloc_scratch(data.len())?;
+            copy.copy_from_slice(data);
+            let offset = entry.slice.address();
+            copy[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+            return Ok(copy);
+        }
+
+        let slice = ptr
+            .into_stored_slice_offset(size.total(), size_of::<ObjectHeader>())
+            .unwrap_byte();
+        let (_, userdata) = self.las.read(&slice)?.split_at(size_of::<ObjectHeader>());
+
+        Ok(userdata)
+    }
+
+    /* writes straight into an object that no one else can see yet */
+    pub fn patch(&self, ptr: &UntypedPointer, offset: usize, src: &[u8]) -> Result<()> {
+        let slice = LogicalSlice::new(ptr.address() + offset, src.len());
+        self.las
+            .write(&ByteLogicalSlice(slice))?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    /* the data of a copy made by this transaction, to be changed in place */
+    pub fn write_own(&self, ptr: &UntypedPointer, size: &ObjectSize) -> Result<&'tx mut [u8]> {
+        let slice = LogicalSlice::new(ptr.address(), size.total());
+        self.las.write(&ByteLogicalSlice(slice))
+    }
+
+    /*
+     * A visible redo record is applied on top of a copy of the object, so
+     * the object itself stays intact for the readers that can't see it.
+     */
+    fn read_logged(
+        &self,
+        ptr: &UntypedPointer,
+        size: &ObjectSize,
+        abort_on_conflict: bool,
+    ) -> Result<(&'tx [u8], &ObjectHeader)> {
+        let (entry, bytes) = LogEntryHeader::read(self.las, ptr)?;
+        let version = entry.version.read(self.las)?;
+        if version == 0 || version > self.version {
+            if abort_on_conflict {
+                return Err(Error::TxAborted {
+                    reason: ConflictReason::VersionConflict {
+                        address: ptr.address(),
+                    },
+                });
+            }
+            return self.read(&entry.base, size, abort_on_conflict);
+        }
+
+        /* the base may be folded into meanwhile, which leaves its checksum behind */
+        let (data, hdr) = self.read_object(&entry.base, size, abort_on_conflict, false)?;
+        let copy = self.las.alloc_scratch(data.len())?;
+        copy.copy_from_slice(data);
+        let offset = entry.slice.address();
+        copy[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        Ok((copy, hdr))
+    }
+
+    pub fn revive(&self, object: &[u8]) {
+        ObjectHeader::from_slice(object).freed.revive();
+    }
+
+    fn pointers(&self, ptr: &UntypedPointer) -> Result<Option<(&'tx [UntypedPointer], usize)>> {
+        let slice = ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>());
+        if let StoredLogicalSlice::Block(block) = slice {
+            return Ok(None);
+        }
+
+        let slice = slice.unwrap_byte();
+
+        let hdr = self.las.read(&slice)?;
+
+        let hdrp = ObjectHeader::from_slice(hdr);
+        let npointers = hdrp.size.pointers as usize / size_of::<UntypedPointer>();
+        if npointers == 0 {
+            return Ok(Some((&[], hdrp.size.total())));
+        }
+
+        let slice = ptr
+            .into_stored_slice(hdrp.size.pointers as usize)
+            .unwrap_byte();
+
+        let data = self.las.read(&slice)?.as_ptr() as *const UntypedPointer;
+        let pointers: &[UntypedPointer] = unsafe { std::slice::from_raw_parts(data, npointers) };
+
+        Ok(Some((pointers, hdrp.size.total())))
+    }
+
+    /*
+     * Objects are flushed in post-order: every byte addressable child is
+     * made durable, and its pointer swizzled to the backing location, before
+     * the object that points to it. This way a crash can never persist a
+     * parent that references a child which didn't make it to storage.
+     * The traversal is iterative so that long chains can't overflow the stack.
+     * Objects are queued up and written out as one batch right before their
+     * parent needs them, so siblings on the same source share the I/O.
+     */
+    pub fn flush(&self, ptr: &UntypedPointer) -> Result<()> {
+        self.write_back(ptr, true)
+    }
+
+    /*
+     * Like flush, but the sources are only synced twice: once for everything
+     * below the given object, and once more for the object itself, which
+     * can then never point to anything that isn't durable yet.
+     */
+    pub fn flush_grouped(&self, ptr: &UntypedPointer) -> Result<()> {
+        self.write_back(ptr, false)
+    }
+
+    fn write_back(&self, ptr: &UntypedPointer, sync: bool) -> Result<()> {
+        let write = |pending: &[ByteLogicalSlice]| match sync {
+            true => self.las.flush_batch(pending),
+            false => self.las.write_back_batch(pending),
+        };
+        let mut visited = HashSet::new();
+        let mut stack = vec![(ptr.internal_clone(), false)];
+        let mut pending = Vec::new();
+        /* objects written back as a compressed copy, and where the copy is */
+        let mut packed = HashMap::new();
+
+        while let Some((ptr, children_done)) = stack.pop() {
+            let (pointers, total) = match self.pointers(&ptr)? {
+                Some(it) => it,
+                None => continue,
+            };
+
+            if !children_done {
+                if !visited.insert(ptr.address()) {
+                    continue;
+                }
+                stack.push((ptr.internal_clone(), true));
+                for p in pointers.iter().filter(|p| p.is_some()) {
+                    if p.is_byte_addressable() && !visited.contains(&p.address()) {
+                        stack.push((p.internal_clone(), false));
+                    }
+                }
+                continue;
+            }
+
+            if pointers
+                .iter()
+                .any(|p| p.is_some() && p.is_byte_addressable())
+            {
+                write(&pending)?;
+                pending.clear();
+            }
+
+            for p in pointers.iter().filter(|p| p.is_some()) {
+                let oldptr = p.internal_clone();
+                if p.is_byte_addressable() {
+                    let newptr = match packed.get(&p.address()) {
+                        Some((object, image)) => self.packed_pointer(object, image, &write)?,
+                        None => {
+                            let stored_slice = p.into_stored_slice(1).unwrap_byte();
+                            let mut backing = self.las.get_backing(&stored_slice)?;
+                            /* only reachable through a cycle, the child is still being visited */
+                            let backing = backing.get_or_insert_with_result(|| {
+                                write(std::slice::from_ref(&stored_slice))?;
+                                Ok(self.las.get_backing(&stored_slice)?.unwrap())
+                            })?;
+                            UntypedPointer::new_from_stored(backing)
+                        }
+                    };
+                    /* a writer moved it in the meantime, the backing is all there was to it */
+                    let newptr = newptr.with_refcount(oldptr.refcount());
+                    p.compare_and_swap(oldptr, newptr);
+                }
+            }
+
+            let header = self.header(&ptr)?;
+            header.freed.settle(self.las)?;
+            self.las.store_version(header.version.settle(self.las)?)?;
+
+            let slice = ptr
+                .into_stored_slice_offset(total, size_of::<ObjectHeader>())
+                .unwrap_byte();
+            /* the pointer to the object asked for isn't swung, it's never packed */
+            match self.pack(&slice, stack.is_empty())? {
+                Some(image) => {
+                    pending.push(image);
+                    packed.insert(ptr.address(), (slice, image));
+                }
+                None => pending.push(slice),
+            }
+        }
+
+        if sync {
+            self.las.flush_batch(&pending)?;
+        } else {
+            let last = pending.pop();
+            self.las.write_back_batch(&pending)?;
+            self.las.sync()?;
+            self.las.flush_batch(last.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /* a compressed copy of the object, if it's big enough and compresses at all */
+    fn pack(&self, object: &ByteLogicalSlice, top: bool) -> Result<Option<ByteLogicalSlice>> {
+        let compression = match &self.compression {
+            Some(compression) if !top => compression,
+            _ => return Ok(None),
+        };
+        let (hdr, payload) = self.las.read(object)?.split_at(size_of::<ObjectHeader>());
+        if payload.len() < compression.above {
+            return Ok(None);
+        }
+        let compressed = compression.codec.compress(payload)?;
+        if compressed.len() >= payload.len() {
+            return Ok(None);
+        }
+
+        let len = size_of::<ObjectHeader>() + compressed.len();
+        let (slice, image) = compression.alloc(self.las, len)?;
+        let (image_hdr, image_payload) = image.split_at_mut(size_of::<ObjectHeader>());
+        image_hdr.copy_from_slice(hdr);
+        image_payload[..compressed.len()].copy_from_slice(&compressed);
+        ObjectHeader::from_slice_mut(image_hdr).packed = compressed.len() as u64;
+
+        let image = LogicalSlice::new(slice.address(), len);
+        Ok(Some(ByteLogicalSlice(image)))
+    }
+
+    /*
+     * Only copies on a block source are ever fetched, one that ended up
+     * anywhere else is useless and the object itself is written back.
+     */
+    fn packed_pointer<W>(
+        &self,
+        object: &ByteLogicalSlice,
+        image: &ByteLogicalSlice,
+        write: &W,
+    ) -> Result<UntypedPointer>
+    where
+        W: Fn(&[ByteLogicalSlice]) -> Result<Vec<StoredLogicalSlice>>,
+    {
+        if let Some(StoredLogicalSlice::Block(image)) = self.las.get_backing(image)? {
+            let address = image.0.address() + size_of::<ObjectHeader>();
+            return Ok(UntypedPointer::new_packed(address));
+        }
+
+        write(std::slice::from_ref(object))?;
+        let data = LogicalSlice::new(object.0.address() + size_of::<ObjectHeader>(), 1);
+        let backing = self.las.get_backing(&ByteLogicalSlice(data))?.unwrap();
+        Ok(UntypedPointer::new_from_stored(&backing))
+    }
+
+    /* the compressed copy is fetched in two reads, its header says how long it is */
+    fn fetch_packed(&self, ptr: &UntypedPointer) -> Result<ByteLogicalSlice> {
+        let header = self
+            .las
+            .fetch(&ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>()))?;
+        let len = ObjectHeader::from_slice(self.las.read(&header)?).packed as usize;
+        self.las.recycle_fetched(&header)?;
+
+        let image = self
+            .las
+            .fetch(&ptr.into_stored_slice_offset(len, size_of::<ObjectHeader>()))?;
+        self.unpack(&image)
+    }
+
+    async fn fetch_packed_async(&self, ptr: &UntypedPointer) -> Result<ByteLogicalSlice> {
+        let header = self
+            .las
+            .fetch_async(&ptr.into_stored_slice_offset(0, size_of::<ObjectHeader>()))
+            .await?;
+        let len = ObjectHeader::from_slice(self.las.read(&header)?).packed as usize;
+        self.las.recycle_fetched(&header)?;
+
+        let image = self
+            .las
+            .fetch_async(&ptr.into_stored_slice_offset(len, size_of::<ObjectHeader>()))
+            .await?;
+        self.unpack(&image)
+    }
+
+    /* decompresses a fetched copy into one that reads like any other fetched object */
+    fn unpack(&self, image: &ByteLogicalSlice) -> Result<ByteLogicalSlice> {
+        let compression = self.compression.as_ref().ok_or(Error::NoCodec {})?;
+        let (hdr, compressed) = self.las.read(image)?.split_at(size_of::<ObjectHeader>());
+        let total = ObjectHeader::from_slice(hdr).size.total();
+
+        let (object, data) = self.las.alloc_copy(size_of::<ObjectHeader>() + total)?;
+        let (object_hdr, payload) = data.split_at_mut(size_of::<ObjectHeader>());
+        object_hdr.copy_from_slice(hdr);
+        ObjectHeader::from_slice_mut(object_hdr).packed = 0;
+        let unpacked = compression.codec.decompress(compressed, payload);
+        self.las.recycle_fetched(image)?;
+        if unpacked.is_err() {
+            self.discarded.lock().push(object);
+        }
+
+        unpacked.map(|_| object)
+    }
+
+    /*
+     * Awaits the fetch of an object that's only on a block source, and then
+     * reads it just like read() would. Only the newest version is fetched
+     * this way, older ones are rarely needed and are read synchronously.
+     */
+    pub async fn read_async(
+        &self,
+        ptr: &UntypedPointer,
+        size: &ObjectSize,
+        abort_on_conflict: bool,
+    ) -> Result<(&'tx [u8], &ObjectHeader)> {
+        if ptr.is_block() || ptr.is_packed() {
+            let oldptr = ptr.internal_clone();
+            let slice = oldptr.into_stored_slice_offset(size.total(), size_of::<ObjectHeader>());
+            let bytes = match oldptr.is_packed() {
+                true => self.fetch_packed_async(&oldptr).await?,
+                false => self.las.fetch_async(&slice).await?,
+            };
+            self.verify_fetched(&bytes, oldptr.address())?;
+            self.point_to_fetched(ptr, oldptr, bytes)?;
+        }
+
+        self.read(ptr, size, abort_on_conflict)
+    }
+
+    /* someone else fetched it first, their copy is the one that's read */
+    fn point_to_fetched(
+        &self,
+        ptr: &UntypedPointer,
+        oldptr: UntypedPointer,
+        bytes: ByteLogicalSlice,
+    ) -> Result<()> {
+        let newptr = UntypedPointer::new_byte(bytes.0.address() + size_of::<ObjectHeader>())
+            .with_refcount(oldptr.refcount());
+        let data = self.las.read(&bytes)?;
+        let resident = self
+            .las
+            .locate(ptr)
+            .map(|owner| Resident::new(owner, &oldptr, bytes, data));
+        match (ptr.compare_and_swap(oldptr, newptr), resident) {
+            (true, Some(resident)) => self.fetched.lock().push(resident),
+            /* an owner outside of the address space keeps its copy for good */
+            (true, None) => {}
+            (false, _) => self.discarded.lock().push(bytes),
+        }
+        Ok(())
+    }
+
+    /* a fetched copy that fails the check is given back right away */
+    fn verify_fetched(&self, bytes: &ByteLogicalSlice, address: LogicalAddress) -> Result<()> {
+        if self.checksums == ChecksumMode::Never {
+            return Ok(());
+        }
+        let checked = ObjectHeader::verify(self.las.read(bytes)?, address);
+        if checked.is_err() {
+            self.discarded.lock().push(*bytes);
+        }
+        checked
+    }
+
+    pub fn read(
+        &self,
+        ptr: &UntypedPointer,
+        size: &ObjectSize,
+        abort_on_conflict: bool,
+    ) -> Result<(&'tx [u8], &ObjectHeader)> {
+        self.read_object(ptr, size, abort_on_conflict, true)
+    }
+
+    fn read_object(
+        &self,
+        ptr: &UntypedPointer,
+        size: &ObjectSize,
+        abort_on_conflict: bool,
+        verify: bool,
+    ) -> Result<(&'tx [u8], &ObjectHeader)> {
+        if !ptr.is_some() {
+            return Err(Error::InvalidLogicalAddress {});
+        }
+        if ptr.is_log() {
+            return self.read_logged(ptr, size, abort_on_conflict);
+        }
+
+        let oldptr = ptr.internal_clone();
+        let slice = oldptr.into_stored_slice_offset(size.total(), size_of::<ObjectHeader>());
+        if let StoredLogicalSlice::Block(block) = slice {
+            let bytes = match oldptr.is_packed() {
+                true => self.fetch_packed(&oldptr)?,
+                false => self.las.fetch(&slice)?,
+            };
+            self.verify_fetched(&bytes, oldptr.address())?;
+            self.point_to_fetched(ptr, oldptr, bytes)?;
+            return self.read_object(ptr, size, abort_on_conflict, verify);
+        }
+
+        let slice = slice.unwrap_byte();
+
+        let data = self.las.read(&slice)?;
+
+        let (hdr, userdata) = data.split_at(size_of::<ObjectHeader>());
+
+        let hdrp = ObjectHeader::from_slice(hdr);
+        let freed = hdrp.freed.read(self.las)?;
+        if freed != 0 && freed <= self.version {
+            return Err(Error::ObjectFreed {});
+        }
+        let version = hdrp.version.read(self.las)?;
         if version == 0 || version > self.version {
             if abort_on_conflict {
-                Err(Error::TxAborted {})
+                Err(Error::TxAborted {
+                    reason: ConflictReason::VersionConflict {
+                        address: ptr.address(),
+                    },
+                })
             } else {
-                self.read(&hdrp.other, size, abort_on_conflict)
+                self.versions_skipped.fetch_add(1, Ordering::Relaxed);
+                self.read_object(&hdrp.other, size, abort_on_conflict, verify)
             }
         } else {
+            if verify && self.checksums == ChecksumMode::Always {
+                ObjectHeader::verify(data, ptr.address())?;
+            }
+            self.objects_read.fetch_add(1, Ordering::Relaxed);
+            self.pages
+                .lock()
+                .insert(math::align_down(slice.0.address(), self.las.pagesize()));
             Ok((userdata, hdrp))
         }
     }
 }
 
+/*
+ * Indirect version slots are tiny, so instead of every write transaction
+ * allocating a log page of its own, they are carved out of a page shared by
+ * all transactions.
+ */
+pub struct IndirectVersion<'data> {
+    version: Version,
+    slot: &'data Version,
+}
+
+impl<'data> IndirectVersion<'data> {
+    pub fn version(&self) -> Version {
+        self.version.clone()
+    }
+
+    fn commit(&self, new_version: usize) {
+        self.slot.commit_direct(new_version)
+    }
+}
+
+/*
+ * A fetched copy that's pointed to in place of the object on a block source.
+ * The owner is the pointer that was swung to it, by its logical address. It's
+ * only ever reached through the address space, and only while the object
+ * holding it isn't released.
+ */
+struct Resident {
+    owner: LogicalAddress,
+    original: UntypedPointer,
+    copy: ByteLogicalSlice,
+    /* of the copy as it was fetched, one that differs has to be written back */
+    crc: u32,
+}
+
+impl Resident {
+    fn new(
+        owner: LogicalAddress,
+        original: &UntypedPointer,
+        copy: ByteLogicalSlice,
+        data: &[u8],
+    ) -> Self {
+        Resident {
+            owner,
+            original: original.with_refcount(0),
+            copy,
+            crc: utils::crc_slice(data),
+        }
+    }
+}
+
+enum Eviction {
+    Evicted,
+    /* the pointer has moved on, what the copy is for isn't up to residency anymore */
+    Untracked,
+    Kept,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BufferStats {
+    /* bytes of fetched copies that are pointed to */
+    pub resident: usize,
+    pub evicted: usize,
+    /* evicted copies that had changed and were written to a new place */
+    pub written_back: usize,
+}
+
+/*
+ * The fetched copies that are pointed to, by the DRAM page they're in.
+ * Pages are gone through like a clock: a page read from since the hand
+ * last passed it gets another round, the copies in any other are evicted,
+ * until the copies fit the limit again.
+ */
+#[derive(Default)]
+struct Residency {
+    pages: BTreeMap<LogicalAddress, (bool, Vec<Resident>)>,
+    hand: LogicalAddress,
+    limit: Option<usize>,
+    stats: BufferStats,
+}
+
+impl Residency {
+    fn admit(&mut self, pagesize: usize, copies: Vec<Resident>) {
+        for resident in copies {
+            let page = math::align_down(resident.copy.0.address(), pagesize);
+            self.stats.resident += resident.copy.0.len();
+            self.pages.entry(page).or_default().1.push(resident);
+        }
+    }
+
+    fn touch(&mut self, pages: HashSet<LogicalAddress>) {
+        for page in pages {
+            if let Some((referenced, _)) = self.pages.get_mut(&page) {
+                *referenced = true;
+            }
+        }
+    }
+
+    /* the page under the hand, which then moves on past it */
+    fn advance(&mut self) -> Option<LogicalAddress> {
+        let page = self
+            .pages
+            .range(self.hand..)
+            .chain(self.pages.iter())
+            .next()
+            .map(|(page, _)| *page)?;
+        self.hand = page + 1;
+        Some(page)
+    }
+}
+
 pub struct VersionedObjectStore<'data> {
     phantom: PhantomData<&'data u8>,
+    /* the newest version that's visible, every one before it is done committing */
     version: RwLock<usize>,
+    /* the newest version handed out to a committing transaction */
+    reserved: AtomicUsize,
+    /* versions done committing, or failing to, that wait for older ones to be */
+    finished: Mutex<BTreeSet<usize>>,
+    published: Condvar,
+    version_slots: Mutex<Option<LogicalMutRef<'data>>>,
+    open_pages: OpenPages<'data>,
+    free_list: FreeList<'data>,
+    /* committed redo records waiting to be applied, with their owners' logical addresses */
+    folds: Mutex<Vec<(usize, Option<LogicalAddress>, UntypedPointer)>>,
+    /*
+     * The logical space of released objects, by start, with its end and the
+     * version that released it. Pointers in there aren't owners of anything
+     * anymore, until the space is reclaimed and this forgets about it.
+     */
+    released: Mutex<BTreeMap<LogicalAddress, (LogicalAddress, usize)>>,
+    /* snapshot versions of the running transactions, and how many share each */
+    snapshots: Mutex<BTreeMap<usize, usize>>,
+    /* new versions, to be cut off from the ones they replaced once those are out of sight */
+    trims: Mutex<Vec<(usize, UntypedPointer)>>,
+    /* discarded fetched copies, with the version that has to be the oldest before reuse */
+    fetched: Mutex<Vec<(usize, ByteLogicalSlice)>>,
+    /* how many versions back from the current one are kept readable */
+    history: usize,
+    /* no version before this one can be read anymore, it may have been collected */
+    horizon: AtomicUsize,
+    checksums: ChecksumMode,
+    compression: Option<Arc<Compression<'data>>>,
+    residency: Mutex<Residency>,
+    /* collection and eviction are left to run_maintenance(), off the transactions' path */
+    deferred: bool,
+    locality: LocalityCounters,
+    epoch: u64,
 }
 
 impl<'data> VersionedObjectStore<'data> {
-    pub fn new() -> Self {
+    pub fn new(pagesize: usize) -> Self {
         VersionedObjectStore {
             phantom: PhantomData,
             version: RwLock::new(1),
+            reserved: AtomicUsize::new(1),
+            finished: Mutex::new(BTreeSet::new()),
+            published: Condvar::new(),
+            version_slots: Mutex::new(None),
+            open_pages: OpenPages::new(pagesize),
+            free_list: FreeList::new(pagesize),
+            folds: Mutex::new(Vec::new()),
+            released: Mutex::new(BTreeMap::new()),
+            snapshots: Mutex::new(BTreeMap::new()),
+            trims: Mutex::new(Vec::new()),
+            fetched: Mutex::new(Vec::new()),
+            history: 0,
+            horizon: AtomicUsize::new(1),
+            checksums: ChecksumMode::default(),
+            compression: None,
+            residency: Mutex::new(Residency::default()),
+            deferred: false,
+            locality: LocalityCounters::default(),
+            epoch: RandomState::new().build_hasher().finish(),
         }
     }
 
-    pub fn new_object_allocator<'tx>(
+    /*
+     * Keeps that many of the latest versions readable with
+     * new_pinned_reader_at(), even while no snapshot is pinning them.
+     */
+    pub fn set_history(&mut self, versions: usize) {
+        self.history = versions;
+    }
+
+    /* goes on from the newest version a reopened store had written back */
+    pub fn restore_version(&self, version: usize) {
+        let mut current = self.version.write();
+        *current = version.max(*current);
+        self.reserved.fetch_max(*current, Ordering::AcqRel);
+        self.horizon.store(*current, Ordering::Release);
+    }
+
+    pub fn set_checksums(&mut self, mode: ChecksumMode) {
+        self.checksums = mode;
+    }
+
+    pub fn set_compression(&mut self, compression: Compression<'data>) {
+        self.compression = Some(Arc::new(compression));
+    }
+
+    /* how many bytes of fetched copies evict() lets stay resident */
+    pub fn set_buffer_limit(&mut self, bytes: usize) {
+        self.residency.get_mut().limit = Some(bytes);
+    }
+
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.residency.lock().stats
+    }
+
+    pub fn set_deferred(&mut self) {
+        self.deferred = true;
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn locality(&self) -> AllocLocality {
+        AllocLocality {
+            near_hits: self.locality.near_hits.load(Ordering::Relaxed),
+            near_misses: self.locality.near_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn new_indirect_version(
         &self,
-        page_alloc: PageAlloc<'tx>,
-    ) -> TransactionalObjectAllocator<'tx> {
-        TransactionalObjectAllocator::new(page_alloc)
+        las: &LogicalAddressSpace<'data>,
+    ) -> Result<IndirectVersion<'data>> {
+        let mut active = self.version_slots.lock();
+
+        let (slice, data) = loop {
+            let page = active.get_or_insert_with_result(|| las.alloc())?;
+            match page.try_consume_bytes(size_of::<Version>(), size_of::<Version>()) {
+                Some(it) => break it,
+                None => *active = None,
+            }
+        };
+
+        let slot = unsafe_utils::any_from_slice_mut::<Version>(data);
+        *slot = Version::new();
+
+        let ptr = UntypedPointer::new_byte(slice.address());
+
+        Ok(IndirectVersion {
+            version: Version::new_indirect(ptr),
+            slot,
+        })
+    }
+
+    pub fn new_object_allocator<'tx>(
+        &'tx self,
+        page_alloc: PageAlloc<'tx, 'data>,
+    ) -> TransactionalObjectAllocator<'tx, 'data> {
+        TransactionalObjectAllocator::new(
+            page_alloc,
+            &self.open_pages,
+            &self.free_list,
+            &self.locality,
+        )
     }
 
     pub fn new_log_allocator<'tx>(
         &self,
-        page_alloc: PageAlloc<'tx>,
-    ) -> TransactionalLogAllocator<'tx> {
+        page_alloc: PageAlloc<'tx, 'data>,
+    ) -> TransactionalLogAllocator<'tx, 'data> {
         TransactionalLogAllocator::new(page_alloc)
     }
 
@@ -558,28 +1922,583 @@ impl<'data> VersionedObjectStore<'data> {
         &self,
         las: &'tx LogicalAddressSpace<'data>,
     ) -> VersionedReader<'tx, 'data> {
-        VersionedReader::new(*self.version.read(), las)
+        VersionedReader::new(
+            *self.version.read(),
+            las,
+            self.checksums,
+            self.compression.clone(),
+        )
+    }
+
+    /*
+     * A reader whose snapshot is kept track of until unpin(), so that no
+     * object it might still see is freed and reused under its feet.
+     */
+    pub fn new_pinned_reader<'tx>(
+        &self,
+        las: &'tx LogicalAddressSpace<'data>,
+    ) -> VersionedReader<'tx, 'data> {
+        let version = self.version.read();
+        *self.snapshots.lock().entry(*version).or_insert(0) += 1;
+
+        VersionedReader::new(*version, las, self.checksums, self.compression.clone())
+    }
+
+    /*
+     * A pinned reader of an earlier version. Only versions that collect()
+     * hasn't moved past yet can be read, anything older may already have
+     * been folded into, unlinked or reused.
+     */
+    pub fn new_pinned_reader_at<'tx>(
+        &self,
+        las: &'tx LogicalAddressSpace<'data>,
+        version: usize,
+    ) -> Result<VersionedReader<'tx, 'data>> {
+        let current = self.version.read();
+        let mut snapshots = self.snapshots.lock();
+        let oldest = self.horizon.load(Ordering::Acquire);
+        if version < oldest || version > *current {
+            return Err(Error::VersionUnavailable {
+                version,
+                oldest,
+                newest: *current,
+            });
+        }
+        *snapshots.entry(version).or_insert(0) += 1;
+
+        Ok(VersionedReader::new(
+            version,
+            las,
+            self.checksums,
+            self.compression.clone(),
+        ))
+    }
+
+    pub fn unpin(&self, reader: &VersionedReader<'_, 'data>) {
+        /* while it's still pinned, so that the owners can't have been reclaimed yet */
+        let mut residency = self.residency.lock();
+        let released = self.released.lock();
+        let copies = reader
+            .take_fetched()
+            .into_iter()
+            .filter(|resident| !Self::is_released(&released, resident.owner))
+            .collect();
+        drop(released);
+        residency.admit(reader.las.pagesize(), copies);
+        residency.touch(reader.pages());
+        drop(residency);
+
+        let mut snapshots = self.snapshots.lock();
+        if let Entry::Occupied(mut pinned) = snapshots.entry(reader.version()) {
+            *pinned.get_mut() -= 1;
+            if *pinned.get() == 0 {
+                pinned.remove();
+            }
+        }
+        drop(snapshots);
+
+        /*
+         * Copies that lost the race to be pointed to go back like freed
+         * objects do, once every transaction running by now is done.
+         */
+        let version = *self.version.read() + 1;
+        self.fetched.lock().extend(
+            reader
+                .take_discarded()
+                .into_iter()
+                .map(|copy| (version, copy)),
+        );
+        /* without run_maintenance(), every transaction cleans up as it's done */
+        if !self.deferred {
+            self.collect(reader.las);
+            self.evict(reader.las, usize::MAX);
+        }
     }
 
-    pub fn valid_page(data: &[u8]) -> bool {
-        let header = ObjectHeader::from_slice(data);
-        header.size.total() != 0
+    fn oldest_snapshot(&self) -> usize {
+        let current = *self.version.read();
+        let snapshots = self.snapshots.lock();
+        let kept = current.saturating_sub(self.history).max(1);
+        let pinned = snapshots.keys().next().copied().unwrap_or(current);
+        let oldest = pinned.min(kept);
+        self.horizon.fetch_max(oldest, Ordering::AcqRel);
+        oldest
     }
 
+    /*
+     * Cleans up after versions that no running transaction is old enough to
+     * look past anymore. Redo records are applied first, then freed space is
+     * made reusable, so that a record can't end up written into an object
+     * that has since been freed and reused.
+     */
+    pub fn collect(&self, las: &LogicalAddressSpace<'data>) {
+        let oldest = self.oldest_snapshot();
+        self.fold(las, oldest);
+        self.trim(las, oldest);
+        self.forget_released(oldest);
+        self.free_list.reclaim(oldest);
+
+        let mut fetched = self.fetched.lock();
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *fetched)
+            .into_iter()
+            .partition(|(version, _)| *version <= oldest);
+        *fetched = waiting;
+        for (_, copy) in ready {
+            las.recycle_fetched(&copy)
+                .expect("fetched copy is byte addressable");
+        }
+    }
+
+    /*
+     * Evicted copies are retired like discarded ones, a reader that got to
+     * one before its pointer was swung back can keep reading it. Changed
+     * copies are only written back while that's within the given number of
+     * bytes, the rest is kept. Returns how many bytes were written.
+     */
+    pub fn evict(&self, las: &LogicalAddressSpace<'data>, mut io: usize) -> usize {
+        let version = *self.version.read() + 1;
+        let mut residency = self.residency.lock();
+        let limit = match residency.limit {
+            Some(limit) => limit,
+            None => return 0,
+        };
+        let budget = io;
+        let mut retired = Vec::new();
+
+        /* every page is passed at most twice, once to clear its bit */
+        let mut rounds = 2 * residency.pages.len();
+        while residency.stats.resident > limit && rounds > 0 {
+            rounds -= 1;
+            let page = match residency.advance() {
+                Some(page) => page,
+                None => break,
+            };
+            let (referenced, copies) = residency.pages.get_mut(&page).unwrap();
+            if std::mem::replace(referenced, false) {
+                continue;
+            }
+
+            let mut kept = Vec::new();
+            for resident in std::mem::take(copies) {
+                let len = resident.copy.0.len();
+                match self.evict_copy(las, &resident, &mut residency.stats, &mut io) {
+                    Eviction::Evicted => retired.push((version, resident.copy)),
+                    Eviction::Untracked => {}
+                    Eviction::Kept => {
+                        kept.push(resident);
+                        continue;
+                    }
+                }
+                residency.stats.resident -= len;
+            }
+            match kept.is_empty() {
+                true => residency.pages.remove(&page),
+                false => residency.pages.insert(page, (false, kept)),
+            };
+        }
+        drop(residency);
+
+        /* pointers in a retired copy are as good as freed */
+        let mut released = self.released.lock();
+        for (version, copy) in &retired {
+            let start = copy.0.address();
+            released.insert(start, (start + copy.0.len(), *version));
+        }
+        drop(released);
+        self.fetched.lock().extend(retired);
+
+        budget - io
+    }
+
+    /*
+     * Swings the pointer back to the object the copy was fetched from, or,
+     * if the copy was changed in place since, to where it's written now.
+     * A copy with anything fetched or written below it, or an older version
+     * linked to it, is kept, it's where the pointers to those are.
+     */
+    fn evict_copy(
+        &self,
+        las: &LogicalAddressSpace<'data>,
+        resident: &Resident,
+        stats: &mut BufferStats,
+        io: &mut usize,
+    ) -> Eviction {
+        if Self::is_released(&self.released.lock(), resident.owner) {
+            return Eviction::Untracked;
+        }
+        let owner = match Self::owner(las, resident.owner) {
+            Some(owner) => owner,
+            None => return Eviction::Untracked,
+        };
+        let current = owner.internal_clone();
+        if current.is_log() {
+            return Eviction::Kept;
+        }
+        if !current.is_byte_addressable()
+            || current.address() != resident.copy.0.address() + size_of::<ObjectHeader>()
+        {
+            return Eviction::Untracked;
+        }
+
+        let data = match las.read(&resident.copy) {
+            Ok(data) => data,
+            Err(_) => return Eviction::Untracked,
+        };
+        let (hdr, payload) = data.split_at(size_of::<ObjectHeader>());
+        let header = ObjectHeader::from_slice(hdr);
+        let npointers = header.size.pointers as usize / size_of::<UntypedPointer>();
+        let pointers: &[UntypedPointer] =
+            unsafe { std::slice::from_raw_parts(payload.as_ptr() as *const _, npointers) };
+        let pinned = |p: &UntypedPointer| p.is_some() && (p.is_byte_addressable() || p.is_log());
+        if header.freed.load() != 0 || pinned(&header.other) || pointers.iter().any(pinned) {
+            return Eviction::Kept;
+        }
+
+        let crc = utils::crc_slice(data);
+        if crc != resident.crc && data.len() > *io {
+            return Eviction::Kept;
+        }
+        let target = match crc == resident.crc {
+            true => resident.original.internal_clone(),
+            false => match las.store(data) {
+                Ok(StoredLogicalSlice::Block(stored)) => {
+                    UntypedPointer::new_block(stored.0.address() + size_of::<ObjectHeader>())
+                }
+                Ok(StoredLogicalSlice::Byte(stored)) => {
+                    UntypedPointer::new_byte(stored.0.address() + size_of::<ObjectHeader>())
+                }
+                Err(_) => return Eviction::Kept,
+            },
+        };
+        if crc != resident.crc {
+            *io -= data.len();
+        }
+        let target = target.with_refcount(current.refcount());
+        if !owner.compare_and_swap(current.internal_clone(), target.internal_clone()) {
+            return Eviction::Kept;
+        }
+        /* changed while it was being evicted, that change has to stay visible */
+        if utils::crc_slice(data) != crc {
+            owner.compare_and_swap(target, current);
+            return Eviction::Kept;
+        }
+
+        stats.evicted += 1;
+        if crc != resident.crc {
+            stats.written_back += 1;
+        }
+        Eviction::Evicted
+    }
+
+    pub fn defer_trim(&self, version: usize, object: UntypedPointer) {
+        self.trims.lock().push((version, object));
+    }
+
+    /*
+     * Unlinks the versions that new ones replaced, once every snapshot sees
+     * the new ones. No reader walks past them anymore, and the space of the
+     * old ones can be reused right after, so the links can't be left dangling.
+     */
+    fn trim(&self, las: &LogicalAddressSpace<'data>, oldest: usize) {
+        let mut trims = self.trims.lock();
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *trims)
+            .into_iter()
+            .partition(|(version, _)| *version <= oldest);
+        *trims = waiting;
+
+        for (_, object) in ready {
+            let slice = object
+                .into_stored_slice_offset(0, size_of::<ObjectHeader>())
+                .unwrap_byte();
+            if let Ok(header) = las.read(&slice) {
+                let other = &ObjectHeader::from_slice(header).other;
+                other.compare_and_swap(other.internal_clone(), UntypedPointer::new_none());
+            }
+        }
+    }
+
+    /*
+     * The kind and size, header included, of every version that a newer one
+     * replaced and that some snapshot may still read. Trims wait on the lock,
+     * so none of them can be reclaimed while they're looked at.
+     */
+    pub fn superseded(&self, las: &LogicalAddressSpace<'data>) -> Result<Vec<(u64, usize)>> {
+        let trims = self.trims.lock();
+        let mut superseded = Vec::new();
+        for (_, object) in trims.iter() {
+            let slice = object
+                .into_stored_slice_offset(0, size_of::<ObjectHeader>())
+                .unwrap_byte();
+            let other = &ObjectHeader::from_slice(las.read(&slice)?).other;
+            if other.is_none() {
+                continue;
+            }
+            let (kind, size) = match other.into_stored_slice_offset(0, size_of::<ObjectHeader>()) {
+                StoredLogicalSlice::Byte(slice) => {
+                    let header = ObjectHeader::from_slice(las.read(&slice)?);
+                    (header.kind, header.size)
+                }
+                slice => {
+                    let copy = las.fetch(&slice)?;
+                    let header = ObjectHeader::from_slice(las.read(&copy)?);
+                    let found = (header.kind, header.size);
+                    las.recycle_fetched(&copy)?;
+                    found
+                }
+            };
+            superseded.push((kind, size_of::<ObjectHeader>() + size.total()));
+        }
+        Ok(superseded)
+    }
+
+    /* an owner outside of the address space is left pointing at the records */
+    pub fn defer_fold(
+        &self,
+        las: &LogicalAddressSpace<'data>,
+        version: usize,
+        owner: &UntypedPointer,
+        entry: UntypedPointer,
+    ) {
+        self.folds.lock().push((version, las.locate(owner), entry));
+    }
+
+    /*
+     * Writes the bytes of redo records into their objects and points the
+     * owners back at the objects. Readers that still go through a record
+     * copy the object and apply the same bytes on top, so a copy taken in
+     * the middle of this comes out the same.
+     */
+    fn fold(&self, las: &LogicalAddressSpace<'data>, oldest: usize) {
+        let mut folds = self.folds.lock();
+        let (mut ready, mut waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *folds)
+            .into_iter()
+            .partition(|(version, _, _)| *version <= oldest);
+        ready.sort_by_key(|(version, _, _)| *version);
+
+        let released = self.released.lock();
+        for (version, mut owner, entry) in ready {
+            /* the records still go into the object, anyone going through them gets the same */
+            if owner.is_some_and(|owner| Self::is_released(&released, owner)) {
+                owner = None;
+            }
+            if Self::apply(las, owner, &entry).is_err() {
+                waiting.push((version, owner, entry));
+            }
+        }
+        *folds = waiting;
+    }
+
+    fn apply(
+        las: &LogicalAddressSpace<'data>,
+        owner: Option<LogicalAddress>,
+        entry: &UntypedPointer,
+    ) -> Result<()> {
+        let mut records = Vec::new();
+        let mut object = entry.internal_clone();
+        while object.is_log() {
+            let (hdr, bytes) = LogEntryHeader::read(las, &object)?;
+            records.push((hdr.slice.address(), bytes));
+            object = hdr.base.internal_clone();
+        }
+        if !object.is_byte_addressable() {
+            return Err(Error::NotByteAddressable {});
+        }
+
+        for (offset, bytes) in records.into_iter().rev() {
+            let slice = LogicalSlice::new(object.address() + offset, bytes.len());
+            las.write(&ByteLogicalSlice(slice))?.copy_from_slice(bytes);
+        }
+
+        let header = object
+            .into_stored_slice_offset(0, size_of::<ObjectHeader>())
+            .unwrap_byte();
+        let header = ObjectHeader::from_slice(las.read(&header)?);
+        if header.checksum != 0 {
+            let slice = object
+                .into_stored_slice_offset(header.size.total(), size_of::<ObjectHeader>())
+                .unwrap_byte();
+            ObjectHeader::seal(las.write(&slice)?);
+        }
+
+        /* the object is written in place, the records can't still be needed by anyone */
+        let owner = match owner.and_then(|owner| Self::owner(las, owner)) {
+            Some(owner) => owner,
+            None => return Ok(()),
+        };
+        let count = owner.refcount();
+        owner.compare_and_swap(entry.with_refcount(count), object.with_refcount(count));
+
+        Ok(())
+    }
+
+    /* hands the space of objects freed by a committed version over to the free list */
+    /* the objects are resolved up front, a commit can't fail once it's visible */
+    pub fn release(&self, objects: Vec<LogicalMutRef<'data>>, version: usize) {
+        let mut released = self.released.lock();
+        for chunk in objects {
+            let start = chunk.address();
+            released.insert(start, (start + chunk.len(), version));
+            self.free_list.release(version, chunk);
+        }
+    }
+
+    /* a pointer is only ever reached through the address space, so it can't dangle */
+    fn owner(
+        las: &LogicalAddressSpace<'data>,
+        owner: LogicalAddress,
+    ) -> Option<&'data UntypedPointer> {
+        let slice = ByteLogicalSlice(LogicalSlice::new(owner, size_of::<UntypedPointer>()));
+        las.read(&slice).ok().map(unsafe_utils::any_from_slice)
+    }
+
+    fn is_released(
+        released: &BTreeMap<LogicalAddress, (LogicalAddress, usize)>,
+        address: LogicalAddress,
+    ) -> bool {
+        match released.range(..=address).next_back() {
+            Some((_, (end, _))) => address < *end,
+            None => false,
+        }
+    }
+
+    /*
+     * Stops tracking the owners in memory that's about to be reclaimed. Every
+     * reader that could have gone through them is done, and has handed its
+     * copies over already, so the ones tracked now are all there are.
+     */
+    fn forget_released(&self, oldest: usize) {
+        let mut released = self.released.lock();
+        let (ready, waiting): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(&mut *released)
+            .into_iter()
+            .partition(|(_, (_, version))| *version <= oldest);
+        *released = waiting;
+        /* residency is locked before it everywhere else */
+        drop(released);
+        if ready.is_empty() {
+            return;
+        }
+
+        let mut residency = self.residency.lock();
+        let mut forgotten = 0;
+        for (_, copies) in residency.pages.values_mut() {
+            copies.retain(|resident| {
+                let gone = Self::is_released(&ready, resident.owner);
+                if gone {
+                    forgotten += resident.copy.0.len();
+                }
+                !gone
+            });
+        }
+        residency.pages.retain(|_, (_, copies)| !copies.is_empty());
+        residency.stats.resident -= forgotten;
+        drop(residency);
+
+        for (_, owner, _) in self.folds.lock().iter_mut() {
+            if owner.is_some_and(|owner| Self::is_released(&ready, owner)) {
+                *owner = None;
+            }
+        }
+    }
+
+    /*
+     * Hands back the space of objects made by an aborted transaction. They
+     * were reachable while the transaction ran, so they wait in limbo until
+     * a later version is the oldest one anyone can be reading.
+     */
+    pub fn discard(&self, objects: Vec<LogicalMutRef<'data>>) {
+        let version = *self.version.read() + 1;
+        self.release(objects, version)
+    }
+
+    /* a page in use starts with an object, which may continue into the next pages */
+    pub fn valid_page(data: &[u8]) -> usize {
+        let size = ObjectHeader::from_slice(data).size;
+        match size.pointers as usize + size.data as usize {
+            0 => 0,
+            total => size_of::<ObjectHeader>() + math::align_up(total, OBJECT_ALIGN),
+        }
+    }
+
+    /*
+     * Validates and commits under a version of its own, without holding up
+     * anyone else. Everything the transaction wrote is locked by then, so a
+     * commit that takes a later version either finds those writes when it
+     * validates, or comes after this one in every respect. Versions become
+     * visible in order, each once all older ones are done, and the commit
+     * only returns when its own is, so that whatever runs next sees it.
+     *
+     * Object versions are the timestamps of their last writes. When the one
+     * reserved comes right after the snapshot, no other transaction started
+     * committing in between, nothing read can be newer than the snapshot,
+     * and the reads don't need to be checked one by one.
+     */
     pub fn commit_version<F>(
         &self,
-        version: &Version,
-        las: &LogicalAddressSpace,
+        version: &IndirectVersion,
+        snapshot: usize,
         validate: F,
-    ) -> Result<()>
+    ) -> Result<usize>
     where
         F: FnOnce() -> Result<()>,
     {
-        let mut new_version = self.version.write();
-        *new_version += 1;
+        let new_version = self.reserved.fetch_add(1, Ordering::AcqRel) + 1;
 
-        validate()?;
+        let result = match new_version == snapshot + 1 {
+            true => Ok(()),
+            false => validate(),
+        };
+        if result.is_ok() {
+            version.commit(new_version);
+        }
+        self.publish(new_version);
+
+        result.map(|_| new_version)
+    }
+
+    /* a version that failed to commit is published all the same, as a gap */
+    fn publish(&self, version: usize) {
+        let mut finished = self.finished.lock();
+        finished.insert(version);
+        {
+            let mut visible = self.version.write();
+            while finished.remove(&(*visible + 1)) {
+                *visible += 1;
+            }
+        }
+        self.published.notify_all();
+
+        while *self.version.read() < version {
+            self.published.wait(&mut finished);
+        }
+    }
+}
 
-        version.commit(*new_version, las)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn little_endian() {
+        /* an image made anywhere reads the same, tag bits included */
+        let pointer = UntypedPointer::new_log(0x0102_0304);
+        let raw = 0x0102_0304 | UntypedPointer::POINTER_LOG;
+        assert_eq!(unsafe_utils::any_as_slice(&pointer), &raw.to_le_bytes()[..]);
+
+        let image = raw.to_le_bytes();
+        let loaded = unsafe_utils::any_from_slice::<UntypedPointer>(&image);
+        assert!(loaded.is_log());
+        assert_eq!(loaded.address(), 0x0102_0304);
+
+        let version = Version::new();
+        version.commit_direct(0x0506);
+        assert_eq!(
+            unsafe_utils::any_as_slice(&version),
+            &0x0506u64.to_le_bytes()[..]
+        );
+
+        let image = 0x0708u64.to_le_bytes();
+        let loaded = unsafe_utils::any_from_slice::<Version>(&image);
+        assert_eq!(loaded.type_bytes(), Version::VERSION_TYPE_DIRECT);
+        assert_eq!(loaded.data_bytes(), 0x0708);
     }
 }